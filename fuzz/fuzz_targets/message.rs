@@ -0,0 +1,64 @@
+#![no_main]
+
+use std::time::Duration;
+
+use bytes::Bytes;
+use hmtk::mqtt::{DeviceInfo, DeviceOptions, Message, Protocol, RetryPolicy, Transform};
+use hmtk::parser::{FieldMap, FieldSpec, FieldType, Parser};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    for transform in [
+        Transform::None,
+        Transform::Base64,
+        Transform::Xor(vec![0x5a]),
+    ] {
+        let options = DeviceOptions {
+            ty: "HMG-50".to_owned(),
+            mac: "abc".to_owned(),
+            protocol: Protocol {
+                transform,
+                ..Protocol::default()
+            },
+            dry_run: false,
+            min_interval: Duration::ZERO,
+            retry: RetryPolicy::default(),
+        };
+        if let Some(device_info) = hmtk::mqtt::fuzz_status(&options, Bytes::copy_from_slice(data)) {
+            for metric in DeviceInfo::METRICS {
+                device_info.metric(metric);
+            }
+        }
+    }
+
+    let Ok(message) = Message::parse(Bytes::copy_from_slice(data)) else {
+        return;
+    };
+    // Whitespace around the message is trimmed, the fields only roundtrip without it.
+    Message::parse(Bytes::from(message.to_string())).unwrap();
+
+    let parser = FieldMap::default()
+        .field("power", FieldSpec::new("w1", FieldType::Integer))
+        .field(
+            "charge",
+            FieldSpec {
+                scale: Some(0.1),
+                ..FieldSpec::new("pe", FieldType::Float)
+            },
+        )
+        .field(
+            "active",
+            FieldSpec {
+                bit: Some(3),
+                ..FieldSpec::new("o1", FieldType::Bool)
+            },
+        )
+        .field(
+            "firmware",
+            FieldSpec {
+                optional: true,
+                ..FieldSpec::new("fv", FieldType::String)
+            },
+        );
+    let _ = parser.parse(&message);
+});
@@ -0,0 +1,387 @@
+//! Alert rules evaluated against device samples.
+use std::time::{Duration, SystemTime};
+
+use crate::mqtt::DeviceInfo;
+
+/// Condition under which a rule raises an alert.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Condition {
+    /// The value is below the threshold.
+    Below(f64),
+    /// The value is above the threshold.
+    Above(f64),
+    /// The value is outside of the inclusive band `min..=max`.
+    Outside { min: f64, max: f64 },
+    /// The value equals the expected value, booleans are represented as `0` or `1`.
+    Equals(f64),
+    /// The value differs from the expected value.
+    NotEquals(f64),
+}
+
+impl Condition {
+    /// Returns `true` if the `value` violates the condition.
+    pub fn matches(&self, value: f64) -> bool {
+        match *self {
+            Condition::Below(threshold) => value < threshold,
+            Condition::Above(threshold) => value > threshold,
+            Condition::Outside { min, max } => !(min..=max).contains(&value),
+            Condition::Equals(expected) => value == expected,
+            Condition::NotEquals(expected) => value != expected,
+        }
+    }
+
+    /// Returns `true` if the `value` satisfies the condition by at least `hysteresis`.
+    ///
+    /// Used to resolve active alerts, which prevents alerts from flapping when the value
+    /// oscillates around a threshold. Equality conditions ignore the hysteresis.
+    pub fn clears(&self, value: f64, hysteresis: f64) -> bool {
+        match *self {
+            Condition::Below(threshold) => value >= threshold + hysteresis,
+            Condition::Above(threshold) => value <= threshold - hysteresis,
+            Condition::Outside { min, max } => {
+                (min + hysteresis..=max - hysteresis).contains(&value)
+            }
+            Condition::Equals(_) | Condition::NotEquals(_) => !self.matches(value),
+        }
+    }
+}
+
+/// Error returned when a rule expression cannot be parsed.
+#[derive(Debug, thiserror::Error)]
+#[error("invalid rule expression `{expression}`, expected for example `battery.charge < 10`")]
+pub struct InvalidExpression {
+    pub expression: String,
+}
+
+/// A named condition on a metric, see [`DeviceInfo::metric`] for available metrics.
+#[derive(Debug, Clone)]
+pub struct Rule {
+    pub name: String,
+    pub metric: String,
+    pub condition: Condition,
+    /// Margin by which the value has to satisfy the condition again to resolve the alert.
+    pub hysteresis: f64,
+    /// Interval in which an active alert is reported again, only reported once if `None`.
+    pub repeat: Option<Duration>,
+}
+
+impl Rule {
+    /// Creates a rule from an expression like `battery.charge < 10` or `output1.active == false`.
+    ///
+    /// Supported operators are `<`, `>`, `==` and `!=`.
+    pub fn parse(name: &str, expression: &str) -> Result<Self, InvalidExpression> {
+        let (metric, condition) =
+            parse_expression(expression).ok_or_else(|| InvalidExpression {
+                expression: expression.to_owned(),
+            })?;
+
+        Ok(Self {
+            name: name.to_owned(),
+            metric,
+            condition,
+            hysteresis: 0.0,
+            repeat: None,
+        })
+    }
+
+    /// Built-in rule for batteries storing far less than the surplus solar power.
+    ///
+    /// Hints at BMS derating or cell imbalance, see [`CHARGE_ACCEPTANCE`].
+    pub fn charging_anomaly() -> Rule {
+        Rule {
+            name: "charging_anomaly".to_owned(),
+            metric: CHARGE_ACCEPTANCE.to_owned(),
+            condition: Condition::Below(0.5),
+            hysteresis: 0.1,
+            repeat: None,
+        }
+    }
+
+    /// Built-in rules for the grid power quality of AC coupled models.
+    ///
+    /// Tolerances follow EN 50160, 230 V ±10% and 50 Hz ±1%.
+    pub fn power_quality() -> Vec<Rule> {
+        vec![
+            Rule {
+                name: "grid_voltage_out_of_band".to_owned(),
+                metric: "grid.voltage".to_owned(),
+                condition: Condition::Outside {
+                    min: 207.0,
+                    max: 253.0,
+                },
+                hysteresis: 0.0,
+                repeat: None,
+            },
+            Rule {
+                name: "grid_frequency_out_of_band".to_owned(),
+                metric: "grid.frequency".to_owned(),
+                condition: Condition::Outside {
+                    min: 49.5,
+                    max: 50.5,
+                },
+                hysteresis: 0.0,
+                repeat: None,
+            },
+        ]
+    }
+}
+
+fn parse_expression(expression: &str) -> Option<(String, Condition)> {
+    let mut parts = expression.split_whitespace();
+    let (Some(metric), Some(operator), Some(value), None) =
+        (parts.next(), parts.next(), parts.next(), parts.next())
+    else {
+        return None;
+    };
+
+    let value = match value {
+        "true" => 1.0,
+        "false" => 0.0,
+        value => value.parse().ok()?,
+    };
+    let condition = match operator {
+        "<" => Condition::Below(value),
+        ">" => Condition::Above(value),
+        "==" => Condition::Equals(value),
+        "!=" => Condition::NotEquals(value),
+        _ => return None,
+    };
+
+    Some((metric.to_owned(), condition))
+}
+
+/// Metric derived from consecutive samples, the ratio of the energy stored in the battery
+/// to the surplus solar energy (solar input minus output power) while charging.
+///
+/// The value is only available after the battery charged for [`ChargeAcceptance::WINDOW`]
+/// with enough surplus power and the battery not being full.
+pub const CHARGE_ACCEPTANCE: &str = "battery.charge_acceptance";
+
+/// Tracks the [`CHARGE_ACCEPTANCE`] of a battery across samples.
+///
+/// The remaining capacity is only reported in whole watt hours, the ratio is therefore
+/// measured over windows of at least [`Self::WINDOW`].
+#[derive(Debug, Default)]
+pub struct ChargeAcceptance {
+    /// Start of the current window and the capacity in Wh at that time.
+    start: Option<(SystemTime, f64)>,
+    /// Time of the previous sample in the current window.
+    previous: Option<SystemTime>,
+    /// Surplus energy in Wh expected to be stored since the start of the window.
+    expected: f64,
+    /// Ratio of the last completed window.
+    ratio: Option<f64>,
+}
+
+impl ChargeAcceptance {
+    /// Minimum duration of a measurement window.
+    pub const WINDOW: Duration = Duration::from_secs(5 * 60);
+    /// Minimum surplus power in W, below it the measurement is dominated by rounding errors.
+    pub const MIN_SURPLUS: f64 = 100.0;
+
+    /// Updates the measurement with a new sample and returns the current charge acceptance.
+    pub fn update(&mut self, device_info: &DeviceInfo) -> Option<f64> {
+        self.sample(
+            device_info.timestamp,
+            device_info.battery.charge.0.into(),
+            device_info.battery.capacity.0.into(),
+            device_info.derived().net_power as f64,
+        )
+    }
+
+    fn sample(
+        &mut self,
+        timestamp: SystemTime,
+        charge: f64,
+        capacity: f64,
+        surplus: f64,
+    ) -> Option<f64> {
+        if charge >= 100.0 || surplus < Self::MIN_SURPLUS {
+            *self = Self::default();
+            return None;
+        }
+
+        let Some((start, start_capacity)) = self.start else {
+            self.start = Some((timestamp, capacity));
+            self.previous = Some(timestamp);
+            return None;
+        };
+
+        let elapsed = self
+            .previous
+            .and_then(|previous| timestamp.duration_since(previous).ok())
+            .unwrap_or_default();
+        self.expected += surplus * elapsed.as_secs_f64() / 3600.0;
+        self.previous = Some(timestamp);
+
+        let window = timestamp.duration_since(start).unwrap_or_default();
+        if window >= Self::WINDOW && self.expected > 0.0 {
+            self.ratio = Some((capacity - start_capacity) / self.expected);
+            self.start = Some((timestamp, capacity));
+            self.expected = 0.0;
+        }
+
+        self.ratio
+    }
+}
+
+/// A change in the state of an alert.
+#[derive(Debug, Clone, Copy)]
+pub enum Alert<'a> {
+    /// The rule started matching.
+    Raised { rule: &'a Rule, value: f64 },
+    /// The rule still matches and its repeat interval elapsed.
+    Repeated { rule: &'a Rule, value: f64 },
+    /// The rule no longer matches.
+    Resolved { rule: &'a Rule, value: f64 },
+}
+
+impl<'a> Alert<'a> {
+    /// Returns the rule of the alert.
+    pub fn rule(&self) -> &'a Rule {
+        match *self {
+            Alert::Raised { rule, .. }
+            | Alert::Repeated { rule, .. }
+            | Alert::Resolved { rule, .. } => rule,
+        }
+    }
+
+    /// Returns the value of the metric which caused the alert.
+    pub fn value(&self) -> f64 {
+        match *self {
+            Alert::Raised { value, .. }
+            | Alert::Repeated { value, .. }
+            | Alert::Resolved { value, .. } => value,
+        }
+    }
+
+    /// Returns the state of the alert as string, e.g. `raised`.
+    pub fn state(&self) -> &'static str {
+        match self {
+            Alert::Raised { .. } => "raised",
+            Alert::Repeated { .. } => "repeated",
+            Alert::Resolved { .. } => "resolved",
+        }
+    }
+}
+
+/// Tracks the state of a set of rules across samples.
+///
+/// An alert is only reported once when it is raised and once when it is resolved,
+/// not for every sample which violates the rule, unless the rule has a repeat interval.
+#[derive(Debug)]
+pub struct Alerts {
+    rules: Vec<Rule>,
+    /// Time the alert was last reported, for every active rule.
+    active: Vec<Option<SystemTime>>,
+    charge_acceptance: ChargeAcceptance,
+}
+
+impl Alerts {
+    pub fn new(rules: Vec<Rule>) -> Self {
+        let active = vec![None; rules.len()];
+        Self {
+            rules,
+            active,
+            charge_acceptance: ChargeAcceptance::default(),
+        }
+    }
+
+    /// Evaluates all rules against the `device_info` and returns all alerts which changed state.
+    ///
+    /// Rules whose metric is not available keep their current state. Besides the metrics
+    /// of the device, rules can use the derived [`CHARGE_ACCEPTANCE`].
+    pub fn evaluate(&mut self, device_info: &DeviceInfo) -> Vec<Alert<'_>> {
+        let mut result = Vec::new();
+
+        let charge_acceptance = self.charge_acceptance.update(device_info);
+        for (rule, active) in self.rules.iter().zip(self.active.iter_mut()) {
+            let value = match rule.metric.as_str() {
+                CHARGE_ACCEPTANCE => charge_acceptance,
+                metric => device_info.metric(metric),
+            };
+            let Some(value) = value else {
+                continue;
+            };
+
+            let now = device_info.timestamp;
+            match *active {
+                None if rule.condition.matches(value) => {
+                    result.push(Alert::Raised { rule, value });
+                    *active = Some(now);
+                }
+                Some(_) if rule.condition.clears(value, rule.hysteresis) => {
+                    result.push(Alert::Resolved { rule, value });
+                    *active = None;
+                }
+                Some(reported) => {
+                    let elapsed = now.duration_since(reported).unwrap_or_default();
+                    if let Some(repeat) = rule.repeat
+                        && elapsed >= repeat
+                    {
+                        result.push(Alert::Repeated { rule, value });
+                        *active = Some(now);
+                    }
+                }
+                None => {}
+            }
+        }
+
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_rule() {
+        let rule = Rule::parse("battery_low", "battery.charge < 10").unwrap();
+        assert_eq!(rule.metric, "battery.charge");
+        assert_eq!(rule.condition, Condition::Below(10.0));
+
+        let rule = Rule::parse("output_off", "output1.active == false").unwrap();
+        assert_eq!(rule.condition, Condition::Equals(0.0));
+
+        let err = Rule::parse("invalid", "battery.charge <= 10").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "invalid rule expression `battery.charge <= 10`, expected for example `battery.charge < 10`"
+        );
+        assert!(Rule::parse("invalid", "battery.charge").is_err());
+    }
+
+    #[test]
+    fn test_hysteresis() {
+        let condition = Condition::Above(45.0);
+        assert!(condition.matches(46.0));
+        assert!(!condition.clears(44.0, 2.0));
+        assert!(condition.clears(43.0, 2.0));
+
+        let condition = Condition::Outside {
+            min: 207.0,
+            max: 253.0,
+        };
+        assert!(!condition.clears(252.0, 5.0));
+        assert!(condition.clears(230.0, 5.0));
+    }
+
+    #[test]
+    fn test_charge_acceptance() {
+        let at = |secs| SystemTime::UNIX_EPOCH + Duration::from_secs(secs);
+        let mut acceptance = ChargeAcceptance::default();
+
+        // 400 W surplus over 6 minutes are 40 Wh, of which only 10 Wh are stored.
+        assert_eq!(acceptance.sample(at(0), 50.0, 1000.0, 400.0), None);
+        assert_eq!(acceptance.sample(at(180), 51.0, 1005.0, 400.0), None);
+        assert_eq!(acceptance.sample(at(360), 51.0, 1010.0, 400.0), Some(0.25));
+        // The ratio is kept until the next window completes.
+        assert_eq!(acceptance.sample(at(420), 52.0, 1015.0, 400.0), Some(0.25));
+
+        // A full battery or too little surplus power reset the measurement.
+        assert_eq!(acceptance.sample(at(480), 100.0, 1020.0, 400.0), None);
+        assert_eq!(acceptance.sample(at(540), 99.0, 1020.0, 400.0), None);
+        assert_eq!(acceptance.sample(at(600), 99.0, 1020.0, 50.0), None);
+    }
+}
@@ -0,0 +1,585 @@
+//! Long term analysis of recorded measurements.
+use std::collections::BTreeMap;
+use std::time::{Duration, SystemTime};
+
+use serde::Serialize;
+
+use crate::mqtt::{DailyEnergy, DeviceInfo};
+use crate::time::LocalTime;
+use crate::units::Watt;
+
+/// Temperature in °C the compensated capacity is normalized to.
+pub const REFERENCE_TEMPERATURE: f64 = 25.0;
+
+/// Minimum charge in percent for a sample to be used for capacity estimates.
+///
+/// The remaining capacity is reported in whole watt hours and the charge in whole percent,
+/// at a low charge the rounding errors dominate the estimate.
+const MIN_CHARGE: f64 = 20.0;
+
+/// A single measurement of the battery.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CapacitySample {
+    /// Battery temperature in °C.
+    pub temperature: f64,
+    /// Charge in percent.
+    pub charge: f64,
+    /// Remaining energy in Wh.
+    pub remaining: f64,
+}
+
+impl CapacitySample {
+    /// Returns the usable capacity in Wh, extrapolated from the remaining energy and the charge.
+    ///
+    /// Returns `None` if the charge is too low for a reliable estimate.
+    pub fn usable(&self) -> Option<f64> {
+        (self.charge >= MIN_CHARGE).then(|| self.remaining * 100.0 / self.charge)
+    }
+}
+
+/// Linear model of the usable capacity depending on the battery temperature.
+///
+/// Lithium batteries deliver less energy when cold, the model is fitted to the history of a
+/// battery to tell seasonal capacity dips apart from degradation.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct CapacityModel {
+    /// Change of the usable capacity in Wh per °C.
+    pub coefficient: f64,
+    /// Usable capacity in Wh at the [`REFERENCE_TEMPERATURE`].
+    pub reference: f64,
+    /// Number of samples the model is fitted to.
+    pub samples: u64,
+}
+
+impl CapacityModel {
+    /// Fits the model with a least squares regression over all usable samples.
+    ///
+    /// Returns `None` if there are no usable samples. Without temperature variation
+    /// the coefficient is `0`.
+    pub fn fit(samples: impl IntoIterator<Item = CapacitySample>) -> Option<Self> {
+        let mut count = 0u64;
+        let (mut sum_x, mut sum_y, mut sum_xx, mut sum_xy) = (0.0, 0.0, 0.0, 0.0);
+        for sample in samples {
+            let Some(usable) = sample.usable() else {
+                continue;
+            };
+            count += 1;
+            sum_x += sample.temperature;
+            sum_y += usable;
+            sum_xx += sample.temperature * sample.temperature;
+            sum_xy += sample.temperature * usable;
+        }
+        if count == 0 {
+            return None;
+        }
+
+        let n = count as f64;
+        let (mean_x, mean_y) = (sum_x / n, sum_y / n);
+        let variance = sum_xx / n - mean_x * mean_x;
+        let coefficient = match variance > f64::EPSILON {
+            true => (sum_xy / n - mean_x * mean_y) / variance,
+            false => 0.0,
+        };
+
+        Some(Self {
+            coefficient,
+            reference: mean_y + coefficient * (REFERENCE_TEMPERATURE - mean_x),
+            samples: count,
+        })
+    }
+
+    /// Normalizes a `usable` capacity measured at `temperature` to the [`REFERENCE_TEMPERATURE`].
+    pub fn compensate(&self, usable: f64, temperature: f64) -> f64 {
+        usable + self.coefficient * (REFERENCE_TEMPERATURE - temperature)
+    }
+}
+
+/// Longest interval between two power samples which is still integrated.
+///
+/// Larger gaps, e.g. while the monitor was not running, are not interpolated.
+pub const MAX_GAP: Duration = Duration::from_secs(10 * 60);
+
+const HOUR: u64 = 3600;
+
+/// Integrates power samples in W to the energy in Wh per hour.
+///
+/// Samples must be ordered by time, the power is interpolated linearly between two samples.
+/// Returns the start of every hour with energy and the energy in that hour.
+pub fn hourly_energy(
+    samples: impl IntoIterator<Item = (SystemTime, f64)>,
+) -> BTreeMap<SystemTime, f64> {
+    let secs = |time: SystemTime| {
+        time.duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs_f64()
+    };
+
+    let mut result = BTreeMap::new();
+    let mut previous: Option<(f64, f64)> = None;
+    for (time, power) in samples {
+        let time = secs(time);
+        if let Some((start, start_power)) = previous
+            && time > start
+            && time - start <= MAX_GAP.as_secs_f64()
+        {
+            let slope = (power - start_power) / (time - start);
+            // Split the interval at hour boundaries.
+            let mut from = start;
+            while from < time {
+                let hour = (from as u64 / HOUR) * HOUR;
+                let to = time.min((hour + HOUR) as f64);
+                let power_from = start_power + slope * (from - start);
+                let power_to = start_power + slope * (to - start);
+                let energy = (power_from + power_to) / 2.0 * (to - from) / HOUR as f64;
+
+                let hour = SystemTime::UNIX_EPOCH + Duration::from_secs(hour);
+                *result.entry(hour).or_default() += energy;
+                from = to;
+            }
+        }
+        previous = Some((time, power));
+    }
+    result
+}
+
+/// Cumulative energy counter, integrating power samples as they are measured.
+///
+/// Uses the same interpolation as [`hourly_energy`], gaps longer than [`MAX_GAP`] are skipped.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct EnergyMeter {
+    previous: Option<(SystemTime, f64)>,
+    total: f64,
+}
+
+impl EnergyMeter {
+    /// Adds a power sample in W and returns the total energy in Wh.
+    pub fn add(&mut self, time: SystemTime, power: f64) -> f64 {
+        if let Some((start, start_power)) = self.previous
+            && let Ok(elapsed) = time.duration_since(start)
+            && elapsed <= MAX_GAP
+        {
+            self.total += (start_power + power) / 2.0 * elapsed.as_secs_f64() / HOUR as f64;
+        }
+        self.previous = Some((time, power));
+        self.total
+    }
+
+    /// Returns the total energy in Wh.
+    pub fn total(&self) -> f64 {
+        self.total
+    }
+
+    /// Resets the total energy, the next sample is still integrated from the previous sample.
+    pub fn reset(&mut self) {
+        self.total = 0.0;
+    }
+}
+
+/// Energy in Wh of every solar input and output, accumulated by a [`DailyEnergyMeter`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct ChannelEnergy {
+    pub solar1: f64,
+    pub solar2: f64,
+    pub output1: f64,
+    pub output2: f64,
+}
+
+/// Integrates the power of every solar input and output into the energy of the current day.
+///
+/// The counters reset once a day, at `reset` after midnight in the local timezone of the host.
+/// The energy between the last sample before and the first sample after the reset is counted
+/// for the new day.
+#[derive(Debug, Clone, Default)]
+pub struct DailyEnergyMeter {
+    reset: Duration,
+    /// Local date of the current day, `(year, month, day)`.
+    day: Option<(i32, u8, u8)>,
+    meters: [EnergyMeter; 4],
+}
+
+impl DailyEnergyMeter {
+    /// Creates a meter, which resets `reset` after local midnight.
+    pub fn new(reset: Duration) -> Self {
+        Self {
+            reset,
+            ..Default::default()
+        }
+    }
+
+    /// Adds the power of a sample and returns the energy of the current day.
+    pub fn add(&mut self, device_info: &DeviceInfo) -> ChannelEnergy {
+        let time = device_info.timestamp;
+        let local = LocalTime::from_system_time(time.checked_sub(self.reset).unwrap_or(time));
+        self.add_on((local.year, local.month, local.day), device_info)
+    }
+
+    fn add_on(&mut self, day: (i32, u8, u8), device_info: &DeviceInfo) -> ChannelEnergy {
+        if self
+            .day
+            .replace(day)
+            .is_some_and(|previous| previous != day)
+        {
+            self.meters.iter_mut().for_each(EnergyMeter::reset);
+        }
+
+        let powers = [
+            device_info.solar1.power,
+            device_info.solar2.power,
+            device_info.output1.power,
+            device_info.output2.power,
+        ];
+        let [solar1, solar2, output1, output2] =
+            std::array::from_fn(|i| self.meters[i].add(device_info.timestamp, powers[i].0.into()));
+
+        ChannelEnergy {
+            solar1,
+            solar2,
+            output1,
+            output2,
+        }
+    }
+}
+
+/// Clock of the device compared to the clock of the host, measured by a [`ClockDriftProbe`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct ClockDrift {
+    /// Seconds the device clock is ahead of the host, negative if it is behind.
+    pub offset: i64,
+    /// Seconds the actual drift may differ from the `offset`.
+    pub uncertainty: u64,
+}
+
+impl ClockDrift {
+    /// Returns the drift of a device which reset its counters `since_midnight` after local
+    /// midnight of the host.
+    fn at(since_midnight: Duration, uncertainty: Duration) -> Self {
+        const DAY: i64 = 24 * 60 * 60;
+
+        // A reset before noon happened after midnight of the host, the device is behind.
+        let secs = since_midnight.as_secs() as i64;
+        let offset = if secs < DAY / 2 { -secs } else { DAY - secs };
+        Self {
+            offset,
+            uncertainty: uncertainty.as_secs(),
+        }
+    }
+
+    /// Returns the absolute drift.
+    pub fn abs(&self) -> Duration {
+        Duration::from_secs(self.offset.unsigned_abs())
+    }
+}
+
+/// Measures the clock drift of the device from the reset of its daily energy counters.
+///
+/// The status does not contain the time of the device, but the device resets its energy counters
+/// at midnight of its clock. The reset is estimated halfway between the last sample before and the
+/// first sample after the reset and compared to local midnight of the host.
+///
+/// Only resets of counters with energy are observable, drifts of 12 hours or more are mistaken
+/// for the opposite direction.
+#[derive(Debug, Clone, Default)]
+pub struct ClockDriftProbe {
+    previous: Option<DailyEnergy>,
+}
+
+impl ClockDriftProbe {
+    /// Adds the energy counters of a sample and returns the drift, if the counters were reset
+    /// since the previous sample.
+    ///
+    /// Resets between samples further apart than [`MAX_GAP`] are ignored.
+    pub fn add(&mut self, energy: &DailyEnergy) -> Option<ClockDrift> {
+        let previous = self.previous.replace(*energy)?;
+        let elapsed = energy.timestamp.duration_since(previous.timestamp).ok()?;
+        if elapsed > MAX_GAP || !is_reset(&previous, energy) {
+            return None;
+        }
+
+        let reset = previous.timestamp + elapsed / 2;
+        let local = LocalTime::from_system_time(reset);
+        Some(ClockDrift::at(local.since_midnight(), elapsed / 2))
+    }
+}
+
+/// Returns `true` if any counter of `current` is lower than in `previous`.
+fn is_reset(previous: &DailyEnergy, current: &DailyEnergy) -> bool {
+    let counters = |energy: &DailyEnergy| {
+        [
+            energy.battery_charge,
+            energy.battery_discharge,
+            energy.solar,
+            energy.output,
+        ]
+    };
+    counters(current)
+        .iter()
+        .zip(counters(previous))
+        .any(|(current, previous)| current.0 < previous.0)
+}
+
+/// Smooths the power of every solar input and output with an exponential moving average.
+///
+/// The weight of a sample decays with its age, by `1/e` per `window`, so irregular sample
+/// intervals are weighted correctly. After a gap longer than [`MAX_GAP`] the average restarts.
+#[derive(Debug, Clone)]
+pub struct PowerSmoother {
+    window: Duration,
+    /// Time of the last sample and the averages of `solar1`, `solar2`, `output1` and `output2`.
+    previous: Option<(SystemTime, [f64; 4])>,
+}
+
+impl PowerSmoother {
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            previous: None,
+        }
+    }
+
+    /// Adds a sample and returns it with the averaged power, rounded to whole watts.
+    pub fn smooth(&mut self, device_info: &DeviceInfo) -> DeviceInfo {
+        let time = device_info.timestamp;
+        let powers = [
+            device_info.solar1.power,
+            device_info.solar2.power,
+            device_info.output1.power,
+            device_info.output2.power,
+        ]
+        .map(|power| f64::from(power.0));
+
+        let averages = match self.previous {
+            Some((previous, averages))
+                if let Ok(elapsed) = time.duration_since(previous)
+                    && elapsed <= MAX_GAP
+                    && !self.window.is_zero() =>
+            {
+                let alpha = 1.0 - (-elapsed.as_secs_f64() / self.window.as_secs_f64()).exp();
+                std::array::from_fn(|i| averages[i] + alpha * (powers[i] - averages[i]))
+            }
+            _ => powers,
+        };
+        self.previous = Some((time, averages));
+
+        let [solar1, solar2, output1, output2] = averages.map(|power| Watt(power.round() as u32));
+        let mut result = *device_info;
+        result.solar1.power = solar1;
+        result.solar2.power = solar2;
+        result.output1.power = output1;
+        result.output2.power = output2;
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::units::WattHours;
+
+    fn device_info(secs: u64, solar1: u32, output2: u32) -> DeviceInfo {
+        let mut device_info: DeviceInfo = serde_json::from_value(serde_json::json!({
+            "timestamp": 0,
+            "solar1": {"charging": true, "pass_through": false, "power": solar1},
+            "solar2": {"charging": false, "pass_through": false, "power": 0},
+            "output1": {"power": 0, "active": false},
+            "output2": {"power": output2, "active": true},
+            "temperature": {"min": 20, "max": 21},
+            "battery": {
+                "charge": 50,
+                "capacity": 1000,
+                "output_threshold": 100,
+                "discharge_depth": 80,
+                "internal": {
+                    "charging": true,
+                    "discharging": false,
+                    "discharge_depth": false,
+                    "undervoltage": false
+                }
+            },
+            "scene": "day"
+        }))
+        .unwrap();
+        device_info.timestamp = SystemTime::UNIX_EPOCH + Duration::from_secs(secs);
+        device_info
+    }
+
+    fn sample(temperature: f64, charge: f64, remaining: f64) -> CapacitySample {
+        CapacitySample {
+            temperature,
+            charge,
+            remaining,
+        }
+    }
+
+    #[test]
+    fn test_usable() {
+        assert_eq!(sample(20.0, 50.0, 1000.0).usable(), Some(2000.0));
+        assert_eq!(sample(20.0, 10.0, 200.0).usable(), None);
+    }
+
+    #[test]
+    fn test_capacity_model() {
+        // 10 Wh less usable capacity per °C below 25 °C.
+        let model = CapacityModel::fit([
+            sample(5.0, 100.0, 1800.0),
+            sample(15.0, 50.0, 950.0),
+            sample(25.0, 100.0, 2000.0),
+            sample(25.0, 5.0, 10.0),
+        ])
+        .unwrap();
+        assert_eq!(model.samples, 3);
+        assert!((model.coefficient - 10.0).abs() < 1e-9);
+        assert!((model.reference - 2000.0).abs() < 1e-9);
+        // A winter measurement is not mistaken for degradation.
+        assert!((model.compensate(1800.0, 5.0) - 2000.0).abs() < 1e-9);
+
+        let model = CapacityModel::fit([sample(20.0, 100.0, 2000.0)]).unwrap();
+        assert_eq!(model.coefficient, 0.0);
+        assert_eq!(model.reference, 2000.0);
+
+        assert_eq!(CapacityModel::fit([sample(20.0, 10.0, 200.0)]), None);
+    }
+
+    #[test]
+    fn test_hourly_energy() {
+        let at = |secs: u64| SystemTime::UNIX_EPOCH + Duration::from_secs(secs);
+
+        let energy = hourly_energy([
+            // Constant 600 W for half an hour, then ramping up to 1200 W across the hour boundary.
+            (at(1800), 600.0),
+            (at(2400), 600.0),
+            (at(3000), 600.0),
+            (at(3300), 600.0),
+            (at(3600), 900.0),
+            (at(3900), 1200.0),
+            // Gaps longer than `MAX_GAP` are skipped.
+            (at(3 * 3600), 1000.0),
+            (at(3 * 3600 + 360), 1000.0),
+        ]);
+        assert_eq!(
+            energy.into_iter().collect::<Vec<_>>(),
+            [
+                (at(0), 250.0 + 62.5),
+                (at(3600), 87.5),
+                (at(3 * 3600), 100.0)
+            ]
+        );
+
+        assert!(hourly_energy([(at(0), 100.0)]).is_empty());
+    }
+
+    #[test]
+    fn test_energy_meter() {
+        let at = |secs: u64| SystemTime::UNIX_EPOCH + Duration::from_secs(secs);
+
+        let mut meter = EnergyMeter::default();
+        assert_eq!(meter.add(at(0), 600.0), 0.0);
+        assert_eq!(meter.add(at(360), 600.0), 60.0);
+        assert_eq!(meter.add(at(720), 1200.0), 150.0);
+        // Gaps longer than `MAX_GAP` and samples out of order are skipped.
+        assert_eq!(meter.add(at(3600), 1200.0), 150.0);
+        assert_eq!(meter.add(at(3000), 1200.0), 150.0);
+        assert_eq!(meter.add(at(3360), 0.0), 210.0);
+        assert_eq!(meter.total(), 210.0);
+
+        meter.reset();
+        assert_eq!(meter.add(at(3720), 0.0), 0.0);
+    }
+
+    #[test]
+    fn test_daily_energy_meter() {
+        let mut meter = DailyEnergyMeter::new(Duration::ZERO);
+        let day = |day| (2024, 3, day);
+        assert_eq!(
+            meter.add_on(day(1), &device_info(0, 600, 100)),
+            ChannelEnergy::default()
+        );
+        let energy = meter.add_on(day(1), &device_info(360, 600, 100));
+        assert_eq!(energy.solar1, 60.0);
+        assert_eq!(energy.output2, 10.0);
+        assert_eq!(energy.solar2, 0.0);
+
+        // The counters restart on the next day, including the energy since the last sample.
+        let energy = meter.add_on(day(2), &device_info(720, 1200, 100));
+        assert_eq!(energy.solar1, 90.0);
+        assert_eq!(energy.output2, 10.0);
+    }
+
+    fn daily_energy(secs: u64, solar: u32, output: u32) -> DailyEnergy {
+        DailyEnergy {
+            timestamp: SystemTime::UNIX_EPOCH + Duration::from_secs(secs),
+            battery_charge: WattHours(0),
+            battery_discharge: WattHours(0),
+            solar: WattHours(solar),
+            output: WattHours(output),
+        }
+    }
+
+    #[test]
+    fn test_clock_drift() {
+        let minutes = |minutes: u64| Duration::from_secs(minutes * 60);
+        let drift = ClockDrift::at(minutes(24 * 60 - 2), minutes(1));
+        assert_eq!(
+            drift,
+            ClockDrift {
+                offset: 120,
+                uncertainty: 60
+            }
+        );
+        let drift = ClockDrift::at(minutes(3), minutes(1));
+        assert_eq!(drift.offset, -180);
+        assert_eq!(drift.abs(), minutes(3));
+        assert_eq!(ClockDrift::at(Duration::ZERO, Duration::ZERO).offset, 0);
+    }
+
+    #[test]
+    fn test_clock_drift_probe() {
+        let mut probe = ClockDriftProbe::default();
+        assert_eq!(probe.add(&daily_energy(0, 3000, 2000)), None);
+        assert_eq!(probe.add(&daily_energy(60, 3000, 2010)), None);
+
+        let drift = probe.add(&daily_energy(120, 0, 0)).unwrap();
+        assert_eq!(drift.uncertainty, 30);
+
+        // Counters which stay at zero do not reveal the reset.
+        let mut probe = ClockDriftProbe::default();
+        assert_eq!(probe.add(&daily_energy(0, 0, 0)), None);
+        assert_eq!(probe.add(&daily_energy(60, 0, 0)), None);
+
+        // A reset during a gap cannot be timed.
+        let mut probe = ClockDriftProbe::default();
+        assert_eq!(probe.add(&daily_energy(0, 3000, 2000)), None);
+        assert_eq!(probe.add(&daily_energy(60 * 60, 0, 0)), None);
+    }
+
+    #[test]
+    fn test_power_smoother() {
+        let mut smoother = PowerSmoother::new(Duration::from_secs(30));
+        assert_eq!(
+            smoother.smooth(&device_info(0, 600, 100)).solar1.power,
+            Watt(600)
+        );
+
+        // After one window the previous average still has a weight of `1/e`.
+        let smoothed = smoother.smooth(&device_info(30, 0, 100));
+        assert_eq!(smoothed.solar1.power, Watt(221));
+        assert_eq!(smoothed.output2.power, Watt(100));
+        assert_eq!(smoothed.derived().solar_power, Watt(221));
+        assert_eq!(
+            smoother.smooth(&device_info(60, 0, 100)).solar1.power,
+            Watt(81)
+        );
+
+        // The average restarts after gaps longer than `MAX_GAP`.
+        assert_eq!(
+            smoother.smooth(&device_info(3600, 300, 0)).solar1.power,
+            Watt(300)
+        );
+
+        let mut disabled = PowerSmoother::new(Duration::ZERO);
+        disabled.smooth(&device_info(0, 600, 100));
+        assert_eq!(
+            disabled.smooth(&device_info(5, 0, 100)).solar1.power,
+            Watt(0)
+        );
+    }
+}
@@ -0,0 +1,165 @@
+//! A blocking client for applications which do not use async Rust.
+//!
+//! ```no_run
+//! use std::time::Duration;
+//!
+//! use hmtk::blocking::Device;
+//! use hmtk::mqtt::{DeviceOptions, Protocol, RetryPolicy};
+//!
+//! let options = DeviceOptions {
+//!     ty: "HMA-1".to_owned(),
+//!     mac: "9523ccae1a9b".to_owned(),
+//!     protocol: Protocol::default(),
+//!     dry_run: false,
+//!     min_interval: Duration::from_secs(1),
+//!     retry: RetryPolicy::default(),
+//! };
+//! let mqtt = rumqttc::MqttOptions::new("hmtk", "127.0.0.1", 1883);
+//!
+//! let mut device = Device::new(mqtt, options)?;
+//! println!("{:?}", device.device_info()?);
+//! device.disconnect()?;
+//! # Ok::<(), hmtk::mqtt::Error>(())
+//! ```
+use std::time::Duration;
+
+use tokio::runtime::Runtime;
+use tokio::task::JoinHandle;
+
+use crate::mqtt::{self, ClientOptions, DailyEnergy, DeviceInfo, DeviceOptions, Error, Result};
+use crate::parser::{Parser, Reading};
+use crate::time::LocalTime;
+
+/// Blocking wrapper of [`mqtt::Device`].
+///
+/// The device loop runs on an internal runtime with a single worker thread, which keeps the
+/// connection alive between calls.
+pub struct Device {
+    runtime: Runtime,
+    device: mqtt::Device,
+    device_loop: JoinHandle<Result<()>>,
+}
+
+impl Device {
+    /// Connects to the broker and starts the device loop.
+    pub fn new(mqtt: impl Into<ClientOptions>, device: DeviceOptions) -> Result<Self> {
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(1)
+            .thread_name("hmtk-blocking")
+            .enable_all()
+            .build()
+            .map_err(Error::Runtime)?;
+
+        let _guard = runtime.enter();
+        let (device, device_loop) = mqtt::Device::new(mqtt, device)?;
+        let device_loop = runtime.spawn(device_loop.into_future());
+
+        Ok(Self {
+            runtime,
+            device,
+            device_loop,
+        })
+    }
+
+    pub fn options(&self) -> &DeviceOptions {
+        self.device.options()
+    }
+
+    /// See [`mqtt::Device::device_info`].
+    pub fn device_info(&mut self) -> Result<DeviceInfo> {
+        self.runtime.block_on(self.device.device_info())
+    }
+
+    /// See [`mqtt::Device::current_device_info`].
+    pub fn current_device_info(&self) -> Option<DeviceInfo> {
+        self.device.current_device_info()
+    }
+
+    /// See [`mqtt::Device::next_device_info`].
+    pub fn next_device_info(&mut self) -> Result<DeviceInfo> {
+        self.runtime.block_on(self.device.next_device_info())
+    }
+
+    /// See [`mqtt::Device::read`].
+    pub fn read(&mut self, parser: &dyn Parser) -> Result<Reading> {
+        self.runtime.block_on(self.device.read(parser))
+    }
+
+    /// See [`mqtt::Device::daily_energy`].
+    pub fn daily_energy(&mut self) -> Result<DailyEnergy> {
+        self.runtime.block_on(self.device.daily_energy())
+    }
+
+    /// See [`mqtt::Device::sync_time`].
+    pub fn sync_time(&mut self, time: LocalTime) -> Result<()> {
+        self.runtime.block_on(self.device.sync_time(time))
+    }
+
+    /// See [`mqtt::Device::verify`].
+    pub fn verify(&mut self, command: mqtt::ControlCommand, timeout: Duration) -> Result<()> {
+        self.runtime.block_on(self.device.verify(command, timeout))
+    }
+
+    /// See [`mqtt::Device::send_command`].
+    pub fn send_command(&self, command: mqtt::ControlCommand, reason: &str) -> Result<()> {
+        self.runtime
+            .block_on(self.device.send_command(command, reason))
+    }
+
+    /// See [`mqtt::Device::publish`].
+    pub fn publish(&self, topic: &str, retain: bool, payload: Vec<u8>) -> Result<()> {
+        self.runtime
+            .block_on(self.device.publish(topic, retain, payload))
+    }
+
+    /// See [`mqtt::Device::reconnects`].
+    pub fn reconnects(&self) -> u64 {
+        self.device.reconnects()
+    }
+
+    /// Disconnects from the broker and waits for the device loop to exit.
+    pub fn disconnect(mut self) -> Result<()> {
+        self.runtime.block_on(async {
+            self.device.disconnect().await?;
+            self.device_loop.await.map_err(|_| Error::Disconnected)?
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime};
+
+    use bytes::Bytes;
+
+    use super::*;
+    use crate::capture::{Direction, Record};
+    use crate::mqtt::{Protocol, RetryPolicy};
+    use crate::time::ManualClock;
+
+    #[test]
+    fn test_blocking_device() {
+        let payload = b"p1=1,p2=1,w1=23,w2=23,pe=99,vv=220,sv=12,cs=0,cd=0,am=0,o1=1,o2=1,do=80,lv=200,cj=2,kn=2217,g1=1,g2=0,b1=0,b2=0,md=0,d1=1,e1=0:0,f1=23:59,h1=200,d2=0,e2=0:0,f2=0:0,h2=600,d3=0,e3=0:0,f3=0:0,h3=0,sg=0,sp=80,st=0,tl=27,th=27,tc=0,tf=0,fc=202310231502,id=5,a0=99,a1=0,a2=0,l0=1,l1=0,c0=255,c1=0,bc=2025,bs=329,pt=3332,it=1518,m0=0,m1=0,m2=0,m3=1,d4=0,e4=0:0,f4=24:0,h4=80,d5=0,e5=0:0,f5=24:0,h5=80,lmo=1830,lmi=272,lmf=1";
+        let records = vec![Record {
+            timestamp: SystemTime::UNIX_EPOCH,
+            direction: Direction::Inbound,
+            topic: "hame_energy/HMA-1/device/abc/ctrl".to_owned(),
+            payload: Bytes::from_static(payload),
+        }];
+        let mqtt = ClientOptions::replay(records, 1.0, ManualClock::new(SystemTime::UNIX_EPOCH));
+        let options = DeviceOptions {
+            ty: "HMA-1".to_owned(),
+            mac: "abc".to_owned(),
+            protocol: Protocol::default(),
+            dry_run: false,
+            min_interval: Duration::ZERO,
+            retry: RetryPolicy::default(),
+        };
+
+        let mut device = Device::new(mqtt, options).unwrap();
+        let device_info = device.next_device_info().unwrap();
+        assert_eq!(device_info.metric("battery.charge"), Some(99.0));
+        assert!(device.current_device_info().is_some());
+        device.disconnect().unwrap();
+    }
+}
@@ -0,0 +1,541 @@
+//! Versioned, compressed capture format for MQTT traffic.
+//!
+//! A capture file starts with a header, followed by a sequence of blocks:
+//!
+//! ```text
+//! header: magic "HMTKCAP\0" | version: u16 | compression: u8
+//! block:  length: u32 | records: u32 | first: u64 | last: u64 | compressed records
+//! record: timestamp: u64 | direction: u8 | topic: u16 + bytes | payload: u32 + bytes
+//! ```
+//!
+//! All integers are little endian, timestamps are microseconds since the unix epoch.
+//! Blocks are compressed with zstd, captures of older versions of hmtk with deflate.
+//!
+//! Block headers are stored uncompressed and contain the time range of their records,
+//! they act as an index which allows skipping blocks outside of a requested time range
+//! without decompressing them.
+use std::collections::BTreeMap;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::time::{Duration, SystemTime};
+
+use bytes::Bytes;
+
+const MAGIC: &[u8; 8] = b"HMTKCAP\0";
+const VERSION: u16 = 1;
+const COMPRESSION_DEFLATE: u8 = 1;
+const COMPRESSION_ZSTD: u8 = 2;
+
+/// Size of uncompressed records after which a block is written.
+const BLOCK_SIZE: usize = 256 * 1024;
+/// Maximum time span of a block, limits the data lost when a capture is interrupted.
+const BLOCK_SPAN: Duration = Duration::from_secs(60);
+/// Size of a record with empty topic and payload: timestamp, direction and both lengths.
+const MIN_RECORD_LEN: usize = 8 + 1 + 2 + 4;
+
+/// Errors which can occur when reading a capture.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error("not a capture file")]
+    InvalidMagic,
+    #[error("unsupported capture version {0}")]
+    UnsupportedVersion(u16),
+    #[error("unsupported capture compression {0}")]
+    UnsupportedCompression(u8),
+    #[error("corrupted capture: {0}")]
+    Corrupted(&'static str),
+}
+
+/// Direction of a captured message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    /// Message received from the broker, e.g. a status published by the device.
+    Inbound,
+    /// Message published by hmtk, e.g. a command sent to the device.
+    Outbound,
+}
+
+/// A single captured MQTT message.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Record {
+    pub timestamp: SystemTime,
+    pub direction: Direction,
+    pub topic: String,
+    pub payload: Bytes,
+}
+
+/// Time range and location of a block in a capture.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BlockInfo {
+    /// Offset of the block header in the capture.
+    pub offset: u64,
+    /// Number of records in the block.
+    pub records: u32,
+    pub first: SystemTime,
+    pub last: SystemTime,
+}
+
+/// Writes records into a capture.
+pub struct Writer<W: Write> {
+    inner: W,
+    block: Vec<u8>,
+    records: u32,
+    first: u64,
+    last: u64,
+}
+
+impl<W: Write> Writer<W> {
+    /// Creates a new capture, writing the header to `inner`.
+    pub fn new(mut inner: W) -> io::Result<Self> {
+        inner.write_all(MAGIC)?;
+        inner.write_all(&VERSION.to_le_bytes())?;
+        inner.write_all(&[COMPRESSION_ZSTD])?;
+
+        Ok(Self {
+            inner,
+            block: Vec::new(),
+            records: 0,
+            first: 0,
+            last: 0,
+        })
+    }
+
+    /// Appends a record to the capture.
+    ///
+    /// Records are buffered and written as compressed blocks,
+    /// use [`Self::flush`] to force writing the current block.
+    pub fn push(&mut self, record: &Record) -> io::Result<()> {
+        let timestamp = micros(record.timestamp);
+        if self.records == 0 {
+            self.first = timestamp;
+        }
+        self.records += 1;
+        self.last = timestamp;
+
+        let topic = record.topic.as_bytes();
+        let topic_len = u16::try_from(topic.len())
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "topic too long"))?;
+        let payload_len = u32::try_from(record.payload.len())
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "payload too large"))?;
+
+        self.block.extend_from_slice(&timestamp.to_le_bytes());
+        self.block.push(match record.direction {
+            Direction::Inbound => 0,
+            Direction::Outbound => 1,
+        });
+        self.block.extend_from_slice(&topic_len.to_le_bytes());
+        self.block.extend_from_slice(topic);
+        self.block.extend_from_slice(&payload_len.to_le_bytes());
+        self.block.extend_from_slice(&record.payload);
+
+        let span = Duration::from_micros(self.last.saturating_sub(self.first));
+        if self.block.len() >= BLOCK_SIZE || span >= BLOCK_SPAN {
+            self.flush()?;
+        }
+
+        Ok(())
+    }
+
+    /// Writes all buffered records as a block.
+    pub fn flush(&mut self) -> io::Result<()> {
+        if self.records == 0 {
+            return self.inner.flush();
+        }
+
+        let compressed = zstd::bulk::compress(&self.block, zstd::DEFAULT_COMPRESSION_LEVEL)?;
+        let length = u32::try_from(compressed.len())
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "block too large"))?;
+
+        self.inner.write_all(&length.to_le_bytes())?;
+        self.inner.write_all(&self.records.to_le_bytes())?;
+        self.inner.write_all(&self.first.to_le_bytes())?;
+        self.inner.write_all(&self.last.to_le_bytes())?;
+        self.inner.write_all(&compressed)?;
+        self.inner.flush()?;
+
+        self.block.clear();
+        self.records = 0;
+        Ok(())
+    }
+
+    /// Flushes all remaining records and returns the inner writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.flush()?;
+        Ok(self.inner)
+    }
+}
+
+/// Reads records from a capture.
+pub struct Reader<R> {
+    inner: R,
+    compression: u8,
+}
+
+impl<R: Read + Seek> Reader<R> {
+    /// Opens a capture, validating its header.
+    pub fn new(mut inner: R) -> Result<Self, Error> {
+        let mut magic = [0; 8];
+        inner.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(Error::InvalidMagic);
+        }
+
+        let version = u16::from_le_bytes(read_array(&mut inner)?);
+        if version != VERSION {
+            return Err(Error::UnsupportedVersion(version));
+        }
+        let [compression] = read_array(&mut inner)?;
+        if !matches!(compression, COMPRESSION_DEFLATE | COMPRESSION_ZSTD) {
+            return Err(Error::UnsupportedCompression(compression));
+        }
+
+        Ok(Self { inner, compression })
+    }
+
+    /// Returns the index of all blocks in the capture.
+    ///
+    /// Only reads block headers, the records are not decompressed.
+    pub fn index(&mut self) -> Result<Vec<BlockInfo>, Error> {
+        self.inner.seek(SeekFrom::Start(header_len()))?;
+
+        let mut index = Vec::new();
+        while let Some((info, length)) = self.read_block_header()? {
+            index.push(info);
+            self.inner.seek(SeekFrom::Current(length.into()))?;
+        }
+        Ok(index)
+    }
+
+    /// Returns all records with a timestamp in the inclusive range `from..=to`.
+    ///
+    /// Blocks outside of the range are skipped without decompressing them.
+    pub fn range(&mut self, from: SystemTime, to: SystemTime) -> Result<Vec<Record>, Error> {
+        let mut result = Vec::new();
+        for block in self.index()? {
+            if block.last < from || block.first > to {
+                continue;
+            }
+
+            let records = self.read_block(block.offset)?;
+            result.extend(
+                records
+                    .into_iter()
+                    .filter(|record| (from..=to).contains(&record.timestamp)),
+            );
+        }
+        Ok(result)
+    }
+
+    /// Returns all records of the capture.
+    pub fn records(&mut self) -> Result<Vec<Record>, Error> {
+        let mut result = Vec::new();
+        for block in self.index()? {
+            result.extend(self.read_block(block.offset)?);
+        }
+        Ok(result)
+    }
+
+    fn read_block_header(&mut self) -> Result<Option<(BlockInfo, u32)>, Error> {
+        let offset = self.inner.stream_position()?;
+
+        let mut length = [0; 4];
+        match self.inner.read_exact(&mut length) {
+            Ok(()) => {}
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(err) => return Err(err.into()),
+        }
+
+        let info = BlockInfo {
+            offset,
+            records: u32::from_le_bytes(read_array(&mut self.inner)?),
+            first: from_micros(u64::from_le_bytes(read_array(&mut self.inner)?)),
+            last: from_micros(u64::from_le_bytes(read_array(&mut self.inner)?)),
+        };
+
+        Ok(Some((info, u32::from_le_bytes(length))))
+    }
+
+    fn read_block(&mut self, offset: u64) -> Result<Vec<Record>, Error> {
+        self.inner.seek(SeekFrom::Start(offset))?;
+        let (info, length) = self
+            .read_block_header()?
+            .ok_or(Error::Corrupted("missing block"))?;
+
+        // The length is not trusted, a corrupted header must not allocate gigabytes.
+        let mut compressed = Vec::new();
+        (&mut self.inner)
+            .take(length.into())
+            .read_to_end(&mut compressed)?;
+        if compressed.len() != length as usize {
+            return Err(Error::Corrupted("truncated block"));
+        }
+        let block = match self.compression {
+            COMPRESSION_DEFLATE => miniz_oxide::inflate::decompress_to_vec(&compressed).ok(),
+            _ => zstd::decode_all(compressed.as_slice()).ok(),
+        }
+        .ok_or(Error::Corrupted("invalid block compression"))?;
+
+        let mut block = block.as_slice();
+        let capacity = (info.records as usize).min(block.len() / MIN_RECORD_LEN);
+        let mut records = Vec::with_capacity(capacity);
+        for _ in 0..info.records {
+            records.push(read_record(&mut block)?);
+        }
+        Ok(records)
+    }
+}
+
+/// Replaces device identities in records with stable pseudonyms.
+///
+/// MACs are collected from the device topics (`hame_energy/<type>/<device|App>/<mac>/...`),
+/// additional identifiers can be registered with [`Self::add_identifier`].
+/// All occurrences of known identifiers in topics and payloads are replaced.
+///
+/// Pseudonyms are derived from a salted hash, the same identifier and salt always
+/// result in the same pseudonym.
+#[derive(Debug)]
+pub struct Scrubber {
+    salt: Vec<u8>,
+    pseudonyms: BTreeMap<String, String>,
+}
+
+impl Scrubber {
+    pub fn new(salt: &[u8]) -> Self {
+        Self {
+            salt: salt.to_vec(),
+            pseudonyms: BTreeMap::new(),
+        }
+    }
+
+    /// Registers an additional identifier which is replaced in all records.
+    pub fn add_identifier(&mut self, identifier: &str) {
+        if identifier.is_empty() || self.pseudonyms.contains_key(identifier) {
+            return;
+        }
+
+        // FNV-1a, stable across platforms and versions unlike the std hasher.
+        let mut hash: u64 = 0xcbf29ce484222325;
+        for byte in self.salt.iter().chain(identifier.as_bytes()) {
+            hash ^= u64::from(*byte);
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+
+        let len = identifier.len().min(16);
+        let pseudonym = format!("{hash:016x}")[..len].to_owned();
+        self.pseudonyms.insert(identifier.to_owned(), pseudonym);
+    }
+
+    /// Returns a copy of the `record` with all known identifiers replaced.
+    pub fn scrub(&mut self, record: &Record) -> Record {
+        let segments = record.topic.split('/').collect::<Vec<_>>();
+        if let ["hame_energy", _, _, mac, ..] = segments.as_slice() {
+            self.add_identifier(mac);
+            // Keep the upper case variant linked to the same pseudonym, empty MACs have none.
+            if let Some(pseudonym) = self.pseudonyms.get(*mac).map(|p| p.to_uppercase()) {
+                self.pseudonyms
+                    .entry(mac.to_uppercase())
+                    .or_insert(pseudonym);
+            }
+        }
+
+        let mut topic = record.topic.clone();
+        let mut payload = record.payload.to_vec();
+        for (identifier, pseudonym) in &self.pseudonyms {
+            topic = topic.replace(identifier, pseudonym);
+            payload = replace_bytes(&payload, identifier.as_bytes(), pseudonym.as_bytes());
+        }
+
+        Record {
+            timestamp: record.timestamp,
+            direction: record.direction,
+            topic,
+            payload: payload.into(),
+        }
+    }
+}
+
+fn replace_bytes(haystack: &[u8], needle: &[u8], replacement: &[u8]) -> Vec<u8> {
+    let mut result = Vec::with_capacity(haystack.len());
+    let mut rest = haystack;
+    while !rest.is_empty() {
+        if rest.starts_with(needle) {
+            result.extend_from_slice(replacement);
+            rest = &rest[needle.len()..];
+        } else {
+            result.push(rest[0]);
+            rest = &rest[1..];
+        }
+    }
+    result
+}
+
+fn read_record(block: &mut &[u8]) -> Result<Record, Error> {
+    let corrupted = |_| Error::Corrupted("truncated record");
+
+    let timestamp = u64::from_le_bytes(read_array(&mut *block).map_err(corrupted)?);
+    let direction = match read_array(&mut *block).map_err(corrupted)? {
+        [0] => Direction::Inbound,
+        [1] => Direction::Outbound,
+        _ => return Err(Error::Corrupted("invalid direction")),
+    };
+
+    let topic_len = u16::from_le_bytes(read_array(&mut *block).map_err(corrupted)?);
+    let topic = take(block, topic_len.into())?;
+    let topic = String::from_utf8(topic.to_vec()).map_err(|_| Error::Corrupted("invalid topic"))?;
+
+    let payload_len = u32::from_le_bytes(read_array(&mut *block).map_err(corrupted)?);
+    let payload = take(block, payload_len as usize)?;
+
+    Ok(Record {
+        timestamp: from_micros(timestamp),
+        direction,
+        topic,
+        payload: Bytes::copy_from_slice(payload),
+    })
+}
+
+fn take<'a>(block: &mut &'a [u8], len: usize) -> Result<&'a [u8], Error> {
+    if block.len() < len {
+        return Err(Error::Corrupted("truncated record"));
+    }
+    let (value, rest) = block.split_at(len);
+    *block = rest;
+    Ok(value)
+}
+
+fn read_array<const N: usize>(mut reader: impl Read) -> io::Result<[u8; N]> {
+    let mut buf = [0; N];
+    reader.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+fn header_len() -> u64 {
+    (MAGIC.len() + size_of::<u16>() + size_of::<u8>()) as u64
+}
+
+fn micros(time: SystemTime) -> u64 {
+    time.duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or(Duration::ZERO)
+        .as_micros() as u64
+}
+
+fn from_micros(micros: u64) -> SystemTime {
+    SystemTime::UNIX_EPOCH + Duration::from_micros(micros)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    fn record(secs: u64, payload: &'static str) -> Record {
+        Record {
+            timestamp: SystemTime::UNIX_EPOCH + Duration::from_secs(secs),
+            direction: Direction::Inbound,
+            topic: "hame_energy/HMA-1/device/9523ccae1a9b/ctrl".to_owned(),
+            payload: Bytes::from_static(payload.as_bytes()),
+        }
+    }
+
+    #[test]
+    fn test_capture_roundtrip() {
+        let mut writer = Writer::new(Cursor::new(Vec::new())).unwrap();
+        for i in 0..200 {
+            // Every 60 seconds a new block is started.
+            writer.push(&record(i * 10, "p1=1,p2=1")).unwrap();
+        }
+        let capture = writer.finish().unwrap().into_inner();
+
+        let mut reader = Reader::new(Cursor::new(capture)).unwrap();
+        let index = reader.index().unwrap();
+        assert_eq!(index.len(), 29);
+        assert_eq!(index.iter().map(|b| b.records).sum::<u32>(), 200);
+
+        let records = reader.records().unwrap();
+        assert_eq!(records.len(), 200);
+        assert_eq!(records[42], record(420, "p1=1,p2=1"));
+
+        let from = SystemTime::UNIX_EPOCH + Duration::from_secs(995);
+        let to = SystemTime::UNIX_EPOCH + Duration::from_secs(1015);
+        let range = reader.range(from, to).unwrap();
+        assert_eq!(
+            range,
+            vec![record(1000, "p1=1,p2=1"), record(1010, "p1=1,p2=1")]
+        );
+    }
+
+    #[test]
+    fn test_capture_deflate() {
+        let mut writer = Writer::new(Cursor::new(Vec::new())).unwrap();
+        writer.push(&record(10, "p1=1,p2=1")).unwrap();
+        let compressed = miniz_oxide::deflate::compress_to_vec(&writer.block, 6);
+
+        // Captures written before zstd are still readable.
+        let mut capture = MAGIC.to_vec();
+        capture.extend_from_slice(&VERSION.to_le_bytes());
+        capture.push(COMPRESSION_DEFLATE);
+        capture.extend_from_slice(&(compressed.len() as u32).to_le_bytes());
+        capture.extend_from_slice(&1u32.to_le_bytes());
+        capture.extend_from_slice(&writer.first.to_le_bytes());
+        capture.extend_from_slice(&writer.last.to_le_bytes());
+        capture.extend_from_slice(&compressed);
+
+        let mut reader = Reader::new(Cursor::new(capture)).unwrap();
+        assert_eq!(reader.records().unwrap(), vec![record(10, "p1=1,p2=1")]);
+    }
+
+    #[test]
+    fn test_scrub() {
+        let mut scrubber = Scrubber::new(b"salt");
+        scrubber.add_identifier("secret-id");
+
+        let record = Record {
+            payload: Bytes::from_static(b"mac=9523ccae1a9b,id=secret-id,MAC=9523CCAE1A9B"),
+            ..record(0, "")
+        };
+        let scrubbed = scrubber.scrub(&record);
+
+        assert_eq!(scrubbed.topic, "hame_energy/HMA-1/device/038abc8417c6/ctrl");
+        assert_eq!(
+            scrubbed.payload,
+            "mac=038abc8417c6,id=0d73f88e2,MAC=038ABC8417C6"
+        );
+        // Pseudonyms are stable.
+        assert_eq!(scrubber.scrub(&record), scrubbed);
+
+        // Topics with an empty MAC are kept.
+        let record = Record {
+            topic: "hame_energy/HMA-1/device//ctrl".to_owned(),
+            ..record
+        };
+        assert_eq!(
+            scrubber.scrub(&record).topic,
+            "hame_energy/HMA-1/device//ctrl"
+        );
+    }
+
+    #[test]
+    fn test_capture_invalid() {
+        let err = Reader::new(Cursor::new(b"HMTKCAP\0\x02\x00\x01".to_vec())).err();
+        assert!(matches!(err, Some(Error::UnsupportedVersion(2))));
+
+        // Lengths and record counts of corrupted headers are not allocated upfront.
+        let mut writer = Writer::new(Cursor::new(Vec::new())).unwrap();
+        writer.push(&record(10, "p1=1,p2=1")).unwrap();
+        let mut capture = writer.finish().unwrap().into_inner();
+        let header = header_len() as usize;
+        capture[header + 4..header + 8].copy_from_slice(&u32::MAX.to_le_bytes());
+        let err = Reader::new(Cursor::new(capture.clone()))
+            .unwrap()
+            .records()
+            .err();
+        assert!(matches!(err, Some(Error::Corrupted("truncated record"))));
+
+        capture[header..header + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+        let err = Reader::new(Cursor::new(capture)).unwrap().records().err();
+        assert!(matches!(err, Some(Error::Corrupted("truncated block"))));
+    }
+}
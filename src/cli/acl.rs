@@ -0,0 +1,86 @@
+//! Least privilege ACL entries for the Mosquitto broker.
+use std::collections::BTreeSet;
+use std::fmt::Write as _;
+
+use hmtk::mqtt::DeviceOptions;
+
+use crate::cli::config::{AlertAction, AlertConfig};
+
+/// Topics published by hmtk besides the control topics of the devices.
+#[derive(Debug)]
+pub struct Topics<'a> {
+    /// Availability topic of the daemon modes.
+    pub availability: &'a str,
+    /// Topic of the `bridge` command.
+    pub bridge: Option<&'a str>,
+    /// Home Assistant discovery prefix of the `bridge` command.
+    pub discovery: Option<&'a str>,
+}
+
+/// Returns the minimal Mosquitto ACL entries required by hmtk for `devices`.
+///
+/// Devices only need read access to their data topic and write access to their control topic,
+/// alerts with an `mqtt` action additionally need write access to their topic.
+pub fn acl(
+    user: Option<&str>,
+    devices: &[DeviceOptions],
+    topics: &Topics<'_>,
+    alerts: &[AlertConfig],
+) -> String {
+    let mut result = String::new();
+    let _ = writeln!(
+        result,
+        "# Generated by `hmtk acl`, for the `acl_file` of Mosquitto."
+    );
+    match user {
+        Some(user) => {
+            let _ = writeln!(result, "user {user}");
+        }
+        None => {
+            let _ = writeln!(
+                result,
+                "# Without a user, the entries apply to anonymous clients."
+            );
+        }
+    }
+
+    for device in devices {
+        let placeholders = |topic: &str| {
+            topic
+                .replace("{type}", &device.ty)
+                .replace("{mac}", &device.mac)
+        };
+
+        let _ = writeln!(result, "\n# {} {}", device.ty, device.mac);
+        let _ = writeln!(result, "topic read {}", device.data_topic());
+        let _ = writeln!(result, "topic write {}", device.control_topic());
+        let _ = writeln!(result, "topic write {}", placeholders(topics.availability));
+        if let Some(bridge) = topics.bridge {
+            let _ = writeln!(result, "topic write {}", placeholders(bridge));
+        }
+        if let Some(prefix) = topics.discovery {
+            let _ = writeln!(
+                result,
+                "topic write {prefix}/sensor/hmtk_{}/+/config",
+                device.mac
+            );
+        }
+    }
+
+    let alert_topics = alerts
+        .iter()
+        .flat_map(|alert| &alert.actions)
+        .filter_map(|action| match action {
+            AlertAction::Mqtt { topic, .. } => Some(topic.as_str()),
+            _ => None,
+        })
+        .collect::<BTreeSet<_>>();
+    if !alert_topics.is_empty() {
+        let _ = writeln!(result, "\n# Alerts");
+        for topic in alert_topics {
+            let _ = writeln!(result, "topic write {topic}");
+        }
+    }
+
+    result
+}
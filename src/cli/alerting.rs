@@ -0,0 +1,58 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use color_eyre::eyre::{Result, eyre};
+use hmtk::alerts::{Alert, Rule};
+use hmtk::mqtt::Device;
+use hmtk::notify::{Exec, MqttPublish, Notification, Notifier, Webhook, notify_all};
+
+use crate::cli::config::{AlertAction, AlertConfig};
+
+/// User configured alert rules and the notifiers of their actions.
+#[derive(Debug, Default)]
+pub struct Alerting {
+    rules: Vec<Rule>,
+    notifiers: HashMap<String, Vec<Arc<dyn Notifier>>>,
+}
+
+impl Alerting {
+    pub fn from_config(alerts: Vec<AlertConfig>, device: &Device) -> Result<Self> {
+        let mut alerting = Self::default();
+        for alert in alerts {
+            alerting.rules.push(alert.rule()?);
+            let notifiers = alert
+                .actions
+                .iter()
+                .map(|action| notifier(action, device))
+                .collect::<Result<_>>()?;
+            alerting.notifiers.insert(alert.name, notifiers);
+        }
+        Ok(alerting)
+    }
+
+    /// Returns the configured rules.
+    pub fn rules(&self) -> &[Rule] {
+        &self.rules
+    }
+
+    /// Notifies all notifiers of the alert's rule in the background.
+    pub fn run(&self, device: &Device, alert: &Alert<'_>) {
+        let Some(notifiers) = self.notifiers.get(&alert.rule().name) else {
+            return;
+        };
+        notify_all(notifiers, Notification::alert(device.options(), alert));
+    }
+}
+
+fn notifier(action: &AlertAction, device: &Device) -> Result<Arc<dyn Notifier>> {
+    Ok(match action {
+        AlertAction::Exec(command) => Arc::new(Exec::new(command)),
+        AlertAction::Webhook(url) => Arc::new(
+            Webhook::new(url)
+                .ok_or_else(|| eyre!("only `http://` and `https://` webhooks are supported"))?,
+        ),
+        AlertAction::Mqtt { topic, retain } => {
+            Arc::new(MqttPublish::new(device.clone(), topic, *retain))
+        }
+    })
+}
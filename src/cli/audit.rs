@@ -0,0 +1,78 @@
+use std::time::{Duration, SystemTime};
+
+use color_eyre::eyre::Result;
+use hmtk::capture::{Direction, Record};
+use hmtk::mqtt::{ControlCommand, Message};
+use hmtk::time::LocalTime;
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::cli::shutdown_signal;
+
+/// Prints every command published to the control topic of the device, e.g. by the official app.
+///
+/// Runs until interrupted, commands hmtk does not know are printed with their raw payload.
+pub async fn audit(device: &hmtk::mqtt::Device, json: bool) -> Result<()> {
+    let mut traffic = device.traffic();
+    device.watch_control()?;
+
+    let control_topic = device.options().control_topic();
+    tracing::info!("watching commands on {control_topic}");
+    loop {
+        let record = tokio::select! {
+            record = traffic.recv() => match record {
+                Ok(record) => record,
+                Err(RecvError::Lagged(skipped)) => {
+                    tracing::warn!("audit lagging behind, skipped {skipped} messages");
+                    continue;
+                }
+                Err(RecvError::Closed) => break,
+            },
+            _ = shutdown_signal() => break,
+        };
+        if record.direction != Direction::Inbound || record.topic != control_topic {
+            continue;
+        }
+
+        let command = decode(device, &record);
+        if json {
+            let timestamp = record
+                .timestamp
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap_or(Duration::ZERO)
+                .as_secs_f64();
+            let line = serde_json::json!({
+                "timestamp": timestamp,
+                "payload": String::from_utf8_lossy(&record.payload),
+                "command": command.map(|command| command.spec().name),
+                "description": command.map(|command| command.describe()),
+            });
+            println!("{line}");
+        } else {
+            let time = LocalTime::from_system_time(record.timestamp);
+            let description = match command {
+                Some(command) => command.describe(),
+                None => "unknown command".to_owned(),
+            };
+            println!(
+                "{:02}:{:02}:{:02}  {description}  ({})",
+                time.hour,
+                time.minute,
+                time.second,
+                String::from_utf8_lossy(&record.payload)
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// Decodes the command of a `record` on the control topic, `None` if it is unknown.
+fn decode(device: &hmtk::mqtt::Device, record: &Record) -> Option<ControlCommand> {
+    let payload = device
+        .options()
+        .protocol
+        .transform
+        .apply(record.payload.clone())
+        .ok()?;
+    ControlCommand::decode(&Message::parse(payload).ok()?)
+}
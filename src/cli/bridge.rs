@@ -0,0 +1,154 @@
+use std::time::Duration;
+
+use color_eyre::eyre::Result;
+use hmtk::analytics::EnergyMeter;
+use hmtk::events::ChangeFilter;
+use hmtk::mqtt::{DeviceInfo, DeviceOptions};
+use serde_json::json;
+
+use crate::cli::shutdown_signal;
+
+/// Cumulative energy sensors announced with the discovery, by key in `energy` and label.
+const ENERGY_SENSORS: [(&str, &str); 4] = [
+    ("solar", "Solar energy"),
+    ("output", "Output energy"),
+    ("battery_charge", "Battery charge energy"),
+    ("battery_discharge", "Battery discharge energy"),
+];
+
+/// Home Assistant MQTT discovery of the bridged state.
+#[derive(Debug)]
+pub struct Discovery<'a> {
+    /// Discovery prefix configured in Home Assistant, usually `homeassistant`.
+    pub prefix: &'a str,
+    /// Availability topic of the bridge, if published.
+    pub availability: Option<&'a str>,
+}
+
+/// Periodically queries the device and re-publishes every sample as JSON to `topic`.
+///
+/// The placeholders `{mac}` and `{type}` in the topic are replaced with the device MAC and type.
+///
+/// With `discovery`, the state additionally contains the solar, output and battery charge and
+/// discharge energy in kWh since the bridge was started, which are announced to Home Assistant as
+/// cumulative energy sensors.
+///
+/// With `changes`, only states passing the filter are published, the timestamp is ignored.
+pub async fn bridge(
+    device: &mut hmtk::mqtt::Device,
+    interval: Duration,
+    topic: &str,
+    retain: bool,
+    discovery: Option<&Discovery<'_>>,
+    mut changes: Option<ChangeFilter<serde_json::Value>>,
+) -> Result<()> {
+    let topic = topic
+        .replace("{mac}", &device.options().mac)
+        .replace("{type}", &device.options().ty);
+
+    tracing::info!("publishing device state to {topic}");
+
+    if let Some(discovery) = discovery {
+        for (name, label) in ENERGY_SENSORS {
+            let (config_topic, config) =
+                energy_sensor(device.options(), &topic, discovery, name, label);
+            device
+                .publish(&config_topic, true, serde_json::to_vec(&config)?)
+                .await?;
+        }
+    }
+
+    let mut interval = tokio::time::interval(interval);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+    let shutdown = shutdown_signal();
+    tokio::pin!(shutdown);
+
+    let mut meters = [EnergyMeter::default(); ENERGY_SENSORS.len()];
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {}
+            _ = &mut shutdown => return Ok(()),
+        }
+
+        let device_info = device.device_info().await?;
+        let state = match discovery {
+            Some(_) => {
+                let derived = device_info.derived();
+                // The battery is charged by the surplus of solar power and discharged otherwise.
+                let powers = [
+                    derived.solar_power.0.into(),
+                    derived.output_power.0.into(),
+                    derived.net_power.max(0) as f64,
+                    (-derived.net_power).max(0) as f64,
+                ];
+
+                let mut state = to_state(&device_info)?;
+                let mut energy = serde_json::Map::new();
+                for (((name, _), meter), power) in
+                    ENERGY_SENSORS.iter().zip(&mut meters).zip(powers)
+                {
+                    let total = meter.add(device_info.timestamp, power);
+                    // Rounded to Wh, the integration is not more accurate than that.
+                    energy.insert((*name).to_owned(), json!(total.round() / 1000.0));
+                }
+                state["energy"] = energy.into();
+                state
+            }
+            None => to_state(&device_info)?,
+        };
+
+        if let Some(changes) = &mut changes {
+            let mut value = state.clone();
+            if let Some(value) = value.as_object_mut() {
+                value.remove("timestamp");
+            }
+            if !changes.check(device_info.timestamp, value) {
+                continue;
+            }
+        }
+        device
+            .publish(&topic, retain, serde_json::to_vec(&state)?)
+            .await?;
+    }
+}
+
+/// Serializes the device info with its derived values, like the JSON output.
+pub fn to_state(device_info: &DeviceInfo) -> Result<serde_json::Value> {
+    let mut state = serde_json::to_value(device_info)?;
+    state["derived"] = serde_json::to_value(device_info.derived())?;
+    Ok(state)
+}
+
+/// Returns the discovery topic and config of a cumulative energy sensor for the Energy dashboard.
+fn energy_sensor(
+    device: &DeviceOptions,
+    state_topic: &str,
+    discovery: &Discovery<'_>,
+    name: &str,
+    label: &str,
+) -> (String, serde_json::Value) {
+    let id = format!("hmtk_{}", device.mac);
+    let topic = format!("{}/sensor/{id}/{name}_energy/config", discovery.prefix);
+
+    let mut config = json!({
+        "name": label,
+        "unique_id": format!("{id}_{name}_energy"),
+        "state_topic": state_topic,
+        "value_template": format!("{{{{ value_json.energy.{name} }}}}"),
+        "unit_of_measurement": "kWh",
+        "device_class": "energy",
+        // The counters restart at zero with the bridge, which Home Assistant treats as a reset.
+        "state_class": "total_increasing",
+        "device": {
+            "identifiers": [id],
+            "name": format!("{} {}", device.ty, device.mac),
+            "model": device.ty,
+        },
+    });
+    if let Some(availability) = discovery.availability {
+        config["availability_topic"] = availability.into();
+    }
+
+    (topic, config)
+}
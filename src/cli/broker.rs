@@ -0,0 +1,134 @@
+//! MQTT broker of `--embedded-broker`, the device can connect directly to hmtk.
+//!
+//! The broker is [`rumqttd`] on its own thread, it runs until hmtk exits. Without credentials
+//! every client is accepted.
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use color_eyre::eyre::{Result, bail};
+use tokio::net::TcpStream;
+use tokio::sync::oneshot;
+
+/// Largest accepted payload, messages of the devices are far smaller.
+const MAX_PAYLOAD_SIZE: usize = 64 * 1024;
+/// Time the broker may take to accept connections.
+const STARTUP_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Credentials every client of the broker, including hmtk itself, has to connect with.
+#[derive(Debug, Clone)]
+pub struct Credentials {
+    pub username: String,
+    pub password: String,
+}
+
+/// Starts the broker on `listen` and waits until it accepts connections.
+pub async fn start(listen: SocketAddr, credentials: Option<Credentials>) -> Result<()> {
+    // rumqttd only logs failures to bind, a port in use is reported here instead.
+    drop(std::net::TcpListener::bind(listen)?);
+
+    let mut broker = rumqttd::Broker::new(config(listen, credentials));
+    let (tx, stopped) = oneshot::channel();
+    std::thread::Builder::new()
+        .name("embedded-broker".to_owned())
+        .spawn(move || {
+            let _ = tx.send(broker.start());
+        })?;
+
+    let local = SocketAddr::from(([127, 0, 0, 1], listen.port()));
+    let ready = async {
+        while TcpStream::connect(local).await.is_err() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    };
+    tokio::select! {
+        result = tokio::time::timeout(STARTUP_TIMEOUT, ready) => {
+            if result.is_err() {
+                bail!("embedded broker did not start within {STARTUP_TIMEOUT:?}");
+            }
+        }
+        result = stopped => match result {
+            Ok(Err(err)) => bail!("embedded broker failed: {err}"),
+            _ => bail!("embedded broker stopped"),
+        },
+    }
+    Ok(())
+}
+
+fn config(listen: SocketAddr, credentials: Option<Credentials>) -> rumqttd::Config {
+    let server = rumqttd::ServerSettings {
+        name: "v4".to_owned(),
+        listen,
+        tls: None,
+        next_connection_delay_ms: 1,
+        connections: rumqttd::ConnectionSettings {
+            connection_timeout_ms: 5000,
+            max_payload_size: MAX_PAYLOAD_SIZE,
+            max_inflight_count: 100,
+            auth: credentials.map(|c| HashMap::from([(c.username, c.password)])),
+            external_auth: None,
+            dynamic_filters: true,
+        },
+    };
+    rumqttd::Config {
+        router: rumqttd::RouterConfig {
+            max_connections: 100,
+            max_outgoing_packet_count: 200,
+            max_segment_size: 1024 * 1024,
+            max_segment_count: 10,
+            ..Default::default()
+        },
+        v4: Some(HashMap::from([("v4".to_owned(), server)])),
+        ..Default::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rumqttc::{AsyncClient, Event, MqttOptions, Packet, QoS};
+
+    use super::*;
+
+    /// Returns a free port on the loopback interface.
+    fn free_port() -> SocketAddr {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.local_addr().unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_start() {
+        let listen = free_port();
+        let credentials = Credentials {
+            username: "hmtk".to_owned(),
+            password: "secret".to_owned(),
+        };
+        start(listen, Some(credentials)).await.unwrap();
+
+        let mut options = MqttOptions::new("test", "127.0.0.1", listen.port());
+        options.set_credentials("hmtk", "secret");
+        let (client, mut ev) = AsyncClient::new(options, 10);
+        client.subscribe("a/#", QoS::AtMostOnce).await.unwrap();
+        client
+            .publish("a/b", QoS::AtMostOnce, false, "x")
+            .await
+            .unwrap();
+        let publish = loop {
+            if let Event::Incoming(Packet::Publish(publish)) = ev.poll().await.unwrap() {
+                break publish;
+            }
+        };
+        assert_eq!(
+            (publish.topic.as_str(), &publish.payload[..]),
+            ("a/b", &b"x"[..])
+        );
+
+        // Clients with wrong credentials are rejected.
+        let mut options = MqttOptions::new("other", "127.0.0.1", listen.port());
+        options.set_credentials("hmtk", "wrong");
+        let (_client, mut ev) = AsyncClient::new(options, 10);
+        assert!(ev.poll().await.is_err());
+
+        // The port is in use now.
+        assert!(start(listen, None).await.is_err());
+    }
+}
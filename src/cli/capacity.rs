@@ -0,0 +1,125 @@
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::path::Path;
+
+use color_eyre::eyre::{Result, bail};
+use hmtk::analytics::{CapacityModel, REFERENCE_TEMPERATURE};
+use hmtk::locale::Locale;
+use hmtk::stats::Accumulator;
+use hmtk::time::LocalTime;
+use serde::Serialize;
+
+use crate::cli::sink::sqlite;
+
+#[derive(Debug, Serialize)]
+struct Report {
+    device_mac: String,
+    model: CapacityModel,
+    months: Vec<Month>,
+}
+
+/// Capacity estimates of a single month.
+#[derive(Debug, Serialize)]
+struct Month {
+    /// The month as `YYYY-MM`.
+    month: String,
+    samples: u64,
+    /// Average battery temperature in °C.
+    temperature: f64,
+    /// Average usable capacity in Wh.
+    usable: f64,
+    /// Usable capacity normalized to the reference temperature in Wh.
+    compensated: f64,
+}
+
+/// Reports the temperature-compensated usable capacity per month from a `monitor --db` database.
+pub async fn capacity(db: &Path, locale: &Locale, json: bool) -> Result<()> {
+    let mut devices = BTreeMap::<_, Vec<_>>::new();
+    for (mac, timestamp, sample) in sqlite::capacity_samples(db)? {
+        devices.entry(mac).or_default().push((timestamp, sample));
+    }
+
+    let mut reports = Vec::new();
+    for (device_mac, samples) in devices {
+        let Some(model) = CapacityModel::fit(samples.iter().map(|(_, sample)| *sample)) else {
+            continue;
+        };
+
+        let mut months = BTreeMap::<_, (Accumulator, Accumulator)>::new();
+        for (timestamp, sample) in &samples {
+            let Some(usable) = sample.usable() else {
+                continue;
+            };
+            let time = LocalTime::from_system_time(*timestamp);
+            let (temperature, capacity) = months.entry((time.year, time.month)).or_default();
+            temperature.push(sample.temperature);
+            capacity.push(usable);
+        }
+
+        let months = months
+            .into_iter()
+            .map(|((year, month), (temperature, usable))| {
+                let (temperature, usable) = (temperature.summary(), usable.summary());
+                Month {
+                    month: format!("{year:04}-{month:02}"),
+                    samples: usable.count,
+                    temperature: temperature.mean,
+                    usable: usable.mean,
+                    compensated: model.compensate(usable.mean, temperature.mean),
+                }
+            })
+            .collect();
+
+        reports.push(Report {
+            device_mac,
+            model,
+            months,
+        });
+    }
+
+    if reports.is_empty() {
+        bail!("{} contains no samples with enough charge", db.display());
+    }
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&reports)?);
+    } else {
+        for report in &reports {
+            print!("{}", to_table(locale, report));
+        }
+    }
+
+    Ok(())
+}
+
+fn to_table(locale: &Locale, report: &Report) -> String {
+    let mut result = String::new();
+
+    let _ = writeln!(
+        result,
+        "device {}: {} Wh at {REFERENCE_TEMPERATURE} °C, {} Wh/°C ({} samples)\n",
+        report.device_mac,
+        locale.number(report.model.reference, 0),
+        locale.number(report.model.coefficient, 1),
+        report.model.samples,
+    );
+    let _ = writeln!(
+        result,
+        "{:<8} {:>7} {:>11} {:>10} {:>12}",
+        "month", "samples", "temperature", "usable", "compensated"
+    );
+    for month in &report.months {
+        let _ = writeln!(
+            result,
+            "{:<8} {:>7} {:>8} °C {:>7} Wh {:>9} Wh",
+            month.month,
+            month.samples,
+            locale.number(month.temperature, 1),
+            locale.number(month.usable, 0),
+            locale.number(month.compensated, 0),
+        );
+    }
+    let _ = writeln!(result);
+
+    result
+}
@@ -0,0 +1,136 @@
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+
+use color_eyre::eyre::Result;
+use hmtk::capture::{Reader, Record, Scrubber, Writer};
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::cli::shutdown_signal;
+
+/// Records all MQTT traffic of the device into a capture file.
+///
+/// Stops after `duration` or when interrupted, optionally requests a status update every `interval`.
+pub async fn record(
+    device: &mut hmtk::mqtt::Device,
+    output: &Path,
+    interval: Option<Duration>,
+    duration: Option<Duration>,
+) -> Result<()> {
+    let mut traffic = device.traffic();
+    let mut writer = Writer::new(BufWriter::new(File::create(output)?))?;
+
+    let mut interval = interval.map(tokio::time::interval);
+    let deadline = async {
+        match duration {
+            Some(duration) => tokio::time::sleep(duration).await,
+            None => std::future::pending().await,
+        }
+    };
+    tokio::pin!(deadline);
+
+    let mut count = 0;
+    loop {
+        let poll = async {
+            match &mut interval {
+                Some(interval) => interval.tick().await,
+                None => std::future::pending().await,
+            }
+        };
+
+        tokio::select! {
+            record = traffic.recv() => match record {
+                Ok(record) => {
+                    writer.push(&record)?;
+                    count += 1;
+                }
+                Err(RecvError::Lagged(skipped)) => {
+                    tracing::warn!("capture lagging behind, skipped {skipped} messages");
+                }
+                Err(RecvError::Closed) => break,
+            },
+            _ = poll => {
+                device.device_info().await?;
+            }
+            _ = &mut deadline => break,
+            _ = shutdown_signal() => break,
+        }
+    }
+
+    writer.finish()?;
+    tracing::info!("captured {count} messages to {}", output.display());
+
+    Ok(())
+}
+
+/// Reads all records of a capture.
+pub fn records(input: &Path) -> Result<Vec<Record>> {
+    let mut reader = Reader::new(BufReader::new(File::open(input)?))?;
+    Ok(reader.records()?)
+}
+
+/// Prints all records of a capture as JSON lines, optionally limited to a time range.
+pub fn dump(input: &Path, from: Option<u64>, to: Option<u64>) -> Result<()> {
+    let mut reader = Reader::new(BufReader::new(File::open(input)?))?;
+
+    let records = match (from, to) {
+        (None, None) => reader.records()?,
+        (from, to) => {
+            let from = SystemTime::UNIX_EPOCH + Duration::from_secs(from.unwrap_or(0));
+            let to = SystemTime::UNIX_EPOCH + Duration::from_secs(to.unwrap_or(u32::MAX.into()));
+            reader.range(from, to)?
+        }
+    };
+
+    for record in records {
+        let timestamp = record
+            .timestamp
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or(Duration::ZERO)
+            .as_secs_f64();
+
+        let record = serde_json::json!({
+            "timestamp": timestamp,
+            "direction": record.direction,
+            "topic": record.topic,
+            "payload": String::from_utf8_lossy(&record.payload),
+        });
+        println!("{record}");
+    }
+
+    Ok(())
+}
+
+/// Copies a capture, replacing device MACs and the additional `identifiers` with pseudonyms.
+///
+/// Without a `salt` a random one is used, pseudonyms are then only stable within the capture.
+pub fn scrub(
+    input: &Path,
+    output: &Path,
+    salt: Option<&str>,
+    identifiers: &[String],
+) -> Result<()> {
+    let salt = match salt {
+        Some(salt) => salt.to_owned(),
+        None => {
+            use std::hash::BuildHasher;
+            let random = std::collections::hash_map::RandomState::new().hash_one(input);
+            format!("{random:016x}")
+        }
+    };
+
+    let mut scrubber = Scrubber::new(salt.as_bytes());
+    for identifier in identifiers {
+        scrubber.add_identifier(identifier);
+    }
+
+    let mut reader = Reader::new(BufReader::new(File::open(input)?))?;
+    let mut writer = Writer::new(BufWriter::new(File::create(output)?))?;
+    for record in reader.records()? {
+        writer.push(&scrubber.scrub(&record))?;
+    }
+    writer.finish()?;
+
+    Ok(())
+}
@@ -0,0 +1,135 @@
+//! Monitoring plugin compatible with Nagios, Icinga and friends.
+//!
+//! See the [monitoring plugins guidelines](https://www.monitoring-plugins.org/doc/guidelines.html)
+//! for the threshold format and exit codes.
+use std::fmt;
+use std::time::Duration;
+
+use hmtk::mqtt::Device;
+
+/// A threshold range in the monitoring plugins format, e.g. `10:`, `~:45` or `@10:20`.
+#[derive(Debug, Clone)]
+pub struct Threshold {
+    raw: String,
+    start: f64,
+    end: f64,
+    /// Alert if the value is inside instead of outside of the range.
+    inside: bool,
+}
+
+impl Threshold {
+    /// Returns `true` if `value` violates the threshold.
+    fn alerts(&self, value: f64) -> bool {
+        let within = (self.start..=self.end).contains(&value);
+        within == self.inside
+    }
+}
+
+impl fmt::Display for Threshold {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.raw)
+    }
+}
+
+/// Parses a threshold range, a plain number `n` is the range `0:n`.
+pub fn parse_threshold(s: String) -> Result<Threshold, String> {
+    let invalid = || format!("invalid threshold `{s}`, expected for example `10:` or `~:45`");
+    let number = |value: &str| value.parse::<f64>().map_err(|_| invalid());
+
+    let (inside, range) = match s.strip_prefix('@') {
+        Some(range) => (true, range),
+        None => (false, s.as_str()),
+    };
+    let (start, end) = match range.split_once(':') {
+        Some(("~", end)) => (f64::NEG_INFINITY, end),
+        Some((start, end)) => (number(start)?, end),
+        None => (0.0, range),
+    };
+    let end = match end {
+        "" => f64::INFINITY,
+        end => number(end)?,
+    };
+    if start > end {
+        return Err(invalid());
+    }
+
+    Ok(Threshold {
+        raw: s.clone(),
+        start,
+        end,
+        inside,
+    })
+}
+
+#[derive(Debug, Clone, Copy)]
+enum State {
+    Ok,
+    Warning,
+    Critical,
+    Unknown,
+}
+
+impl State {
+    fn as_str(self) -> &'static str {
+        match self {
+            State::Ok => "OK",
+            State::Warning => "WARNING",
+            State::Critical => "CRITICAL",
+            State::Unknown => "UNKNOWN",
+        }
+    }
+
+    fn exit_code(self) -> i32 {
+        match self {
+            State::Ok => 0,
+            State::Warning => 1,
+            State::Critical => 2,
+            State::Unknown => 3,
+        }
+    }
+}
+
+/// Checks `metric` against the thresholds, prints the status line and returns the exit code.
+pub async fn check(
+    device: &mut Device,
+    metric: &str,
+    warn: Option<&Threshold>,
+    crit: Option<&Threshold>,
+    timeout: Duration,
+) -> i32 {
+    let (state, message, value) = match tokio::time::timeout(timeout, device.device_info()).await {
+        Err(_) => (
+            State::Unknown,
+            "device did not respond in time".to_owned(),
+            None,
+        ),
+        Ok(Err(err)) => (State::Unknown, err.to_string(), None),
+        Ok(Ok(device_info)) => match device_info.metric(metric) {
+            None => (State::Unknown, format!("unknown metric `{metric}`"), None),
+            Some(value) => {
+                let state = if crit.is_some_and(|crit| crit.alerts(value)) {
+                    State::Critical
+                } else if warn.is_some_and(|warn| warn.alerts(value)) {
+                    State::Warning
+                } else {
+                    State::Ok
+                };
+                (state, format!("{metric} is {value}"), Some(value))
+            }
+        },
+    };
+
+    let mut line = format!("HMTK {} - {message}", state.as_str());
+    if let Some(value) = value {
+        let threshold =
+            |threshold: Option<&Threshold>| threshold.map(ToString::to_string).unwrap_or_default();
+        line.push_str(&format!(
+            " | '{metric}'={value};{};{}",
+            threshold(warn),
+            threshold(crit)
+        ));
+    }
+    println!("{line}");
+
+    state.exit_code()
+}
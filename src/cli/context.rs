@@ -0,0 +1,61 @@
+//! Named connection contexts, stored in the configuration file.
+use std::path::Path;
+
+use color_eyre::eyre::{Result, WrapErr, bail};
+
+use crate::cli::config::Config;
+
+/// Prints all contexts with their devices, the current context is marked with `*`.
+pub fn list(config: &Config) {
+    for (name, context) in &config.contexts {
+        let current = match config.current_context.as_deref() == Some(name) {
+            true => '*',
+            false => ' ',
+        };
+        println!(
+            "{current} {name:<16} mqtt://{}:{}",
+            context.host, context.port
+        );
+        for device in &context.devices {
+            let name = device.name.as_deref().unwrap_or("-");
+            println!("    {name:<14} {} ({})", device.mac, device.ty);
+        }
+    }
+}
+
+/// Sets the `current_context` of the configuration file at `path` to `name`.
+///
+/// Only the `current_context` line is rewritten, comments and formatting are preserved.
+pub fn use_context(path: &Path, name: &str) -> Result<()> {
+    let config = Config::load(path)?;
+    if !config.contexts.contains_key(name) {
+        bail!("unknown context `{name}`");
+    }
+
+    let content = std::fs::read_to_string(path)?;
+    let line = format!("current_context = {}", serde_json::to_string(name)?);
+
+    let mut lines = content.lines().map(str::to_owned).collect::<Vec<_>>();
+    // Top level keys precede the first table header.
+    let top_level = lines
+        .iter()
+        .position(|line| line.trim_start().starts_with('['))
+        .unwrap_or(lines.len());
+    let existing = lines[..top_level].iter().position(|line| {
+        line.trim_start()
+            .strip_prefix("current_context")
+            .is_some_and(|rest| rest.trim_start().starts_with('='))
+    });
+    match existing {
+        Some(i) => lines[i] = line,
+        None => lines.insert(0, line),
+    }
+
+    let mut content = lines.join("\n");
+    content.push('\n');
+    std::fs::write(path, content)
+        .wrap_err_with(|| format!("failed to write config file {}", path.display()))?;
+
+    println!("switched to context `{name}`");
+    Ok(())
+}
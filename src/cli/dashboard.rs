@@ -0,0 +1,251 @@
+use std::collections::VecDeque;
+use std::fmt::Write as _;
+use std::io::{Stdout, Write as _};
+use std::time::Duration;
+
+use color_eyre::eyre::Result;
+use crossterm::event::{Event, EventStream};
+use crossterm::{cursor, terminal};
+use futures::StreamExt;
+use hmtk::locale::Locale;
+use hmtk::mqtt::DeviceInfo;
+
+use crate::cli::shutdown_signal;
+
+/// Number of samples kept for the sparklines.
+const HISTORY: usize = 60;
+/// Characters used to draw sparklines, from lowest to highest.
+const SPARKS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+/// Metrics shown on the dashboard, as label key, unit and precision.
+const ROWS: &[(&str, &str, usize)] = &[
+    ("solar1.power", "W", 0),
+    ("solar2.power", "W", 0),
+    ("output1.power", "W", 0),
+    ("output2.power", "W", 0),
+    ("battery.charge", "%", 0),
+    ("temperature.min", "°C", 0),
+    ("temperature.max", "°C", 0),
+    ("grid.voltage", "V", 1),
+    ("grid.frequency", "Hz", 2),
+];
+
+/// Shows a live updating dashboard of the device in the terminal until interrupted.
+pub async fn dashboard(
+    device: &mut hmtk::mqtt::Device,
+    locale: &Locale,
+    interval: Duration,
+) -> Result<()> {
+    let mut interval = tokio::time::interval(interval);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+    let mut history = vec![VecDeque::with_capacity(HISTORY); ROWS.len()];
+    let mut updates = device.subscribe_device_info();
+    let mut events = EventStream::new();
+
+    let mut screen = Screen::enter()?;
+    screen.draw("waiting for the device...")?;
+
+    let mut latest = None;
+    loop {
+        tokio::select! {
+            _ = interval.tick() => device.request_device_info().await?,
+            device_info = updates.recv() => {
+                let device_info = device_info?;
+                for (values, (metric, ..)) in history.iter_mut().zip(ROWS) {
+                    let Some(value) = device_info.metric(metric) else {
+                        continue;
+                    };
+                    if values.len() == HISTORY {
+                        values.pop_front();
+                    }
+                    values.push_back(value);
+                }
+                latest = Some(device_info);
+            }
+            Some(event) = events.next() => match event? {
+                // Redrawn below for the new size.
+                Event::Resize(..) => {}
+                _ => continue,
+            },
+            _ = shutdown_signal() => return Ok(()),
+        }
+
+        if let Some(device_info) = &latest {
+            let (columns, rows) = terminal::size()?;
+            let size = (usize::from(columns), usize::from(rows));
+            screen.draw(&render(locale, device_info, &history, size))?;
+        }
+    }
+}
+
+/// The alternate screen of the terminal, restored when dropped, including on errors and panics.
+struct Screen {
+    stdout: Stdout,
+}
+
+impl Screen {
+    /// Switches to the alternate screen and hides the cursor.
+    fn enter() -> std::io::Result<Self> {
+        let mut stdout = std::io::stdout();
+        crossterm::execute!(stdout, terminal::EnterAlternateScreen, cursor::Hide)?;
+        Ok(Self { stdout })
+    }
+
+    /// Replaces the contents of the screen with `content`.
+    fn draw(&mut self, content: &str) -> std::io::Result<()> {
+        crossterm::queue!(
+            self.stdout,
+            terminal::Clear(terminal::ClearType::All),
+            cursor::MoveTo(0, 0)
+        )?;
+        self.stdout.write_all(content.as_bytes())?;
+        self.stdout.flush()
+    }
+}
+
+impl Drop for Screen {
+    fn drop(&mut self) {
+        let _ = crossterm::execute!(self.stdout, cursor::Show, terminal::LeaveAlternateScreen);
+    }
+}
+
+/// Renders the dashboard for a terminal of `(columns, rows)`.
+///
+/// Sparklines show as many of the latest values as fit, lines beyond the size are cut off.
+fn render(
+    locale: &Locale,
+    device_info: &DeviceInfo,
+    history: &[VecDeque<f64>],
+    (columns, rows): (usize, usize),
+) -> String {
+    let mut lines = Vec::new();
+
+    lines.push(format!(
+        "{}: {}    {}: {} Wh",
+        locale.label("scene"),
+        locale.label(device_info.scene.as_str()),
+        locale.label("battery.capacity"),
+        locale.number(device_info.battery.capacity.0.into(), 0),
+    ));
+    lines.push(String::new());
+
+    let width = ROWS
+        .iter()
+        .map(|(key, ..)| locale.label(key).chars().count())
+        .max()
+        .unwrap_or_default();
+
+    for ((metric, unit, precision), values) in ROWS.iter().zip(history) {
+        let Some(value) = device_info.metric(metric) else {
+            continue;
+        };
+        let value = format!("{} {unit}", locale.number(value, *precision));
+        let mut line = format!("{:<width$}  {value:>10}  ", locale.label(metric));
+
+        let available = columns.saturating_sub(line.chars().count());
+        let skip = values.len().saturating_sub(available);
+        let _ = write!(line, "{}", sparkline(values.range(skip..)));
+        lines.push(line);
+    }
+
+    lines.push(String::new());
+    lines.push("Press Ctrl-C to exit.".to_owned());
+
+    lines
+        .iter()
+        .take(rows)
+        .map(|line| line.chars().take(columns).collect::<String>())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Draws `values` as sparkline, scaled between their minimum and maximum.
+fn sparkline<'a>(values: impl Iterator<Item = &'a f64> + Clone) -> String {
+    let min = values.clone().copied().fold(f64::INFINITY, f64::min);
+    let max = values.clone().copied().fold(f64::NEG_INFINITY, f64::max);
+    let range = max - min;
+
+    values
+        .map(|value| match range > 0.0 {
+            true => {
+                let level = (value - min) / range * (SPARKS.len() - 1) as f64;
+                SPARKS[level.round() as usize]
+            }
+            false => SPARKS[0],
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn device_info() -> DeviceInfo {
+        serde_json::from_value(serde_json::json!({
+            "timestamp": 1700000000,
+            "solar1": { "charging": true, "pass_through": false, "power": 120 },
+            "solar2": { "charging": true, "pass_through": false, "power": 80 },
+            "output1": { "active": true, "power": 150 },
+            "output2": { "active": false, "power": 0 },
+            "temperature": { "min": 21, "max": 24 },
+            "battery": {
+                "charge": 64,
+                "capacity": 1440,
+                "output_threshold": 200,
+                "discharge_depth": 80,
+                "internal": {
+                    "charging": true,
+                    "discharging": false,
+                    "discharge_depth": false,
+                    "undervoltage": false
+                }
+            },
+            "scene": "day"
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_sparkline() {
+        assert_eq!(sparkline([0.0, 7.0, 3.5, 1.0].iter()), "▁█▅▂");
+        // Without variation all values are drawn at the lowest level.
+        assert_eq!(sparkline([5.0, 5.0].iter()), "▁▁");
+        assert_eq!(sparkline([].iter()), "");
+    }
+
+    #[test]
+    fn test_render() {
+        let history = ROWS
+            .iter()
+            .map(|_| {
+                (0..HISTORY)
+                    .map(|i| (i % 8) as f64)
+                    .collect::<VecDeque<_>>()
+            })
+            .collect::<Vec<_>>();
+        let screen = render(&Locale::default(), &device_info(), &history, (60, 40));
+        insta::assert_snapshot!(screen, @r"
+        Scene: day    Battery capacity: 1440 Wh
+
+        Solar 1 power         120 W  ▆▇█▁▂▃▄▅▆▇█▁▂▃▄▅▆▇█▁▂▃▄▅▆▇█▁▂▃▄
+        Solar 2 power          80 W  ▆▇█▁▂▃▄▅▆▇█▁▂▃▄▅▆▇█▁▂▃▄▅▆▇█▁▂▃▄
+        Output 1 power        150 W  ▆▇█▁▂▃▄▅▆▇█▁▂▃▄▅▆▇█▁▂▃▄▅▆▇█▁▂▃▄
+        Output 2 power          0 W  ▆▇█▁▂▃▄▅▆▇█▁▂▃▄▅▆▇█▁▂▃▄▅▆▇█▁▂▃▄
+        Battery charge         64 %  ▆▇█▁▂▃▄▅▆▇█▁▂▃▄▅▆▇█▁▂▃▄▅▆▇█▁▂▃▄
+        Temperature min       21 °C  ▆▇█▁▂▃▄▅▆▇█▁▂▃▄▅▆▇█▁▂▃▄▅▆▇█▁▂▃▄
+        Temperature max       24 °C  ▆▇█▁▂▃▄▅▆▇█▁▂▃▄▅▆▇█▁▂▃▄▅▆▇█▁▂▃▄
+
+        Press Ctrl-C to exit.
+        ");
+
+        // The latest values are kept when the terminal is too narrow, rows are cut off.
+        let screen = render(&Locale::default(), &device_info(), &history, (40, 4));
+        insta::assert_snapshot!(screen, @r"
+        Scene: day    Battery capacity: 1440 Wh
+
+        Solar 1 power         120 W  ▂▃▄▅▆▇█▁▂▃▄
+        Solar 2 power          80 W  ▂▃▄▅▆▇█▁▂▃▄
+        ");
+    }
+}
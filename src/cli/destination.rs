@@ -0,0 +1,163 @@
+//! Destination of the formatted records, stdout or a file with optional rotation.
+use std::fs::{File, OpenOptions};
+use std::io::Write as _;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use color_eyre::eyre::{Result, WrapErr};
+
+/// When and how a file output is rotated.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Rotation {
+    /// Rotates before a record would grow the file beyond this size in bytes.
+    pub size: Option<u64>,
+    /// Rotates when a record is written in a later period than the last record, periods are
+    /// aligned to the Unix epoch, e.g. `1h` rotates at every full hour (UTC).
+    pub interval: Option<Duration>,
+    /// Compresses rotated files with gzip.
+    pub compress: bool,
+}
+
+/// Where records are written to.
+pub enum Destination {
+    Stdout,
+    File(RotatingFile),
+}
+
+impl Destination {
+    /// Writes to `path` if set, otherwise to stdout.
+    pub fn new(path: Option<&Path>, rotation: Rotation) -> Result<Self> {
+        Ok(match path {
+            Some(path) => Self::File(RotatingFile::open(path, rotation)?),
+            None => Self::Stdout,
+        })
+    }
+
+    /// Writes a record followed by a newline.
+    pub fn writeln(&mut self, record: &str) -> Result<()> {
+        match self {
+            Self::Stdout => println!("{record}"),
+            Self::File(file) => file.writeln(record)?,
+        }
+        Ok(())
+    }
+}
+
+/// A file records are appended to, which is rotated according to a [`Rotation`].
+///
+/// Rotated files are renamed to `<path>.<unix timestamp>`, with a `.gz` suffix if compressed.
+pub struct RotatingFile {
+    path: PathBuf,
+    rotation: Rotation,
+    file: File,
+    size: u64,
+    /// Time of the last write, the creation for a new file.
+    modified: SystemTime,
+}
+
+impl RotatingFile {
+    /// Opens `path` for appending, the file is created if it does not exist.
+    pub fn open(path: &Path, rotation: Rotation) -> Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .wrap_err_with(|| format!("failed to open {}", path.display()))?;
+        let metadata = file.metadata()?;
+
+        Ok(Self {
+            path: path.to_owned(),
+            rotation,
+            file,
+            size: metadata.len(),
+            modified: metadata.modified().unwrap_or_else(|_| SystemTime::now()),
+        })
+    }
+
+    /// Appends a record followed by a newline, rotating the file first if necessary.
+    pub fn writeln(&mut self, record: &str) -> Result<()> {
+        let now = SystemTime::now();
+        let len = record.len() as u64 + 1;
+        if self.size > 0 && self.should_rotate(now, len) {
+            self.rotate(now)?;
+        }
+
+        writeln!(self.file, "{record}")
+            .wrap_err_with(|| format!("failed to write to {}", self.path.display()))?;
+        self.size += len;
+        self.modified = now;
+        Ok(())
+    }
+
+    fn should_rotate(&self, now: SystemTime, len: u64) -> bool {
+        let size = self.rotation.size.is_some_and(|max| self.size + len > max);
+        let interval = self
+            .rotation
+            .interval
+            .is_some_and(|interval| period(self.modified, interval) != period(now, interval));
+        size || interval
+    }
+
+    fn rotate(&mut self, now: SystemTime) -> Result<()> {
+        let timestamp = unix_secs(now);
+        let suffix = if self.rotation.compress { ".gz" } else { "" };
+        let target = (0..)
+            .map(|n| match n {
+                0 => format!("{}.{timestamp}{suffix}", self.path.display()),
+                n => format!("{}.{timestamp}-{n}{suffix}", self.path.display()),
+            })
+            .map(PathBuf::from)
+            .find(|target| !target.exists())
+            .expect("unbounded iterator");
+
+        if self.rotation.compress {
+            let data = std::fs::read(&self.path)?;
+            std::fs::write(&target, gzip(&data))
+                .wrap_err_with(|| format!("failed to write {}", target.display()))?;
+            std::fs::remove_file(&self.path)?;
+        } else {
+            std::fs::rename(&self.path, &target)
+                .wrap_err_with(|| format!("failed to rotate {}", self.path.display()))?;
+        }
+        tracing::debug!("rotated {} to {}", self.path.display(), target.display());
+
+        *self = Self::open(&self.path, self.rotation)?;
+        Ok(())
+    }
+}
+
+fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// Index of the rotation period `time` falls into.
+fn period(time: SystemTime, interval: Duration) -> u64 {
+    unix_secs(time) / interval.as_secs().max(1)
+}
+
+/// Wraps the deflate compressed `data` in the gzip format (RFC 1952).
+fn gzip(data: &[u8]) -> Vec<u8> {
+    // Magic, deflate, no flags, no modification time, no extra flags, unknown OS.
+    let mut result = vec![0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 0xff];
+    result.extend(miniz_oxide::deflate::compress_to_vec(data, 6));
+    result.extend(crc32(data).to_le_bytes());
+    result.extend((data.len() as u32).to_le_bytes());
+    result
+}
+
+/// CRC-32 (IEEE) checksum of `data`, as required by gzip.
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= u32::from(byte);
+        for _ in 0..8 {
+            crc = match crc & 1 {
+                1 => (crc >> 1) ^ 0xedb8_8320,
+                _ => crc >> 1,
+            };
+        }
+    }
+    !crc
+}
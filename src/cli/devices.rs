@@ -0,0 +1,48 @@
+use std::fmt::Write as _;
+
+use color_eyre::eyre::Result;
+use hmtk::cloud::{Account, CloudDevice};
+use hmtk::locale::Locale;
+
+/// Lists the devices registered with the cloud `account`.
+pub async fn devices(account: &Account, locale: &Locale, json: bool) -> Result<()> {
+    let devices = account.devices().await?;
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&devices)?);
+    } else {
+        print!("{}", to_table(locale, &devices));
+    }
+
+    Ok(())
+}
+
+fn to_table(locale: &Locale, devices: &[CloudDevice]) -> String {
+    let width = |value: fn(&CloudDevice) -> &str, header: &str| {
+        devices
+            .iter()
+            .map(|device| value(device).chars().count())
+            .chain([header.len()])
+            .max()
+            .unwrap_or_default()
+    };
+    let name = width(|device| &device.name, "name");
+    let ty = width(|device| &device.ty, "type");
+    let mac = width(|device| &device.mac, "mac");
+
+    let mut result = String::new();
+    let _ = writeln!(
+        result,
+        "{:<name$} {:<ty$} {:<mac$} online",
+        "name", "type", "mac"
+    );
+    for device in devices {
+        let online = device.online.map_or("-", |online| locale.bool(online));
+        let _ = writeln!(
+            result,
+            "{:<name$} {:<ty$} {:<mac$} {online}",
+            device.name, device.ty, device.mac
+        );
+    }
+    result
+}
@@ -0,0 +1,53 @@
+use std::fmt::Write as _;
+use std::time::Duration;
+
+use color_eyre::eyre::{Result, bail};
+use hmtk::mdns::{self, Broker};
+
+/// Lists the MQTT brokers announced on the local network with the `--mqtt-url` to use them.
+pub async fn discover(mdns: bool, timeout: Duration, json: bool) -> Result<()> {
+    if !mdns {
+        bail!("select a discovery mode, e.g. `--mdns`");
+    }
+
+    let brokers = mdns::browse(timeout).await?;
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&brokers)?);
+    } else if brokers.is_empty() {
+        eprintln!("no MQTT broker announced itself within {timeout:?}");
+    } else {
+        print!("{}", to_table(&brokers));
+        if let [broker] = brokers.as_slice() {
+            eprintln!("\nconnect with `hmtk --mqtt-url {}`", broker.url());
+        }
+    }
+
+    Ok(())
+}
+
+fn to_table(brokers: &[Broker]) -> String {
+    let width = |value: fn(&Broker) -> &str, header: &str| {
+        brokers
+            .iter()
+            .map(|broker| value(broker).chars().count())
+            .chain([header.len()])
+            .max()
+            .unwrap_or_default()
+    };
+    let name = width(|broker| &broker.name, "name");
+    let host = width(|broker| &broker.host, "host");
+
+    let mut result = String::new();
+    let _ = writeln!(result, "{:<name$} {:<host$} url", "name", "host");
+    for broker in brokers {
+        let _ = writeln!(
+            result,
+            "{:<name$} {:<host$} {}",
+            broker.name,
+            broker.host,
+            broker.url()
+        );
+    }
+    result
+}
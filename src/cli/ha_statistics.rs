@@ -0,0 +1,72 @@
+//! Hourly energy statistics for the Home Assistant statistics importer.
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+
+use color_eyre::eyre::{Result, bail};
+use hmtk::analytics::hourly_energy;
+use hmtk::time::LocalTime;
+
+use crate::cli::sink::sqlite;
+
+/// Prints the solar and output energy per hour from a `monitor --db` database.
+///
+/// The statistics are written as tab separated counters (`state` and `sum` in kWh), as
+/// accepted by the `import_from_file` service of the Home Assistant statistics importer.
+/// Only complete hours are exported.
+pub async fn ha_statistics(db: &Path, prefix: &str) -> Result<()> {
+    let mut devices = BTreeMap::<_, Vec<_>>::new();
+    for (mac, sample) in sqlite::power_samples(db)? {
+        devices.entry(mac).or_default().push(sample);
+    }
+    if devices.is_empty() {
+        bail!("{} contains no samples", db.display());
+    }
+
+    let mut result = String::from("statistic_id\tunit\tstart\tstate\tsum\n");
+    for (mac, samples) in &devices {
+        let end = samples.last().map(|sample| sample.timestamp);
+        let solar = hourly_energy(samples.iter().map(|s| (s.timestamp, s.solar)));
+        let output = hourly_energy(samples.iter().map(|s| (s.timestamp, s.output)));
+
+        write_counter(
+            &mut result,
+            &format!("{prefix}_{mac}_solar_energy"),
+            solar,
+            end,
+        );
+        write_counter(
+            &mut result,
+            &format!("{prefix}_{mac}_output_energy"),
+            output,
+            end,
+        );
+    }
+    print!("{result}");
+
+    Ok(())
+}
+
+/// Writes the hourly `energy` in Wh as a cumulative counter in kWh, up to the last complete hour.
+fn write_counter(
+    result: &mut String,
+    id: &str,
+    energy: BTreeMap<SystemTime, f64>,
+    end: Option<SystemTime>,
+) {
+    let mut sum = 0.0;
+    for (start, energy) in energy {
+        if end.is_none_or(|end| start + Duration::from_secs(3600) > end) {
+            break;
+        }
+        sum += energy / 1000.0;
+
+        let start = LocalTime::from_system_time(start);
+        let _ = writeln!(
+            result,
+            "{id}\tkWh\t{:02}.{:02}.{:04} {:02}:{:02}\t{sum:.3}\t{sum:.3}",
+            start.day, start.month, start.year, start.hour, start.minute,
+        );
+    }
+}
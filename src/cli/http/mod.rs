@@ -0,0 +1,287 @@
+//! HTTP server exposing the device state.
+//!
+//! Endpoints:
+//!
+//! - `GET /`: live dashboard of the device, only if enabled.
+//! - `GET /devices/{mac}/info`: latest known device info, with the `age` of the data in seconds.
+//! - `POST /devices/{mac}/refresh`: requests a status update from the device and returns it.
+//! - `GET /devices/{mac}/ws`: WebSocket streaming every new device info as JSON.
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use axum::Router;
+use axum::extract::ws::WebSocketUpgrade;
+use axum::extract::ws::rejection::WebSocketUpgradeRejection;
+use axum::extract::{Path, State};
+use axum::http::{HeaderMap, StatusCode, header};
+use axum::response::{Html, IntoResponse, Json, Response};
+use axum::routing::{get, post};
+use color_eyre::eyre::Result;
+use hmtk::mqtt::{Device, DeviceInfo};
+use tokio::net::TcpListener;
+
+use crate::cli::{output, shutdown_signal};
+
+mod websocket;
+
+/// Time the device has to respond to a refresh.
+const REFRESH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Page rendering the device state streamed by the WebSocket, `{{mac}}` is replaced with the MAC.
+const DASHBOARD: &str = include_str!("dashboard.html");
+
+/// Serves the HTTP API on `listen`.
+///
+/// If an `interval` is configured, status updates are requested from the device periodically
+/// and pushed to all connected WebSocket clients. With `dashboard` a live dashboard is served
+/// at `/`.
+pub async fn serve(
+    device: Device,
+    listen: SocketAddr,
+    interval: Option<Duration>,
+    dashboard: bool,
+) -> Result<()> {
+    let listener = TcpListener::bind(listen).await?;
+    tracing::info!("serving http on {}", listener.local_addr()?);
+
+    if let Some(interval) = interval {
+        let device = device.clone();
+        tokio::task::spawn(async move {
+            let mut interval = tokio::time::interval(interval);
+            loop {
+                interval.tick().await;
+                if let Err(err) = device.request_device_info().await {
+                    tracing::warn!("failed to request device status: {err}");
+                }
+            }
+        });
+    }
+
+    // Open WebSockets would delay a graceful shutdown indefinitely.
+    tokio::select! {
+        result = axum::serve(listener, router(device, dashboard)) => result?,
+        _ = shutdown_signal() => {}
+    }
+    Ok(())
+}
+
+/// Returns the routes of the HTTP API.
+fn router(device: Device, dashboard: bool) -> Router {
+    let mut router = Router::new()
+        .route("/devices/{mac}/info", get(info))
+        .route("/devices/{mac}/refresh", post(refresh))
+        .route("/devices/{mac}/ws", get(websocket));
+    if dashboard {
+        router = router.route("/", get(dashboard_page));
+    }
+
+    router
+        .fallback(|| async { error(StatusCode::NOT_FOUND, "not found") })
+        .method_not_allowed_fallback(|| async {
+            error(StatusCode::METHOD_NOT_ALLOWED, "method not allowed")
+        })
+        .with_state(device)
+}
+
+fn error(status: StatusCode, message: &str) -> Response {
+    (status, Json(serde_json::json!({ "error": message }))).into_response()
+}
+
+/// Responds with the device info and the age of its data.
+fn device_info(device: &Device, device_info: &DeviceInfo) -> Response {
+    let now = device.clock().now();
+    Json(output::JsonDeviceInfo::new(device_info, now)).into_response()
+}
+
+/// Returns an error response unless `mac` is the MAC of the served device.
+fn unknown_device(device: &Device, mac: &str) -> Option<Response> {
+    (!mac.eq_ignore_ascii_case(&device.options().mac))
+        .then(|| error(StatusCode::NOT_FOUND, "unknown device"))
+}
+
+/// Responds with the dashboard of the served device.
+async fn dashboard_page(State(device): State<Device>) -> Html<String> {
+    // Embedded into a script, `<` is escaped so the MAC cannot close the script element.
+    let mac = serde_json::to_string(&device.options().mac)
+        .expect("serializing to json never fails")
+        .replace('<', "\\u003c");
+    Html(DASHBOARD.replace("{{mac}}", &mac))
+}
+
+async fn info(State(mut device): State<Device>, Path(mac): Path<String>) -> Response {
+    if let Some(response) = unknown_device(&device, &mac) {
+        return response;
+    }
+    match device.current_device_info() {
+        Some(current) => device_info(&device, &current),
+        None => request_refresh(&mut device).await,
+    }
+}
+
+async fn refresh(State(mut device): State<Device>, Path(mac): Path<String>) -> Response {
+    if let Some(response) = unknown_device(&device, &mac) {
+        return response;
+    }
+    request_refresh(&mut device).await
+}
+
+async fn request_refresh(device: &mut Device) -> Response {
+    match tokio::time::timeout(REFRESH_TIMEOUT, device.device_info()).await {
+        Ok(Ok(current)) => device_info(device, &current),
+        Ok(Err(err)) => error(StatusCode::BAD_GATEWAY, &err.to_string()),
+        Err(_) => error(
+            StatusCode::GATEWAY_TIMEOUT,
+            "device did not respond in time",
+        ),
+    }
+}
+
+/// Upgrades the connection to a WebSocket streaming the device info.
+async fn websocket(
+    State(device): State<Device>,
+    Path(mac): Path<String>,
+    headers: HeaderMap,
+    upgrade: Result<WebSocketUpgrade, WebSocketUpgradeRejection>,
+) -> Response {
+    if let Some(response) = unknown_device(&device, &mac) {
+        return response;
+    }
+
+    let upgrade = match upgrade {
+        Ok(upgrade) => upgrade,
+        Err(_) if !headers.contains_key(header::UPGRADE) => {
+            return error(StatusCode::UPGRADE_REQUIRED, "websocket upgrade required");
+        }
+        Err(rejection) => return error(rejection.status(), "bad websocket handshake"),
+    };
+
+    upgrade.on_upgrade(move |socket| async move {
+        if let Err(err) = websocket::stream_device_info(socket, &device).await {
+            tracing::debug!("websocket connection failed: {err}");
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::time::SystemTime;
+
+    use axum::body::Body;
+    use axum::extract::Request;
+    use bytes::Bytes;
+    use futures::StreamExt;
+    use hmtk::capture::{Direction, Record};
+    use hmtk::mqtt::{ClientOptions, DeviceOptions, Protocol, RetryPolicy};
+    use hmtk::time::ManualClock;
+    use tower::ServiceExt;
+
+    use super::*;
+
+    const STATUS: &[u8] = b"p1=1,p2=1,w1=23,w2=23,pe=99,vv=220,sv=12,cs=0,cd=0,am=0,o1=1,o2=1,do=80,lv=200,cj=2,kn=2217,g1=1,g2=0,b1=0,b2=0,md=0,d1=1,e1=0:0,f1=23:59,h1=200,d2=0,e2=0:0,f2=0:0,h2=600,d3=0,e3=0:0,f3=0:0,h3=0,sg=0,sp=80,st=0,tl=27,th=27,tc=0,tf=0,fc=202310231502,id=5,a0=99,a1=0,a2=0,l0=1,l1=0,c0=255,c1=0,bc=2025,bs=329,pt=3332,it=1518,m0=0,m1=0,m2=0,m3=1,d4=0,e4=0:0,f4=24:0,h4=80,d5=0,e5=0:0,f5=24:0,h5=80,lmo=1830,lmi=272,lmf=1";
+
+    /// Returns a device, which received a single status replayed from a capture.
+    async fn device() -> Device {
+        let options = DeviceOptions {
+            ty: "HMA-1".to_owned(),
+            mac: "0123456789ab".to_owned(),
+            protocol: Protocol::default(),
+            dry_run: false,
+            min_interval: Duration::ZERO,
+            retry: RetryPolicy::default(),
+        };
+        let record = Record {
+            timestamp: SystemTime::UNIX_EPOCH + Duration::from_secs(60),
+            direction: Direction::Inbound,
+            topic: options.data_topic(),
+            payload: Bytes::from_static(STATUS),
+        };
+
+        let clock = ManualClock::new(SystemTime::UNIX_EPOCH);
+        let replay = ClientOptions::replay(vec![record], 1.0, clock.clone());
+        let (device, device_loop) = Device::with_clock(replay, options, Arc::new(clock)).unwrap();
+
+        let mut updates = device.subscribe_device_info();
+        tokio::task::spawn(device_loop.into_future());
+        updates.recv().await.unwrap();
+        device
+    }
+
+    async fn request(router: Router, method: &str, uri: &str) -> (StatusCode, String) {
+        let request = Request::builder()
+            .method(method)
+            .uri(uri)
+            .body(Body::empty())
+            .unwrap();
+        let response = router.oneshot(request).await.unwrap();
+
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_info() {
+        let router = router(device().await, false);
+
+        // The MAC is case insensitive.
+        let (status, body) = request(router.clone(), "GET", "/devices/0123456789AB/info").await;
+        assert_eq!(status, StatusCode::OK);
+        let value: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(value["age"], 0);
+        assert_eq!(value["timestamp"], 60);
+        assert_eq!(value["battery"]["charge"], 99);
+
+        let (status, body) = request(router.clone(), "GET", "/devices/abc/info").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(body, r#"{"error":"unknown device"}"#);
+    }
+
+    #[tokio::test]
+    async fn test_errors() {
+        let router = router(device().await, false);
+
+        let (status, body) = request(router.clone(), "GET", "/devices/0123456789ab/refresh").await;
+        assert_eq!(status, StatusCode::METHOD_NOT_ALLOWED);
+        assert_eq!(body, r#"{"error":"method not allowed"}"#);
+
+        let (status, body) = request(router.clone(), "GET", "/devices/0123456789ab/ws").await;
+        assert_eq!(status, StatusCode::UPGRADE_REQUIRED);
+        assert_eq!(body, r#"{"error":"websocket upgrade required"}"#);
+
+        // The dashboard is only served if enabled.
+        let (status, body) = request(router, "GET", "/").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(body, r#"{"error":"not found"}"#);
+    }
+
+    #[tokio::test]
+    async fn test_websocket() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let router = router(device().await, false);
+        tokio::task::spawn(async move { axum::serve(listener, router).await });
+
+        let url = format!("ws://{address}/devices/0123456789ab/ws");
+        let (mut socket, _) = tokio_tungstenite::connect_async(url).await.unwrap();
+
+        // The most recent device info is sent right after the upgrade.
+        let message = socket.next().await.unwrap().unwrap();
+        let value: serde_json::Value = serde_json::from_str(message.to_text().unwrap()).unwrap();
+        assert_eq!(value["timestamp"], 60);
+        assert_eq!(value["battery"]["charge"], 99);
+
+        socket.close(None).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_dashboard() {
+        let router = router(device().await, true);
+
+        let (status, body) = request(router, "GET", "/").await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(body.contains(r#"const MAC = "0123456789ab";"#));
+    }
+}
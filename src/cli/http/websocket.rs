@@ -0,0 +1,36 @@
+//! WebSocket streaming the device info to the client.
+use axum::extract::ws::{CloseFrame, Message, WebSocket, close_code};
+use hmtk::mqtt::Device;
+
+/// Streams every new device info as JSON text message until the client closes the connection.
+///
+/// The first message is the most recently received device info, if there is one.
+pub async fn stream_device_info(mut socket: WebSocket, device: &Device) -> Result<(), axum::Error> {
+    let mut updates = device.subscribe_device_info();
+    if let Some(device_info) = device.current_device_info() {
+        socket.send(to_message(&device_info)).await?;
+    }
+
+    loop {
+        tokio::select! {
+            device_info = updates.recv() => {
+                let Ok(device_info) = device_info else {
+                    let frame = CloseFrame { code: close_code::AWAY, reason: "".into() };
+                    return socket.send(Message::Close(Some(frame))).await;
+                };
+                socket.send(to_message(&device_info)).await?;
+            }
+            message = socket.recv() => match message {
+                // Messages from the client are ignored, pings are answered by axum.
+                Some(Ok(_)) => {}
+                Some(Err(err)) => return Err(err),
+                None => return Ok(()),
+            },
+        }
+    }
+}
+
+fn to_message(device_info: &hmtk::mqtt::DeviceInfo) -> Message {
+    let json = serde_json::to_string(device_info).expect("serializing to json never fails");
+    Message::Text(json.into())
+}
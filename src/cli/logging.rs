@@ -0,0 +1,242 @@
+//! Log output, either human readable or JSON lines for log shippers.
+use std::fmt;
+
+use hmtk::capture::{Direction, Record};
+use serde_json::{Map, Value};
+use tokio::sync::broadcast;
+use tokio::sync::broadcast::error::RecvError;
+use tracing::field::{Field, Visit};
+use tracing::{Event, Level, Subscriber};
+use tracing_subscriber::Registry;
+use tracing_subscriber::field::RecordFields;
+use tracing_subscriber::filter::Targets;
+use tracing_subscriber::fmt::format::{FormatEvent, FormatFields, Writer};
+use tracing_subscriber::fmt::time::{FormatTime, SystemTime};
+use tracing_subscriber::fmt::{FmtContext, FormattedFields};
+use tracing_subscriber::layer::{Layer, SubscriberExt};
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::util::SubscriberInitExt;
+
+#[derive(Debug, Clone, Copy, Default)]
+pub enum LogFormat {
+    #[default]
+    Text,
+    /// One JSON object per line, with the fields of the event and all its spans.
+    Json,
+}
+
+impl std::str::FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(Self::Text),
+            "json" => Ok(Self::Json),
+            _ => Err(format!(
+                "invalid log format `{s}`, expected `text` or `json`"
+            )),
+        }
+    }
+}
+
+/// Target of the events logged by [`trace_payloads`].
+const PAYLOAD_TARGET: &str = "hmtk::payload";
+
+/// Installs the global subscriber writing logs in `format` to stderr.
+///
+/// With `trace_payloads` the debug events of [`trace_payloads`] are written as well. The
+/// additional `layer` receives all events and spans regardless of the log level, e.g. to export
+/// traces.
+pub fn init<L>(format: LogFormat, trace_payloads: bool, layer: L)
+where
+    L: Layer<Registry> + Send + Sync + 'static,
+{
+    // The embedded broker logs every connection and subscription at info level.
+    let mut filter = Targets::new()
+        .with_default(Level::INFO)
+        .with_target("rumqttd", Level::WARN);
+    if trace_payloads {
+        filter = filter.with_target(PAYLOAD_TARGET, Level::DEBUG);
+    }
+
+    let registry = tracing_subscriber::registry().with(layer);
+    let logs = tracing_subscriber::fmt::layer().with_writer(std::io::stderr);
+    match format {
+        LogFormat::Text => registry.with(logs.with_filter(filter)).init(),
+        LogFormat::Json => registry
+            .with(
+                logs.fmt_fields(JsonFields)
+                    .event_format(Json)
+                    .with_filter(filter),
+            )
+            .init(),
+    }
+}
+
+/// Logs every payload of the `traffic` as escaped string and hex dump, with its topic and size.
+///
+/// The events are logged at debug level, see [`init`].
+pub async fn trace_payloads(mut traffic: broadcast::Receiver<Record>) {
+    loop {
+        let record = match traffic.recv().await {
+            Ok(record) => record,
+            Err(RecvError::Lagged(skipped)) => {
+                tracing::warn!("payload trace lagging behind, skipped {skipped} messages");
+                continue;
+            }
+            Err(RecvError::Closed) => return,
+        };
+
+        let direction = match record.direction {
+            Direction::Inbound => "received",
+            Direction::Outbound => "sent",
+        };
+        tracing::debug!(
+            target: PAYLOAD_TARGET,
+            topic = %record.topic,
+            size = record.payload.len(),
+            "{direction} `{}`\n{}",
+            record.payload.escape_ascii(),
+            hexdump(&record.payload),
+        );
+    }
+}
+
+/// Formats `data` like `hexdump -C`, 16 bytes per line with their offset and ASCII characters.
+fn hexdump(data: &[u8]) -> String {
+    use std::fmt::Write as _;
+
+    let mut result = String::new();
+    for (line, chunk) in data.chunks(16).enumerate() {
+        let _ = write!(result, "{:08x} ", line * 16);
+        for i in 0..16 {
+            if i % 8 == 0 {
+                result.push(' ');
+            }
+            match chunk.get(i) {
+                Some(byte) => {
+                    let _ = write!(result, "{byte:02x} ");
+                }
+                None => result.push_str("   "),
+            }
+        }
+        let ascii: String = chunk
+            .iter()
+            .map(|&byte| match byte {
+                0x20..0x7f => char::from(byte),
+                _ => '.',
+            })
+            .collect();
+        let _ = writeln!(result, " |{ascii}|");
+    }
+    result.truncate(result.trim_end().len());
+    result
+}
+
+/// Formats events as JSON objects.
+///
+/// Span fields, e.g. the MAC of the device, are merged into the object,
+/// fields of inner spans and the event take precedence.
+struct Json;
+
+impl<S, N> FormatEvent<S, N> for Json
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'a> FormatFields<'a> + 'static,
+{
+    fn format_event(
+        &self,
+        ctx: &FmtContext<'_, S, N>,
+        mut writer: Writer<'_>,
+        event: &Event<'_>,
+    ) -> fmt::Result {
+        let mut timestamp = String::new();
+        SystemTime.format_time(&mut Writer::new(&mut timestamp))?;
+
+        let metadata = event.metadata();
+        let mut object = Map::new();
+        object.insert("timestamp".to_owned(), timestamp.into());
+        object.insert("level".to_owned(), metadata.level().as_str().into());
+        object.insert("target".to_owned(), metadata.target().into());
+
+        for span in ctx
+            .event_scope()
+            .into_iter()
+            .flat_map(|scope| scope.from_root())
+        {
+            let extensions = span.extensions();
+            let Some(fields) = extensions.get::<FormattedFields<N>>() else {
+                continue;
+            };
+            if let Ok(Value::Object(fields)) = serde_json::from_str(&fields.fields) {
+                object.extend(fields);
+            }
+        }
+
+        let mut visitor = JsonVisitor::default();
+        event.record(&mut visitor);
+        object.extend(visitor.0);
+
+        writeln!(writer, "{}", Value::Object(object))
+    }
+}
+
+/// Records span fields as JSON object, for [`Json`] to merge into events.
+struct JsonFields;
+
+impl<'writer> FormatFields<'writer> for JsonFields {
+    fn format_fields<R: RecordFields>(
+        &self,
+        mut writer: Writer<'writer>,
+        fields: R,
+    ) -> fmt::Result {
+        let mut visitor = JsonVisitor::default();
+        fields.record(&mut visitor);
+        write!(writer, "{}", Value::Object(visitor.0))
+    }
+
+    fn add_fields(
+        &self,
+        current: &'writer mut FormattedFields<Self>,
+        fields: &tracing::span::Record<'_>,
+    ) -> fmt::Result {
+        let mut visitor = JsonVisitor::default();
+        if let Ok(Value::Object(fields)) = serde_json::from_str(&current.fields) {
+            visitor.0 = fields;
+        }
+        fields.record(&mut visitor);
+        current.fields = Value::Object(visitor.0).to_string();
+        Ok(())
+    }
+}
+
+/// Collects the fields of events and spans into a JSON object.
+#[derive(Default)]
+pub struct JsonVisitor(pub Map<String, Value>);
+
+impl Visit for JsonVisitor {
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.0.insert(field.name().to_owned(), value.into());
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.0.insert(field.name().to_owned(), value.into());
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.0.insert(field.name().to_owned(), value.into());
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.0.insert(field.name().to_owned(), value.into());
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.insert(field.name().to_owned(), value.into());
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.0
+            .insert(field.name().to_owned(), format!("{value:?}").into());
+    }
+}
@@ -0,0 +1,168 @@
+use std::time::Duration;
+
+use hmtk::mqtt::ControlCommand;
+
+pub mod acl;
+pub mod alerting;
+pub mod audit;
+pub mod bridge;
+#[cfg(feature = "embedded-broker")]
+pub mod broker;
+pub mod capacity;
+pub mod capture;
+pub mod check;
+pub mod config;
+pub mod context;
+pub mod dashboard;
+pub mod destination;
+pub mod devices;
+pub mod discover;
+pub mod ha_statistics;
+#[cfg(feature = "http")]
+pub mod http;
+pub mod logging;
+pub mod monitor;
+#[cfg(feature = "otel")]
+pub mod otel;
+pub mod output;
+pub mod protocol;
+pub mod proxy;
+pub mod pv_diag;
+pub mod selftest;
+pub mod sink;
+pub mod ssh;
+pub mod stats;
+pub mod status;
+pub mod sync_time;
+#[cfg(target_os = "linux")]
+pub mod systemd;
+
+/// Waits until the process is asked to shut down with SIGINT (Ctrl+C) or SIGTERM.
+pub async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{SignalKind, signal};
+
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                tokio::select! {
+                    _ = tokio::signal::ctrl_c() => {}
+                    _ = terminate.recv() => {}
+                }
+                return;
+            }
+            Err(err) => tracing::warn!("failed to listen for SIGTERM: {err}"),
+        }
+    }
+    let _ = tokio::signal::ctrl_c().await;
+}
+
+/// Prints the topic and payload of `command`, if it was not sent because of `--dry-run`.
+///
+/// Printed to stdout unlike the log, scripts and schedules can be reviewed from their output.
+pub fn print_dry_run(device: &hmtk::mqtt::Device, command: ControlCommand) {
+    if !device.options().dry_run {
+        return;
+    }
+
+    let topic = device.options().control_topic();
+    match command.expected_status() {
+        Some((field, value)) => println!("{topic} {command} (expects {field}={value})"),
+        None => println!("{topic} {command}"),
+    }
+}
+
+/// Parses a human readable size in bytes like `512K`, `10M` or `1G`.
+///
+/// The units are powers of 1024, a plain number is interpreted as bytes.
+pub fn parse_size(s: String) -> Result<u64, String> {
+    let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (value, unit) = s.split_at(split);
+
+    let value: u64 = value
+        .parse()
+        .map_err(|_| format!("invalid size `{s}`, expected for example `10M`"))?;
+
+    let factor = match unit {
+        "" | "B" => 1,
+        "K" => 1 << 10,
+        "M" => 1 << 20,
+        "G" => 1 << 30,
+        unit => {
+            return Err(format!(
+                "invalid size unit `{unit}`, expected `K`, `M` or `G`"
+            ));
+        }
+    };
+    value
+        .checked_mul(factor)
+        .ok_or_else(|| format!("size `{s}` is too large"))
+}
+
+/// Parses a human readable duration like `500ms`, `30s`, `5m` or `1h`.
+///
+/// A plain number is interpreted as seconds.
+pub fn parse_duration(s: String) -> Result<Duration, String> {
+    let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (value, unit) = s.split_at(split);
+
+    let value: u64 = value
+        .parse()
+        .map_err(|_| format!("invalid duration `{s}`, expected for example `30s`"))?;
+
+    Ok(match unit {
+        "ms" => Duration::from_millis(value),
+        "" | "s" => Duration::from_secs(value),
+        "m" => Duration::from_secs(value * 60),
+        "h" => Duration::from_secs(value * 60 * 60),
+        unit => {
+            return Err(format!(
+                "invalid duration unit `{unit}`, expected `ms`, `s`, `m` or `h`"
+            ));
+        }
+    })
+}
+
+/// A time of day with minute precision.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TimeOfDay {
+    pub hour: u8,
+    pub minute: u8,
+}
+
+impl TimeOfDay {
+    /// Returns the time since midnight.
+    pub fn since_midnight(self) -> Duration {
+        Duration::from_secs(u64::from(self.hour) * 60 * 60 + u64::from(self.minute) * 60)
+    }
+}
+
+/// Parses a time of day like `04:00`.
+pub fn parse_time_of_day(s: String) -> Result<TimeOfDay, String> {
+    let invalid = || format!("invalid time `{s}`, expected for example `04:00`");
+
+    let (hour, minute) = s.split_once(':').ok_or_else(invalid)?;
+    let hour: u8 = hour.parse().map_err(|_| invalid())?;
+    let minute: u8 = minute.parse().map_err(|_| invalid())?;
+    if hour >= 24 || minute >= 60 {
+        return Err(invalid());
+    }
+    Ok(TimeOfDay { hour, minute })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(
+            parse_duration("500ms".into()),
+            Ok(Duration::from_millis(500))
+        );
+        assert_eq!(parse_duration("5m".into()), Ok(Duration::from_secs(300)));
+        // Plain numbers are seconds, like `monitor --interval 30` before it accepted units.
+        assert_eq!(parse_duration("30".into()), Ok(Duration::from_secs(30)));
+        assert!(parse_duration("30d".into()).is_err());
+    }
+}
@@ -0,0 +1,323 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+
+use color_eyre::eyre::Result;
+use hmtk::alerts::{Alert, Alerts, Rule};
+use hmtk::analytics::{DailyEnergyMeter, PowerSmoother};
+use hmtk::events::{ChangeFilter, Event};
+use hmtk::locale::Locale;
+use hmtk::mqtt::{DeviceInfo, DeviceOptions};
+use hmtk::notify::{Notification, Notifier, notify_all};
+use hmtk::parser::{Field, Parser};
+
+use crate::QueryFormat;
+use crate::cli::alerting::Alerting;
+use crate::cli::destination::Destination;
+use crate::cli::output::{
+    Diff, format_device_info, format_health, format_reading, format_sink_stats,
+};
+use crate::cli::shutdown_signal;
+use crate::cli::sink::queue::QueuedSink;
+use crate::cli::status::Status;
+
+/// Destinations of the samples collected by [`monitor`].
+pub struct Outputs {
+    /// Format of the samples written to the `destination`, nothing is written if `None`.
+    pub format: Option<QueryFormat>,
+    pub destination: Destination,
+    pub sinks: Vec<QueuedSink>,
+    /// File the monitor status is written to after every sample.
+    pub status: Option<PathBuf>,
+    /// Only samples passing the filter are written to stdout and the sinks, if set.
+    pub changes: Option<ChangeFilter<DeviceInfo>>,
+    /// Accumulates the energy of the day, which is written with every sample, if set.
+    pub energy: Option<DailyEnergyMeter>,
+    /// Averages the power of the samples, if set.
+    pub smoother: Option<PowerSmoother>,
+}
+
+/// When samples are taken and when sampling stops.
+#[derive(Debug, Clone, Copy)]
+pub struct Schedule {
+    /// Interval between two samples.
+    pub interval: Duration,
+    /// Stops after this many samples.
+    pub count: Option<u64>,
+    /// Stops after this duration.
+    pub duration: Option<Duration>,
+}
+
+impl Schedule {
+    /// Completes once the configured duration elapsed, never without a duration.
+    async fn deadline(self) {
+        match self.duration {
+            Some(duration) => tokio::time::sleep(duration).await,
+            None => std::future::pending().await,
+        }
+    }
+
+    fn is_done(&self, samples: u64) -> bool {
+        self.count.is_some_and(|count| samples >= count)
+    }
+}
+
+/// Number of samples taken and written until sampling stopped.
+#[derive(Debug)]
+struct Summary {
+    start: Instant,
+    samples: u64,
+    written: u64,
+    /// Status requests which failed, e.g. because the device did not respond.
+    failed: u64,
+}
+
+impl Summary {
+    fn new() -> Self {
+        Self {
+            start: Instant::now(),
+            samples: 0,
+            written: 0,
+            failed: 0,
+        }
+    }
+
+    /// Logs and counts a failed status request, monitoring continues with the next one.
+    ///
+    /// Only returns an error if the device loop exited and no further request can succeed.
+    fn failed(&mut self, err: hmtk::mqtt::Error) -> Result<()> {
+        if matches!(err, hmtk::mqtt::Error::Disconnected) {
+            return Err(err.into());
+        }
+        tracing::warn!("failed to request device status: {err}");
+        self.failed += 1;
+        Ok(())
+    }
+
+    fn log(&self) {
+        tracing::info!(
+            "stopped after {} samples in {:.1}s, {} written, {} failed",
+            self.samples,
+            self.start.elapsed().as_secs_f64(),
+            self.written,
+            self.failed
+        );
+    }
+}
+
+pub async fn monitor(
+    device: &mut hmtk::mqtt::Device,
+    locale: &Locale,
+    schedule: Schedule,
+    hooks: Hooks,
+    mut outputs: Outputs,
+    alerting: Alerting,
+) -> Result<()> {
+    let mut interval = tokio::time::interval(schedule.interval);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+    let mut alerts = Alerts::new(
+        [
+            Rule::power_quality(),
+            vec![Rule::charging_anomaly()],
+            alerting.rules().to_vec(),
+        ]
+        .concat(),
+    );
+
+    let shutdown = shutdown_signal();
+    let deadline = schedule.deadline();
+    tokio::pin!(shutdown, deadline);
+
+    let mut summary = Summary::new();
+    let mut diff = Diff::default();
+    let mut previous = None;
+    while !schedule.is_done(summary.samples) {
+        tokio::select! {
+            _ = interval.tick() => {}
+            _ = &mut shutdown => break,
+            _ = &mut deadline => break,
+        }
+
+        let device_info = tokio::select! {
+            device_info = device.device_info() => device_info,
+            _ = &mut deadline => break,
+        };
+        let device_info = match device_info {
+            Ok(device_info) => device_info,
+            Err(err) => {
+                summary.failed(err)?;
+                continue;
+            }
+        };
+        summary.samples += 1;
+        // Every sample is integrated, including those which are not emitted.
+        let energy = outputs.energy.as_mut().map(|meter| meter.add(&device_info));
+        let device_info = match &mut outputs.smoother {
+            Some(smoother) => smoother.smooth(&device_info),
+            None => device_info,
+        };
+        let emit = outputs
+            .changes
+            .as_mut()
+            .is_none_or(|changes| changes.check_device_info(&device_info));
+        if emit {
+            summary.written += 1;
+            for sink in &mut outputs.sinks {
+                sink.push(device.options(), &device_info);
+            }
+        }
+        let sink_stats = outputs
+            .sinks
+            .iter()
+            .map(QueuedSink::stats)
+            .collect::<Vec<_>>();
+
+        if let Some(format) = outputs.format.as_ref().filter(|_| emit) {
+            let now = device.clock().now();
+            let out = match format {
+                QueryFormat::Diff => diff.device_info(locale, &device_info, energy.as_ref()),
+                _ => Some(format_device_info(
+                    device.options(),
+                    locale,
+                    format,
+                    &device_info,
+                    None,
+                    energy.as_ref(),
+                    now,
+                )?),
+            };
+            if let Some(out) = out {
+                outputs.destination.writeln(&out)?;
+            }
+
+            if let Some(out) = format_health(device, format, device_info.timestamp) {
+                outputs.destination.writeln(&out)?;
+            }
+            if !sink_stats.is_empty()
+                && let Some(out) =
+                    format_sink_stats(device.options(), format, device_info.timestamp, &sink_stats)
+            {
+                outputs.destination.writeln(&out)?;
+            }
+        }
+        if let Some(path) = &outputs.status {
+            Status::new(device, sink_stats).write(path)?;
+        }
+
+        if let Some(previous) = previous {
+            for event in hmtk::events::diff(&previous, &device_info) {
+                tracing::info!("{event:?}");
+                hooks.run(device.options(), &event, device_info.timestamp);
+            }
+        }
+        for alert in alerts.evaluate(&device_info) {
+            match alert {
+                Alert::Raised { rule, value } | Alert::Repeated { rule, value } => {
+                    tracing::warn!(
+                        "alert {} {}, {} is {value}",
+                        rule.name,
+                        alert.state(),
+                        rule.metric
+                    )
+                }
+                Alert::Resolved { rule, value } => {
+                    tracing::info!("alert {} resolved, {} is {value}", rule.name, rule.metric)
+                }
+            }
+            alerting.run(device, &alert);
+        }
+
+        previous = Some(device_info);
+    }
+    summary.log();
+
+    tracing::info!("shutting down, flushing sinks");
+    for sink in outputs.sinks {
+        sink.close().await?;
+    }
+
+    Ok(())
+}
+
+/// Continuously queries a device type without built-in support, parsing it with `parser`.
+pub async fn monitor_fields(
+    device: &mut hmtk::mqtt::Device,
+    locale: &Locale,
+    parser: &dyn Parser,
+    schedule: Schedule,
+    format: Option<QueryFormat>,
+    mut destination: Destination,
+    mut changes: Option<ChangeFilter<Vec<Field>>>,
+) -> Result<()> {
+    let mut interval = tokio::time::interval(schedule.interval);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+    let shutdown = shutdown_signal();
+    let deadline = schedule.deadline();
+    tokio::pin!(shutdown, deadline);
+
+    let mut summary = Summary::new();
+    let mut diff = Diff::default();
+    while !schedule.is_done(summary.samples) {
+        tokio::select! {
+            _ = interval.tick() => {}
+            _ = &mut shutdown => break,
+            _ = &mut deadline => break,
+        }
+
+        let reading = tokio::select! {
+            reading = device.read(parser) => reading,
+            _ = &mut deadline => break,
+        };
+        let reading = match reading {
+            Ok(reading) => reading,
+            Err(err) => {
+                summary.failed(err)?;
+                continue;
+            }
+        };
+        summary.samples += 1;
+        if let Some(changes) = &mut changes
+            && !changes.check(reading.timestamp, reading.fields.clone())
+        {
+            continue;
+        }
+        summary.written += 1;
+        if let Some(format) = &format {
+            let out = match format {
+                QueryFormat::Diff => diff.reading(locale, &reading),
+                _ => Some(format_reading(device.options(), locale, format, &reading)?),
+            };
+            if let Some(out) = out {
+                destination.writeln(&out)?;
+            }
+            if let Some(out) = format_health(device, format, reading.timestamp) {
+                destination.writeln(&out)?;
+            }
+        }
+    }
+    summary.log();
+
+    Ok(())
+}
+
+/// User configured notifiers, which are notified about device events.
+#[derive(Debug, Default)]
+pub struct Hooks {
+    /// Notified when the scene changes, e.g. the `--on-scene-change` command.
+    pub on_scene_change: Vec<Arc<dyn Notifier>>,
+    /// Notified about every event, e.g. the `--webhook`.
+    pub on_event: Vec<Arc<dyn Notifier>>,
+}
+
+impl Hooks {
+    /// Notifies the notifiers matching the `event` in the background.
+    fn run(&self, device: &DeviceOptions, event: &Event, timestamp: SystemTime) {
+        let notification = Notification::event(device, event, timestamp);
+        if let Event::SceneChanged { .. } = event {
+            notify_all(&self.on_scene_change, notification.clone());
+        }
+        notify_all(&self.on_event, notification);
+    }
+}
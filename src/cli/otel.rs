@@ -0,0 +1,291 @@
+//! Exports the spans of device requests, reconnects and sink writes via OTLP, e.g. to Tempo or
+//! Jaeger.
+//!
+//! Spans are sent in batches with the JSON encoding of OTLP/HTTP. A span with an `error` field,
+//! e.g. a failed request, is exported with an error status.
+use std::hash::BuildHasher;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime};
+
+use color_eyre::eyre::{Result, bail};
+use hmtk::http;
+use serde_json::{Map, Value, json};
+use tokio::sync::{mpsc, oneshot};
+use tokio::task::JoinHandle;
+use tracing::span::{Attributes, Id, Record};
+use tracing::{Event, Subscriber};
+use tracing_subscriber::Layer;
+use tracing_subscriber::filter::filter_fn;
+use tracing_subscriber::layer::Context;
+use tracing_subscriber::registry::LookupSpan;
+
+use crate::cli::logging::JsonVisitor;
+
+/// Names of the exported spans, all other spans are ignored.
+const SPANS: &[&str] = &["request", "reconnect", "sink_write"];
+
+/// Sends the spans collected by its [`Exporter::layer`] to an OTLP collector.
+pub struct Exporter {
+    spans: mpsc::Sender<SpanData>,
+    shutdown: oneshot::Sender<()>,
+    task: JoinHandle<()>,
+}
+
+impl Exporter {
+    /// Maximum number of spans sent in one request.
+    const BATCH: usize = 512;
+    /// Interval in which incomplete batches are sent.
+    const INTERVAL: Duration = Duration::from_secs(5);
+    /// Number of spans which can wait for the export, further spans are dropped.
+    const CAPACITY: usize = 4096;
+    /// Time to wait for the last batch when shutting down.
+    const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+
+    /// Starts exporting to the OTLP/HTTP `endpoint`, e.g. `http://localhost:4318`.
+    ///
+    /// The service name is taken from `OTEL_SERVICE_NAME` and defaults to `hmtk`.
+    pub fn start(endpoint: &str) -> Result<Self> {
+        let url = format!("{}/v1/traces", endpoint.trim_end_matches('/'));
+        let Ok(url) = http::parse_url(&url) else {
+            bail!(
+                "unsupported OTLP endpoint `{endpoint}`, expected an `http://` or `https://` url"
+            );
+        };
+        let service = std::env::var("OTEL_SERVICE_NAME").unwrap_or_else(|_| "hmtk".to_owned());
+
+        let (spans, mut queue) = mpsc::channel(Self::CAPACITY);
+        let (shutdown, mut shutdown_rx) = oneshot::channel();
+        let task = tokio::spawn(async move {
+            let mut interval = tokio::time::interval(Self::INTERVAL);
+            let mut batch = Vec::new();
+            loop {
+                tokio::select! {
+                    span = queue.recv() => match span {
+                        Some(span) => {
+                            batch.push(span);
+                            if batch.len() < Self::BATCH {
+                                continue;
+                            }
+                        }
+                        None => break,
+                    },
+                    _ = interval.tick() => {}
+                    _ = &mut shutdown_rx => {
+                        while let Ok(span) = queue.try_recv() {
+                            batch.push(span);
+                        }
+                        break;
+                    }
+                }
+                export(&url, &service, std::mem::take(&mut batch)).await;
+            }
+            export(&url, &service, batch).await;
+        });
+
+        Ok(Self {
+            spans,
+            shutdown,
+            task,
+        })
+    }
+
+    /// Returns the layer collecting the spans, to be added to the global subscriber.
+    pub fn layer<S>(&self) -> impl Layer<S> + use<S>
+    where
+        S: Subscriber + for<'a> LookupSpan<'a>,
+    {
+        let filter = filter_fn(|metadata| match metadata.is_span() {
+            true => SPANS.contains(&metadata.name()),
+            // Errors of `#[instrument(err)]` are reported as events within the span.
+            false => metadata.fields().field("error").is_some(),
+        });
+        OtlpLayer {
+            spans: self.spans.clone(),
+        }
+        .with_filter(filter)
+    }
+
+    /// Sends the remaining spans and stops the export.
+    pub async fn shutdown(self) {
+        let _ = self.shutdown.send(());
+        let abort = self.task.abort_handle();
+        if tokio::time::timeout(Self::SHUTDOWN_TIMEOUT, self.task)
+            .await
+            .is_err()
+        {
+            abort.abort();
+            tracing::warn!("timed out exporting the remaining spans");
+        }
+    }
+}
+
+/// A span waiting for its export, stored in the extensions of the span until it is closed.
+struct SpanData {
+    trace_id: u128,
+    span_id: u64,
+    parent_span_id: Option<u64>,
+    name: &'static str,
+    start: SystemTime,
+    /// Time the span was closed, the start until then.
+    end: SystemTime,
+    attributes: Map<String, Value>,
+}
+
+struct OtlpLayer {
+    spans: mpsc::Sender<SpanData>,
+}
+
+impl<S> Layer<S> for OtlpLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(id) else {
+            return;
+        };
+        // Only exported spans are visible as parents, other spans start a new trace.
+        let parent = span.parent().and_then(|parent| {
+            let extensions = parent.extensions();
+            let parent = extensions.get::<SpanData>()?;
+            Some((parent.trace_id, parent.span_id))
+        });
+
+        let mut visitor = JsonVisitor::default();
+        attrs.record(&mut visitor);
+        let now = SystemTime::now();
+        let data = SpanData {
+            trace_id: match parent {
+                Some((trace_id, _)) => trace_id,
+                None => u128::from(random_id()) << 64 | u128::from(random_id()),
+            },
+            span_id: random_id(),
+            parent_span_id: parent.map(|(_, span_id)| span_id),
+            name: attrs.metadata().name(),
+            start: now,
+            end: now,
+            attributes: visitor.0,
+        };
+        span.extensions_mut().insert(data);
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(id) else {
+            return;
+        };
+        if let Some(data) = span.extensions_mut().get_mut::<SpanData>() {
+            let mut visitor = JsonVisitor(std::mem::take(&mut data.attributes));
+            values.record(&mut visitor);
+            data.attributes = visitor.0;
+        }
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let Some(span) = ctx.event_span(event) else {
+            return;
+        };
+        let mut visitor = JsonVisitor::default();
+        event.record(&mut visitor);
+        if let Some(error) = visitor.0.remove("error")
+            && let Some(data) = span.extensions_mut().get_mut::<SpanData>()
+        {
+            data.attributes.insert("error".to_owned(), error);
+        }
+    }
+
+    fn on_close(&self, id: Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(&id) else {
+            return;
+        };
+        let Some(mut data) = span.extensions_mut().remove::<SpanData>() else {
+            return;
+        };
+        data.end = SystemTime::now();
+        // Spans are dropped instead of slowing down the device while the collector is down.
+        let _ = self.spans.try_send(data);
+    }
+}
+
+/// Returns a random, non-zero id.
+fn random_id() -> u64 {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let state = std::collections::hash_map::RandomState::new();
+    state
+        .hash_one(COUNTER.fetch_add(1, Ordering::Relaxed))
+        .max(1)
+}
+
+/// Sends `spans` to the collector, failures are logged and the spans dropped.
+async fn export(url: &http::Url, service: &str, spans: Vec<SpanData>) {
+    if spans.is_empty() {
+        return;
+    }
+    let body = to_json(service, &spans).to_string();
+    if let Err(err) = http::send(http::Method::POST, url.clone(), "application/json", body).await {
+        tracing::warn!("failed to export {} spans: {err}", spans.len());
+    }
+}
+
+/// Encodes `spans` as OTLP `ExportTraceServiceRequest`.
+fn to_json(service: &str, spans: &[SpanData]) -> Value {
+    let nanos = |time: SystemTime| {
+        time.duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or(Duration::ZERO)
+            .as_nanos()
+            .to_string()
+    };
+
+    let spans: Vec<_> = spans
+        .iter()
+        .map(|span| {
+            let mut result = json!({
+                "traceId": format!("{:032x}", span.trace_id),
+                "spanId": format!("{:016x}", span.span_id),
+                "name": span.name,
+                // SPAN_KIND_CLIENT, every exported span waits for a remote party.
+                "kind": 3,
+                "startTimeUnixNano": nanos(span.start),
+                "endTimeUnixNano": nanos(span.end),
+                "attributes": span
+                    .attributes
+                    .iter()
+                    .map(|(key, value)| json!({ "key": key, "value": any_value(value) }))
+                    .collect::<Vec<_>>(),
+            });
+            if let Some(parent) = span.parent_span_id {
+                result["parentSpanId"] = format!("{parent:016x}").into();
+            }
+            if let Some(error) = span.attributes.get("error") {
+                let message = match error {
+                    Value::String(error) => error.clone(),
+                    error => error.to_string(),
+                };
+                // STATUS_CODE_ERROR
+                result["status"] = json!({ "code": 2, "message": message });
+            }
+            result
+        })
+        .collect();
+
+    json!({
+        "resourceSpans": [{
+            "resource": {
+                "attributes": [{ "key": "service.name", "value": { "stringValue": service } }],
+            },
+            "scopeSpans": [{
+                "scope": { "name": "hmtk", "version": env!("CARGO_PKG_VERSION") },
+                "spans": spans,
+            }],
+        }],
+    })
+}
+
+/// Encodes a field value as OTLP `AnyValue`, 64 bit integers are encoded as strings.
+fn any_value(value: &Value) -> Value {
+    match value {
+        Value::Bool(value) => json!({ "boolValue": value }),
+        Value::Number(value) if value.is_f64() => json!({ "doubleValue": value }),
+        Value::Number(value) => json!({ "intValue": value.to_string() }),
+        Value::String(value) => json!({ "stringValue": value }),
+        value => json!({ "stringValue": value.to_string() }),
+    }
+}
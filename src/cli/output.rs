@@ -0,0 +1,908 @@
+use std::fmt::Write as _;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime};
+
+use color_eyre::eyre::Result;
+use hmtk::analytics::ChannelEnergy;
+use hmtk::locale::Locale;
+use hmtk::mqtt::{DerivedInfo, Device, DeviceInfo, DeviceOptions, Message};
+use hmtk::parser::{Reading, Value};
+use serde::Serialize;
+
+use crate::QueryFormat;
+use crate::cli::sink::queue::SinkStats;
+
+/// Additional data which can be requested from the device.
+#[derive(Debug, Clone, Copy)]
+pub enum Detail {
+    /// Extended battery data, requested with `cd=16`.
+    Cells,
+}
+
+impl FromStr for Detail {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "cells" => Ok(Self::Cells),
+            _ => Err(format!("unknown detail `{s}`, expected `cells`")),
+        }
+    }
+}
+
+/// Formats the device info, merged with the extended battery data and the accumulated energy
+/// if available.
+///
+/// The fields of the battery data are not decoded, numeric values are emitted as integers
+/// and everything else as strings. The JSON and Influx formats include the age of the data at
+/// `now`.
+pub fn format_device_info(
+    device: &DeviceOptions,
+    locale: &Locale,
+    format: &QueryFormat,
+    device_info: &DeviceInfo,
+    cells: Option<&Message>,
+    energy: Option<&ChannelEnergy>,
+    now: SystemTime,
+) -> Result<String> {
+    Ok(match format {
+        QueryFormat::Json | QueryFormat::Ndjson => {
+            let mut value = JsonDeviceInfo::new(device_info, now);
+            value.cells = cells.map(|cells| {
+                cells
+                    .fields()
+                    .map(|(key, value)| {
+                        let value = match value.parse::<i64>() {
+                            Ok(value) => value.into(),
+                            Err(_) => value.into(),
+                        };
+                        (key.to_owned(), value)
+                    })
+                    .collect()
+            });
+            value.accumulated = energy;
+            match format {
+                QueryFormat::Json => serde_json::to_string_pretty(&value)?,
+                _ => serde_json::to_string(&value)?,
+            }
+        }
+        QueryFormat::Influx {
+            influx_measurement,
+            influx_tag,
+            ..
+        } => {
+            let mut result = to_influx(device, influx_measurement, influx_tag, device_info, now);
+            if let Some(cells) = cells {
+                let mut measurement = device_measurement(device, influx_measurement, influx_tag);
+                measurement.timestamp(device_info.timestamp);
+                for (key, value) in cells.fields() {
+                    let name = format!("cells_{key}");
+                    match value.parse::<i64>() {
+                        Ok(value) => measurement.field(&name, value),
+                        Err(_) => measurement.field(&name, value),
+                    };
+                }
+                measurement.write_to(&mut result);
+            }
+            if let Some(energy) = energy {
+                // Same series as the power of the channel.
+                for (tag, channel, energy) in energy_channels(energy) {
+                    device_measurement(device, influx_measurement, influx_tag)
+                        .timestamp(device_info.timestamp)
+                        .tag(tag, channel)
+                        .field(&format!("{tag}_energy"), energy)
+                        .write_to(&mut result);
+                }
+            }
+            result
+        }
+        QueryFormat::Table | QueryFormat::Diff => {
+            let mut result = to_table(locale, device_info);
+            if let Some(energy) = energy {
+                let rows = energy_rows(locale, energy)
+                    .into_iter()
+                    .map(|(key, value)| (locale.label(key).to_owned(), value))
+                    .collect::<Vec<_>>();
+                let _ = write!(result, "\n{}", format_rows(&rows));
+            }
+            if let Some(cells) = cells {
+                let width = cells
+                    .fields()
+                    .map(|(key, _)| key.chars().count())
+                    .max()
+                    .unwrap_or_default();
+                let _ = writeln!(result, "\n{}", locale.label("cells"));
+                for (key, value) in cells.fields() {
+                    let _ = writeln!(result, "{key:<width$}  {value}");
+                }
+            }
+            result
+        }
+        QueryFormat::Graphite {
+            graphite_prefix, ..
+        } => {
+            let mut result = to_graphite(device, graphite_prefix, device_info);
+            let mut metrics = hmtk::graphite::Metrics::new(graphite_prefix);
+            metrics
+                .segment(&device.mac)
+                .timestamp(device_info.timestamp);
+            if let Some(cells) = cells {
+                for (key, value) in cells.fields() {
+                    // Graphite only supports numeric values.
+                    if let Ok(value) = value.parse::<i64>() {
+                        metrics.metric(&format!("cells.{key}"), value);
+                    }
+                }
+            }
+            if let Some(energy) = energy {
+                for (tag, channel, energy) in energy_channels(energy) {
+                    metrics.metric(&format!("accumulated.{tag}{channel}"), energy);
+                }
+            }
+            result.push_str(&metrics.finish());
+            result
+        }
+    })
+}
+
+/// Returns the kind, the number and the energy of every channel.
+fn energy_channels(energy: &ChannelEnergy) -> [(&'static str, &'static str, f64); 4] {
+    [
+        ("solar", "1", energy.solar1),
+        ("solar", "2", energy.solar2),
+        ("output", "1", energy.output1),
+        ("output", "2", energy.output2),
+    ]
+}
+
+/// Rows of the accumulated energy, keyed by the label key.
+fn energy_rows(locale: &Locale, energy: &ChannelEnergy) -> Vec<(&'static str, String)> {
+    let wh = |value: f64| format!("{} Wh", locale.number(value, 0));
+    vec![
+        ("accumulated.solar1", wh(energy.solar1)),
+        ("accumulated.solar2", wh(energy.solar2)),
+        ("accumulated.output1", wh(energy.output1)),
+        ("accumulated.output2", wh(energy.output2)),
+    ]
+}
+
+/// Formats a reading of a device type without built-in support.
+pub fn format_reading(
+    device: &DeviceOptions,
+    locale: &Locale,
+    format: &QueryFormat,
+    reading: &Reading,
+) -> Result<String> {
+    let timestamp = reading
+        .timestamp
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or(Duration::ZERO)
+        .as_secs();
+
+    Ok(match format {
+        QueryFormat::Json | QueryFormat::Ndjson => {
+            let mut object = serde_json::Map::new();
+            object.insert("timestamp".to_owned(), timestamp.into());
+            for field in &reading.fields {
+                object.insert(field.name.clone(), serde_json::to_value(&field.value)?);
+            }
+            match format {
+                QueryFormat::Json => serde_json::to_string_pretty(&object)?,
+                _ => serde_json::to_string(&object)?,
+            }
+        }
+        QueryFormat::Influx {
+            influx_measurement,
+            influx_tag,
+            ..
+        } => {
+            let mut measurement = device_measurement(device, influx_measurement, influx_tag);
+            measurement.timestamp(reading.timestamp);
+            for field in &reading.fields {
+                let name = field.name.replace('.', "_");
+                match &field.value {
+                    Value::Integer(value) => measurement.field(&name, *value),
+                    Value::Float(value) => measurement.field(&name, *value),
+                    Value::Bool(value) => measurement.field(&name, *value),
+                    Value::String(value) => measurement.field(&name, value.as_str()),
+                };
+            }
+            let mut result = String::new();
+            measurement.write_to(&mut result);
+            result
+        }
+        QueryFormat::Table | QueryFormat::Diff => {
+            let mut rows = vec![(locale.label("timestamp").to_owned(), timestamp.to_string())];
+            rows.extend(reading_rows(locale, reading));
+            format_rows(&rows)
+        }
+        QueryFormat::Graphite {
+            graphite_prefix, ..
+        } => {
+            let mut metrics = hmtk::graphite::Metrics::new(graphite_prefix);
+            metrics.segment(&device.mac).timestamp(reading.timestamp);
+            for field in &reading.fields {
+                // Graphite only supports numeric values.
+                if let Some(value) = field.value.as_f64() {
+                    metrics.metric(&field.name, value);
+                }
+            }
+            metrics.finish()
+        }
+    })
+}
+
+/// Formats samples as the fields, which changed since the previous sample.
+#[derive(Debug, Default)]
+pub struct Diff {
+    previous: Option<Vec<(String, String)>>,
+}
+
+impl Diff {
+    /// Returns the changed fields of the device info, `None` if nothing changed.
+    ///
+    /// The first sample is formatted as a table of all fields.
+    pub fn device_info(
+        &mut self,
+        locale: &Locale,
+        device_info: &DeviceInfo,
+        energy: Option<&ChannelEnergy>,
+    ) -> Option<String> {
+        let rows = device_info_rows(locale, device_info)
+            .into_iter()
+            .chain(
+                energy
+                    .map(|energy| energy_rows(locale, energy))
+                    .unwrap_or_default(),
+            )
+            .filter(|(key, _)| *key != "timestamp")
+            .map(|(key, value)| (locale.label(key).to_owned(), value))
+            .collect();
+        self.diff(locale, device_info.timestamp, rows)
+    }
+
+    /// Returns the changed fields of a reading, `None` if nothing changed.
+    pub fn reading(&mut self, locale: &Locale, reading: &Reading) -> Option<String> {
+        self.diff(locale, reading.timestamp, reading_rows(locale, reading))
+    }
+
+    fn diff(
+        &mut self,
+        locale: &Locale,
+        timestamp: SystemTime,
+        rows: Vec<(String, String)>,
+    ) -> Option<String> {
+        let timestamp = timestamp
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or(Duration::ZERO)
+            .as_secs();
+        let mut changed = vec![(locale.label("timestamp").to_owned(), timestamp.to_string())];
+
+        let Some(previous) = self.previous.replace(rows.clone()) else {
+            changed.extend(rows);
+            return Some(format_rows(&changed));
+        };
+        for (label, value) in &rows {
+            match previous.iter().find(|(previous, _)| previous == label) {
+                Some((_, old)) if old == value => {}
+                Some((_, old)) => changed.push((label.clone(), format!("{old} → {value}"))),
+                None => changed.push((label.clone(), format!("- → {value}"))),
+            }
+        }
+        for (label, old) in &previous {
+            if !rows.iter().any(|(current, _)| current == label) {
+                changed.push((label.clone(), format!("{old} → -")));
+            }
+        }
+
+        (changed.len() > 1).then(|| format_rows(&changed))
+    }
+}
+
+/// Formats the health metrics of hmtk itself, only supported by the Influx and Graphite formats.
+pub fn format_health(
+    device: &Device,
+    format: &QueryFormat,
+    timestamp: SystemTime,
+) -> Option<String> {
+    let latency = device
+        .latency()
+        .map(|latency| latency.as_secs_f64() * 1000.0);
+    match format {
+        QueryFormat::Influx {
+            influx_measurement,
+            influx_tag,
+            ..
+        } => {
+            let name = format!("{influx_measurement}_health");
+            let mut measurement = hmtk::influx::Measurement::new(&name);
+            measurement.tag("device_mac", &device.options().mac);
+            for (key, value) in influx_tag {
+                measurement.tag(key, value);
+            }
+            measurement
+                .field("reconnects", device.reconnects())
+                .field("rate_limits", device.rate_limits())
+                .field("publish_failures", device.publish_failures())
+                .field("parse_errors", device.parse_errors())
+                .timestamp(timestamp);
+            if let Some(latency) = latency {
+                measurement.field("latency_ms", latency);
+            }
+            let mut result = String::new();
+            measurement.write_to(&mut result);
+            Some(result)
+        }
+        QueryFormat::Graphite {
+            graphite_prefix, ..
+        } => {
+            let mut metrics = hmtk::graphite::Metrics::new(graphite_prefix);
+            metrics
+                .segment(&device.options().mac)
+                .timestamp(timestamp)
+                .metric("health.reconnects", device.reconnects())
+                .metric("health.rate_limits", device.rate_limits())
+                .metric("health.publish_failures", device.publish_failures())
+                .metric("health.parse_errors", device.parse_errors());
+            if let Some(latency) = latency {
+                metrics.metric("health.latency_ms", latency);
+            }
+            Some(metrics.finish())
+        }
+        QueryFormat::Json | QueryFormat::Ndjson | QueryFormat::Table | QueryFormat::Diff => None,
+    }
+}
+
+/// Formats the health metrics of the sinks, only supported by the Influx and Graphite formats.
+pub fn format_sink_stats(
+    device: &DeviceOptions,
+    format: &QueryFormat,
+    timestamp: SystemTime,
+    sinks: &[SinkStats],
+) -> Option<String> {
+    match format {
+        QueryFormat::Influx {
+            influx_measurement,
+            influx_tag,
+            ..
+        } => {
+            let name = format!("{influx_measurement}_sink");
+            let mut result = String::new();
+            for sink in sinks {
+                let mut measurement = hmtk::influx::Measurement::new(&name);
+                measurement
+                    .tag("device_mac", &device.mac)
+                    .tag("sink", &sink.name);
+                for (key, value) in influx_tag {
+                    measurement.tag(key, value);
+                }
+                measurement
+                    .field("queue_depth", sink.queue_depth as u64)
+                    .field("written", sink.written)
+                    .field("dropped", sink.dropped)
+                    .field("failed", sink.failed)
+                    .timestamp(timestamp);
+                if let Some(latency) = sink.last_latency_ms {
+                    measurement.field("latency_ms", latency);
+                }
+                measurement.write_to(&mut result);
+            }
+            Some(result)
+        }
+        QueryFormat::Graphite {
+            graphite_prefix, ..
+        } => {
+            let mut metrics = hmtk::graphite::Metrics::new(graphite_prefix);
+            metrics.segment(&device.mac).timestamp(timestamp);
+            for sink in sinks {
+                let path = |name| format!("sinks.{}.{name}", sink.name);
+                metrics
+                    .metric(&path("queue_depth"), sink.queue_depth as u64)
+                    .metric(&path("written"), sink.written)
+                    .metric(&path("dropped"), sink.dropped)
+                    .metric(&path("failed"), sink.failed);
+                if let Some(latency) = sink.last_latency_ms {
+                    metrics.metric(&path("latency_ms"), latency);
+                }
+            }
+            Some(metrics.finish())
+        }
+        QueryFormat::Json | QueryFormat::Ndjson | QueryFormat::Table | QueryFormat::Diff => None,
+    }
+}
+
+fn to_graphite(device: &DeviceOptions, prefix: &str, device_info: &DeviceInfo) -> String {
+    let mut metrics = hmtk::graphite::Metrics::new(prefix);
+    metrics
+        .segment(&device.mac)
+        .timestamp(device_info.timestamp);
+
+    for (i, solar) in [device_info.solar1, device_info.solar2].iter().enumerate() {
+        let solar_path = |name| format!("solar{}.{name}", i + 1);
+        metrics
+            .metric(&solar_path("charging"), solar.charging)
+            .metric(&solar_path("pass_through"), solar.pass_through)
+            .metric(&solar_path("power"), solar.power.0);
+    }
+
+    for (i, output) in [device_info.output1, device_info.output2]
+        .iter()
+        .enumerate()
+    {
+        let output_path = |name| format!("output{}.{name}", i + 1);
+        metrics
+            .metric(&output_path("active"), output.active)
+            .metric(&output_path("power"), output.power.0);
+    }
+
+    let battery = &device_info.battery;
+    metrics
+        // Same encoding as used by the device.
+        .metric(
+            "scene",
+            match device_info.scene {
+                hmtk::mqtt::Scene::Day => 0,
+                hmtk::mqtt::Scene::Night => 1,
+                hmtk::mqtt::Scene::Dusk => 2,
+            },
+        )
+        .metric("temperature.min", device_info.temperature.min.0)
+        .metric("temperature.max", device_info.temperature.max.0)
+        .metric("battery.charge", battery.charge.0)
+        .metric("battery.capacity", battery.capacity.0)
+        .metric("battery.output_threshold", battery.output_threshold.0)
+        .metric("battery.discharge_depth", battery.discharge_depth.0)
+        .metric("battery.internal.charging", battery.internal.charging)
+        .metric("battery.internal.discharging", battery.internal.discharging)
+        .metric(
+            "battery.internal.discharge_depth",
+            battery.internal.discharge_depth,
+        )
+        .metric(
+            "battery.internal.undervoltage",
+            battery.internal.undervoltage,
+        );
+
+    if let Some(grid) = device_info.grid {
+        metrics
+            .metric("grid.voltage", grid.voltage.0)
+            .metric("grid.frequency", grid.frequency.0);
+    }
+    if let Some(limits) = device_info.limits {
+        metrics
+            .metric("limits.charge", limits.charge.0)
+            .metric("limits.output", limits.output.0);
+    }
+
+    let derived = device_info.derived();
+    metrics
+        .metric("derived.solar_power", derived.solar_power.0)
+        .metric("derived.output_power", derived.output_power.0)
+        .metric("derived.net_power", derived.net_power);
+
+    metrics.finish()
+}
+
+fn to_table(locale: &Locale, device_info: &DeviceInfo) -> String {
+    let rows = device_info_rows(locale, device_info)
+        .into_iter()
+        .map(|(key, value)| (locale.label(key).to_owned(), value))
+        .collect::<Vec<_>>();
+    format_rows(&rows)
+}
+
+/// Rows of the human readable table of a device info, keyed by the label key.
+fn device_info_rows(locale: &Locale, device_info: &DeviceInfo) -> Vec<(&'static str, String)> {
+    // Precision of the metric, the integer units have none.
+    let number = |value: f64, precision: usize, unit: &str| {
+        format!("{} {unit}", locale.number(value, precision))
+    };
+
+    let timestamp = device_info
+        .timestamp
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or(Duration::ZERO)
+        .as_secs();
+
+    let battery = &device_info.battery;
+    let derived = device_info.derived();
+    let mut rows = vec![
+        ("timestamp", timestamp.to_string()),
+        (
+            "solar1.power",
+            number(device_info.solar1.power.0.into(), 0, "W"),
+        ),
+        (
+            "solar1.charging",
+            locale.bool(device_info.solar1.charging).to_owned(),
+        ),
+        (
+            "solar1.pass_through",
+            locale.bool(device_info.solar1.pass_through).to_owned(),
+        ),
+        (
+            "solar2.power",
+            number(device_info.solar2.power.0.into(), 0, "W"),
+        ),
+        (
+            "solar2.charging",
+            locale.bool(device_info.solar2.charging).to_owned(),
+        ),
+        (
+            "solar2.pass_through",
+            locale.bool(device_info.solar2.pass_through).to_owned(),
+        ),
+        (
+            "output1.power",
+            number(device_info.output1.power.0.into(), 0, "W"),
+        ),
+        (
+            "output1.active",
+            locale.bool(device_info.output1.active).to_owned(),
+        ),
+        (
+            "output2.power",
+            number(device_info.output2.power.0.into(), 0, "W"),
+        ),
+        (
+            "output2.active",
+            locale.bool(device_info.output2.active).to_owned(),
+        ),
+        (
+            "temperature.min",
+            number(device_info.temperature.min.0.into(), 0, "°C"),
+        ),
+        (
+            "temperature.max",
+            number(device_info.temperature.max.0.into(), 0, "°C"),
+        ),
+        ("battery.charge", number(battery.charge.0.into(), 0, "%")),
+        (
+            "battery.capacity",
+            number(battery.capacity.0.into(), 0, "Wh"),
+        ),
+        (
+            "battery.output_threshold",
+            number(battery.output_threshold.0.into(), 0, "W"),
+        ),
+        (
+            "battery.discharge_depth",
+            number(battery.discharge_depth.0.into(), 0, "%"),
+        ),
+        (
+            "battery.internal.charging",
+            locale.bool(battery.internal.charging).to_owned(),
+        ),
+        (
+            "battery.internal.discharging",
+            locale.bool(battery.internal.discharging).to_owned(),
+        ),
+        (
+            "battery.internal.discharge_depth",
+            locale.bool(battery.internal.discharge_depth).to_owned(),
+        ),
+        (
+            "battery.internal.undervoltage",
+            locale.bool(battery.internal.undervoltage).to_owned(),
+        ),
+        ("scene", locale.label(device_info.scene.as_str()).to_owned()),
+        (
+            "derived.solar_power",
+            number(derived.solar_power.0.into(), 0, "W"),
+        ),
+        (
+            "derived.output_power",
+            number(derived.output_power.0.into(), 0, "W"),
+        ),
+        (
+            "derived.net_power",
+            number(derived.net_power as f64, 0, "W"),
+        ),
+    ];
+    if let Some(grid) = device_info.grid {
+        rows.push(("grid.voltage", number(grid.voltage.0.into(), 1, "V")));
+        rows.push(("grid.frequency", number(grid.frequency.0.into(), 2, "Hz")));
+    }
+    if let Some(limits) = device_info.limits {
+        rows.push(("limits.charge", number(limits.charge.0.into(), 0, "W")));
+        rows.push(("limits.output", number(limits.output.0.into(), 0, "W")));
+    }
+
+    rows
+}
+
+/// Rows of the human readable table of a reading, without the timestamp.
+fn reading_rows(locale: &Locale, reading: &Reading) -> Vec<(String, String)> {
+    reading
+        .fields
+        .iter()
+        .map(|field| {
+            let value = match &field.value {
+                Value::Integer(value) => locale.number(*value as f64, 0),
+                Value::Float(value) => locale.number(*value, 2),
+                Value::Bool(value) => locale.bool(*value).to_owned(),
+                Value::String(value) => value.clone(),
+            };
+            let value = match &field.unit {
+                Some(unit) => format!("{value} {unit}"),
+                None => value,
+            };
+            (locale.label(&field.name).to_owned(), value)
+        })
+        .collect()
+}
+
+/// Formats labeled rows as a table with aligned values.
+fn format_rows(rows: &[(String, String)]) -> String {
+    let width = rows
+        .iter()
+        .map(|(label, _)| label.chars().count())
+        .max()
+        .unwrap_or_default();
+
+    let mut result = String::new();
+    for (label, value) in rows {
+        let _ = writeln!(result, "{label:<width$}  {value}");
+    }
+    result
+}
+
+/// Creates a measurement named `name` with the device and the additional `tags`.
+fn device_measurement<'a>(
+    device: &DeviceOptions,
+    name: &'a str,
+    tags: &[(String, String)],
+) -> hmtk::influx::Measurement<'a> {
+    let mut measurement = hmtk::influx::Measurement::new(name);
+    measurement
+        .tag("device_type", &device.ty)
+        .tag("device_mac", &device.mac);
+    for (key, value) in tags {
+        measurement.tag(key, value);
+    }
+    measurement
+}
+
+/// JSON representation of a device info, the fields keep the order of [`DeviceInfo`] and are
+/// followed by the `age` and the values computed by hmtk.
+#[derive(Debug, Serialize)]
+pub struct JsonDeviceInfo<'a> {
+    #[serde(flatten)]
+    device_info: &'a DeviceInfo,
+    /// Age of the data in seconds.
+    age: u64,
+    derived: DerivedInfo,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cells: Option<serde_json::Map<String, serde_json::Value>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    accumulated: Option<&'a ChannelEnergy>,
+}
+
+impl<'a> JsonDeviceInfo<'a> {
+    /// Creates the representation of `device_info` with its age at `now`.
+    pub fn new(device_info: &'a DeviceInfo, now: SystemTime) -> Self {
+        Self {
+            device_info,
+            age: device_info.age_at(now).as_secs(),
+            derived: device_info.derived(),
+            cells: None,
+            accumulated: None,
+        }
+    }
+}
+
+fn to_influx(
+    device: &DeviceOptions,
+    name: &str,
+    tags: &[(String, String)],
+    device_info: &DeviceInfo,
+    now: SystemTime,
+) -> String {
+    let mut result = String::new();
+
+    macro_rules! measurement {
+        () => {
+            device_measurement(device, name, tags).timestamp(device_info.timestamp)
+        };
+    }
+
+    for (i, solar) in [device_info.solar1, device_info.solar2].iter().enumerate() {
+        measurement!()
+            .tag("solar", &(i + 1).to_string())
+            .field("solar_charging", solar.charging)
+            .field("solar_pass_through", solar.pass_through)
+            .field("solar_power", solar.power.0)
+            .write_to(&mut result);
+    }
+
+    for (i, output) in [device_info.output1, device_info.output2]
+        .iter()
+        .enumerate()
+    {
+        measurement!()
+            .tag("output", &(i + 1).to_string())
+            .field("output_active", output.active)
+            .field("output_power", output.power.0)
+            .write_to(&mut result);
+    }
+
+    let derived = device_info.derived();
+    measurement!()
+        .field("age", device_info.age_at(now).as_secs())
+        .field("scene", device_info.scene.as_str())
+        .field("temperature_min", device_info.temperature.min.0)
+        .field("temperature_max", device_info.temperature.max.0)
+        .field("battery_charge", device_info.battery.charge.0)
+        .field("battery_capacity", device_info.battery.capacity.0)
+        .field(
+            "battery_output_threshold",
+            device_info.battery.output_threshold.0,
+        )
+        .field(
+            "battery_discharge_depth",
+            device_info.battery.discharge_depth.0,
+        )
+        .field("derived_solar_power", derived.solar_power.0)
+        .field("derived_output_power", derived.output_power.0)
+        .field("derived_net_power", derived.net_power)
+        .write_to(&mut result);
+
+    measurement!()
+        .tag("battery_cell", "internal")
+        .field(
+            "battery_cell_charging",
+            device_info.battery.internal.charging,
+        )
+        .field(
+            "battery_cell_discharging",
+            device_info.battery.internal.discharging,
+        )
+        .field(
+            "battery_cell_discharge_depth",
+            device_info.battery.internal.discharge_depth,
+        )
+        .field(
+            "battery_cell_undervoltage",
+            device_info.battery.internal.undervoltage,
+        )
+        .write_to(&mut result);
+
+    if let Some(grid) = device_info.grid {
+        measurement!()
+            .field("grid_voltage", grid.voltage.0)
+            .field("grid_frequency", grid.frequency.0)
+            .write_to(&mut result);
+    }
+
+    if let Some(limits) = device_info.limits {
+        measurement!()
+            .field("limits_charge", limits.charge.0)
+            .field("limits_output", limits.output.0)
+            .write_to(&mut result);
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn device_info() -> DeviceInfo {
+        serde_json::from_value(serde_json::json!({
+            "timestamp": 1700000000,
+            "solar1": { "charging": true, "pass_through": false, "power": 120 },
+            "solar2": { "charging": true, "pass_through": false, "power": 80 },
+            "output1": { "active": true, "power": 150 },
+            "output2": { "active": false, "power": 0 },
+            "temperature": { "min": 21, "max": 24 },
+            "battery": {
+                "charge": 64,
+                "capacity": 1440,
+                "output_threshold": 200,
+                "discharge_depth": 80,
+                "internal": {
+                    "charging": true,
+                    "discharging": false,
+                    "discharge_depth": false,
+                    "undervoltage": false
+                }
+            },
+            "scene": "day",
+            "grid": { "voltage": 229.5, "frequency": 49.98 }
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_json() {
+        let device = DeviceOptions {
+            ty: "HMA-1".to_owned(),
+            mac: "abc".to_owned(),
+            protocol: Default::default(),
+            dry_run: false,
+            min_interval: Duration::ZERO,
+            retry: Default::default(),
+        };
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1700000030);
+        let json = format_device_info(
+            &device,
+            &Locale::default(),
+            &QueryFormat::Json,
+            &device_info(),
+            None,
+            None,
+            now,
+        )
+        .unwrap();
+
+        // The fields keep the order of the device info, `age` and `derived` are appended.
+        insta::assert_snapshot!(json, @r#"
+        {
+          "timestamp": 1700000000,
+          "solar1": {
+            "charging": true,
+            "pass_through": false,
+            "power": 120
+          },
+          "solar2": {
+            "charging": true,
+            "pass_through": false,
+            "power": 80
+          },
+          "output1": {
+            "power": 150,
+            "active": true
+          },
+          "output2": {
+            "power": 0,
+            "active": false
+          },
+          "temperature": {
+            "min": 21,
+            "max": 24
+          },
+          "battery": {
+            "charge": 64,
+            "capacity": 1440,
+            "output_threshold": 200,
+            "discharge_depth": 80,
+            "internal": {
+              "charging": true,
+              "discharging": false,
+              "discharge_depth": false,
+              "undervoltage": false
+            }
+          },
+          "scene": "day",
+          "grid": {
+            "voltage": 229.5,
+            "frequency": 49.98
+          },
+          "age": 30,
+          "derived": {
+            "solar_power": 200,
+            "output_power": 150,
+            "net_power": 50
+          }
+        }
+        "#);
+    }
+
+    #[test]
+    fn test_table_decimal_separator() {
+        let locale = Locale::default()
+            .with_bundle("decimal_separator = \",\"")
+            .unwrap();
+        let rows = device_info_rows(&locale, &device_info());
+        let row = |key| {
+            rows.iter()
+                .find(|(k, _)| *k == key)
+                .map(|(_, v)| v.as_str())
+        };
+
+        assert_eq!(row("grid.voltage"), Some("229,5 V"));
+        assert_eq!(row("grid.frequency"), Some("49,98 Hz"));
+        assert_eq!(row("battery.charge"), Some("64 %"));
+    }
+}
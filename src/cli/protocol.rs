@@ -0,0 +1,159 @@
+//! Describes the device protocol, generated from the registries of the implementation.
+use std::fmt::Write as _;
+
+use color_eyre::eyre::Result;
+use hmtk::mqtt::{Command, DeviceInfo, DeviceModel, FieldDoc, Protocol};
+use hmtk::parser::Registry;
+use serde_json::json;
+
+/// Renders the known topics, commands, status fields, model families and metrics as JSON or,
+/// with `markdown`, as Markdown.
+///
+/// Device types declared in the `registry` are included with their protocol.
+pub fn dump(registry: &Registry, markdown: bool) -> Result<String> {
+    let default = Protocol::default();
+    let device_types = registry.protocols().collect::<Vec<_>>();
+
+    if !markdown {
+        let device_types = device_types
+            .iter()
+            .map(|(ty, protocol)| {
+                json!({
+                    "type": ty,
+                    "data_topic": protocol.data_topic,
+                    "control_topic": protocol.control_topic,
+                    "poll_command": protocol.poll_command,
+                    "transform": protocol.transform.to_string(),
+                    "built_in": registry.get(ty).is_none(),
+                })
+            })
+            .collect::<Vec<_>>();
+        let models = DeviceModel::ALL
+            .iter()
+            .map(|&model| {
+                let protocol = model.protocol();
+                json!({
+                    "name": model.name(),
+                    "prefixes": model.prefixes(),
+                    "ac_coupled": model.ac_coupled(),
+                    "data_topic": protocol.data_topic,
+                    "control_topic": protocol.control_topic,
+                    "fields": model.fields().map(|field| field.key).collect::<Vec<_>>(),
+                })
+            })
+            .collect::<Vec<_>>();
+        let value = json!({
+            "topics": {
+                "data": default.data_topic,
+                "control": default.control_topic,
+            },
+            "commands": Command::ALL,
+            "fields": FieldDoc::status().collect::<Vec<_>>(),
+            "models": models,
+            "metrics": DeviceInfo::METRICS,
+            "device_types": device_types,
+        });
+        return Ok(serde_json::to_string_pretty(&value)?);
+    }
+
+    let mut result = String::new();
+    writeln!(result, "# Hame Energy Protocol\n")?;
+    writeln!(
+        result,
+        "Generated by `hmtk protocol dump --markdown`. `{{type}}` and `{{mac}}` are replaced \
+         with the device type and MAC.\n"
+    )?;
+
+    writeln!(result, "## Topics\n")?;
+    writeln!(result, "| Direction | Topic |\n| --- | --- |")?;
+    writeln!(result, "| Device to client | `{}` |", default.data_topic)?;
+    writeln!(
+        result,
+        "| Client to device | `{}` |\n",
+        default.control_topic
+    )?;
+
+    writeln!(result, "## Commands\n")?;
+    writeln!(
+        result,
+        "| Code | Name | Arguments | Description |\n| --- | --- | --- | --- |"
+    )?;
+    for command in Command::ALL {
+        let arguments = command
+            .arguments
+            .iter()
+            .map(|argument| format!("`{argument}`"))
+            .collect::<Vec<_>>()
+            .join(", ");
+        writeln!(
+            result,
+            "| `{}` | {} | {arguments} | {} |",
+            command.payload(),
+            command.name,
+            command.description
+        )?;
+    }
+
+    writeln!(result, "\n## Status Fields\n")?;
+    writeln!(
+        result,
+        "| Key | Unit | Optional | Description |\n| --- | --- | --- | --- |"
+    )?;
+    for field in FieldDoc::status() {
+        let optional = if field.optional { "yes" } else { "no" };
+        writeln!(
+            result,
+            "| `{}` | {} | {optional} | {} |",
+            field.key,
+            field.unit.unwrap_or(""),
+            field.description
+        )?;
+    }
+
+    writeln!(result, "\n## Models\n")?;
+    writeln!(
+        result,
+        "| Model | Type Prefixes | AC Coupled | Fields |\n| --- | --- | --- | --- |"
+    )?;
+    for &model in DeviceModel::ALL {
+        let code = |values: &mut dyn Iterator<Item = &str>| {
+            values
+                .map(|value| format!("`{value}`"))
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        let ac_coupled = if model.ac_coupled() { "yes" } else { "no" };
+        writeln!(
+            result,
+            "| {} | {} | {ac_coupled} | {} |",
+            model.name(),
+            code(&mut model.prefixes().iter().copied()),
+            code(&mut model.fields().map(|field| field.key)),
+        )?;
+    }
+
+    writeln!(result, "\n## Metrics\n")?;
+    for metric in DeviceInfo::METRICS {
+        writeln!(result, "- `{metric}`")?;
+    }
+
+    if !device_types.is_empty() {
+        writeln!(result, "\n## Device Types\n")?;
+        writeln!(
+            result,
+            "| Type | Data Topic | Control Topic | Poll Command | Transform |\n| --- | --- | --- | --- | --- |"
+        )?;
+        for (ty, protocol) in device_types {
+            writeln!(
+                result,
+                "| `{ty}` | `{}` | `{}` | `{}` | `{}` |",
+                protocol.data_topic,
+                protocol.control_topic,
+                protocol.poll_command,
+                protocol.transform
+            )?;
+        }
+    }
+
+    Ok(result)
+}
@@ -0,0 +1,230 @@
+//! Connections to brokers through a SOCKS5 or HTTP proxy, e.g. on a jump host.
+use std::str::FromStr;
+
+use color_eyre::eyre::{Result, bail, eyre};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::JoinHandle;
+
+/// A proxy given as `socks5://[user:password@]host:port` or `http://[user:password@]host:port`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Proxy {
+    kind: Kind,
+    host: String,
+    port: u16,
+    credentials: Option<(String, String)>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Socks5,
+    /// An HTTP proxy, which supports `CONNECT`.
+    Http,
+}
+
+impl FromStr for Proxy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid =
+            || format!("invalid proxy `{s}`, expected `socks5://host:port` or `http://host:port`");
+
+        let (kind, rest) = match s.split_once("://") {
+            Some(("socks5", rest)) => (Kind::Socks5, rest),
+            Some(("http", rest)) => (Kind::Http, rest),
+            _ => return Err(invalid()),
+        };
+        let rest = rest.trim_end_matches('/');
+        let (credentials, address) = match rest.rsplit_once('@') {
+            Some((credentials, address)) => {
+                let (user, password) = credentials.split_once(':').ok_or_else(invalid)?;
+                (Some((user.to_owned(), password.to_owned())), address)
+            }
+            None => (None, rest),
+        };
+        let (host, port) = address.rsplit_once(':').ok_or_else(invalid)?;
+        if host.is_empty() {
+            return Err(invalid());
+        }
+
+        Ok(Self {
+            kind,
+            host: host.to_owned(),
+            port: port.parse().map_err(|_| invalid())?,
+            credentials,
+        })
+    }
+}
+
+/// A local port, which forwards every connection through a [`Proxy`] to the broker.
+///
+/// Connections are no longer accepted once the forward is dropped.
+#[derive(Debug)]
+pub struct Forward {
+    /// Local port forwarded to the broker.
+    pub port: u16,
+    task: JoinHandle<()>,
+}
+
+impl Forward {
+    /// Forwards a local port to `host:port`, as seen from the `proxy`.
+    pub async fn open(proxy: Proxy, host: &str, port: u16) -> Result<Self> {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).await?;
+        let local_port = listener.local_addr()?.port();
+
+        tracing::info!(
+            "Connecting to {host}:{port} via proxy {}:{}",
+            proxy.host,
+            proxy.port
+        );
+        let host = host.to_owned();
+        let task = tokio::spawn(async move {
+            loop {
+                let mut client = match listener.accept().await {
+                    Ok((client, _)) => client,
+                    Err(err) => {
+                        tracing::warn!("failed to accept connection: {err}");
+                        continue;
+                    }
+                };
+                let proxy = proxy.clone();
+                let host = host.clone();
+                tokio::spawn(async move {
+                    match proxy.connect(&host, port).await {
+                        Ok(mut server) => {
+                            let _ = tokio::io::copy_bidirectional(&mut client, &mut server).await;
+                        }
+                        // The MQTT client reconnects, the error is logged with each attempt.
+                        Err(err) => tracing::warn!("failed to connect through the proxy: {err}"),
+                    }
+                });
+            }
+        });
+
+        Ok(Self {
+            port: local_port,
+            task,
+        })
+    }
+}
+
+impl Drop for Forward {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+impl Proxy {
+    /// Opens a connection to `host:port` through the proxy.
+    async fn connect(&self, host: &str, port: u16) -> Result<TcpStream> {
+        let mut stream = TcpStream::connect((self.host.as_str(), self.port)).await?;
+        match self.kind {
+            Kind::Socks5 => self.socks5(&mut stream, host, port).await?,
+            Kind::Http => self.http(&mut stream, host, port).await?,
+        }
+        Ok(stream)
+    }
+
+    /// Performs the SOCKS5 handshake of RFC 1928, with username and password authentication of
+    /// RFC 1929 if credentials are configured.
+    async fn socks5(&self, stream: &mut TcpStream, host: &str, port: u16) -> Result<()> {
+        let method = match self.credentials {
+            Some(_) => 0x02,
+            None => 0x00,
+        };
+        stream.write_all(&[0x05, 0x01, method]).await?;
+        let mut response = [0; 2];
+        stream.read_exact(&mut response).await?;
+        if response != [0x05, method] {
+            bail!("proxy does not accept the authentication method");
+        }
+
+        if let Some((user, password)) = &self.credentials {
+            let mut request = vec![0x01];
+            for value in [user, password] {
+                let length =
+                    u8::try_from(value.len()).map_err(|_| eyre!("proxy credentials too long"))?;
+                request.push(length);
+                request.extend_from_slice(value.as_bytes());
+            }
+            stream.write_all(&request).await?;
+            stream.read_exact(&mut response).await?;
+            if response[1] != 0x00 {
+                bail!("proxy rejected the credentials");
+            }
+        }
+
+        let length = u8::try_from(host.len()).map_err(|_| eyre!("host name too long"))?;
+        let mut request = vec![0x05, 0x01, 0x00, 0x03, length];
+        request.extend_from_slice(host.as_bytes());
+        request.extend_from_slice(&port.to_be_bytes());
+        stream.write_all(&request).await?;
+
+        let mut reply = [0; 4];
+        stream.read_exact(&mut reply).await?;
+        if reply[1] != 0x00 {
+            bail!("proxy failed to connect with reply code {}", reply[1]);
+        }
+        // Skips the bound address and port, which are of no use to the client.
+        let address = match reply[3] {
+            0x01 => 4,
+            0x04 => 16,
+            0x03 => usize::from(stream.read_u8().await?),
+            ty => bail!("proxy replied with unknown address type {ty}"),
+        };
+        stream.read_exact(&mut vec![0; address + 2]).await?;
+        Ok(())
+    }
+
+    /// Opens a tunnel with an HTTP `CONNECT` request.
+    async fn http(&self, stream: &mut TcpStream, host: &str, port: u16) -> Result<()> {
+        let mut request = format!("CONNECT {host}:{port} HTTP/1.1\r\nHost: {host}:{port}\r\n");
+        if let Some((user, password)) = &self.credentials {
+            let credentials = base64(format!("{user}:{password}").as_bytes());
+            request.push_str(&format!("Proxy-Authorization: Basic {credentials}\r\n"));
+        }
+        request.push_str("\r\n");
+        stream.write_all(request.as_bytes()).await?;
+
+        // Reads the response byte by byte, the tunneled data must stay in the stream.
+        let mut reader = BufReader::with_capacity(1, stream);
+        let mut status = String::new();
+        reader.read_line(&mut status).await?;
+        match status.split_whitespace().nth(1) {
+            Some(code) if code.starts_with('2') => {}
+            _ => bail!("proxy refused to connect: {}", status.trim()),
+        }
+        loop {
+            let mut line = String::new();
+            if reader.read_line(&mut line).await? == 0 {
+                bail!("proxy closed the connection");
+            }
+            if line == "\r\n" || line == "\n" {
+                return Ok(());
+            }
+        }
+    }
+}
+
+/// Encodes `data` with the standard Base64 alphabet and padding.
+fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut result = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let bytes = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let value = u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]]);
+        for i in 0..4 {
+            if i <= chunk.len() {
+                result.push(ALPHABET[(value >> (18 - 6 * i) & 0x3f) as usize].into());
+            } else {
+                result.push('=');
+            }
+        }
+    }
+    result
+}
@@ -0,0 +1,80 @@
+use std::fmt::Write as _;
+use std::time::Duration;
+
+use color_eyre::eyre::Result;
+use hmtk::locale::Locale;
+use hmtk::stats::{Accumulator, Summary};
+use serde::Serialize;
+
+#[derive(Debug, Serialize)]
+struct Report {
+    solar1: Summary,
+    solar2: Summary,
+}
+
+/// Samples the solar input power every `interval` for `duration` and reports statistics per input.
+pub async fn pv_diag(
+    device: &mut hmtk::mqtt::Device,
+    locale: &Locale,
+    interval: Duration,
+    duration: Duration,
+    json: bool,
+) -> Result<()> {
+    let mut solar1 = Accumulator::default();
+    let mut solar2 = Accumulator::default();
+
+    let mut interval = tokio::time::interval(interval);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+    let sampling = async {
+        loop {
+            interval.tick().await;
+
+            let device_info = device.device_info().await?;
+            solar1.push(device_info.solar1.power.0.into());
+            solar2.push(device_info.solar2.power.0.into());
+            tracing::debug!("sampled solar input: {device_info:?}");
+        }
+    };
+    // Sampling only stops on an error or when the duration elapses.
+    if let Ok(result) = tokio::time::timeout(duration, sampling).await {
+        return result;
+    }
+
+    let report = Report {
+        solar1: solar1.summary(),
+        solar2: solar2.summary(),
+    };
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        print!("{}", to_table(locale, &report));
+    }
+
+    Ok(())
+}
+
+fn to_table(locale: &Locale, report: &Report) -> String {
+    let mut result = String::new();
+
+    let _ = writeln!(
+        result,
+        "{:<8} {:>7} {:>9} {:>9} {:>9} {:>9} {:>9}",
+        "input", "samples", "min", "avg", "max", "variance", "std dev"
+    );
+    for (name, summary) in [("solar1", &report.solar1), ("solar2", &report.solar2)] {
+        let _ = writeln!(
+            result,
+            "{name:<8} {:>7} {:>7} W {:>7} W {:>7} W {:>9} {:>7} W",
+            summary.count,
+            locale.number(summary.min, 0),
+            locale.number(summary.mean, 1),
+            locale.number(summary.max, 0),
+            locale.number(summary.variance, 1),
+            locale.number(summary.std_dev(), 1),
+        );
+    }
+
+    result
+}
@@ -0,0 +1,128 @@
+//! End to end check of a new installation.
+use std::time::Duration;
+
+use color_eyre::eyre::{Result, bail, eyre};
+use hmtk::locale::Locale;
+use hmtk::mqtt::Device;
+use hmtk::parser::Registry;
+
+use crate::QueryFormat;
+use crate::cli::output::{format_device_info, format_reading};
+use crate::cli::sink::{SinkKind, SinkOptions};
+
+/// Queries the device once, encodes the sample in every output format and writes it to `sinks`.
+///
+/// Prints the result of every step and returns `false` if any step failed.
+pub async fn selftest(
+    device: &mut Device,
+    locale: &Locale,
+    registry: &Registry,
+    sinks: &[SinkKind],
+    sink_options: &SinkOptions<'_>,
+    timeout: Duration,
+) -> bool {
+    let formats = [
+        ("json", QueryFormat::Json),
+        ("ndjson", QueryFormat::Ndjson),
+        (
+            "influx",
+            QueryFormat::Influx {
+                influx: (),
+                influx_measurement: "hmtk".to_owned(),
+                influx_tag: Vec::new(),
+            },
+        ),
+        ("table", QueryFormat::Table),
+        (
+            "graphite",
+            QueryFormat::Graphite {
+                graphite: (),
+                graphite_prefix: "hmtk".to_owned(),
+            },
+        ),
+    ];
+    let options = device.options().clone();
+    let no_response = || {
+        eyre!(
+            "no response within {}s, check the broker connection and the device MAC and type",
+            timeout.as_secs()
+        )
+    };
+
+    // The device only responds after the client connected and subscribed to the data topic.
+    let mut ok = true;
+    match registry.get(&options.ty) {
+        Some(parser) => {
+            let reading = match tokio::time::timeout(timeout, device.read(parser)).await {
+                Ok(Ok(reading)) => reading,
+                Ok(Err(err)) => return report("query device", Err(err.into())),
+                Err(_) => return report("query device", Err(no_response())),
+            };
+            report(
+                "query device",
+                Ok(format!("{} fields", reading.fields.len())),
+            );
+
+            for (name, format) in &formats {
+                let result = format_reading(&options, locale, format, &reading);
+                ok &= report(&format!("encode {name}"), encoded(result));
+            }
+            for sink in sinks {
+                let result = Err(eyre!("not supported for custom device types"));
+                ok &= report(&format!("write {}", sink.name()), result);
+            }
+        }
+        None => {
+            let device_info = match tokio::time::timeout(timeout, device.device_info()).await {
+                Ok(Ok(device_info)) => device_info,
+                Ok(Err(err)) => return report("query device", Err(err.into())),
+                Err(_) => return report("query device", Err(no_response())),
+            };
+            report(
+                "query device",
+                Ok(format!("battery at {}%", device_info.battery.charge.0)),
+            );
+
+            let now = device.clock().now();
+            for (name, format) in &formats {
+                let result =
+                    format_device_info(&options, locale, format, &device_info, None, None, now);
+                ok &= report(&format!("encode {name}"), encoded(result));
+            }
+            for &kind in sinks {
+                let result = async {
+                    let mut sink = kind.open(sink_options).await?;
+                    sink.write(&options, &device_info).await?;
+                    sink.close().await?;
+                    Ok("sample written".to_owned())
+                };
+                ok &= report(&format!("write {}", kind.name()), result.await);
+            }
+        }
+    }
+
+    ok
+}
+
+/// Summarizes the encoded `output` of a sample, an empty output is an error.
+fn encoded(output: Result<String>) -> Result<String> {
+    let output = output?;
+    if output.trim().is_empty() {
+        bail!("empty output");
+    }
+    Ok(format!("{} bytes", output.len()))
+}
+
+/// Prints the result of a step and returns whether it succeeded.
+fn report(step: &str, result: Result<String>) -> bool {
+    match result {
+        Ok(detail) => {
+            println!("ok    {step}: {detail}");
+            true
+        }
+        Err(err) => {
+            println!("FAIL  {step}: {err}");
+            false
+        }
+    }
+}
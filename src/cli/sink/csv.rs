@@ -0,0 +1,144 @@
+//! Appends samples to one CSV file per day, e.g. `2025-04-27.csv`, for analysis in spreadsheets.
+//!
+//! Days follow the local timezone of the host, the first sample after midnight starts a new file.
+use std::fmt::Write as _;
+use std::fs::{File, OpenOptions};
+use std::io::Write as _;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use color_eyre::eyre::{Result, WrapErr};
+use futures::future::BoxFuture;
+use hmtk::mqtt::{DeviceInfo, DeviceOptions};
+use hmtk::time::LocalTime;
+
+use super::Sink;
+
+const HEADER: &str = "time,timestamp,device_type,device_mac,\
+solar1_charging,solar1_pass_through,solar1_power,\
+solar2_charging,solar2_pass_through,solar2_power,\
+output1_active,output1_power,output2_active,output2_power,\
+temperature_min,temperature_max,\
+battery_charge,battery_capacity,battery_output_threshold,battery_discharge_depth,\
+battery_cell_charging,battery_cell_discharging,battery_cell_discharge_depth,\
+battery_cell_undervoltage,scene,grid_voltage,grid_frequency";
+
+pub struct CsvSink {
+    dir: PathBuf,
+    /// File of the day of the last sample.
+    file: Option<(String, File)>,
+}
+
+impl CsvSink {
+    /// Writes the files into `dir`, which is created if it does not exist.
+    pub fn open(dir: &Path) -> Result<Self> {
+        std::fs::create_dir_all(dir)
+            .wrap_err_with(|| format!("failed to create {}", dir.display()))?;
+        Ok(Self {
+            dir: dir.to_owned(),
+            file: None,
+        })
+    }
+
+    fn append(&mut self, device: &DeviceOptions, device_info: &DeviceInfo) -> Result<()> {
+        let time = LocalTime::from_system_time(device_info.timestamp);
+        let date = format!("{:04}-{:02}-{:02}", time.year, time.month, time.day);
+
+        let file = match &mut self.file {
+            Some((day, file)) if *day == date => file,
+            file => {
+                let path = self.dir.join(format!("{date}.csv"));
+                let opened =
+                    open(&path).wrap_err_with(|| format!("failed to open {}", path.display()))?;
+                &mut file.insert((date, opened)).1
+            }
+        };
+
+        let mut line = row(device, device_info, &time);
+        line.push('\n');
+        file.write_all(line.as_bytes())?;
+        Ok(())
+    }
+}
+
+impl Sink for CsvSink {
+    fn write<'a>(
+        &'a mut self,
+        device: &'a DeviceOptions,
+        device_info: &'a DeviceInfo,
+    ) -> BoxFuture<'a, Result<()>> {
+        let result = self.append(device, device_info);
+        Box::pin(async move { result })
+    }
+}
+
+/// Opens `path` for appending and writes the header into new files.
+fn open(path: &Path) -> std::io::Result<File> {
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    if file.metadata()?.len() == 0 {
+        writeln!(file, "{HEADER}")?;
+    }
+    Ok(file)
+}
+
+fn row(device: &DeviceOptions, device_info: &DeviceInfo, time: &LocalTime) -> String {
+    let timestamp = device_info
+        .timestamp
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or(Duration::ZERO)
+        .as_secs();
+    let (grid_voltage, grid_frequency) = match device_info.grid {
+        Some(grid) => (grid.voltage.0.to_string(), grid.frequency.0.to_string()),
+        None => (String::new(), String::new()),
+    };
+
+    let mut row = format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02},{timestamp},{},{}",
+        time.year,
+        time.month,
+        time.day,
+        time.hour,
+        time.minute,
+        time.second,
+        text(&device.ty),
+        text(&device.mac),
+    );
+    let battery = &device_info.battery;
+    let values = [
+        device_info.solar1.charging.to_string(),
+        device_info.solar1.pass_through.to_string(),
+        device_info.solar1.power.0.to_string(),
+        device_info.solar2.charging.to_string(),
+        device_info.solar2.pass_through.to_string(),
+        device_info.solar2.power.0.to_string(),
+        device_info.output1.active.to_string(),
+        device_info.output1.power.0.to_string(),
+        device_info.output2.active.to_string(),
+        device_info.output2.power.0.to_string(),
+        device_info.temperature.min.0.to_string(),
+        device_info.temperature.max.0.to_string(),
+        battery.charge.0.to_string(),
+        battery.capacity.0.to_string(),
+        battery.output_threshold.0.to_string(),
+        battery.discharge_depth.0.to_string(),
+        battery.internal.charging.to_string(),
+        battery.internal.discharging.to_string(),
+        battery.internal.discharge_depth.to_string(),
+        battery.internal.undervoltage.to_string(),
+        device_info.scene.as_str().to_owned(),
+        grid_voltage,
+        grid_frequency,
+    ];
+    for value in values {
+        let _ = write!(row, ",{value}");
+    }
+    row
+}
+
+/// Quotes `value` if it contains a separator, quote or newline.
+fn text(value: &str) -> String {
+    match value.contains([',', '"', '\n', '\r']) {
+        true => format!("\"{}\"", value.replace('"', "\"\"")),
+        false => value.to_owned(),
+    }
+}
@@ -0,0 +1,161 @@
+use std::path::Path;
+use std::str::FromStr;
+
+use color_eyre::eyre::{Result, eyre};
+use futures::future::BoxFuture;
+use hmtk::mqtt::{DeviceInfo, DeviceOptions};
+
+use self::csv::CsvSink;
+use self::mirror::MirrorSink;
+use self::nats::NatsSink;
+#[cfg(feature = "parquet")]
+use self::parquet::ParquetSink;
+use self::pushgateway::PushgatewaySink;
+use self::sqlite::SqliteSink;
+
+pub mod csv;
+pub mod mirror;
+pub mod nats;
+#[cfg(feature = "parquet")]
+pub mod parquet;
+pub mod pushgateway;
+pub mod queue;
+pub mod sqlite;
+
+/// Destination for device samples in long-running modes.
+pub trait Sink: Send {
+    /// Writes a single sample to the sink.
+    fn write<'a>(
+        &'a mut self,
+        device: &'a DeviceOptions,
+        device_info: &'a DeviceInfo,
+    ) -> BoxFuture<'a, Result<()>>;
+
+    /// Waits until all written samples are persisted and closes the sink.
+    fn close(self: Box<Self>) -> BoxFuture<'static, Result<()>> {
+        Box::pin(async { Ok(()) })
+    }
+}
+
+/// Kind of sink selected on the command line.
+#[derive(Debug, Clone, Copy)]
+pub enum SinkKind {
+    /// Appends samples to a SQLite database.
+    Sqlite,
+    /// Pushes samples to a Prometheus Pushgateway.
+    Pushgateway,
+    /// Publishes samples as JSON to a second MQTT broker.
+    Mirror,
+    /// Appends samples to one CSV file per day.
+    Csv,
+    /// Publishes samples as JSON to a NATS server.
+    Nats,
+    /// Writes samples to one Parquet file per day.
+    #[cfg(feature = "parquet")]
+    Parquet,
+}
+
+/// Command line options of the sinks.
+#[derive(Debug, Clone, Copy)]
+pub struct SinkOptions<'a> {
+    /// Database file of the `sqlite` sink.
+    pub db: Option<&'a Path>,
+    /// Pushgateway URL of the `pushgateway` sink.
+    pub url: Option<&'a str>,
+    /// Job name of the `pushgateway` sink.
+    pub job: &'a str,
+    /// Broker URL of the `mirror` sink.
+    pub mirror: Option<&'a str>,
+    /// Topic of the `mirror` sink.
+    pub mirror_topic: &'a str,
+    /// Directory of the `csv` sink.
+    pub csv_dir: Option<&'a Path>,
+    /// Server URL of the `nats` sink.
+    pub nats: Option<&'a str>,
+    /// Subject of the `nats` sink.
+    pub nats_subject: &'a str,
+    /// Whether the `nats` sink waits for the acknowledgement of JetStream.
+    pub jetstream: bool,
+    /// Directory of the `parquet` sink.
+    #[cfg(feature = "parquet")]
+    pub parquet_dir: Option<&'a Path>,
+    /// Size after which the `parquet` sink starts a new file.
+    #[cfg(feature = "parquet")]
+    pub parquet_max_size: Option<u64>,
+}
+
+impl SinkKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Sqlite => "sqlite",
+            Self::Pushgateway => "pushgateway",
+            Self::Mirror => "mirror",
+            Self::Csv => "csv",
+            Self::Nats => "nats",
+            #[cfg(feature = "parquet")]
+            Self::Parquet => "parquet",
+        }
+    }
+
+    /// Opens the sink with the `options` given on the command line.
+    pub async fn open(self, options: &SinkOptions<'_>) -> Result<Box<dyn Sink>> {
+        Ok(match self {
+            Self::Sqlite => {
+                let db = options
+                    .db
+                    .ok_or_else(|| eyre!("the sqlite sink requires `--db`"))?;
+                Box::new(SqliteSink::open(db)?)
+            }
+            Self::Pushgateway => {
+                let url = options
+                    .url
+                    .ok_or_else(|| eyre!("the pushgateway sink requires `--url`"))?;
+                Box::new(PushgatewaySink::new(url, options.job))
+            }
+            Self::Mirror => {
+                let url = options
+                    .mirror
+                    .ok_or_else(|| eyre!("the mirror sink requires `--mirror`"))?;
+                Box::new(MirrorSink::connect(url, options.mirror_topic)?)
+            }
+            Self::Csv => {
+                let dir = options
+                    .csv_dir
+                    .ok_or_else(|| eyre!("the csv sink requires `--csv-dir`"))?;
+                Box::new(CsvSink::open(dir)?)
+            }
+            Self::Nats => {
+                let url = options
+                    .nats
+                    .ok_or_else(|| eyre!("the nats sink requires `--nats`"))?;
+                Box::new(NatsSink::new(url, options.nats_subject, options.jetstream)?)
+            }
+            #[cfg(feature = "parquet")]
+            Self::Parquet => {
+                let dir = options
+                    .parquet_dir
+                    .ok_or_else(|| eyre!("the parquet sink requires `--parquet-dir`"))?;
+                Box::new(ParquetSink::open(dir, options.parquet_max_size)?)
+            }
+        })
+    }
+}
+
+impl FromStr for SinkKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "sqlite" => Ok(Self::Sqlite),
+            "pushgateway" => Ok(Self::Pushgateway),
+            "mirror" => Ok(Self::Mirror),
+            "csv" => Ok(Self::Csv),
+            "nats" => Ok(Self::Nats),
+            #[cfg(feature = "parquet")]
+            "parquet" => Ok(Self::Parquet),
+            _ => Err(format!(
+                "unknown sink `{s}`, expected `sqlite`, `pushgateway`, `mirror`, `csv` or `nats`"
+            )),
+        }
+    }
+}
@@ -0,0 +1,471 @@
+//! Parquet sink, writes one file per day, e.g. `2025-04-27.parquet`, to query with DuckDB or
+//! Polars.
+//!
+//! Samples are buffered by the [`ArrowWriter`] and written as a zstd compressed row group every
+//! [`ParquetSink::ROW_GROUP`] samples. The footer is written once a file is complete, when the
+//! sink rotates to a new file or closes.
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use arrow_array::{
+    ArrayRef, BooleanArray, Float32Array, Int32Array, RecordBatch, StringArray,
+    TimestampMillisecondArray,
+};
+use arrow_schema::{DataType, Field, Schema, SchemaRef, TimeUnit};
+use color_eyre::eyre::{Result, WrapErr};
+use futures::future::BoxFuture;
+use hmtk::mqtt::{DeviceInfo, DeviceOptions};
+use hmtk::time::LocalTime;
+use parquet::arrow::ArrowWriter;
+use parquet::basic::{Compression, ZstdLevel};
+use parquet::file::properties::WriterProperties;
+
+use super::Sink;
+
+pub struct ParquetSink {
+    dir: PathBuf,
+    /// Rotates to a new file of the same day once a file grows beyond this size in bytes.
+    max_size: Option<u64>,
+    schema: SchemaRef,
+    /// File of the day of the last sample.
+    file: Option<ParquetFile>,
+}
+
+impl ParquetSink {
+    /// Number of samples written as one row group.
+    const ROW_GROUP: usize = 360;
+
+    /// Writes the files into `dir`, which is created if it does not exist.
+    pub fn open(dir: &Path, max_size: Option<u64>) -> Result<Self> {
+        std::fs::create_dir_all(dir)
+            .wrap_err_with(|| format!("failed to create {}", dir.display()))?;
+        Ok(Self {
+            dir: dir.to_owned(),
+            max_size,
+            schema: schema(),
+            file: None,
+        })
+    }
+
+    fn append(&mut self, device: &DeviceOptions, device_info: &DeviceInfo) -> Result<()> {
+        let date = date(device_info.timestamp);
+
+        let rotate = match &self.file {
+            Some(file) => {
+                file.date != date
+                    || self
+                        .max_size
+                        .is_some_and(|max_size| file.size() >= max_size)
+            }
+            None => true,
+        };
+        if rotate {
+            self.finish()?;
+            let path = next_path(&self.dir, &date);
+            let file = ParquetFile::create(path, date, self.schema.clone())?;
+            self.file = Some(file);
+        }
+
+        let columns = COLUMNS
+            .iter()
+            .map(|column| column.ty.array((column.value)(device, device_info)))
+            .collect();
+        let batch = RecordBatch::try_new(self.schema.clone(), columns)?;
+
+        let file = self.file.as_mut().expect("file was opened above");
+        file.writer
+            .write(&batch)
+            .wrap_err_with(|| format!("failed to write {}", file.path.display()))
+    }
+
+    /// Writes the remaining samples and the footer of the current file.
+    fn finish(&mut self) -> Result<()> {
+        let Some(file) = self.file.take() else {
+            return Ok(());
+        };
+        file.writer
+            .close()
+            .wrap_err_with(|| format!("failed to write {}", file.path.display()))?;
+        Ok(())
+    }
+}
+
+impl Sink for ParquetSink {
+    fn write<'a>(
+        &'a mut self,
+        device: &'a DeviceOptions,
+        device_info: &'a DeviceInfo,
+    ) -> BoxFuture<'a, Result<()>> {
+        let result = self.append(device, device_info);
+        Box::pin(async move { result })
+    }
+
+    fn close(mut self: Box<Self>) -> BoxFuture<'static, Result<()>> {
+        let result = self.finish();
+        Box::pin(async move { result })
+    }
+}
+
+/// A file which is written to, it is only readable once the writer is closed.
+struct ParquetFile {
+    path: PathBuf,
+    /// Day of the samples in the file.
+    date: String,
+    writer: ArrowWriter<File>,
+}
+
+impl ParquetFile {
+    fn create(path: PathBuf, date: String, schema: SchemaRef) -> Result<Self> {
+        let file = File::create_new(&path)
+            .wrap_err_with(|| format!("failed to create {}", path.display()))?;
+        let properties = WriterProperties::builder()
+            .set_compression(Compression::ZSTD(ZstdLevel::default()))
+            .set_max_row_group_row_count(Some(ParquetSink::ROW_GROUP))
+            .set_created_by(format!("hmtk version {}", env!("CARGO_PKG_VERSION")))
+            .build();
+        let writer = ArrowWriter::try_new(file, schema, Some(properties))?;
+        Ok(Self { path, date, writer })
+    }
+
+    /// Size of the written row groups and the buffered samples in bytes.
+    fn size(&self) -> u64 {
+        (self.writer.bytes_written() + self.writer.in_progress_size()) as u64
+    }
+}
+
+/// Returns the local date of `timestamp`, e.g. `2025-04-27`.
+fn date(timestamp: SystemTime) -> String {
+    let time = LocalTime::from_system_time(timestamp);
+    format!("{:04}-{:02}-{:02}", time.year, time.month, time.day)
+}
+
+/// Returns the first path of the `date` which does not exist yet, e.g. `2025-04-27-1.parquet`.
+///
+/// Parquet files cannot be appended to, a restart continues in a new file.
+fn next_path(dir: &Path, date: &str) -> PathBuf {
+    let mut path = dir.join(format!("{date}.parquet"));
+    for i in 1.. {
+        if !path.exists() {
+            break;
+        }
+        path = dir.join(format!("{date}-{i}.parquet"));
+    }
+    path
+}
+
+/// A value of a column, `Null` only for optional columns.
+#[derive(Debug, Clone)]
+enum Value {
+    Boolean(bool),
+    Int32(i32),
+    /// Milliseconds since the Unix epoch.
+    Timestamp(i64),
+    Float(f32),
+    String(String),
+    Null,
+}
+
+/// Type of a column.
+#[derive(Debug, Clone, Copy)]
+enum Type {
+    Boolean,
+    Int32,
+    Float,
+    String,
+    /// Milliseconds since the Unix epoch, in UTC.
+    Timestamp,
+}
+
+impl Type {
+    fn data_type(self) -> DataType {
+        match self {
+            Self::Boolean => DataType::Boolean,
+            Self::Int32 => DataType::Int32,
+            Self::Float => DataType::Float32,
+            Self::String => DataType::Utf8,
+            Self::Timestamp => DataType::Timestamp(TimeUnit::Millisecond, Some("UTC".into())),
+        }
+    }
+
+    /// Returns an array of the single `value`, values of another type are null.
+    fn array(self, value: Value) -> ArrayRef {
+        match (self, value) {
+            (Self::Boolean, Value::Boolean(value)) => Arc::new(BooleanArray::from(vec![value])),
+            (Self::Boolean, _) => Arc::new(BooleanArray::new_null(1)),
+            (Self::Int32, Value::Int32(value)) => Arc::new(Int32Array::from(vec![value])),
+            (Self::Int32, _) => Arc::new(Int32Array::new_null(1)),
+            (Self::Float, Value::Float(value)) => Arc::new(Float32Array::from(vec![value])),
+            (Self::Float, _) => Arc::new(Float32Array::new_null(1)),
+            (Self::String, Value::String(value)) => Arc::new(StringArray::from(vec![value])),
+            (Self::String, _) => Arc::new(StringArray::new_null(1)),
+            (Self::Timestamp, Value::Timestamp(value)) => {
+                Arc::new(TimestampMillisecondArray::from(vec![value]).with_timezone("UTC"))
+            }
+            (Self::Timestamp, _) => {
+                Arc::new(TimestampMillisecondArray::new_null(1).with_timezone("UTC"))
+            }
+        }
+    }
+}
+
+struct Column {
+    name: &'static str,
+    ty: Type,
+    optional: bool,
+    value: fn(&DeviceOptions, &DeviceInfo) -> Value,
+}
+
+impl Column {
+    const fn new(
+        name: &'static str,
+        ty: Type,
+        value: fn(&DeviceOptions, &DeviceInfo) -> Value,
+    ) -> Self {
+        Self {
+            name,
+            ty,
+            optional: false,
+            value,
+        }
+    }
+
+    const fn optional(mut self) -> Self {
+        self.optional = true;
+        self
+    }
+}
+
+fn schema() -> SchemaRef {
+    let fields: Vec<_> = COLUMNS
+        .iter()
+        .map(|column| Field::new(column.name, column.ty.data_type(), column.optional))
+        .collect();
+    Arc::new(Schema::new(fields))
+}
+
+/// Columns of the files, named like the columns of the `sqlite` and `csv` sinks.
+const COLUMNS: &[Column] = &[
+    Column::new("timestamp", Type::Timestamp, |_, info| {
+        let timestamp = info
+            .timestamp
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or(Duration::ZERO);
+        Value::Timestamp(timestamp.as_millis() as i64)
+    }),
+    Column::new("device_type", Type::String, |device, _| {
+        Value::String(device.ty.clone())
+    }),
+    Column::new("device_mac", Type::String, |device, _| {
+        Value::String(device.mac.clone())
+    }),
+    Column::new("solar1_charging", Type::Boolean, |_, info| {
+        Value::Boolean(info.solar1.charging)
+    }),
+    Column::new("solar1_pass_through", Type::Boolean, |_, info| {
+        Value::Boolean(info.solar1.pass_through)
+    }),
+    Column::new("solar1_power", Type::Int32, |_, info| {
+        Value::Int32(info.solar1.power.0 as i32)
+    }),
+    Column::new("solar2_charging", Type::Boolean, |_, info| {
+        Value::Boolean(info.solar2.charging)
+    }),
+    Column::new("solar2_pass_through", Type::Boolean, |_, info| {
+        Value::Boolean(info.solar2.pass_through)
+    }),
+    Column::new("solar2_power", Type::Int32, |_, info| {
+        Value::Int32(info.solar2.power.0 as i32)
+    }),
+    Column::new("output1_active", Type::Boolean, |_, info| {
+        Value::Boolean(info.output1.active)
+    }),
+    Column::new("output1_power", Type::Int32, |_, info| {
+        Value::Int32(info.output1.power.0 as i32)
+    }),
+    Column::new("output2_active", Type::Boolean, |_, info| {
+        Value::Boolean(info.output2.active)
+    }),
+    Column::new("output2_power", Type::Int32, |_, info| {
+        Value::Int32(info.output2.power.0 as i32)
+    }),
+    Column::new("temperature_min", Type::Int32, |_, info| {
+        Value::Int32(info.temperature.min.0)
+    }),
+    Column::new("temperature_max", Type::Int32, |_, info| {
+        Value::Int32(info.temperature.max.0)
+    }),
+    Column::new("battery_charge", Type::Int32, |_, info| {
+        Value::Int32(info.battery.charge.0.into())
+    }),
+    Column::new("battery_capacity", Type::Int32, |_, info| {
+        Value::Int32(info.battery.capacity.0 as i32)
+    }),
+    Column::new("battery_output_threshold", Type::Int32, |_, info| {
+        Value::Int32(info.battery.output_threshold.0 as i32)
+    }),
+    Column::new("battery_discharge_depth", Type::Int32, |_, info| {
+        Value::Int32(info.battery.discharge_depth.0.into())
+    }),
+    Column::new("battery_cell_charging", Type::Boolean, |_, info| {
+        Value::Boolean(info.battery.internal.charging)
+    }),
+    Column::new("battery_cell_discharging", Type::Boolean, |_, info| {
+        Value::Boolean(info.battery.internal.discharging)
+    }),
+    Column::new("battery_cell_discharge_depth", Type::Boolean, |_, info| {
+        Value::Boolean(info.battery.internal.discharge_depth)
+    }),
+    Column::new("battery_cell_undervoltage", Type::Boolean, |_, info| {
+        Value::Boolean(info.battery.internal.undervoltage)
+    }),
+    Column::new("scene", Type::String, |_, info| {
+        Value::String(info.scene.as_str().to_owned())
+    }),
+    Column::new("grid_voltage", Type::Float, |_, info| match info.grid {
+        Some(grid) => Value::Float(grid.voltage.0),
+        None => Value::Null,
+    })
+    .optional(),
+    Column::new("grid_frequency", Type::Float, |_, info| match info.grid {
+        Some(grid) => Value::Float(grid.frequency.0),
+        None => Value::Null,
+    })
+    .optional(),
+    Column::new("limits_charge", Type::Int32, |_, info| match info.limits {
+        Some(limits) => Value::Int32(limits.charge.0 as i32),
+        None => Value::Null,
+    })
+    .optional(),
+    Column::new("limits_output", Type::Int32, |_, info| match info.limits {
+        Some(limits) => Value::Int32(limits.output.0 as i32),
+        None => Value::Null,
+    })
+    .optional(),
+];
+
+#[cfg(test)]
+mod tests {
+    use arrow_array::Array;
+    use hmtk::mqtt::{Protocol, RetryPolicy};
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+
+    use super::*;
+
+    /// 2025-04-27 12:00 UTC.
+    const NOON: u64 = 1745755200;
+
+    fn device_info(secs: u64, solar1: u32, grid: bool) -> DeviceInfo {
+        let grid = grid.then(|| serde_json::json!({"voltage": 230.5, "frequency": 50.0}));
+        let mut device_info: DeviceInfo = serde_json::from_value(serde_json::json!({
+            "timestamp": 0,
+            "solar1": {"charging": true, "pass_through": false, "power": solar1},
+            "solar2": {"charging": false, "pass_through": false, "power": 100},
+            "output1": {"power": 0, "active": false},
+            "output2": {"power": 200, "active": true},
+            "temperature": {"min": 20, "max": 23},
+            "battery": {
+                "charge": 50,
+                "capacity": 1000,
+                "output_threshold": 100,
+                "discharge_depth": 80,
+                "internal": {
+                    "charging": true,
+                    "discharging": false,
+                    "discharge_depth": false,
+                    "undervoltage": false
+                }
+            },
+            "scene": "day",
+            "grid": grid
+        }))
+        .unwrap();
+        device_info.timestamp = SystemTime::UNIX_EPOCH + Duration::from_secs(secs);
+        device_info
+    }
+
+    fn options() -> DeviceOptions {
+        DeviceOptions {
+            ty: "HMA-1".to_owned(),
+            mac: "0123456789ab".to_owned(),
+            protocol: Protocol::default(),
+            dry_run: false,
+            min_interval: Duration::ZERO,
+            retry: RetryPolicy::default(),
+        }
+    }
+
+    fn read(path: &Path) -> RecordBatch {
+        let file = File::open(path).unwrap();
+        let reader = ParquetRecordBatchReaderBuilder::try_new(file)
+            .unwrap()
+            .build()
+            .unwrap();
+        // Few samples are read as a single batch.
+        let batches: Vec<_> = reader.map(Result::unwrap).collect();
+        assert_eq!(batches.len(), 1);
+        batches.into_iter().next().unwrap()
+    }
+
+    fn column<'a, T: 'static>(batch: &'a RecordBatch, name: &str) -> &'a T {
+        batch
+            .column_by_name(name)
+            .unwrap()
+            .as_any()
+            .downcast_ref()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_parquet_sink() {
+        let dir = std::env::temp_dir().join(format!("hmtk-parquet-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+
+        let mut sink = ParquetSink::open(&dir, None).unwrap();
+        sink.append(&options(), &device_info(NOON, 300, true))
+            .unwrap();
+        sink.append(&options(), &device_info(NOON + 60, 400, false))
+            .unwrap();
+        // The next day starts a new file.
+        sink.append(&options(), &device_info(NOON + 86400, 500, false))
+            .unwrap();
+        sink.finish().unwrap();
+
+        // A restart continues in a new file of the day.
+        let mut sink = ParquetSink::open(&dir, None).unwrap();
+        sink.append(&options(), &device_info(NOON + 120, 600, false))
+            .unwrap();
+        sink.finish().unwrap();
+
+        let today = date(SystemTime::UNIX_EPOCH + Duration::from_secs(NOON));
+        let tomorrow = date(SystemTime::UNIX_EPOCH + Duration::from_secs(NOON + 86400));
+
+        let batch = read(&dir.join(format!("{today}.parquet")));
+        assert_eq!(batch.schema(), schema());
+        assert_eq!(batch.num_rows(), 2);
+        let timestamp: &TimestampMillisecondArray = column(&batch, "timestamp");
+        assert_eq!(
+            timestamp.values(),
+            &[NOON as i64 * 1000, (NOON as i64 + 60) * 1000]
+        );
+        let mac: &StringArray = column(&batch, "device_mac");
+        assert_eq!(mac.value(1), "0123456789ab");
+        let solar1: &Int32Array = column(&batch, "solar1_power");
+        assert_eq!(solar1.values(), &[300, 400]);
+        let charging: &BooleanArray = column(&batch, "solar1_charging");
+        assert!(charging.value(0));
+        let voltage: &Float32Array = column(&batch, "grid_voltage");
+        assert_eq!(voltage.iter().collect::<Vec<_>>(), [Some(230.5), None]);
+
+        let batch = read(&dir.join(format!("{tomorrow}.parquet")));
+        let solar1: &Int32Array = column(&batch, "solar1_power");
+        assert_eq!(solar1.values(), &[500]);
+
+        let batch = read(&dir.join(format!("{today}-1.parquet")));
+        let solar1: &Int32Array = column(&batch, "solar1_power");
+        assert_eq!(solar1.values(), &[600]);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,66 @@
+//! Prometheus Pushgateway sink, for cron-style usage where Prometheus cannot scrape hmtk.
+//!
+//! Every sample replaces the metrics of the device group, the group is identified by the
+//! job and the device MAC.
+use std::fmt::Write as _;
+
+use color_eyre::eyre::Result;
+use futures::FutureExt;
+use futures::future::BoxFuture;
+use hmtk::http;
+use hmtk::mqtt::{DeviceInfo, DeviceOptions};
+
+use super::Sink;
+
+pub struct PushgatewaySink {
+    url: String,
+    job: String,
+}
+
+impl PushgatewaySink {
+    /// Creates a sink pushing to the Pushgateway at `url` with the job name `job`.
+    pub fn new(url: &str, job: &str) -> Self {
+        Self {
+            url: url.trim_end_matches('/').to_owned(),
+            job: job.to_owned(),
+        }
+    }
+
+    async fn push(&self, device: &DeviceOptions, device_info: &DeviceInfo) -> Result<()> {
+        let url = format!(
+            "{}/metrics/job/{}/device_mac/{}",
+            self.url, self.job, device.mac
+        );
+        let body = to_text(device, device_info);
+        // `PUT` replaces all metrics of the group, metrics missing in a sample do not linger.
+        let url = http::parse_url(&url)?;
+        http::send(http::Method::PUT, url, "text/plain; version=0.0.4", body).await?;
+        Ok(())
+    }
+}
+
+impl Sink for PushgatewaySink {
+    fn write<'a>(
+        &'a mut self,
+        device: &'a DeviceOptions,
+        device_info: &'a DeviceInfo,
+    ) -> BoxFuture<'a, Result<()>> {
+        self.push(device, device_info).boxed()
+    }
+}
+
+/// Formats the sample in the Prometheus text exposition format.
+///
+/// The Pushgateway does not accept timestamps, the time of the push is used instead.
+fn to_text(device: &DeviceOptions, device_info: &DeviceInfo) -> String {
+    let mut result = String::new();
+    for metric in DeviceInfo::METRICS {
+        let Some(value) = device_info.metric(metric) else {
+            continue;
+        };
+        let name = format!("hmtk_{}", metric.replace('.', "_"));
+        let _ = writeln!(result, "# TYPE {name} gauge");
+        let _ = writeln!(result, "{name}{{device_type=\"{}\"}} {value}", device.ty);
+    }
+    result
+}
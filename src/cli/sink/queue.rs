@@ -0,0 +1,184 @@
+//! Decouples slow sinks from the sampling loop.
+//!
+//! Every sink is written from its own task through a bounded queue, when the sink cannot keep
+//! up, new samples are dropped instead of delaying the sampling of the device.
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+use color_eyre::eyre::Result;
+use hmtk::mqtt::{DeviceInfo, DeviceOptions};
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tracing::Instrument;
+
+use super::Sink;
+
+/// Number of samples which can be queued per sink.
+const CAPACITY: usize = 64;
+
+/// Health metrics of a sink.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SinkStats {
+    pub name: String,
+    /// Number of samples waiting to be written.
+    pub queue_depth: usize,
+    pub queue_capacity: usize,
+    /// Number of samples successfully written.
+    pub written: u64,
+    /// Number of samples dropped because the queue was full.
+    pub dropped: u64,
+    /// Number of samples which failed to be written.
+    pub failed: u64,
+    /// Duration of the last write in milliseconds.
+    pub last_latency_ms: Option<f64>,
+    pub last_error: Option<String>,
+}
+
+/// Reduces the rate samples are written to a sink with.
+#[derive(Debug, Clone, Copy)]
+pub struct Downsample {
+    every: u32,
+    throttle: Option<Duration>,
+    /// Number of samples since the last sample written.
+    skipped: u32,
+    /// Interval of the throttle the last sample was written in.
+    last: Option<u128>,
+}
+
+impl Downsample {
+    /// Writes only every `every`-th sample and of those at most one per `throttle`.
+    ///
+    /// The throttle intervals are aligned to the Unix epoch and follow the timestamps of the
+    /// samples, jitter of the sampling does not delay the next write.
+    pub fn new(every: u32, throttle: Option<Duration>) -> Self {
+        Self {
+            every: every.max(1),
+            throttle,
+            skipped: 0,
+            last: None,
+        }
+    }
+
+    /// Returns whether the sample taken at `timestamp` is written.
+    fn accept(&mut self, timestamp: SystemTime) -> bool {
+        self.skipped += 1;
+        if self.skipped < self.every {
+            return false;
+        }
+        self.skipped = 0;
+
+        let Some(throttle) = self.throttle.filter(|throttle| !throttle.is_zero()) else {
+            return true;
+        };
+        let since_epoch = timestamp
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or(Duration::ZERO);
+        let interval = since_epoch.as_millis() / throttle.as_millis().max(1);
+        if self.last == Some(interval) {
+            return false;
+        }
+        self.last = Some(interval);
+        true
+    }
+}
+
+impl Default for Downsample {
+    fn default() -> Self {
+        Self::new(1, None)
+    }
+}
+
+/// A sink written in the background through a bounded queue.
+pub struct QueuedSink {
+    queue: mpsc::Sender<(DeviceOptions, DeviceInfo)>,
+    downsample: Downsample,
+    stats: Arc<Mutex<SinkStats>>,
+    task: JoinHandle<Result<()>>,
+}
+
+impl QueuedSink {
+    /// Spawns a task writing all queued samples, which pass `downsample`, to `sink`.
+    pub fn spawn(name: &str, mut sink: Box<dyn Sink>, downsample: Downsample) -> Self {
+        let (queue, mut samples) = mpsc::channel::<(DeviceOptions, DeviceInfo)>(CAPACITY);
+        let stats = Arc::new(Mutex::new(SinkStats {
+            name: name.to_owned(),
+            queue_capacity: CAPACITY,
+            ..Default::default()
+        }));
+
+        let task_stats = Arc::clone(&stats);
+        let name = name.to_owned();
+        let task = tokio::task::spawn(async move {
+            while let Some((device, device_info)) = samples.recv().await {
+                let span = tracing::debug_span!(
+                    "sink_write",
+                    sink = %name,
+                    mac = %device.mac,
+                    error = tracing::field::Empty
+                );
+                let start = Instant::now();
+                let result = sink
+                    .write(&device, &device_info)
+                    .instrument(span.clone())
+                    .await;
+                let latency = start.elapsed();
+                if let Err(err) = &result {
+                    span.record("error", tracing::field::display(err));
+                }
+
+                let mut stats = task_stats.lock().unwrap();
+                stats.last_latency_ms = Some(latency.as_secs_f64() * 1000.0);
+                match result {
+                    Ok(()) => stats.written += 1,
+                    Err(err) => {
+                        tracing::warn!("failed to write to sink {}: {err}", stats.name);
+                        stats.failed += 1;
+                        stats.last_error = Some(err.to_string());
+                    }
+                }
+            }
+            sink.close().await
+        });
+
+        Self {
+            queue,
+            downsample,
+            stats,
+            task,
+        }
+    }
+
+    /// Queues a sample, the sample is dropped if the queue is full.
+    ///
+    /// Samples skipped by the downsampling of the sink are neither queued nor counted.
+    pub fn push(&mut self, device: &DeviceOptions, device_info: &DeviceInfo) {
+        if !self.downsample.accept(device_info.timestamp) {
+            return;
+        }
+        if let Err(mpsc::error::TrySendError::Full(_)) =
+            self.queue.try_send((device.clone(), *device_info))
+        {
+            let mut stats = self.stats.lock().unwrap();
+            stats.dropped += 1;
+            tracing::warn!(
+                "sink {} is not keeping up, dropped {} samples so far",
+                stats.name,
+                stats.dropped
+            );
+        }
+    }
+
+    /// Writes all queued samples and closes the sink.
+    pub async fn close(self) -> Result<()> {
+        drop(self.queue);
+        self.task.await?
+    }
+
+    /// Returns the current metrics of the sink.
+    pub fn stats(&self) -> SinkStats {
+        let mut stats = self.stats.lock().unwrap().clone();
+        stats.queue_depth = CAPACITY - self.queue.capacity();
+        stats
+    }
+}
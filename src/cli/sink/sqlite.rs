@@ -0,0 +1,308 @@
+//! SQLite sink, appends every sample to a table in the database.
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+
+use color_eyre::eyre::{Result, WrapErr, bail};
+use futures::future::BoxFuture;
+use hmtk::analytics::CapacitySample;
+use hmtk::mqtt::{DeviceInfo, DeviceOptions};
+use rusqlite::{Connection, OpenFlags, params};
+
+use super::Sink;
+
+/// Schema migrations, the index of a migration + 1 is stored as `user_version`.
+///
+/// Existing migrations must never be changed, only new ones appended.
+const MIGRATIONS: &[&str] = &[r#"
+CREATE TABLE measurements (
+    timestamp INTEGER NOT NULL,
+    device_type TEXT NOT NULL,
+    device_mac TEXT NOT NULL,
+    solar1_charging INTEGER NOT NULL,
+    solar1_pass_through INTEGER NOT NULL,
+    solar1_power INTEGER NOT NULL,
+    solar2_charging INTEGER NOT NULL,
+    solar2_pass_through INTEGER NOT NULL,
+    solar2_power INTEGER NOT NULL,
+    output1_active INTEGER NOT NULL,
+    output1_power INTEGER NOT NULL,
+    output2_active INTEGER NOT NULL,
+    output2_power INTEGER NOT NULL,
+    temperature_min INTEGER NOT NULL,
+    temperature_max INTEGER NOT NULL,
+    battery_charge INTEGER NOT NULL,
+    battery_capacity INTEGER NOT NULL,
+    battery_output_threshold INTEGER NOT NULL,
+    battery_discharge_depth INTEGER NOT NULL,
+    battery_cell_charging INTEGER NOT NULL,
+    battery_cell_discharging INTEGER NOT NULL,
+    battery_cell_discharge_depth INTEGER NOT NULL,
+    battery_cell_undervoltage INTEGER NOT NULL,
+    scene TEXT NOT NULL,
+    grid_voltage REAL,
+    grid_frequency REAL
+);
+CREATE INDEX measurements_device_timestamp ON measurements (device_mac, timestamp);
+"#];
+
+pub struct SqliteSink {
+    connection: Connection,
+}
+
+impl SqliteSink {
+    /// Opens the database at `path`, creating it and migrating its schema if necessary.
+    pub fn open(path: &Path) -> Result<Self> {
+        let mut connection = Connection::open(path)
+            .wrap_err_with(|| format!("failed to open {}", path.display()))?;
+        migrate(&mut connection)
+            .wrap_err_with(|| format!("failed to migrate {}", path.display()))?;
+
+        Ok(Self { connection })
+    }
+
+    fn insert(&mut self, device: &DeviceOptions, device_info: &DeviceInfo) -> Result<()> {
+        let timestamp = device_info
+            .timestamp
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or(Duration::ZERO)
+            .as_secs();
+        let grid = device_info.grid;
+        let battery = &device_info.battery;
+
+        let mut statement = self.connection.prepare_cached(
+            "INSERT INTO measurements VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, \
+             ?13, ?14, ?15, ?16, ?17, ?18, ?19, ?20, ?21, ?22, ?23, ?24, ?25, ?26)",
+        )?;
+        statement.execute(params![
+            timestamp,
+            device.ty,
+            device.mac,
+            device_info.solar1.charging,
+            device_info.solar1.pass_through,
+            device_info.solar1.power.0,
+            device_info.solar2.charging,
+            device_info.solar2.pass_through,
+            device_info.solar2.power.0,
+            device_info.output1.active,
+            device_info.output1.power.0,
+            device_info.output2.active,
+            device_info.output2.power.0,
+            device_info.temperature.min.0,
+            device_info.temperature.max.0,
+            battery.charge.0,
+            battery.capacity.0,
+            battery.output_threshold.0,
+            battery.discharge_depth.0,
+            battery.internal.charging,
+            battery.internal.discharging,
+            battery.internal.discharge_depth,
+            battery.internal.undervoltage,
+            device_info.scene.as_str(),
+            grid.map(|grid| f64::from(grid.voltage.0)),
+            grid.map(|grid| f64::from(grid.frequency.0)),
+        ])?;
+        Ok(())
+    }
+}
+
+impl Sink for SqliteSink {
+    fn write<'a>(
+        &'a mut self,
+        device: &'a DeviceOptions,
+        device_info: &'a DeviceInfo,
+    ) -> BoxFuture<'a, Result<()>> {
+        let result = self.insert(device, device_info);
+        Box::pin(async move { result })
+    }
+}
+
+/// Reads the battery history of all devices from the database at `path`.
+///
+/// Returns the device MAC and time of every sample.
+pub fn capacity_samples(path: &Path) -> Result<Vec<(String, SystemTime, CapacitySample)>> {
+    read_capacity_samples(&open_existing(path)?)
+        .wrap_err_with(|| format!("failed to read {}", path.display()))
+}
+
+fn read_capacity_samples(
+    connection: &Connection,
+) -> rusqlite::Result<Vec<(String, SystemTime, CapacitySample)>> {
+    let mut statement = connection.prepare(
+        "SELECT device_mac, timestamp, temperature_min, temperature_max, battery_charge, \
+         battery_capacity FROM measurements ORDER BY timestamp",
+    )?;
+    statement
+        .query_map([], |row| {
+            let temperature_min: f64 = row.get(2)?;
+            let temperature_max: f64 = row.get(3)?;
+            Ok((
+                row.get(0)?,
+                SystemTime::UNIX_EPOCH + Duration::from_secs(row.get(1)?),
+                CapacitySample {
+                    temperature: (temperature_min + temperature_max) / 2.0,
+                    charge: row.get(4)?,
+                    remaining: row.get(5)?,
+                },
+            ))
+        })?
+        .collect()
+}
+
+/// Power samples of a device.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PowerSample {
+    pub timestamp: SystemTime,
+    /// Power of both solar inputs in W.
+    pub solar: f64,
+    /// Power of both outputs in W.
+    pub output: f64,
+}
+
+/// Reads the solar and output power history of all devices from the database at `path`.
+///
+/// Returns the device MAC of every sample.
+pub fn power_samples(path: &Path) -> Result<Vec<(String, PowerSample)>> {
+    read_power_samples(&open_existing(path)?)
+        .wrap_err_with(|| format!("failed to read {}", path.display()))
+}
+
+fn read_power_samples(connection: &Connection) -> rusqlite::Result<Vec<(String, PowerSample)>> {
+    let mut statement = connection.prepare(
+        "SELECT device_mac, timestamp, solar1_power + solar2_power, \
+         output1_power + output2_power FROM measurements ORDER BY timestamp",
+    )?;
+    statement
+        .query_map([], |row| {
+            Ok((
+                row.get(0)?,
+                PowerSample {
+                    timestamp: SystemTime::UNIX_EPOCH + Duration::from_secs(row.get(1)?),
+                    solar: row.get(2)?,
+                    output: row.get(3)?,
+                },
+            ))
+        })?
+        .collect()
+}
+
+/// Opens an existing database read only.
+fn open_existing(path: &Path) -> Result<Connection> {
+    if !path.exists() {
+        bail!("database {} does not exist", path.display());
+    }
+    Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)
+        .wrap_err_with(|| format!("failed to open {}", path.display()))
+}
+
+/// Applies all migrations which have not yet been applied to the database.
+fn migrate(connection: &mut Connection) -> rusqlite::Result<()> {
+    let version: usize = connection.pragma_query_value(None, "user_version", |row| row.get(0))?;
+    if version >= MIGRATIONS.len() {
+        return Ok(());
+    }
+
+    tracing::info!(
+        "migrating database from version {version} to {}",
+        MIGRATIONS.len()
+    );
+
+    let transaction = connection.transaction()?;
+    for migration in &MIGRATIONS[version..] {
+        transaction.execute_batch(migration)?;
+    }
+    transaction.pragma_update(None, "user_version", MIGRATIONS.len())?;
+    transaction.commit()
+}
+
+#[cfg(test)]
+mod tests {
+    use hmtk::mqtt::{Protocol, RetryPolicy};
+
+    use super::*;
+
+    fn device_info(secs: u64, solar1: u32, output2: u32) -> DeviceInfo {
+        let mut device_info: DeviceInfo = serde_json::from_value(serde_json::json!({
+            "timestamp": 0,
+            "solar1": {"charging": true, "pass_through": false, "power": solar1},
+            "solar2": {"charging": false, "pass_through": false, "power": 100},
+            "output1": {"power": 0, "active": false},
+            "output2": {"power": output2, "active": true},
+            "temperature": {"min": 20, "max": 23},
+            "battery": {
+                "charge": 50,
+                "capacity": 1000,
+                "output_threshold": 100,
+                "discharge_depth": 80,
+                "internal": {
+                    "charging": true,
+                    "discharging": false,
+                    "discharge_depth": false,
+                    "undervoltage": false
+                }
+            },
+            "scene": "day"
+        }))
+        .unwrap();
+        device_info.timestamp = SystemTime::UNIX_EPOCH + Duration::from_secs(secs);
+        device_info
+    }
+
+    fn options() -> DeviceOptions {
+        DeviceOptions {
+            ty: "HMA-1".to_owned(),
+            mac: "0123456789ab".to_owned(),
+            protocol: Protocol::default(),
+            dry_run: false,
+            min_interval: Duration::ZERO,
+            retry: RetryPolicy::default(),
+        }
+    }
+
+    #[test]
+    fn test_sqlite_sink() {
+        let mut connection = Connection::open_in_memory().unwrap();
+        migrate(&mut connection).unwrap();
+        // Migrating twice is a no-op.
+        migrate(&mut connection).unwrap();
+
+        let mut sink = SqliteSink { connection };
+        // Quotes in values are bound as parameters and not interpreted as SQL.
+        let device = DeviceOptions {
+            mac: "01'23".to_owned(),
+            ..options()
+        };
+        sink.insert(&device, &device_info(60, 300, 200)).unwrap();
+        sink.insert(&options(), &device_info(120, 400, 250))
+            .unwrap();
+
+        let power = read_power_samples(&sink.connection).unwrap();
+        assert_eq!(
+            power,
+            [
+                (
+                    "01'23".to_owned(),
+                    PowerSample {
+                        timestamp: SystemTime::UNIX_EPOCH + Duration::from_secs(60),
+                        solar: 400.0,
+                        output: 200.0,
+                    }
+                ),
+                (
+                    "0123456789ab".to_owned(),
+                    PowerSample {
+                        timestamp: SystemTime::UNIX_EPOCH + Duration::from_secs(120),
+                        solar: 500.0,
+                        output: 250.0,
+                    }
+                ),
+            ]
+        );
+
+        let capacity = read_capacity_samples(&sink.connection).unwrap();
+        assert_eq!(capacity.len(), 2);
+        let (_, _, sample) = &capacity[1];
+        assert_eq!(sample.temperature, 21.5);
+        assert_eq!(sample.charge, 50.0);
+        assert_eq!(sample.remaining, 1000.0);
+    }
+}
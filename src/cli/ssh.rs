@@ -0,0 +1,66 @@
+//! SSH tunnels to brokers which are not reachable directly.
+use std::process::Stdio;
+use std::time::Duration;
+
+use color_eyre::eyre::{Result, WrapErr, bail};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::process::{Child, Command};
+
+/// A local port forward to a remote broker through the system `ssh`.
+///
+/// The `ssh` process is terminated when the tunnel is dropped.
+#[derive(Debug)]
+pub struct Tunnel {
+    /// Local port forwarded to the broker.
+    pub port: u16,
+    _child: Child,
+}
+
+impl Tunnel {
+    /// Time to wait for `ssh` to establish the forward, including authentication.
+    const TIMEOUT: Duration = Duration::from_secs(30);
+
+    /// Forwards a local port to `host:port`, as seen from the SSH server `destination`.
+    ///
+    /// `destination` is passed to `ssh` as is, e.g. `user@example.com` or a host
+    /// from `~/.ssh/config`.
+    pub async fn open(destination: &str, host: &str, port: u16) -> Result<Self> {
+        let local_port = TcpListener::bind(("127.0.0.1", 0))
+            .await?
+            .local_addr()?
+            .port();
+
+        tracing::info!("Opening SSH tunnel to {host}:{port} via {destination}");
+        let mut child = Command::new("ssh")
+            .arg("-N")
+            .args(["-o", "ExitOnForwardFailure=yes"])
+            .arg("-L")
+            .arg(format!("127.0.0.1:{local_port}:{host}:{port}"))
+            .arg("--")
+            .arg(destination)
+            .stdin(Stdio::inherit())
+            .stdout(Stdio::null())
+            .kill_on_drop(true)
+            .spawn()
+            .wrap_err("failed to run `ssh`")?;
+
+        let deadline = tokio::time::Instant::now() + Self::TIMEOUT;
+        loop {
+            if let Some(status) = child.try_wait()? {
+                bail!("ssh exited with {status} before the tunnel was established");
+            }
+            if TcpStream::connect(("127.0.0.1", local_port)).await.is_ok() {
+                break;
+            }
+            if tokio::time::Instant::now() > deadline {
+                bail!("timed out waiting for the SSH tunnel to {destination}");
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+
+        Ok(Self {
+            port: local_port,
+            _child: child,
+        })
+    }
+}
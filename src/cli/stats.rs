@@ -0,0 +1,41 @@
+use std::fmt::Write as _;
+
+use color_eyre::eyre::Result;
+use hmtk::locale::Locale;
+use hmtk::mqtt::DailyEnergy;
+
+/// Queries the energy counters of the current day from the device.
+pub async fn stats(device: &mut hmtk::mqtt::Device, locale: &Locale, json: bool) -> Result<()> {
+    let energy = device.daily_energy().await?;
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&energy)?);
+    } else {
+        print!("{}", to_table(locale, &energy));
+    }
+
+    Ok(())
+}
+
+fn to_table(locale: &Locale, energy: &DailyEnergy) -> String {
+    let rows = [
+        ("energy.solar", energy.solar),
+        ("energy.output", energy.output),
+        ("energy.battery_charge", energy.battery_charge),
+        ("energy.battery_discharge", energy.battery_discharge),
+    ];
+
+    let width = rows
+        .iter()
+        .map(|(key, _)| locale.label(key).chars().count())
+        .max()
+        .unwrap_or_default();
+
+    let mut result = String::new();
+    for (key, value) in rows {
+        let label = locale.label(key);
+        let value = locale.number(value.0.into(), 0);
+        let _ = writeln!(result, "{label:<width$}  {value} Wh");
+    }
+    result
+}
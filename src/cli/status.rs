@@ -0,0 +1,125 @@
+//! Status of a running monitor, shared through a file.
+use std::fmt::Write as _;
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+
+use color_eyre::eyre::{Result, WrapErr};
+use hmtk::mqtt::Device;
+use serde::{Deserialize, Serialize};
+
+use crate::cli::sink::queue::SinkStats;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Status {
+    /// Unix timestamp of the last update.
+    pub updated: u64,
+    pub device_type: String,
+    pub device_mac: String,
+    /// Number of times the connection to the broker was re-established.
+    #[serde(default)]
+    pub reconnects: u64,
+    /// Number of times the broker rate limited the client.
+    #[serde(default)]
+    pub rate_limits: u64,
+    /// Number of messages which could not be published.
+    #[serde(default)]
+    pub publish_failures: u64,
+    /// Number of received messages which could not be parsed.
+    #[serde(default)]
+    pub parse_errors: u64,
+    /// Time between the last status request and its response in milliseconds.
+    #[serde(default)]
+    pub latency_ms: Option<f64>,
+    pub sinks: Vec<SinkStats>,
+}
+
+impl Status {
+    pub fn new(device: &Device, sinks: Vec<SinkStats>) -> Self {
+        Self {
+            updated: SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap_or(Duration::ZERO)
+                .as_secs(),
+            device_type: device.options().ty.clone(),
+            device_mac: device.options().mac.clone(),
+            reconnects: device.reconnects(),
+            rate_limits: device.rate_limits(),
+            publish_failures: device.publish_failures(),
+            parse_errors: device.parse_errors(),
+            latency_ms: device
+                .latency()
+                .map(|latency| latency.as_secs_f64() * 1000.0),
+            sinks,
+        }
+    }
+
+    /// Atomically replaces the status file at `path`.
+    pub fn write(&self, path: &Path) -> Result<()> {
+        let tmp = path.with_extension("tmp");
+        std::fs::write(&tmp, serde_json::to_vec_pretty(self)?)?;
+        std::fs::rename(&tmp, path)?;
+        Ok(())
+    }
+}
+
+/// Prints the status written by a running monitor to `path`.
+pub fn status(path: &Path, json: bool) -> Result<()> {
+    let content = std::fs::read(path)
+        .wrap_err_with(|| format!("failed to read status file {}", path.display()))?;
+    let status: Status = serde_json::from_slice(&content)?;
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&status)?);
+    } else {
+        print!("{}", to_table(&status));
+    }
+    Ok(())
+}
+
+fn to_table(status: &Status) -> String {
+    let mut result = String::new();
+    let latency = status
+        .latency_ms
+        .map(|latency| format!("{latency:.1} ms"))
+        .unwrap_or_else(|| "-".to_owned());
+    let _ = writeln!(
+        result,
+        "device {} ({}), updated {}, {} reconnects, {} rate limits",
+        status.device_mac,
+        status.device_type,
+        status.updated,
+        status.reconnects,
+        status.rate_limits
+    );
+    let _ = writeln!(
+        result,
+        "{} publish failures, {} parse errors, latency {latency}\n",
+        status.publish_failures, status.parse_errors,
+    );
+
+    let _ = writeln!(
+        result,
+        "{:<10} {:>7} {:>9} {:>8} {:>7} {:>12}",
+        "sink", "queue", "written", "dropped", "failed", "latency"
+    );
+    for sink in &status.sinks {
+        let latency = sink
+            .last_latency_ms
+            .map(|latency| format!("{latency:.1} ms"))
+            .unwrap_or_else(|| "-".to_owned());
+        let _ = writeln!(
+            result,
+            "{:<10} {:>7} {:>9} {:>8} {:>7} {:>12}",
+            sink.name,
+            format!("{}/{}", sink.queue_depth, sink.queue_capacity),
+            sink.written,
+            sink.dropped,
+            sink.failed,
+            latency,
+        );
+        if let Some(err) = &sink.last_error {
+            let _ = writeln!(result, "  last error: {err}");
+        }
+    }
+    result
+}
@@ -0,0 +1,92 @@
+use std::time::Duration;
+
+use color_eyre::eyre::{Result, bail};
+use hmtk::analytics::{ClockDrift, ClockDriftProbe, MAX_GAP};
+use hmtk::mqtt::ControlCommand;
+use hmtk::time::LocalTime;
+
+use crate::cli::{print_dry_run, shutdown_signal};
+
+/// Drift measurement before synchronizing the clock.
+#[derive(Debug, Clone, Copy)]
+pub struct Measure {
+    /// Interval between two queries of the energy counters.
+    pub interval: Duration,
+    /// Only synchronizes the clock if the drift exceeds this, never without.
+    pub max_drift: Option<Duration>,
+}
+
+/// Sets the clock of the device to the local time of the host.
+///
+/// With `measure`, first waits until the device resets its energy counters at midnight, prints
+/// the drift of its clock and only synchronizes the clock if the drift exceeds the `max_drift`.
+pub async fn sync_time(device: &mut hmtk::mqtt::Device, measure: Option<Measure>) -> Result<()> {
+    if let Some(measure) = measure {
+        let Some(drift) = measure_drift(device, measure.interval).await? else {
+            return Ok(());
+        };
+        println!("{}", format_drift(&drift));
+
+        match measure.max_drift {
+            Some(max_drift) if drift.abs() > max_drift => {
+                tracing::info!("the drift exceeds {}s", max_drift.as_secs());
+            }
+            _ => return Ok(()),
+        }
+    }
+
+    let now = LocalTime::now();
+    tracing::info!("setting device time to {now:?}");
+    device.sync_time(now).await?;
+    print_dry_run(device, ControlCommand::SyncTime(now));
+
+    Ok(())
+}
+
+/// Queries the energy counters every `interval` until they reset.
+///
+/// Returns `None` if the process is asked to shut down before.
+async fn measure_drift(
+    device: &mut hmtk::mqtt::Device,
+    interval: Duration,
+) -> Result<Option<ClockDrift>> {
+    if interval > MAX_GAP {
+        bail!(
+            "the interval must be at most {}s to time the reset",
+            MAX_GAP.as_secs()
+        );
+    }
+    tracing::info!("waiting for the device to reset its energy counters at midnight");
+
+    let mut interval = tokio::time::interval(interval);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+    let shutdown = shutdown_signal();
+    tokio::pin!(shutdown);
+
+    let mut probe = ClockDriftProbe::default();
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {}
+            _ = &mut shutdown => return Ok(None),
+        }
+
+        let energy = device.daily_energy().await?;
+        if let Some(drift) = probe.add(&energy) {
+            return Ok(Some(drift));
+        }
+    }
+}
+
+fn format_drift(drift: &ClockDrift) -> String {
+    let direction = if drift.offset < 0 {
+        "behind"
+    } else {
+        "ahead of"
+    };
+    format!(
+        "device clock is {}s {direction} the host (±{}s)",
+        drift.offset.unsigned_abs(),
+        drift.uncertainty
+    )
+}
@@ -0,0 +1,92 @@
+//! Service notifications for systemd, see `sd_notify(3)`.
+//!
+//! Only active when hmtk runs as a service with `Type=notify`, which sets `NOTIFY_SOCKET`.
+//! With `WatchdogSec=` systemd additionally sets `WATCHDOG_USEC` and restarts the service,
+//! when it stops pinging the watchdog.
+use std::os::linux::net::SocketAddrExt;
+use std::os::unix::net::{SocketAddr, UnixDatagram};
+use std::time::Duration;
+
+use hmtk::capture::Direction;
+use hmtk::mqtt::Device;
+use tokio::sync::broadcast::error::RecvError;
+
+/// Connection to the notification socket of the service manager.
+pub struct Notifier {
+    socket: UnixDatagram,
+    addr: SocketAddr,
+    watchdog: Option<Duration>,
+}
+
+impl Notifier {
+    /// Connects to the socket passed by systemd, `None` if not started by systemd.
+    pub fn from_env() -> Option<Self> {
+        let path = std::env::var_os("NOTIFY_SOCKET")?;
+        let path = path.as_encoded_bytes();
+        let addr = match path.strip_prefix(b"@") {
+            Some(name) => SocketAddr::from_abstract_name(name),
+            None => SocketAddr::from_pathname(std::str::from_utf8(path).ok()?),
+        };
+        let addr = addr
+            .inspect_err(|err| tracing::warn!("invalid NOTIFY_SOCKET: {err}"))
+            .ok()?;
+        let socket = UnixDatagram::unbound()
+            .inspect_err(|err| tracing::warn!("failed to create notify socket: {err}"))
+            .ok()?;
+
+        // The watchdog is meant for the main process only.
+        let pid = std::env::var("WATCHDOG_PID").ok();
+        let watchdog = std::env::var("WATCHDOG_USEC")
+            .ok()
+            .and_then(|usec| usec.parse().ok())
+            .filter(|_| pid.is_none_or(|pid| pid == std::process::id().to_string()))
+            .map(Duration::from_micros);
+
+        Some(Self {
+            socket,
+            addr,
+            watchdog,
+        })
+    }
+
+    /// Signals readiness once the device responded and pings the watchdog while it is healthy.
+    pub async fn run(self, device: Device) {
+        let mut traffic = device.traffic();
+        if let Err(err) = device.request_device_info().await {
+            tracing::warn!("failed to request device status: {err}");
+        }
+        loop {
+            match traffic.recv().await {
+                Ok(record) if record.direction == Direction::Inbound => break,
+                Ok(_) | Err(RecvError::Lagged(_)) => {}
+                Err(RecvError::Closed) => return,
+            }
+        }
+        drop(traffic);
+        self.notify("READY=1");
+
+        let Some(watchdog) = self.watchdog else {
+            return;
+        };
+        let mut interval = tokio::time::interval(watchdog / 2);
+        let mut was_healthy = true;
+        loop {
+            interval.tick().await;
+            let healthy = device.is_healthy();
+            match healthy {
+                true => self.notify("WATCHDOG=1"),
+                false if was_healthy => {
+                    tracing::warn!("not connected to the broker, stopped pinging the watchdog")
+                }
+                false => {}
+            }
+            was_healthy = healthy;
+        }
+    }
+
+    fn notify(&self, state: &str) {
+        if let Err(err) = self.socket.send_to_addr(state.as_bytes(), &self.addr) {
+            tracing::warn!("failed to notify systemd: {err}");
+        }
+    }
+}
@@ -0,0 +1,237 @@
+//! Client for the Hame cloud account, which knows all devices registered with the app.
+//!
+//! The API is not documented, requests mirror the ones of the official app.
+use md5::{Digest, Md5};
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::http;
+
+/// API used by the official app for accounts in Europe.
+pub const DEFAULT_URL: &str = "https://eu.hamedata.com";
+
+/// Errors which can occur when querying the cloud account.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error(transparent)]
+    Http(#[from] http::Error),
+    #[error("invalid api response: {0}")]
+    InvalidResponse(String),
+    /// The API rejected the request, e.g. because of wrong credentials.
+    #[error("api error: {0}")]
+    Api(String),
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// A device registered with the cloud account.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct CloudDevice {
+    /// Name given to the device in the app.
+    pub name: String,
+    /// Device type, e.g. `HMA-1`.
+    #[serde(rename = "type")]
+    pub ty: String,
+    pub mac: String,
+    /// Whether the device is connected to the cloud, `None` if the API does not report it.
+    pub online: Option<bool>,
+}
+
+/// Credentials of a Hame cloud account, as used to log into the app.
+#[derive(Debug, Clone)]
+pub struct Account {
+    url: String,
+    email: String,
+    password: String,
+}
+
+impl Account {
+    pub fn new(email: impl Into<String>, password: impl Into<String>) -> Self {
+        Self {
+            url: DEFAULT_URL.to_owned(),
+            email: email.into(),
+            password: password.into(),
+        }
+    }
+
+    /// Uses the API at `url` instead of the [`DEFAULT_URL`].
+    ///
+    /// The URL may contain a base path, e.g. of a reverse proxy, which is kept.
+    pub fn url(mut self, url: impl Into<String>) -> Self {
+        self.url = url.into();
+        self
+    }
+
+    /// Lists all devices registered with the account.
+    pub async fn devices(&self) -> Result<Vec<CloudDevice>> {
+        let body = http::get(self.devices_url()?).await?;
+        parse_devices(&body)
+    }
+
+    /// Returns the URL listing the devices, the app sends the password only as MD5 digest.
+    fn devices_url(&self) -> Result<http::Url> {
+        let mut url = http::parse_url(&self.url)?;
+        url.path_segments_mut()
+            .expect("http urls have a path")
+            .pop_if_empty()
+            .extend(["app", "Solar", "get_device.php"]);
+
+        let digest = Md5::digest(self.password.as_bytes());
+        url.query_pairs_mut()
+            .clear()
+            .append_pair("mailbox", &self.email)
+            .append_pair("pwd", &format!("{digest:x}"));
+        Ok(url)
+    }
+}
+
+/// Parses the device list, values are reported as strings or numbers.
+fn parse_devices(body: &[u8]) -> Result<Vec<CloudDevice>> {
+    let value: Value =
+        serde_json::from_slice(body).map_err(|err| Error::InvalidResponse(err.to_string()))?;
+
+    let Some(devices) = value.get("data").and_then(Value::as_array) else {
+        let message = value.get("msg").and_then(text).unwrap_or_default();
+        return Err(Error::Api(message));
+    };
+
+    devices
+        .iter()
+        .map(|device| {
+            let field = |name: &str| device.get(name).and_then(text);
+            let required = |name: &str| {
+                field(name)
+                    .ok_or_else(|| Error::InvalidResponse(format!("device without `{name}`")))
+            };
+            Ok(CloudDevice {
+                name: field("name").unwrap_or_default(),
+                ty: required("type")?,
+                mac: required("mac")?,
+                online: field("online").map(|online| online == "1" || online == "true"),
+            })
+        })
+        .collect()
+}
+
+fn text(value: &Value) -> Option<String> {
+    match value {
+        Value::String(value) => Some(value.clone()),
+        Value::Number(value) => Some(value.to_string()),
+        Value::Bool(value) => Some(value.to_string()),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    #[test]
+    fn test_devices_url() {
+        let url = |base: &str| {
+            Account::new("me+hm@example.com", "abc")
+                .url(base)
+                .devices_url()
+                .map(|url| url.to_string())
+        };
+
+        let query = "mailbox=me%2Bhm%40example.com&pwd=900150983cd24fb0d6963f7d28e17f72";
+        assert_eq!(
+            url(DEFAULT_URL).unwrap(),
+            format!("https://eu.hamedata.com/app/Solar/get_device.php?{query}")
+        );
+        // Base paths are kept, with or without a trailing slash.
+        assert_eq!(
+            url("http://proxy:8080/hame/").unwrap(),
+            format!("http://proxy:8080/hame/app/Solar/get_device.php?{query}")
+        );
+        assert_eq!(
+            url("http://proxy/hame").unwrap(),
+            format!("http://proxy/hame/app/Solar/get_device.php?{query}")
+        );
+        assert_eq!(
+            url("http://[::1]:8080").unwrap(),
+            format!("http://[::1]:8080/app/Solar/get_device.php?{query}")
+        );
+
+        assert!(matches!(
+            url("ftp://eu.hamedata.com"),
+            Err(Error::Http(http::Error::UnsupportedUrl(_)))
+        ));
+        assert!(url("eu.hamedata.com").is_err());
+    }
+
+    #[test]
+    fn test_parse_devices() {
+        let body = br#"{"code":"2","msg":"ok","data":[
+            {"devid":"1","name":"Balcony","mac":"abc","type":"HMA-1","online":"1"},
+            {"devid":"2","name":"Garage","mac":"def","type":"HMG-50","online":0},
+            {"devid":"3","mac":"123","type":"HMB-1"}
+        ]}"#;
+
+        insta::assert_debug_snapshot!(parse_devices(body).unwrap(), @r#"
+        [
+            CloudDevice {
+                name: "Balcony",
+                ty: "HMA-1",
+                mac: "abc",
+                online: Some(
+                    true,
+                ),
+            },
+            CloudDevice {
+                name: "Garage",
+                ty: "HMG-50",
+                mac: "def",
+                online: Some(
+                    false,
+                ),
+            },
+            CloudDevice {
+                name: "",
+                ty: "HMB-1",
+                mac: "123",
+                online: None,
+            },
+        ]
+        "#);
+
+        let err = parse_devices(br#"{"code":"3","msg":"wrong password"}"#).unwrap_err();
+        assert!(matches!(err, Error::Api(message) if message == "wrong password"));
+        assert!(parse_devices(br#"{"data":[{"name":"x"}]}"#).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_account_devices() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+
+        let server = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = vec![0; 1024];
+            let len = stream.read(&mut request).await.unwrap();
+            stream
+                .write_all(
+                    b"HTTP/1.1 200 OK\r\nConnection: close\r\n\r\n{\"data\":[{\"mac\":\"abc\",\"type\":\"HMA-1\"}]}",
+                )
+                .await
+                .unwrap();
+            String::from_utf8_lossy(&request[..len]).into_owned()
+        });
+
+        let devices = Account::new("me+hm@example.com", "abc")
+            .url(url)
+            .devices()
+            .await
+            .unwrap();
+        assert_eq!(devices[0].mac, "abc");
+
+        let request = server.await.unwrap();
+        assert!(request.starts_with(
+            "GET /app/Solar/get_device.php?mailbox=me%2Bhm%40example.com&pwd=900150983cd24fb0d6963f7d28e17f72 HTTP/1.1\r\n"
+        ));
+    }
+}
@@ -0,0 +1,235 @@
+use std::fmt;
+use std::time::{Duration, SystemTime};
+
+use serde::Serialize;
+
+use crate::mqtt::{DeviceInfo, Scene};
+
+/// A discrete state transition observed between two consecutive device samples.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    /// The scene of the device changed, e.g. from day to dusk.
+    SceneChanged { from: Scene, to: Scene },
+    /// An output, `1` or `2`, was switched on or off.
+    OutputToggled { output: u8, active: bool },
+    /// The battery started charging.
+    ChargingStarted,
+    /// The battery stopped charging.
+    ChargingStopped,
+    /// The battery reported an undervoltage.
+    UndervoltageSet,
+    /// The undervoltage of the battery is resolved.
+    UndervoltageCleared,
+}
+
+impl Event {
+    /// Name of the event, as used in hooks and serialized output.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Event::SceneChanged { .. } => "scene_changed",
+            Event::OutputToggled { .. } => "output_toggled",
+            Event::ChargingStarted => "charging_started",
+            Event::ChargingStopped => "charging_stopped",
+            Event::UndervoltageSet => "undervoltage_set",
+            Event::UndervoltageCleared => "undervoltage_cleared",
+        }
+    }
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Event::SceneChanged { from, to } => {
+                write!(f, "scene changed from {} to {}", from.as_str(), to.as_str())
+            }
+            Event::OutputToggled { output, active } => {
+                let state = if *active { "on" } else { "off" };
+                write!(f, "output {output} switched {state}")
+            }
+            Event::ChargingStarted => f.write_str("charging started"),
+            Event::ChargingStopped => f.write_str("charging stopped"),
+            Event::UndervoltageSet => f.write_str("battery undervoltage"),
+            Event::UndervoltageCleared => f.write_str("battery undervoltage resolved"),
+        }
+    }
+}
+
+/// Returns all events which happened between the `previous` and the `current` sample.
+pub fn diff(previous: &DeviceInfo, current: &DeviceInfo) -> Vec<Event> {
+    let mut events = Vec::new();
+
+    if previous.scene != current.scene {
+        events.push(Event::SceneChanged {
+            from: previous.scene,
+            to: current.scene,
+        });
+    }
+
+    let outputs = [
+        (previous.output1, current.output1),
+        (previous.output2, current.output2),
+    ];
+    for (output, (previous, current)) in (1..).zip(outputs) {
+        if previous.active != current.active {
+            events.push(Event::OutputToggled {
+                output,
+                active: current.active,
+            });
+        }
+    }
+
+    let (previous, current) = (previous.battery.internal, current.battery.internal);
+    match (previous.charging, current.charging) {
+        (false, true) => events.push(Event::ChargingStarted),
+        (true, false) => events.push(Event::ChargingStopped),
+        _ => {}
+    }
+    match (previous.undervoltage, current.undervoltage) {
+        (false, true) => events.push(Event::UndervoltageSet),
+        (true, false) => events.push(Event::UndervoltageCleared),
+        _ => {}
+    }
+
+    events
+}
+
+/// Suppresses samples, which do not differ from the previously emitted sample.
+///
+/// With a `heartbeat`, a sample is emitted regardless once the previously emitted sample is at
+/// least the heartbeat old, which lets consumers tell an idle device from a missing one.
+#[derive(Debug)]
+pub struct ChangeFilter<T> {
+    heartbeat: Option<Duration>,
+    last: Option<(SystemTime, T)>,
+}
+
+impl<T: PartialEq> ChangeFilter<T> {
+    pub fn new(heartbeat: Option<Duration>) -> Self {
+        Self {
+            heartbeat,
+            last: None,
+        }
+    }
+
+    /// Returns `true` if the sample with the `value` taken at `timestamp` should be emitted.
+    ///
+    /// The `value` must not contain the timestamp, otherwise every sample differs.
+    pub fn check(&mut self, timestamp: SystemTime, value: T) -> bool {
+        if let Some((last_timestamp, last)) = &self.last {
+            let age = timestamp
+                .duration_since(*last_timestamp)
+                .unwrap_or_default();
+            let expired = self.heartbeat.is_some_and(|heartbeat| age >= heartbeat);
+            if *last == value && !expired {
+                return false;
+            }
+        }
+
+        self.last = Some((timestamp, value));
+        true
+    }
+}
+
+impl ChangeFilter<DeviceInfo> {
+    /// Returns `true` if the `device_info` differs from the previously emitted one, ignoring
+    /// the timestamp.
+    pub fn check_device_info(&mut self, device_info: &DeviceInfo) -> bool {
+        let value = DeviceInfo {
+            timestamp: SystemTime::UNIX_EPOCH,
+            ..*device_info
+        };
+        self.check(device_info.timestamp, value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn device_info() -> DeviceInfo {
+        serde_json::from_value(serde_json::json!({
+            "timestamp": 0,
+            "solar1": { "charging": true, "pass_through": false, "power": 84 },
+            "solar2": { "charging": true, "pass_through": false, "power": 86 },
+            "output1": { "power": 1, "active": true },
+            "output2": { "power": 0, "active": true },
+            "temperature": { "min": 21, "max": 21 },
+            "battery": {
+                "charge": 53,
+                "capacity": 1187,
+                "output_threshold": 300,
+                "discharge_depth": 80,
+                "internal": {
+                    "charging": false,
+                    "discharging": true,
+                    "discharge_depth": false,
+                    "undervoltage": false
+                }
+            },
+            "scene": "day"
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_diff() {
+        let previous = device_info();
+        assert_eq!(diff(&previous, &previous), []);
+
+        let mut current = previous;
+        current.scene = Scene::Dusk;
+        current.output2.active = false;
+        current.battery.internal.charging = true;
+        current.battery.internal.undervoltage = true;
+        assert_eq!(
+            diff(&previous, &current),
+            [
+                Event::SceneChanged {
+                    from: Scene::Day,
+                    to: Scene::Dusk
+                },
+                Event::OutputToggled {
+                    output: 2,
+                    active: false
+                },
+                Event::ChargingStarted,
+                Event::UndervoltageSet,
+            ]
+        );
+        assert_eq!(
+            diff(&current, &previous)[2..],
+            [Event::ChargingStopped, Event::UndervoltageCleared]
+        );
+
+        let event = serde_json::to_value(Event::OutputToggled {
+            output: 1,
+            active: true,
+        })
+        .unwrap();
+        assert_eq!(
+            event,
+            serde_json::json!({ "event": "output_toggled", "output": 1, "active": true })
+        );
+    }
+
+    #[test]
+    fn test_change_filter() {
+        let at = |secs: u64| SystemTime::UNIX_EPOCH + Duration::from_secs(secs);
+
+        let mut filter = ChangeFilter::new(None);
+        assert!(filter.check(at(0), 1));
+        assert!(!filter.check(at(10), 1));
+        assert!(filter.check(at(20), 2));
+        assert!(!filter.check(at(9999), 2));
+
+        let mut filter = ChangeFilter::new(Some(Duration::from_secs(60)));
+        assert!(filter.check(at(0), 1));
+        assert!(!filter.check(at(30), 1));
+        // The heartbeat is measured from the last emitted sample.
+        assert!(filter.check(at(60), 1));
+        assert!(!filter.check(at(90), 1));
+        assert!(filter.check(at(100), 2));
+        assert!(!filter.check(at(150), 2));
+    }
+}
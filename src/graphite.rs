@@ -0,0 +1,119 @@
+use std::fmt::Write as _;
+use std::time::{Duration, SystemTime};
+
+macro_rules! wrt {
+    ($dst:expr, $($arg:tt)*) => {
+        write!($dst, $($arg)*).expect("writing to a string never fails");
+    };
+}
+
+/// A collection of metrics in the Graphite plaintext protocol.
+///
+/// Every metric is written as a `path value timestamp` line.
+pub struct Metrics {
+    prefix: String,
+    timestamp: u64,
+    lines: String,
+}
+
+impl Metrics {
+    /// Creates a new collection of metrics, all paths are prefixed with `prefix`.
+    pub fn new(prefix: &str) -> Self {
+        Self {
+            prefix: prefix.to_owned(),
+            timestamp: 0,
+            lines: String::new(),
+        }
+    }
+
+    /// Appends `segment` to the prefix of all following metrics.
+    ///
+    /// Characters which are not valid in a Graphite path are replaced with `_`.
+    pub fn segment(&mut self, segment: &str) -> &mut Self {
+        if !self.prefix.is_empty() {
+            self.prefix.push('.');
+        }
+        self.prefix.extend(segment.chars().map(sanitize));
+        self
+    }
+
+    /// Sets the timestamp for all following metrics.
+    pub fn timestamp(&mut self, timestamp: SystemTime) -> &mut Self {
+        self.timestamp = timestamp
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or(Duration::ZERO)
+            .as_secs();
+        self
+    }
+
+    /// Appends a metric, `path` is relative to the prefix.
+    pub fn metric<T: GraphiteValue>(&mut self, path: &str, value: T) -> &mut Self {
+        if !self.prefix.is_empty() {
+            wrt!(&mut self.lines, "{}.", self.prefix);
+        }
+        wrt!(&mut self.lines, "{path} ");
+        value.write_to(&mut self.lines);
+        wrt!(&mut self.lines, " {}\n", self.timestamp);
+        self
+    }
+
+    /// Returns all metrics, terminated by a newline.
+    pub fn finish(self) -> String {
+        self.lines
+    }
+}
+
+fn sanitize(c: char) -> char {
+    match c {
+        'a'..='z' | 'A'..='Z' | '0'..='9' | '_' | '-' => c,
+        _ => '_',
+    }
+}
+
+mod ඞ {
+    use std::fmt::Write;
+
+    pub trait GraphiteValue {
+        fn write_to(&self, sink: &mut String);
+    }
+
+    impl GraphiteValue for bool {
+        fn write_to(&self, sink: &mut String) {
+            sink.push(if *self { '1' } else { '0' });
+        }
+    }
+
+    macro_rules! impl_display {
+        ($($ty:ty),*) => {
+            $(impl GraphiteValue for $ty {
+                fn write_to(&self, sink: &mut String) {
+                    wrt!(sink, "{self}");
+                }
+            })*
+        };
+    }
+    impl_display!(f32, f64, i8, i16, i32, i64, u8, u16, u32, u64);
+}
+use self::ඞ::GraphiteValue;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_metrics() {
+        let mut metrics = Metrics::new("home.battery");
+        metrics
+            .segment("9523ccae1a9b")
+            .timestamp(SystemTime::UNIX_EPOCH + Duration::from_secs(1745745900))
+            .metric("solar1.power", 84u32)
+            .metric("solar1.charging", true)
+            .metric("temperature.min", -3i32);
+
+        insta::assert_snapshot!(metrics.finish(), @r###"
+        home.battery.9523ccae1a9b.solar1.power 84 1745745900
+        home.battery.9523ccae1a9b.solar1.charging 1 1745745900
+        home.battery.9523ccae1a9b.temperature.min -3 1745745900
+        "###);
+    }
+}
@@ -0,0 +1,173 @@
+//! HTTP client shared by webhooks, sinks and the cloud account.
+//!
+//! Requests time out after [`TIMEOUT`] and response bodies are limited to [`MAX_BODY_SIZE`],
+//! `https://` URLs are verified against the certificates of the system.
+use std::sync::LazyLock;
+use std::time::Duration;
+
+use bytes::Bytes;
+pub use reqwest::{Method, Url};
+
+/// Time a request may take in total, from connecting until the body is read.
+pub const TIMEOUT: Duration = Duration::from_secs(30);
+/// Maximum size of a response body.
+pub const MAX_BODY_SIZE: usize = 1024 * 1024;
+
+static CLIENT: LazyLock<reqwest::Client> = LazyLock::new(|| {
+    reqwest::Client::builder()
+        .timeout(TIMEOUT)
+        .user_agent(concat!("hmtk/", env!("CARGO_PKG_VERSION")))
+        .build()
+        .expect("valid http client configuration")
+});
+
+/// Errors which can occur when sending a request.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("unsupported url `{0}`, expected an `http://` or `https://` url")]
+    UnsupportedUrl(String),
+    #[error(transparent)]
+    Request(reqwest::Error),
+    /// The server responded with a status other than `2xx`.
+    #[error("{url} responded with status `{status}`")]
+    Status { url: String, status: u16 },
+    #[error("response of {url} is larger than {MAX_BODY_SIZE} bytes")]
+    TooLarge { url: String },
+}
+
+/// Parses an `http://` or `https://` URL.
+pub fn parse_url(url: &str) -> Result<Url, Error> {
+    match Url::parse(url) {
+        Ok(parsed) if matches!(parsed.scheme(), "http" | "https") && parsed.has_host() => {
+            Ok(parsed)
+        }
+        _ => Err(Error::UnsupportedUrl(url.to_owned())),
+    }
+}
+
+/// Sends a request with `method` and `body` to `url` and returns the body of the response.
+///
+/// Fails unless the response is a `2xx`.
+pub async fn send(
+    method: Method,
+    url: Url,
+    content_type: &str,
+    body: impl Into<Vec<u8>>,
+) -> Result<Bytes, Error> {
+    let request = CLIENT
+        .request(method, url.clone())
+        .header(reqwest::header::CONTENT_TYPE, content_type)
+        .body(body.into());
+    read(url, request).await
+}
+
+/// Sends a `GET` request to `url` and returns the body of the response.
+///
+/// Fails unless the response is a `2xx`.
+pub async fn get(url: Url) -> Result<Bytes, Error> {
+    let request = CLIENT.get(url.clone());
+    read(url, request).await
+}
+
+async fn read(mut url: Url, request: reqwest::RequestBuilder) -> Result<Bytes, Error> {
+    // Queries may contain credentials, they are not included in errors.
+    url.set_query(None);
+    let error = |err: reqwest::Error| Error::Request(err.without_url());
+
+    let mut response = request.send().await.map_err(error)?;
+    let status = response.status();
+    if !status.is_success() {
+        return Err(Error::Status {
+            url: url.to_string(),
+            status: status.as_u16(),
+        });
+    }
+
+    let too_large = || Error::TooLarge {
+        url: url.to_string(),
+    };
+    if response
+        .content_length()
+        .is_some_and(|length| length > MAX_BODY_SIZE as u64)
+    {
+        return Err(too_large());
+    }
+
+    let mut body = Vec::new();
+    while let Some(chunk) = response.chunk().await.map_err(error)? {
+        if body.len() + chunk.len() > MAX_BODY_SIZE {
+            return Err(too_large());
+        }
+        body.extend_from_slice(&chunk);
+    }
+    Ok(body.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    /// Serves a single request with the raw `response`, returns the URL and the request.
+    async fn serve(response: Vec<u8>) -> (Url, tokio::task::JoinHandle<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/path?secret=1", listener.local_addr().unwrap());
+
+        let server = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = vec![0; 4096];
+            let n = stream.read(&mut request).await.unwrap();
+            let _ = stream.write_all(&response).await;
+            String::from_utf8_lossy(&request[..n]).into_owned()
+        });
+        (parse_url(&url).unwrap(), server)
+    }
+
+    #[test]
+    fn test_parse_url() {
+        assert!(parse_url("https://example.com/hook").is_ok());
+        assert_eq!(
+            parse_url("http://[::1]:8080/a").unwrap().host_str(),
+            Some("[::1]")
+        );
+        assert!(matches!(
+            parse_url("ftp://example.com"),
+            Err(Error::UnsupportedUrl(_))
+        ));
+        assert!(parse_url("example.com").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_send() {
+        let (url, server) =
+            serve(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\nConnection: close\r\n\r\nok".to_vec())
+                .await;
+        let body = send(Method::PUT, url, "text/plain", "body").await.unwrap();
+        assert_eq!(body, "ok");
+
+        let request = server.await.unwrap();
+        assert!(request.starts_with("PUT /path?secret=1 HTTP/1.1\r\n"));
+        assert!(request.contains("content-type: text/plain\r\n"));
+        assert!(request.ends_with("\r\n\r\nbody"));
+    }
+
+    #[tokio::test]
+    async fn test_errors() {
+        let (url, _) = serve(b"HTTP/1.1 503 Service Unavailable\r\n\r\n".to_vec()).await;
+        let err = get(url.clone()).await.unwrap_err();
+        // The query is not part of the error, it may contain credentials.
+        let expected = url.as_str().replace("?secret=1", "");
+        assert_eq!(
+            err.to_string(),
+            format!("{expected} responded with status `503`")
+        );
+
+        // Bodies are limited, also without a content length.
+        let mut response = b"HTTP/1.1 200 OK\r\nConnection: close\r\n\r\n".to_vec();
+        response.resize(response.len() + MAX_BODY_SIZE + 1, b'a');
+        let (url, _) = serve(response).await;
+        assert!(matches!(get(url).await, Err(Error::TooLarge { .. })));
+    }
+}
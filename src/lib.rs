@@ -1,3 +1,19 @@
+pub mod alerts;
+pub mod analytics;
+#[cfg(feature = "blocking")]
+pub mod blocking;
+pub mod capture;
+pub mod cloud;
+pub mod events;
+pub mod graphite;
+pub mod http;
 pub mod influx;
+pub mod locale;
+pub mod mdns;
 pub mod mqtt;
+pub mod notify;
+pub mod parser;
+pub mod stats;
+pub mod time;
+pub mod toml;
 pub mod units;
@@ -0,0 +1,242 @@
+//! Locale aware formatting for human-facing outputs.
+//!
+//! Machine readable formats (JSON, Influx, ...) must never go through this module,
+//! their representation is fixed and independent of the user's locale.
+//!
+//! Translations are loaded from simple TOML bundles:
+//!
+//! ```toml
+//! decimal_separator = ","
+//!
+//! [labels]
+//! "solar1.power" = "Solar 1 Leistung"
+//! yes = "ja"
+//! no = "nein"
+//! ```
+//!
+//! Bundles are parsed with [`crate::toml`].
+use std::collections::BTreeMap;
+
+/// Languages which use a `,` as their decimal separator.
+const COMMA_LANGUAGES: &[&str] = &[
+    "bg", "cs", "da", "de", "el", "es", "fi", "fr", "hr", "hu", "id", "it", "nb", "nl", "nn", "pl",
+    "pt", "ro", "ru", "sk", "sl", "sr", "sv", "tr", "uk", "vi",
+];
+
+/// Default (english) labels.
+const DEFAULT_LABELS: &[(&str, &str)] = &[
+    ("timestamp", "Timestamp"),
+    ("solar1.charging", "Solar 1 charging"),
+    ("solar1.pass_through", "Solar 1 pass through"),
+    ("solar1.power", "Solar 1 power"),
+    ("solar2.charging", "Solar 2 charging"),
+    ("solar2.pass_through", "Solar 2 pass through"),
+    ("solar2.power", "Solar 2 power"),
+    ("output1.active", "Output 1 active"),
+    ("output1.power", "Output 1 power"),
+    ("output2.active", "Output 2 active"),
+    ("output2.power", "Output 2 power"),
+    ("temperature.min", "Temperature min"),
+    ("temperature.max", "Temperature max"),
+    ("battery.charge", "Battery charge"),
+    ("battery.capacity", "Battery capacity"),
+    ("battery.output_threshold", "Battery output threshold"),
+    ("battery.discharge_depth", "Battery discharge depth"),
+    ("battery.internal.charging", "Battery charging"),
+    ("battery.internal.discharging", "Battery discharging"),
+    (
+        "battery.internal.discharge_depth",
+        "Battery discharge depth reached",
+    ),
+    ("battery.internal.undervoltage", "Battery undervoltage"),
+    ("scene", "Scene"),
+    ("grid.voltage", "Grid voltage"),
+    ("grid.frequency", "Grid frequency"),
+    ("limits.charge", "Charge limit"),
+    ("limits.output", "Output limit"),
+    ("derived.solar_power", "Total solar power"),
+    ("derived.output_power", "Total output power"),
+    ("derived.net_power", "Net battery power"),
+    ("energy.solar", "Solar energy today"),
+    ("energy.output", "Output energy today"),
+    ("energy.battery_charge", "Battery charged today"),
+    ("energy.battery_discharge", "Battery discharged today"),
+    ("accumulated.solar1", "Solar 1 energy today"),
+    ("accumulated.solar2", "Solar 2 energy today"),
+    ("accumulated.output1", "Output 1 energy today"),
+    ("accumulated.output2", "Output 2 energy today"),
+    ("day", "day"),
+    ("dusk", "dusk"),
+    ("night", "night"),
+    ("yes", "yes"),
+    ("no", "no"),
+];
+
+/// Error returned when a translation bundle cannot be parsed.
+#[derive(Debug, thiserror::Error)]
+pub enum InvalidBundle {
+    #[error("invalid locale bundle: {0}")]
+    Syntax(#[from] crate::toml::Error),
+    #[error("invalid locale bundle: {0}")]
+    Invalid(&'static str),
+}
+
+/// Layout of a translation bundle.
+#[derive(Debug, serde::Deserialize)]
+struct Bundle {
+    decimal_separator: Option<String>,
+    #[serde(default)]
+    labels: serde_json::Map<String, serde_json::Value>,
+}
+
+/// Formatting rules and translations for human-facing outputs.
+#[derive(Debug, Clone)]
+pub struct Locale {
+    decimal_separator: char,
+    labels: BTreeMap<String, String>,
+}
+
+impl Default for Locale {
+    fn default() -> Self {
+        Self {
+            decimal_separator: '.',
+            labels: BTreeMap::new(),
+        }
+    }
+}
+
+impl Locale {
+    /// Creates a locale from the `LC_ALL`, `LC_NUMERIC` or `LANG` environment variables.
+    ///
+    /// The environment only determines number formatting, labels are always english.
+    pub fn from_env() -> Self {
+        let lang = ["LC_ALL", "LC_NUMERIC", "LANG"]
+            .into_iter()
+            .filter_map(|name| std::env::var(name).ok())
+            .find(|value| !value.is_empty());
+
+        let mut locale = Self::default();
+        if let Some(lang) = lang {
+            let language = lang.split(['_', '.', '@']).next().unwrap_or_default();
+            if COMMA_LANGUAGES.contains(&language) {
+                locale.decimal_separator = ',';
+            }
+        }
+        locale
+    }
+
+    /// Loads a translation bundle, settings not contained in the bundle are taken from `self`.
+    pub fn with_bundle(mut self, bundle: &str) -> Result<Self, InvalidBundle> {
+        let bundle: Bundle = crate::toml::from_str(bundle)?;
+
+        if let Some(separator) = bundle.decimal_separator {
+            let mut chars = separator.chars();
+            match (chars.next(), chars.next()) {
+                (Some(c), None) => self.decimal_separator = c,
+                _ => {
+                    return Err(InvalidBundle::Invalid(
+                        "decimal separator must be a single character",
+                    ));
+                }
+            }
+        }
+
+        flatten_labels(&mut self.labels, "", bundle.labels)?;
+        Ok(self)
+    }
+
+    /// Returns the translated label for `key`.
+    ///
+    /// Falls back to the default english label and, if the key is unknown, to the key itself.
+    pub fn label<'a>(&'a self, key: &'a str) -> &'a str {
+        if let Some(label) = self.labels.get(key) {
+            return label;
+        }
+        DEFAULT_LABELS
+            .iter()
+            .find(|(k, _)| *k == key)
+            .map(|(_, label)| *label)
+            .unwrap_or(key)
+    }
+
+    /// Translates a boolean into `yes` or `no`.
+    pub fn bool(&self, value: bool) -> &str {
+        self.label(if value { "yes" } else { "no" })
+    }
+
+    /// Formats a number with `precision` decimal places.
+    pub fn number(&self, value: f64, precision: usize) -> String {
+        let value = format!("{value:.precision$}");
+        match self.decimal_separator {
+            '.' => value,
+            separator => value.replace('.', &separator.to_string()),
+        }
+    }
+}
+
+/// Flattens nested label tables into dotted keys, `battery.charge = ".."` is a nested table in TOML.
+fn flatten_labels(
+    labels: &mut BTreeMap<String, String>,
+    prefix: &str,
+    table: serde_json::Map<String, serde_json::Value>,
+) -> Result<(), InvalidBundle> {
+    for (key, value) in table {
+        let key = match prefix.is_empty() {
+            true => key,
+            false => format!("{prefix}.{key}"),
+        };
+        match value {
+            serde_json::Value::String(label) => {
+                labels.insert(key, label);
+            }
+            serde_json::Value::Object(table) => flatten_labels(labels, &key, table)?,
+            _ => return Err(InvalidBundle::Invalid("labels must be strings")),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bundle() {
+        let bundle = r#"
+            # German translation.
+            decimal_separator = ","
+
+            [labels]
+            "solar1.power" = "Solar 1 Leistung" # inline comment
+            yes = "ja"
+            battery.charge = "Ladung \"Akku\""
+        "#;
+
+        let locale = Locale::default().with_bundle(bundle).unwrap();
+        assert_eq!(locale.label("solar1.power"), "Solar 1 Leistung");
+        assert_eq!(locale.label("battery.charge"), "Ladung \"Akku\"");
+        assert_eq!(locale.label("solar2.power"), "Solar 2 power");
+        assert_eq!(locale.label("unknown"), "unknown");
+        assert_eq!(locale.bool(true), "ja");
+        assert_eq!(locale.number(1.5, 1), "1,5");
+    }
+
+    #[test]
+    fn test_bundle_invalid() {
+        let err = Locale::default()
+            .with_bundle("[labels]\nyes = ja")
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "invalid locale bundle: line 2: string values must be quoted, expected literal string"
+        );
+
+        let err = Locale::default()
+            .with_bundle("[labels]\nyes = 1")
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "invalid locale bundle: labels must be strings"
+        );
+    }
+}
@@ -0,0 +1,350 @@
+//! Discovery of MQTT brokers, which announce themselves with mDNS/DNS-SD on the local network.
+//!
+//! Brokers like Mosquitto with Avahi, or the Home Assistant add-on, announce `_mqtt._tcp` and
+//! `_secure-mqtt._tcp` services. Queries are sent from an ephemeral port (legacy unicast), the
+//! responders answer directly, which also works next to a local mDNS daemon bound to port 5353.
+use std::collections::HashMap;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::Duration;
+
+use serde::Serialize;
+use tokio::net::UdpSocket;
+
+/// Service type of MQTT brokers.
+pub const MQTT_SERVICE: &str = "_mqtt._tcp.local";
+/// Service type of MQTT brokers, which require TLS.
+pub const SECURE_MQTT_SERVICE: &str = "_secure-mqtt._tcp.local";
+
+const MDNS_ADDRESS: SocketAddr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(224, 0, 0, 251)), 5353);
+
+const TYPE_A: u16 = 1;
+const TYPE_PTR: u16 = 12;
+const TYPE_AAAA: u16 = 28;
+const TYPE_SRV: u16 = 33;
+const CLASS_IN: u16 = 1;
+
+/// An MQTT broker announced on the local network.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Broker {
+    /// Name of the service instance, e.g. `Mosquitto MQTT server on raspberrypi`.
+    pub name: String,
+    /// Host name of the broker, e.g. `raspberrypi.local`.
+    pub host: String,
+    pub port: u16,
+    /// Addresses of the host, IPv4 addresses first.
+    pub addresses: Vec<IpAddr>,
+    /// Whether the broker requires TLS, announced as `_secure-mqtt._tcp`.
+    pub tls: bool,
+}
+
+impl Broker {
+    /// URL of the broker, as accepted by `--mqtt-url`, with the first address of the host.
+    pub fn url(&self) -> String {
+        let scheme = if self.tls { "mqtts" } else { "mqtt" };
+        match self.addresses.first() {
+            Some(IpAddr::V6(address)) => format!("{scheme}://[{address}]:{}", self.port),
+            Some(address) => format!("{scheme}://{address}:{}", self.port),
+            None => format!("{scheme}://{}:{}", self.host, self.port),
+        }
+    }
+}
+
+/// Browses the local network for MQTT brokers, collecting the answers until `timeout`.
+pub async fn browse(timeout: Duration) -> io::Result<Vec<Broker>> {
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).await?;
+    let query = query(&[MQTT_SERVICE, SECURE_MQTT_SERVICE]);
+
+    let mut records = Vec::new();
+    let mut buffer = vec![0; 9000];
+    let deadline = tokio::time::Instant::now() + timeout;
+    // Multicast is unreliable, the query is repeated once.
+    let mut resend = Some(tokio::time::Instant::now() + timeout / 3);
+    socket.send_to(&query, MDNS_ADDRESS).await?;
+    loop {
+        let until = resend.map_or(deadline, |resend| resend.min(deadline));
+        match tokio::time::timeout_at(until, socket.recv_from(&mut buffer)).await {
+            Ok(result) => {
+                let (len, _) = result?;
+                // Other traffic or malformed responses are ignored.
+                if let Some(response) = parse_response(&buffer[..len]) {
+                    records.extend(response);
+                }
+            }
+            Err(_) if resend.take().is_some() => {
+                socket.send_to(&query, MDNS_ADDRESS).await?;
+            }
+            Err(_) => break,
+        }
+    }
+
+    Ok(brokers(&records))
+}
+
+/// Encodes a query for the `PTR` records of all `services`.
+fn query(services: &[&str]) -> Vec<u8> {
+    let mut packet = vec![0; 12];
+    packet[4..6].copy_from_slice(&(services.len() as u16).to_be_bytes());
+    for service in services {
+        for label in service.split('.') {
+            packet.push(label.len() as u8);
+            packet.extend_from_slice(label.as_bytes());
+        }
+        packet.push(0);
+        packet.extend_from_slice(&TYPE_PTR.to_be_bytes());
+        packet.extend_from_slice(&CLASS_IN.to_be_bytes());
+    }
+    packet
+}
+
+/// A resource record of a response, names are without the trailing dot.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Record {
+    Ptr {
+        name: String,
+        target: String,
+    },
+    Srv {
+        name: String,
+        host: String,
+        port: u16,
+    },
+    Address {
+        name: String,
+        address: IpAddr,
+    },
+}
+
+/// Parses all records of a response, returns `None` for queries and malformed packets.
+fn parse_response(packet: &[u8]) -> Option<Vec<Record>> {
+    let header = |index: usize| {
+        let offset = 2 * index;
+        Some(u16::from_be_bytes(
+            packet.get(offset..offset + 2)?.try_into().ok()?,
+        ))
+    };
+    let is_response = header(1)? & 0x8000 != 0;
+    if !is_response {
+        return None;
+    }
+    let questions = header(2)?;
+    let records = header(3)? as usize + header(4)? as usize + header(5)? as usize;
+
+    let mut offset = 12;
+    for _ in 0..questions {
+        offset = read_name(packet, offset)?.1 + 4;
+    }
+
+    let mut result = Vec::new();
+    for _ in 0..records {
+        let (name, end) = read_name(packet, offset)?;
+        let ty = u16::from_be_bytes(packet.get(end..end + 2)?.try_into().ok()?);
+        let len = u16::from_be_bytes(packet.get(end + 8..end + 10)?.try_into().ok()?) as usize;
+        let data_offset = end + 10;
+        let data = packet.get(data_offset..data_offset + len)?;
+        offset = data_offset + len;
+
+        let record = match ty {
+            TYPE_PTR => Record::Ptr {
+                name,
+                target: read_name(packet, data_offset)?.0,
+            },
+            TYPE_SRV => Record::Srv {
+                name,
+                host: read_name(packet, data_offset + 6)?.0,
+                port: u16::from_be_bytes(data.get(4..6)?.try_into().ok()?),
+            },
+            TYPE_A => Record::Address {
+                name,
+                address: Ipv4Addr::from(<[u8; 4]>::try_from(data).ok()?).into(),
+            },
+            TYPE_AAAA => Record::Address {
+                name,
+                address: Ipv6Addr::from(<[u8; 16]>::try_from(data).ok()?).into(),
+            },
+            _ => continue,
+        };
+        result.push(record);
+    }
+    Some(result)
+}
+
+/// Reads the possibly compressed name at `offset`, returns it with the offset after the name.
+fn read_name(packet: &[u8], mut offset: usize) -> Option<(String, usize)> {
+    let mut labels = Vec::new();
+    let mut end = None;
+    // Limits the pointers followed, malicious packets could contain loops.
+    for _ in 0..64 {
+        let len = *packet.get(offset)? as usize;
+        match len {
+            0 => {
+                let name = labels.join(".");
+                return Some((name, end.unwrap_or(offset + 1)));
+            }
+            _ if len & 0xc0 == 0xc0 => {
+                let pointer = u16::from_be_bytes([*packet.get(offset)?, *packet.get(offset + 1)?]);
+                end.get_or_insert(offset + 2);
+                offset = (pointer & 0x3fff) as usize;
+            }
+            _ => {
+                let label = packet.get(offset + 1..offset + 1 + len)?;
+                labels.push(String::from_utf8_lossy(label).into_owned());
+                offset += 1 + len;
+            }
+        }
+    }
+    None
+}
+
+/// Resolves the announced services into brokers, services without `SRV` record are skipped.
+///
+/// Names are compared case-insensitively, like all DNS names.
+fn brokers(records: &[Record]) -> Vec<Broker> {
+    let mut services = HashMap::new();
+    let mut addresses = HashMap::<String, Vec<IpAddr>>::new();
+    for record in records {
+        match record {
+            Record::Srv { name, host, port } => {
+                services.insert(name.to_ascii_lowercase(), (host.as_str(), *port));
+            }
+            Record::Address { name, address } => {
+                let addresses = addresses.entry(name.to_ascii_lowercase()).or_default();
+                if !addresses.contains(address) {
+                    addresses.push(*address);
+                }
+            }
+            Record::Ptr { .. } => {}
+        }
+    }
+
+    let mut brokers = Vec::new();
+    for record in records {
+        let Record::Ptr { name, target } = record else {
+            continue;
+        };
+        let service = name.to_ascii_lowercase();
+        let tls = match service.as_str() {
+            MQTT_SERVICE => false,
+            SECURE_MQTT_SERVICE => true,
+            _ => continue,
+        };
+        let target_key = target.to_ascii_lowercase();
+        let Some(&(host, port)) = services.get(&target_key) else {
+            continue;
+        };
+        let mut addresses = addresses
+            .get(&host.to_ascii_lowercase())
+            .cloned()
+            .unwrap_or_default();
+        addresses.sort_by_key(IpAddr::is_ipv6);
+        // The instance name is the first label, it may contain dots itself.
+        let instance = match target_key.strip_suffix(&format!(".{service}")) {
+            Some(instance) => &target[..instance.len()],
+            None => target,
+        };
+        let broker = Broker {
+            name: instance.to_owned(),
+            host: host.to_owned(),
+            port,
+            addresses,
+            tls,
+        };
+        if !brokers.contains(&broker) {
+            brokers.push(broker);
+        }
+    }
+    brokers.sort_by(|a: &Broker, b: &Broker| (&a.host, a.port).cmp(&(&b.host, b.port)));
+    brokers
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn name(name: &str) -> Vec<u8> {
+        let mut result = Vec::new();
+        for label in name.split('.') {
+            result.push(label.len() as u8);
+            result.extend_from_slice(label.as_bytes());
+        }
+        result.push(0);
+        result
+    }
+
+    fn record(packet: &mut Vec<u8>, owner: &[u8], ty: u16, data: &[u8]) {
+        packet.extend_from_slice(owner);
+        packet.extend_from_slice(&ty.to_be_bytes());
+        packet.extend_from_slice(&CLASS_IN.to_be_bytes());
+        packet.extend_from_slice(&120u32.to_be_bytes());
+        packet.extend_from_slice(&(data.len() as u16).to_be_bytes());
+        packet.extend_from_slice(data);
+    }
+
+    #[test]
+    fn test_query() {
+        let query = query(&[MQTT_SERVICE]);
+        assert_eq!(&query[..12], &[0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(
+            &query[12..],
+            [name(MQTT_SERVICE), vec![0, 12, 0, 1]].concat()
+        );
+    }
+
+    #[test]
+    fn test_browse_response() {
+        let mut packet = vec![0, 0, 0x84, 0, 0, 0, 0, 1, 0, 0, 0, 4];
+        // The service type is referenced with a compression pointer to offset 12.
+        record(&mut packet, &name(MQTT_SERVICE), TYPE_PTR, &{
+            let mut target = vec![9];
+            target.extend_from_slice(b"Mosquitto");
+            target.extend_from_slice(&[0xc0, 12]);
+            target
+        });
+        let instance = [&[9][..], b"Mosquitto", &[0xc0, 12]].concat();
+        let mut srv = vec![0, 0, 0, 0, 0x07, 0x5b];
+        srv.extend_from_slice(&name("Pi.local"));
+        record(&mut packet, &instance, TYPE_SRV, &srv);
+        record(
+            &mut packet,
+            &name("pi.local"),
+            TYPE_AAAA,
+            &[0xfe, 0x80, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1],
+        );
+        record(&mut packet, &name("pi.local"), TYPE_A, &[192, 168, 1, 10]);
+        // Unrelated records are skipped.
+        record(&mut packet, &name("pi.local"), 16, b"\x03a=b");
+
+        let records = parse_response(&packet).unwrap();
+        assert_eq!(
+            records[0],
+            Record::Ptr {
+                name: MQTT_SERVICE.to_owned(),
+                target: format!("Mosquitto.{MQTT_SERVICE}"),
+            }
+        );
+        assert_eq!(
+            brokers(&records),
+            [Broker {
+                name: "Mosquitto".to_owned(),
+                host: "Pi.local".to_owned(),
+                port: 1883,
+                addresses: vec![
+                    Ipv4Addr::new(192, 168, 1, 10).into(),
+                    "fe80::1".parse().unwrap()
+                ],
+                tls: false,
+            }]
+        );
+        assert_eq!(brokers(&records)[0].url(), "mqtt://192.168.1.10:1883");
+
+        // Queries and truncated packets are no responses.
+        assert_eq!(parse_response(&query(&[MQTT_SERVICE])), None);
+        assert_eq!(parse_response(&packet[..packet.len() - 3]), None);
+    }
+
+    #[test]
+    fn test_read_name_loop() {
+        let packet = [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0xc0, 12];
+        assert_eq!(read_name(&packet, 12), None);
+    }
+}
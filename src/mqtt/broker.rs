@@ -0,0 +1,295 @@
+//! In-process MQTT broker for tests, [`rumqttd`] behind a proxy which observes the traffic.
+//!
+//! The proxy records connects, subscriptions and publishes of the clients and can drop all
+//! connections, like a restarting broker.
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use bytes::Bytes;
+use rumqttc::MqttOptions;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::JoinHandle;
+
+const CONNECT: u8 = 1;
+const PUBLISH: u8 = 3;
+const SUBSCRIBE: u8 = 8;
+const SUBACK: u8 = 9;
+const DISCONNECT: u8 = 14;
+
+pub struct Broker {
+    address: SocketAddr,
+    state: Arc<Mutex<State>>,
+    task: JoinHandle<()>,
+}
+
+#[derive(Default)]
+struct State {
+    connections: HashMap<u64, Connection>,
+    next_connection: u64,
+    connects: usize,
+    disconnects: usize,
+    published: Vec<(String, Bytes)>,
+}
+
+#[derive(Default)]
+struct Connection {
+    /// Topic filters acknowledged by the broker, with the QoS requested by the client.
+    subscriptions: Vec<(String, u8)>,
+    /// Topic filters not yet acknowledged, by packet id.
+    pending: HashMap<u16, Vec<(String, u8)>>,
+    task: Option<JoinHandle<()>>,
+}
+
+impl Broker {
+    /// Starts rumqttd and the proxy on random ports of the loopback interface.
+    pub async fn start() -> Self {
+        let backend = start_rumqttd().await;
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let state = Arc::new(Mutex::new(State::default()));
+
+        let task = tokio::spawn({
+            let state = Arc::clone(&state);
+            async move {
+                while let Ok((stream, _)) = listener.accept().await {
+                    let mut guard = state.lock().unwrap();
+                    guard.next_connection += 1;
+                    let id = guard.next_connection;
+                    let task = tokio::spawn(proxy(id, stream, backend, Arc::clone(&state)));
+                    guard.connections.entry(id).or_default().task = Some(task);
+                }
+            }
+        });
+
+        Self {
+            address,
+            state,
+            task,
+        }
+    }
+
+    /// Options of a client connecting to the broker with the id `client_id`.
+    pub fn options(&self, client_id: &str) -> MqttOptions {
+        MqttOptions::new(client_id, "127.0.0.1", self.address.port())
+    }
+
+    /// Number of connections, which sent a connect.
+    pub fn connects(&self) -> usize {
+        self.state.lock().unwrap().connects
+    }
+
+    /// Number of clients which disconnected gracefully.
+    pub fn disconnects(&self) -> usize {
+        self.state.lock().unwrap().disconnects
+    }
+
+    /// Waits until the broker acknowledged a subscription of `topic`.
+    pub async fn subscribed(&self, topic: &str) {
+        while self.requested_qos(topic).is_none() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    }
+
+    /// Highest QoS requested by an acknowledged subscription of `topic`.
+    pub fn requested_qos(&self, topic: &str) -> Option<u8> {
+        let state = self.state.lock().unwrap();
+        let subscriptions = state.connections.values().flat_map(|c| &c.subscriptions);
+        subscriptions
+            .filter(|(subscription, _)| subscription == topic)
+            .map(|&(_, qos)| qos)
+            .max()
+    }
+
+    /// Payloads of all messages published to `topic`.
+    pub fn published(&self, topic: &str) -> Vec<Bytes> {
+        let state = self.state.lock().unwrap();
+        state
+            .published
+            .iter()
+            .filter(|(t, _)| t == topic)
+            .map(|(_, payload)| payload.clone())
+            .collect()
+    }
+
+    /// Drops all connections, like a restarting broker.
+    pub fn drop_connections(&self) {
+        let mut state = self.state.lock().unwrap();
+        for (_, connection) in state.connections.drain() {
+            if let Some(task) = connection.task {
+                task.abort();
+            }
+        }
+    }
+
+    /// Stops accepting connections and drops all existing ones.
+    pub fn stop(&self) {
+        self.task.abort();
+        self.drop_connections();
+    }
+}
+
+impl Drop for Broker {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+/// Starts rumqttd on its own thread and waits until it accepts connections.
+async fn start_rumqttd() -> SocketAddr {
+    let address = std::net::TcpListener::bind("127.0.0.1:0")
+        .and_then(|listener| listener.local_addr())
+        .unwrap();
+
+    let server = rumqttd::ServerSettings {
+        name: "v4".to_owned(),
+        listen: address,
+        tls: None,
+        next_connection_delay_ms: 1,
+        connections: rumqttd::ConnectionSettings {
+            connection_timeout_ms: 5000,
+            max_payload_size: 64 * 1024,
+            max_inflight_count: 100,
+            auth: None,
+            external_auth: None,
+            dynamic_filters: true,
+        },
+    };
+    let config = rumqttd::Config {
+        router: rumqttd::RouterConfig {
+            max_connections: 100,
+            max_outgoing_packet_count: 200,
+            max_segment_size: 1024 * 1024,
+            max_segment_count: 10,
+            ..Default::default()
+        },
+        v4: Some(HashMap::from([("v4".to_owned(), server)])),
+        ..Default::default()
+    };
+    let mut broker = rumqttd::Broker::new(config);
+    std::thread::spawn(move || {
+        if let Err(err) = broker.start() {
+            tracing::error!("rumqttd failed: {err}");
+        }
+    });
+
+    while TcpStream::connect(address).await.is_err() {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    address
+}
+
+/// Forwards the connection `id` of a client to rumqttd, until either side closes it.
+async fn proxy(id: u64, client: TcpStream, backend: SocketAddr, state: Arc<Mutex<State>>) {
+    let Ok(server) = TcpStream::connect(backend).await else {
+        return;
+    };
+    let (client_read, client_write) = client.into_split();
+    let (server_read, server_write) = server.into_split();
+
+    let outgoing = forward(client_read, server_write, |ty, flags, body| {
+        // Malformed packets are left to rumqttd.
+        let _ = observe_client(&state, id, ty, flags, body);
+    });
+    let incoming = forward(server_read, client_write, |ty, _, body| {
+        if ty == SUBACK {
+            observe_suback(&state, id, body);
+        }
+    });
+    tokio::select! {
+        _ = outgoing => {}
+        _ = incoming => {}
+    }
+
+    state.lock().unwrap().connections.remove(&id);
+}
+
+/// Copies packets from `reader` to `writer` until either side is closed, passing the type,
+/// flags and body of every packet to `observe`.
+async fn forward(
+    mut reader: impl AsyncRead + Unpin,
+    mut writer: impl AsyncWrite + Unpin,
+    mut observe: impl FnMut(u8, u8, &[u8]),
+) {
+    while let Some((header, body_start, packet)) = read_packet(&mut reader).await {
+        observe(header >> 4, header & 0x0f, &packet[body_start..]);
+        if writer.write_all(&packet).await.is_err() {
+            return;
+        }
+    }
+}
+
+fn observe_client(state: &Mutex<State>, id: u64, ty: u8, flags: u8, body: &[u8]) -> Option<()> {
+    let mut state = state.lock().unwrap();
+    match ty {
+        CONNECT => state.connects += 1,
+        PUBLISH => {
+            let (topic, rest) = read_str(body)?;
+            let payload = match (flags >> 1) & 0b11 {
+                0 => rest,
+                _ => rest.get(2..)?,
+            };
+            let published = (topic.to_owned(), Bytes::copy_from_slice(payload));
+            state.published.push(published);
+        }
+        SUBSCRIBE => {
+            let (packet_id, mut rest) = body.split_at_checked(2)?;
+            let mut filters = Vec::new();
+            while !rest.is_empty() {
+                let (topic, remaining) = read_str(rest)?;
+                filters.push((topic.to_owned(), remaining.first()? & 0b11));
+                rest = remaining.get(1..)?;
+            }
+            let packet_id = u16::from_be_bytes([packet_id[0], packet_id[1]]);
+            let connection = state.connections.entry(id).or_default();
+            connection.pending.insert(packet_id, filters);
+        }
+        DISCONNECT => state.disconnects += 1,
+        _ => {}
+    }
+    Some(())
+}
+
+fn observe_suback(state: &Mutex<State>, id: u64, body: &[u8]) {
+    let Some(&[high, low]) = body.first_chunk() else {
+        return;
+    };
+    let mut state = state.lock().unwrap();
+    if let Some(connection) = state.connections.get_mut(&id)
+        && let Some(filters) = connection.pending.remove(&u16::from_be_bytes([high, low]))
+    {
+        connection.subscriptions.extend(filters);
+    }
+}
+
+/// Reads the next packet, returns its fixed header, the offset of the body and the raw packet.
+async fn read_packet(reader: &mut (impl AsyncRead + Unpin)) -> Option<(u8, usize, Vec<u8>)> {
+    let header = reader.read_u8().await.ok()?;
+    let mut packet = vec![header];
+
+    let mut length = 0;
+    for shift in (0..28).step_by(7) {
+        let byte = reader.read_u8().await.ok()?;
+        packet.push(byte);
+        length |= usize::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            break;
+        }
+    }
+
+    let body_start = packet.len();
+    packet.resize(body_start + length, 0);
+    reader.read_exact(&mut packet[body_start..]).await.ok()?;
+    Some((header, body_start, packet))
+}
+
+/// Splits a length prefixed string off `data`, `None` if it is truncated or not UTF-8.
+fn read_str(data: &[u8]) -> Option<(&str, &[u8])> {
+    let (length, rest) = data.split_at_checked(2)?;
+    let length = usize::from(u16::from_be_bytes([length[0], length[1]]));
+    let (value, rest) = rest.split_at_checked(length)?;
+    Some((std::str::from_utf8(value).ok()?, rest))
+}
@@ -0,0 +1,446 @@
+//! A thin layer over the MQTT 3.1.1 and MQTT 5 clients of rumqttc.
+use std::io::ErrorKind;
+use std::sync::Arc;
+use std::time::Duration;
+
+use bytes::Bytes;
+use rumqttc::tokio_rustls::rustls;
+use rumqttc::v5::mqttbytes::v5::{ConnectReturnCode, DisconnectReasonCode};
+use rumqttc::{QoS, TlsConfiguration, Transport, v5};
+use tokio::sync::mpsc;
+
+use crate::capture::Record;
+use crate::mqtt::Result;
+use crate::mqtt::replay::ReplayLoop;
+use crate::time::ManualClock;
+
+/// Options to connect to the MQTT broker, with either MQTT 3.1.1 or MQTT 5.
+#[derive(Clone)]
+pub enum ClientOptions {
+    /// MQTT 3.1.1, the network options configure the TCP connection of the event loop.
+    V4(Box<rumqttc::MqttOptions>, rumqttc::NetworkOptions),
+    V5(Box<v5::MqttOptions>),
+    /// Replays a capture instead of connecting to a broker.
+    Replay {
+        records: Vec<Record>,
+        speed: f64,
+        clock: ManualClock,
+    },
+    /// Connects to the first broker and fails over to the next one whenever the connection
+    /// fails, see [`Self::failover`].
+    Failover(Vec<ClientOptions>),
+}
+
+impl ClientOptions {
+    /// Replays the inbound messages of `records`, `speed` times faster than recorded.
+    ///
+    /// The `clock` is set to the original timestamp of every replayed message, it should
+    /// also be passed to [`Device::with_clock`](crate::mqtt::Device::with_clock).
+    /// Published messages are discarded.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `speed` is not positive.
+    pub fn replay(records: Vec<Record>, speed: f64, clock: ManualClock) -> Self {
+        assert!(speed > 0.0, "replay speed must be positive");
+        Self::Replay {
+            records,
+            speed,
+            clock,
+        }
+    }
+
+    /// Rotates through `brokers` whenever the connection to the current one fails, e.g. the
+    /// nodes of a cluster without a load balancer.
+    ///
+    /// The options of all brokers should only differ in their address, the subscriptions are
+    /// restored after every failover.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `brokers` is empty or contains replays.
+    pub fn failover(mut brokers: Vec<ClientOptions>) -> Self {
+        assert!(!brokers.is_empty(), "failover requires at least one broker");
+        assert!(
+            brokers
+                .iter()
+                .all(|broker| matches!(broker, Self::V4(..) | Self::V5(_))),
+            "failover requires the options of MQTT brokers"
+        );
+        match brokers.len() {
+            1 => brokers.remove(0),
+            _ => Self::Failover(brokers),
+        }
+    }
+
+    /// Address of the broker as host and port, `None` for replays.
+    pub fn broker_address(&self) -> Option<(String, u16)> {
+        match self {
+            Self::V4(options, _) => Some(options.broker_address()),
+            Self::V5(options) => Some(options.broker_address()),
+            Self::Replay { .. } => None,
+            Self::Failover(brokers) => brokers[0].broker_address(),
+        }
+    }
+
+    /// Registers a message the broker publishes when the client disconnects unexpectedly.
+    pub fn set_last_will(&mut self, topic: &str, payload: &[u8], retain: bool) {
+        match self {
+            Self::V4(options, _) => {
+                let will = rumqttc::LastWill::new(topic, payload, QoS::AtLeastOnce, retain);
+                options.set_last_will(will);
+            }
+            Self::V5(options) => {
+                let qos = v5_qos(QoS::AtLeastOnce);
+                let will = v5::mqttbytes::v5::LastWill::new(topic, payload, qos, retain, None);
+                options.set_last_will(will);
+            }
+            Self::Replay { .. } => {}
+            Self::Failover(brokers) => {
+                for broker in brokers {
+                    broker.set_last_will(topic, payload, retain);
+                }
+            }
+        }
+    }
+
+    /// Time to wait for the broker to accept a connection, at least one second.
+    ///
+    /// Slow links, e.g. cellular connections, may need more than the default of five seconds.
+    pub fn set_connection_timeout(&mut self, timeout: Duration) {
+        let secs = timeout.as_secs().max(1);
+        match self {
+            Self::V4(_, network) => {
+                network.set_connection_timeout(secs);
+            }
+            Self::V5(options) => {
+                options.set_connection_timeout(secs);
+            }
+            Self::Replay { .. } => {}
+            Self::Failover(brokers) => {
+                for broker in brokers {
+                    broker.set_connection_timeout(timeout);
+                }
+            }
+        }
+    }
+
+    /// Connects with TLS, the certificate of the broker is verified with the certificates of
+    /// the system.
+    pub fn set_tls(&mut self) -> std::io::Result<()> {
+        let mut roots = rustls::RootCertStore::empty();
+        for certificate in rustls_native_certs::load_native_certs()? {
+            // Unusable system certificates are skipped, like browsers do.
+            let _ = roots.add(certificate);
+        }
+        let config = rustls::ClientConfig::builder()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        self.set_transport(Transport::tls_with_config(TlsConfiguration::Rustls(
+            Arc::new(config),
+        )));
+        Ok(())
+    }
+
+    fn set_transport(&mut self, transport: Transport) {
+        match self {
+            Self::V4(options, _) => {
+                options.set_transport(transport);
+            }
+            Self::V5(options) => {
+                options.set_transport(transport);
+            }
+            Self::Replay { .. } => {}
+            Self::Failover(brokers) => {
+                for broker in brokers {
+                    broker.set_transport(transport.clone());
+                }
+            }
+        }
+    }
+
+    /// Limits the size of packets the client sends and accepts to `size` bytes.
+    ///
+    /// MQTT 5 clients announce the limit to the broker, which then does not send larger packets.
+    pub fn set_max_packet_size(&mut self, size: u32) {
+        match self {
+            Self::V4(options, _) => {
+                options.set_max_packet_size(size as usize, size as usize);
+            }
+            Self::V5(options) => {
+                options.set_max_packet_size(Some(size));
+            }
+            Self::Replay { .. } => {}
+            Self::Failover(brokers) => {
+                for broker in brokers {
+                    broker.set_max_packet_size(size);
+                }
+            }
+        }
+    }
+
+    /// Interval of the keep alive pings, `None` if the client does not ping the broker.
+    pub(crate) fn keep_alive(&self) -> Option<Duration> {
+        let keep_alive = match self {
+            Self::V4(options, _) => options.keep_alive(),
+            Self::V5(options) => options.keep_alive(),
+            Self::Replay { .. } => return None,
+            Self::Failover(brokers) => return brokers[0].keep_alive(),
+        };
+        (!keep_alive.is_zero()).then_some(keep_alive)
+    }
+
+    /// QoS of the subscriptions, persistent sessions subscribe with QoS 1, so the broker queues
+    /// the messages published while the client is disconnected.
+    pub(crate) fn subscription_qos(&self) -> QoS {
+        let persistent = match self {
+            Self::V4(options, _) => !options.clean_session(),
+            Self::V5(options) => !options.clean_start(),
+            Self::Replay { .. } => false,
+            Self::Failover(brokers) => return brokers[0].subscription_qos(),
+        };
+        match persistent {
+            true => QoS::AtLeastOnce,
+            false => QoS::AtMostOnce,
+        }
+    }
+}
+
+impl From<rumqttc::MqttOptions> for ClientOptions {
+    fn from(value: rumqttc::MqttOptions) -> Self {
+        Self::V4(Box::new(value), rumqttc::NetworkOptions::new())
+    }
+}
+
+impl From<v5::MqttOptions> for ClientOptions {
+    fn from(value: v5::MqttOptions) -> Self {
+        Self::V5(Box::new(value))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(crate) enum Client {
+    V4(rumqttc::AsyncClient),
+    V5(v5::AsyncClient),
+    /// Signals the replay to stop on disconnect.
+    Replay(mpsc::UnboundedSender<()>),
+}
+
+impl Client {
+    pub fn new(options: ClientOptions) -> (Self, EventLoop) {
+        match options {
+            ClientOptions::V4(options, network) => {
+                let cap = options.request_channel_capacity();
+                let (client, mut ev) = rumqttc::AsyncClient::new(*options, cap);
+                ev.set_network_options(network);
+                (Self::V4(client), EventLoop::V4(Box::new(ev)))
+            }
+            ClientOptions::V5(options) => {
+                let cap = options.request_channel_capacity();
+                let (client, ev) = v5::AsyncClient::new(*options, cap);
+                (Self::V5(client), EventLoop::V5(Box::new(ev)))
+            }
+            ClientOptions::Replay {
+                records,
+                speed,
+                clock,
+            } => {
+                let (disconnect, ev) = ReplayLoop::new(records, speed, clock);
+                (Self::Replay(disconnect), EventLoop::Replay(ev))
+            }
+            ClientOptions::Failover(brokers) => {
+                let (client, ev) = Self::new(brokers[0].clone());
+                let ev = EventLoop::Failover {
+                    ev: Box::new(ev),
+                    brokers,
+                    current: 0,
+                };
+                (client, ev)
+            }
+        }
+    }
+
+    pub fn try_subscribe(&self, topic: String, qos: QoS) -> Result<()> {
+        match self {
+            Self::V4(client) => client.try_subscribe(topic, qos)?,
+            Self::V5(client) => client.try_subscribe(topic, v5_qos(qos)).map_err(Box::new)?,
+            Self::Replay(_) => {}
+        }
+        Ok(())
+    }
+
+    pub fn try_publish(&self, topic: String, qos: QoS, retain: bool, payload: Bytes) -> Result<()> {
+        match self {
+            Self::V4(client) => client.try_publish(topic, qos, retain, payload)?,
+            Self::V5(client) => client
+                .try_publish(topic, v5_qos(qos), retain, payload)
+                .map_err(Box::new)?,
+            Self::Replay(_) => {}
+        }
+        Ok(())
+    }
+
+    pub async fn publish_bytes(
+        &self,
+        topic: &str,
+        qos: QoS,
+        retain: bool,
+        payload: Bytes,
+    ) -> Result<()> {
+        match self {
+            Self::V4(client) => client.publish_bytes(topic, qos, retain, payload).await?,
+            Self::V5(client) => client
+                .publish_bytes(topic, v5_qos(qos), retain, payload)
+                .await
+                .map_err(Box::new)?,
+            Self::Replay(_) => {}
+        }
+        Ok(())
+    }
+
+    pub async fn disconnect(&self) -> Result<()> {
+        match self {
+            Self::V4(client) => client.disconnect().await?,
+            Self::V5(client) => client.disconnect().await.map_err(Box::new)?,
+            Self::Replay(disconnect) => {
+                let _ = disconnect.send(());
+            }
+        }
+        Ok(())
+    }
+}
+
+/// An event of the event loop, independent of the protocol version.
+pub(crate) enum Event {
+    /// The broker accepted the connection, `session_present` is set if subscriptions of a
+    /// previous session were restored.
+    ConnAck { session_present: bool },
+    /// A message was published to a subscribed topic.
+    Publish { topic: String, payload: Bytes },
+    /// Any other incoming packet.
+    Incoming(String),
+    /// The client sent a disconnect.
+    Disconnect,
+    /// Any other outgoing packet.
+    Outgoing(String),
+    /// The connection was closed by the client.
+    ConnectionAborted,
+    /// The connection failed, the event loop reconnects when polled again.
+    ///
+    /// Subscriptions are lost unless the next [`Event::ConnAck`] has a session present.
+    Error(String),
+    /// The broker refused or closed the connection because of a rate limit or quota.
+    ///
+    /// Only MQTT 5 brokers report the reason, otherwise this is an [`Event::Error`].
+    RateLimited(String),
+    /// There are no more events, e.g. the end of a replay.
+    Closed,
+}
+
+pub(crate) enum EventLoop {
+    V4(Box<rumqttc::EventLoop>),
+    V5(Box<v5::EventLoop>),
+    Replay(ReplayLoop),
+    Failover {
+        ev: Box<EventLoop>,
+        brokers: Vec<ClientOptions>,
+        /// Index of the broker the event loop connects to.
+        current: usize,
+    },
+}
+
+impl EventLoop {
+    pub async fn poll(&mut self) -> Event {
+        match self {
+            Self::V4(ev) => match ev.poll().await {
+                Ok(rumqttc::Event::Incoming(rumqttc::Packet::Publish(publish))) => Event::Publish {
+                    topic: publish.topic,
+                    payload: publish.payload,
+                },
+                Ok(rumqttc::Event::Incoming(rumqttc::Packet::ConnAck(ack))) => Event::ConnAck {
+                    session_present: ack.session_present,
+                },
+                Ok(rumqttc::Event::Incoming(packet)) => Event::Incoming(format!("{packet:?}")),
+                Ok(rumqttc::Event::Outgoing(rumqttc::Outgoing::Disconnect)) => Event::Disconnect,
+                Ok(rumqttc::Event::Outgoing(out)) => Event::Outgoing(format!("{out:?}")),
+                Err(rumqttc::ConnectionError::MqttState(rumqttc::StateError::Io(io)))
+                    if io.kind() == ErrorKind::ConnectionAborted =>
+                {
+                    Event::ConnectionAborted
+                }
+                Err(err) => Event::Error(err.to_string()),
+            },
+            Self::V5(ev) => match ev.poll().await {
+                Ok(v5::Event::Incoming(v5::Incoming::Publish(publish))) => Event::Publish {
+                    topic: String::from_utf8_lossy(&publish.topic).into_owned(),
+                    payload: publish.payload,
+                },
+                Ok(v5::Event::Incoming(v5::Incoming::ConnAck(ack))) => Event::ConnAck {
+                    session_present: ack.session_present,
+                },
+                Ok(v5::Event::Incoming(packet)) => Event::Incoming(format!("{packet:?}")),
+                Ok(v5::Event::Outgoing(rumqttc::Outgoing::Disconnect)) => Event::Disconnect,
+                Ok(v5::Event::Outgoing(out)) => Event::Outgoing(format!("{out:?}")),
+                Err(v5::ConnectionError::MqttState(v5::StateError::Io(io)))
+                    if io.kind() == ErrorKind::ConnectionAborted =>
+                {
+                    Event::ConnectionAborted
+                }
+                Err(
+                    err @ v5::ConnectionError::MqttState(v5::StateError::ServerDisconnect {
+                        reason_code:
+                            DisconnectReasonCode::MessageRateTooHigh
+                            | DisconnectReasonCode::QuotaExceeded
+                            | DisconnectReasonCode::ServerBusy,
+                        ..
+                    }),
+                ) => Event::RateLimited(err.to_string()),
+                Err(
+                    err @ v5::ConnectionError::ConnectionRefused(
+                        ConnectReturnCode::QuotaExceeded
+                        | ConnectReturnCode::ConnectionRateExceeded
+                        | ConnectReturnCode::ServerBusy,
+                    ),
+                ) => Event::RateLimited(err.to_string()),
+                Err(err) => Event::Error(err.to_string()),
+            },
+            Self::Replay(ev) => ev.poll().await,
+            Self::Failover {
+                ev,
+                brokers,
+                current,
+            } => {
+                let event = Box::pin(ev.poll()).await;
+                if let Event::Error(_) | Event::RateLimited(_) = event {
+                    *current = (*current + 1) % brokers.len();
+                    let broker = &brokers[*current];
+                    if let Some((host, port)) = broker.broker_address() {
+                        tracing::info!("failing over to the broker at {host}:{port}");
+                    }
+                    ev.set_broker(broker);
+                }
+                event
+            }
+        }
+    }
+
+    /// Connects to the broker of `options` on the next reconnect.
+    fn set_broker(&mut self, options: &ClientOptions) {
+        match (self, options) {
+            (Self::V4(ev), ClientOptions::V4(options, network)) => {
+                ev.mqtt_options = (**options).clone();
+                ev.set_network_options(network.clone());
+            }
+            (Self::V5(ev), ClientOptions::V5(options)) => ev.options = (**options).clone(),
+            _ => tracing::warn!("failover between different protocol versions is not supported"),
+        }
+    }
+}
+
+fn v5_qos(qos: QoS) -> v5::mqttbytes::QoS {
+    match qos {
+        QoS::AtMostOnce => v5::mqttbytes::QoS::AtMostOnce,
+        QoS::AtLeastOnce => v5::mqttbytes::QoS::AtLeastOnce,
+        QoS::ExactlyOnce => v5::mqttbytes::QoS::ExactlyOnce,
+    }
+}
@@ -1,42 +1,118 @@
 use core::fmt;
 use std::{
-    collections::BTreeMap,
-    io::ErrorKind,
+    collections::{BTreeMap, HashMap, hash_map::Entry},
     str::FromStr,
-    time::{Duration, SystemTime},
+    sync::{
+        Arc, Mutex, MutexGuard,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{Duration, Instant, SystemTime},
 };
 
 use futures::FutureExt;
-use rumqttc::{
-    AsyncClient, ConnectionError, Event, EventLoop, MqttOptions, Outgoing, Packet, QoS, StateError,
-};
-use serde::Serialize;
-use tokio::sync::watch;
+use rumqttc::QoS;
+use serde::{Deserialize, Serialize};
+use tokio::sync::{broadcast, watch};
 
 use crate::{
-    mqtt::{Error, InvalidStatus, Result},
-    units::{Celsius, Percentage, Watt, WattHours},
+    capture::{Direction, Record},
+    mqtt::{
+        ClientOptions, Error, InvalidStatus, Result, RetryPolicy, Transform,
+        client::{Client, Event, EventLoop},
+        retry::{Attempts, InFlight},
+        spec::{Command, ControlCommand, DeviceModel, FieldDoc},
+        throttle::Throttle,
+    },
+    parser::{Parser, Reading},
+    time::{Clock, LocalTime, SystemClock},
+    units::{Celsius, Hertz, Percentage, Volt, Watt, WattHours},
 };
 
 #[derive(Debug, Clone)]
 pub struct DeviceOptions {
     pub ty: String,
     pub mac: String,
+    pub protocol: Protocol,
+    /// Only logs control commands instead of sending them to the device.
+    ///
+    /// Status requests are still sent, they do not change the state of the device.
+    pub dry_run: bool,
+    /// Minimum interval between publishes to the control topic.
+    ///
+    /// Concurrent status requests are coalesced into a single request regardless.
+    pub min_interval: Duration,
+    /// Retries of requests, which fail or are not answered by the device.
+    pub retry: RetryPolicy,
 }
 
 impl DeviceOptions {
-    fn data_topic(&self) -> String {
-        format!("hame_energy/{}/device/{}/ctrl", self.ty, self.mac)
+    /// Topic the device publishes its status messages to.
+    pub fn data_topic(&self) -> String {
+        self.protocol.topic(&self.protocol.data_topic, self)
+    }
+
+    /// Topic the device receives commands on.
+    pub fn control_topic(&self) -> String {
+        self.protocol.topic(&self.protocol.control_topic, self)
+    }
+
+    /// Returns the model selected by the device type, `None` for unknown types.
+    pub fn model(&self) -> Option<DeviceModel> {
+        DeviceModel::from_type(&self.ty)
+    }
+
+    /// Whether the device reports grid measurements, see [`DeviceModel::GRID_TYPES`].
+    fn reports_grid(&self) -> bool {
+        DeviceModel::GRID_TYPES.contains(&self.ty.as_str())
+    }
+}
+
+/// Describes how to communicate with a device over MQTT.
+///
+/// Topics may contain the placeholders `{type}` and `{mac}`, which are replaced with the
+/// device type and MAC.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Protocol {
+    /// Topic the device publishes its status messages to.
+    pub data_topic: String,
+    /// Topic the device receives commands on.
+    pub control_topic: String,
+    /// Command which makes the device publish a status message.
+    pub poll_command: String,
+    /// Decodes the payload of status messages.
+    pub transform: Transform,
+}
+
+impl Protocol {
+    fn topic(&self, template: &str, device: &DeviceOptions) -> String {
+        template
+            .replace("{type}", &device.ty)
+            .replace("{mac}", &device.mac)
     }
+}
 
-    fn control_topic(&self) -> String {
-        format!("hame_energy/{}/App/{}/ctrl", self.ty, self.mac)
+impl Default for Protocol {
+    /// The protocol used by Hame energy storage devices.
+    fn default() -> Self {
+        Self {
+            data_topic: "hame_energy/{type}/device/{mac}/ctrl".to_owned(),
+            control_topic: "hame_energy/{type}/App/{mac}/ctrl".to_owned(),
+            poll_command: ControlCommand::ReadStatus.encode(),
+            transform: Transform::None,
+        }
     }
 }
 
-#[derive(Debug, Clone, Copy, Serialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
 pub struct DeviceInfo {
-    #[serde(serialize_with = "ser_system_time_secs")]
+    /// Time the status message was received, according to the [`Device::clock`].
+    ///
+    /// The status message does not contain the time of the device itself, replays use the time
+    /// the message was recorded.
+    #[serde(
+        serialize_with = "ser_system_time_secs",
+        deserialize_with = "de_system_time_secs"
+    )]
     pub timestamp: SystemTime,
     pub solar1: SolarInfo,
     pub solar2: SolarInfo,
@@ -45,28 +121,142 @@ pub struct DeviceInfo {
     pub temperature: TemperatureInfo,
     pub battery: BatteryInfo,
     pub scene: Scene,
+    /// Grid measurements, only available for AC coupled models.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub grid: Option<GridInfo>,
+    /// Configured power limits, only reported by newer firmware versions.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub limits: Option<LimitInfo>,
 }
 
-#[derive(Debug, Clone, Copy, Serialize)]
+impl DeviceInfo {
+    /// Names of all metrics, which can be retrieved with [`Self::metric`].
+    pub const METRICS: &[&str] = &[
+        "solar1.charging",
+        "solar1.pass_through",
+        "solar1.power",
+        "solar2.charging",
+        "solar2.pass_through",
+        "solar2.power",
+        "output1.power",
+        "output1.active",
+        "output2.power",
+        "output2.active",
+        "temperature.min",
+        "temperature.max",
+        "battery.charge",
+        "battery.capacity",
+        "battery.output_threshold",
+        "battery.discharge_depth",
+        "battery.internal.charging",
+        "battery.internal.discharging",
+        "battery.internal.discharge_depth",
+        "battery.internal.undervoltage",
+        "grid.voltage",
+        "grid.frequency",
+        "limits.charge",
+        "limits.output",
+        "derived.solar_power",
+        "derived.output_power",
+        "derived.net_power",
+    ];
+
+    /// Returns how long ago the device reported the values, according to the system clock.
+    pub fn age(&self) -> Duration {
+        self.age_at(SystemTime::now())
+    }
+
+    /// Returns the age of the values at `now`, e.g. the time of the [`Device::clock`].
+    pub fn age_at(&self, now: SystemTime) -> Duration {
+        now.duration_since(self.timestamp).unwrap_or_default()
+    }
+
+    /// Returns the numeric value of the metric with the name `name`.
+    ///
+    /// Metric names are the dotted paths of the JSON representation,
+    /// e.g. `battery.charge` or `solar1.power`. Booleans are represented as `0` or `1`.
+    ///
+    /// Returns `None` if the metric does not exist or is not available.
+    pub fn metric(&self, name: &str) -> Option<f64> {
+        let bool = |value: bool| if value { 1.0 } else { 0.0 };
+
+        Some(match name {
+            "solar1.charging" => bool(self.solar1.charging),
+            "solar1.pass_through" => bool(self.solar1.pass_through),
+            "solar1.power" => self.solar1.power.0.into(),
+            "solar2.charging" => bool(self.solar2.charging),
+            "solar2.pass_through" => bool(self.solar2.pass_through),
+            "solar2.power" => self.solar2.power.0.into(),
+            "output1.power" => self.output1.power.0.into(),
+            "output1.active" => bool(self.output1.active),
+            "output2.power" => self.output2.power.0.into(),
+            "output2.active" => bool(self.output2.active),
+            "temperature.min" => self.temperature.min.0.into(),
+            "temperature.max" => self.temperature.max.0.into(),
+            "battery.charge" => self.battery.charge.0.into(),
+            "battery.capacity" => self.battery.capacity.0.into(),
+            "battery.output_threshold" => self.battery.output_threshold.0.into(),
+            "battery.discharge_depth" => self.battery.discharge_depth.0.into(),
+            "battery.internal.charging" => bool(self.battery.internal.charging),
+            "battery.internal.discharging" => bool(self.battery.internal.discharging),
+            "battery.internal.discharge_depth" => bool(self.battery.internal.discharge_depth),
+            "battery.internal.undervoltage" => bool(self.battery.internal.undervoltage),
+            "grid.voltage" => self.grid?.voltage.0.into(),
+            "grid.frequency" => self.grid?.frequency.0.into(),
+            "limits.charge" => self.limits?.charge.0.into(),
+            "limits.output" => self.limits?.output.0.into(),
+            "derived.solar_power" => self.derived().solar_power.0.into(),
+            "derived.output_power" => self.derived().output_power.0.into(),
+            "derived.net_power" => self.derived().net_power as f64,
+            _ => return None,
+        })
+    }
+
+    /// Returns the values computed from the measurements, e.g. the total solar power.
+    pub fn derived(&self) -> DerivedInfo {
+        let solar_power = Watt(self.solar1.power.0.saturating_add(self.solar2.power.0));
+        let output_power = Watt(self.output1.power.0.saturating_add(self.output2.power.0));
+
+        DerivedInfo {
+            solar_power,
+            output_power,
+            net_power: i64::from(solar_power.0) - i64::from(output_power.0),
+        }
+    }
+}
+
+/// Values computed from the measurements of a [`DeviceInfo`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct DerivedInfo {
+    /// Combined power of both solar inputs.
+    pub solar_power: Watt,
+    /// Combined power of both outputs.
+    pub output_power: Watt,
+    /// Solar power minus output power in W, positive while the battery charges and negative
+    /// while it discharges.
+    pub net_power: i64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct SolarInfo {
     pub charging: bool,
     pub pass_through: bool,
     pub power: Watt,
 }
 
-#[derive(Debug, Clone, Copy, Serialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct OutputInfo {
     pub power: Watt,
     pub active: bool,
 }
 
-#[derive(Debug, Clone, Copy, Serialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct TemperatureInfo {
     pub min: Celsius,
     pub max: Celsius,
 }
 
-#[derive(Debug, Clone, Copy, Serialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct BatteryInfo {
     pub charge: Percentage,
     pub capacity: WattHours,
@@ -75,7 +265,7 @@ pub struct BatteryInfo {
     pub internal: BatteryCellInfo,
 }
 
-#[derive(Debug, Clone, Copy, Serialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct BatteryCellInfo {
     pub charging: bool,
     pub discharging: bool,
@@ -83,7 +273,55 @@ pub struct BatteryCellInfo {
     pub undervoltage: bool,
 }
 
-#[derive(Debug, Clone, Copy, Serialize)]
+/// Only `PartialEq`, unlike the other parts of [`DeviceInfo`] the measurements are floating point.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct GridInfo {
+    pub voltage: Volt,
+    pub frequency: Hertz,
+}
+
+/// Power limits configured on the device, reported by newer firmware versions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct LimitInfo {
+    /// Maximum charging power of the battery.
+    pub charge: Watt,
+    /// Maximum output power.
+    pub output: Watt,
+}
+
+/// Energy counters of the current day, reset by the device at midnight of its clock.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct DailyEnergy {
+    #[serde(
+        serialize_with = "ser_system_time_secs",
+        deserialize_with = "de_system_time_secs"
+    )]
+    pub timestamp: SystemTime,
+    /// Energy charged into the battery.
+    pub battery_charge: WattHours,
+    /// Energy discharged from the battery.
+    pub battery_discharge: WattHours,
+    /// Energy produced by the solar inputs.
+    pub solar: WattHours,
+    /// Energy delivered by the outputs to the inverter.
+    pub output: WattHours,
+}
+
+impl From<&Measurement<RawDailyEnergy>> for DailyEnergy {
+    fn from(value: &Measurement<RawDailyEnergy>) -> Self {
+        let timestamp = value.time;
+        let value = value.data.as_ref().expect("valid measurement");
+        DailyEnergy {
+            timestamp,
+            battery_charge: value.bc,
+            battery_discharge: value.bs,
+            solar: value.pt,
+            output: value.it,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 #[serde(rename_all = "lowercase")]
 pub enum Scene {
     Day,
@@ -165,78 +403,701 @@ impl From<&Measurement<RawDeviceInfo>> for DeviceInfo {
                 },
             },
             scene: value.cj,
+            // Depends on the device type, see `to_device_info`.
+            grid: None,
+            limits: match (value.lmi, value.lmo) {
+                (Some(charge), Some(output)) => Some(LimitInfo { charge, output }),
+                _ => None,
+            },
+        }
+    }
+}
+
+fn to_device_info(options: &DeviceOptions, value: &Measurement<RawDeviceInfo>) -> DeviceInfo {
+    let mut device_info = DeviceInfo::from(value);
+    // Other models, e.g. the B2500, report their firmware version in `vv` and `sv`.
+    if options.reports_grid()
+        && let Some(value) = &value.data
+        && let (Some(voltage), Some(frequency)) = (value.vv, value.sv)
+    {
+        device_info.grid = Some(GridInfo {
+            voltage: Volt(voltage),
+            frequency: Hertz(frequency),
+        });
+    }
+    device_info
+}
+
+/// Every device info reported by a device, see [`Device::subscribe_device_info`].
+#[derive(Debug)]
+pub struct DeviceInfoUpdates {
+    options: DeviceOptions,
+    receiver: broadcast::Receiver<Measurement<RawDeviceInfo>>,
+}
+
+impl DeviceInfoUpdates {
+    /// Waits for the next device info.
+    ///
+    /// A subscriber which falls behind by more than 16 device infos skips the oldest ones.
+    pub async fn recv(&mut self) -> Result<DeviceInfo> {
+        loop {
+            match self.receiver.recv().await {
+                Ok(value) => return Ok(to_device_info(&self.options, &value)),
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    tracing::warn!(
+                        mac = %self.options.mac,
+                        "device info subscriber lagging behind, skipped {skipped} device infos"
+                    );
+                }
+                Err(broadcast::error::RecvError::Closed) => return Err(Error::Disconnected),
+            }
         }
     }
 }
 
 /// A Hame energy storage device as represented in MQTT.
+///
+/// Devices attached with [`Device::attach`] share the connection and [`DeviceLoop`].
 #[derive(Debug, Clone)]
 pub struct Device {
-    client: AsyncClient,
+    client: Client,
     options: DeviceOptions,
     device_info: watch::Receiver<Measurement<RawDeviceInfo>>,
+    /// Every received device info, unlike `device_info` not only the most recent one.
+    device_infos: broadcast::Sender<Measurement<RawDeviceInfo>>,
+    message: watch::Receiver<Measurement<Message>>,
+    /// Responses by the code of the [`Command`] they answer.
+    responses: HashMap<u8, watch::Receiver<Measurement<Message>>>,
+    traffic: broadcast::Sender<Record>,
+    clock: Arc<dyn Clock>,
+    reconnects: Arc<AtomicU64>,
+    publish_failures: Arc<AtomicU64>,
+    parse_errors: Arc<AtomicU64>,
+    throttle: Arc<Throttle>,
+    in_flight: Arc<InFlight>,
+    activity: Arc<Mutex<Option<Instant>>>,
+    /// Time without activity after which the event loop is considered stalled.
+    stall_timeout: Option<Duration>,
+    subscription_qos: QoS,
+    shutdown: Arc<watch::Sender<bool>>,
+    routes: Routes,
 }
 
 impl Device {
-    pub fn new(mqtt: MqttOptions, device: DeviceOptions) -> Result<(Self, DeviceLoop)> {
-        let (client, ev) = AsyncClient::new(mqtt, 10);
+    pub fn new(
+        mqtt: impl Into<ClientOptions>,
+        device: DeviceOptions,
+    ) -> Result<(Self, DeviceLoop)> {
+        Self::with_clock(mqtt, device, Arc::new(SystemClock))
+    }
 
-        client
-            .try_subscribe(device.data_topic(), QoS::AtMostOnce)
-            .expect("initial subscribe to succeed");
+    /// Creates a device which timestamps all measurements and traffic with `clock`.
+    pub fn with_clock(
+        mqtt: impl Into<ClientOptions>,
+        device: DeviceOptions,
+        clock: Arc<dyn Clock>,
+    ) -> Result<(Self, DeviceLoop)> {
+        let mqtt = mqtt.into();
+        // The event loop pings the broker at least every keep alive interval.
+        let stall_timeout = mqtt.keep_alive().map(|keep_alive| keep_alive * 2);
+        let subscription_qos = mqtt.subscription_qos();
+        let (client, ev) = Client::new(mqtt);
 
-        let (device_info_tx, device_info_rx) = watch::channel(Default::default());
+        let route = Route::new(&device);
+        let routes = Arc::new(Mutex::new(HashMap::new()));
+        let reconnects = Arc::new(AtomicU64::new(0));
+        let activity = Arc::new(Mutex::new(None));
+        let (shutdown_tx, shutdown_rx) = watch::channel(false);
 
-        let dev = Self {
-            client,
-            options: device,
-            device_info: device_info_rx,
-        };
         let ev = DeviceLoop {
             ev,
+            client: client.clone(),
+            routes: Arc::clone(&routes),
             disconnect: false,
-            device_info: device_info_tx,
+            connected: false,
+            connected_at: None,
+            rapid_disconnects: 0,
+            backoff: DeviceLoop::MIN_BACKOFF,
+            subscription_qos,
+            clock: Arc::clone(&clock),
+            reconnects: Arc::clone(&reconnects),
+            activity: Arc::clone(&activity),
+            shutdown: shutdown_rx,
+            reconnecting: None,
+        };
+        let dev = Self {
+            client,
+            device_info: route.device_info.subscribe(),
+            device_infos: route.device_infos.clone(),
+            message: route.message.subscribe(),
+            responses: route.subscribe_responses(),
+            traffic: route.traffic.clone(),
+            clock,
+            reconnects,
+            publish_failures: Arc::clone(&route.publish_failures),
+            parse_errors: Arc::clone(&route.parse_errors),
+            throttle: Arc::clone(&route.throttle),
+            in_flight: Arc::clone(&route.in_flight),
+            activity,
+            stall_timeout,
+            subscription_qos,
+            shutdown: Arc::new(shutdown_tx),
+            routes,
+            options: device,
         };
+        dev.lock_routes().insert(dev.options.data_topic(), route);
 
         Ok((dev, ev))
     }
 
+    /// Returns a handle of another device, which shares the connection to the broker.
+    ///
+    /// The device loop dispatches received messages by their topic, any number of devices
+    /// can be served by a single connection. Attaching a device with the data topic of an
+    /// already attached device returns another handle of that device.
+    pub fn attach(&self, device: DeviceOptions) -> Self {
+        let data_topic = device.data_topic();
+        let mut routes = self.lock_routes();
+        let route = match routes.entry(data_topic.clone()) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                // Before the first connection the subscription is queued, after reconnects the
+                // device loop subscribes the data topics of all attached devices.
+                if let Err(err) = self.client.try_subscribe(data_topic, self.subscription_qos) {
+                    tracing::warn!(topic = %entry.key(), "failed to subscribe: {err}");
+                }
+                entry.insert(Route::new(&device))
+            }
+        };
+
+        Self {
+            client: self.client.clone(),
+            device_info: route.device_info.subscribe(),
+            device_infos: route.device_infos.clone(),
+            message: route.message.subscribe(),
+            responses: route.subscribe_responses(),
+            traffic: route.traffic.clone(),
+            clock: Arc::clone(&self.clock),
+            reconnects: Arc::clone(&self.reconnects),
+            publish_failures: Arc::clone(&route.publish_failures),
+            parse_errors: Arc::clone(&route.parse_errors),
+            throttle: Arc::clone(&route.throttle),
+            in_flight: Arc::clone(&route.in_flight),
+            activity: Arc::clone(&self.activity),
+            stall_timeout: self.stall_timeout,
+            subscription_qos: self.subscription_qos,
+            shutdown: Arc::clone(&self.shutdown),
+            routes: Arc::clone(&self.routes),
+            options: device,
+        }
+    }
+
+    fn lock_routes(&self) -> MutexGuard<'_, HashMap<String, Route>> {
+        self.routes.lock().expect("routes lock poisoned")
+    }
+
     pub fn options(&self) -> &DeviceOptions {
         &self.options
     }
 
-    // TODO: there should be a variant which forces a refresh, async refreshes or just reads the
-    // current values.
+    /// Returns the clock used to timestamp measurements.
+    pub fn clock(&self) -> &dyn Clock {
+        &*self.clock
+    }
+
+    /// Returns how often the connection to the broker was re-established.
+    pub fn reconnects(&self) -> u64 {
+        self.reconnects.load(Ordering::Relaxed)
+    }
+
+    /// Returns whether the event loop is connected to the broker and still making progress.
+    ///
+    /// The event loop is considered stalled, if it neither sent nor received a packet within
+    /// twice the keep alive interval.
+    pub fn is_healthy(&self) -> bool {
+        let activity = *self.activity.lock().unwrap();
+        activity.is_some_and(|at| {
+            self.stall_timeout
+                .is_none_or(|stall_timeout| at.elapsed() < stall_timeout)
+        })
+    }
+
+    /// Returns how often the broker rate limited the client.
+    ///
+    /// Status requests are slowed down after every rate limit and recover over time.
+    pub fn rate_limits(&self) -> u64 {
+        self.throttle.rate_limits()
+    }
+
+    /// Returns how many messages could not be handed to the MQTT client for publishing.
+    pub fn publish_failures(&self) -> u64 {
+        self.publish_failures.load(Ordering::Relaxed)
+    }
+
+    /// Returns how many received messages could not be parsed.
+    pub fn parse_errors(&self) -> u64 {
+        self.parse_errors.load(Ordering::Relaxed)
+    }
+
+    /// Returns the time between the last status request and the response of the device.
+    ///
+    /// Returns `None` if no request has been answered yet.
+    pub fn latency(&self) -> Option<Duration> {
+        self.throttle.latency()
+    }
+
+    // TODO: there should be a variant which async refreshes.
+    #[tracing::instrument(
+        name = "request",
+        level = "debug",
+        skip_all,
+        fields(mac = %self.options.mac, command = Command::STATUS.name),
+        err(level = "debug")
+    )]
     pub async fn device_info(&mut self) -> Result<DeviceInfo> {
-        self.client
-            .publish(
-                self.options.control_topic(),
-                QoS::AtLeastOnce,
-                false,
-                "cd=1",
-            )
-            .await?;
+        let _in_flight = self.in_flight.track(self.poll_command());
+        let mut attempts = self.options.retry.attempts();
+        loop {
+            // Only wait for data which is received after the request, clones of
+            // the device may have not seen data received for previous requests.
+            self.device_info.mark_unchanged();
 
-        let _ = self.device_info.changed().await;
+            let result = async {
+                self.request_device_info().await?;
+                attempts
+                    .timeout(async {
+                        match self.device_info.changed().await {
+                            Ok(()) => Ok(()),
+                            Err(_) => Err(Error::Disconnected),
+                        }
+                    })
+                    .await
+            }
+            .await;
+            match result {
+                Ok(()) => break,
+                Err(err) => self.retry_status(&mut attempts, err).await?,
+            }
+        }
         let value = self.device_info.borrow_and_update();
 
-        Ok(DeviceInfo::from(&*value))
+        Ok(to_device_info(&self.options, &value))
+    }
+
+    /// Returns the most recently received device info without requesting an update.
+    ///
+    /// Returns `None` if the device has not reported any data yet.
+    pub fn current_device_info(&self) -> Option<DeviceInfo> {
+        let value = self.device_info.borrow();
+        value.data.as_ref()?;
+
+        Some(to_device_info(&self.options, &value))
+    }
+
+    /// Requests a status update from the device without waiting for the response.
+    ///
+    /// The response can be awaited with [`Self::next_device_info`]. No request is sent while
+    /// a previous request is still waiting for its response.
+    pub async fn request_device_info(&self) -> Result<()> {
+        let Some(delay) = self.throttle.reserve_status(Instant::now()) else {
+            tracing::debug!("status request pending, waiting for its response");
+            return Ok(());
+        };
+        self.publish_control(delay, self.poll_command().to_vec())
+            .await
+    }
+
+    fn poll_command(&self) -> bytes::Bytes {
+        bytes::Bytes::from(self.options.protocol.poll_command.clone())
+    }
+
+    /// Waits before the next attempt of a status request, see [`Attempts::retry`].
+    async fn retry_status(&self, attempts: &mut Attempts, err: Error) -> Result<()> {
+        attempts.retry(err).await?;
+        // The lost request would otherwise be awaited again instead of sending a new one.
+        self.throttle.status_lost();
+        Ok(())
+    }
+
+    /// Publishes `command` to the control topic after the `delay` reserved from the throttle.
+    async fn publish_control(&self, delay: Duration, command: Vec<u8>) -> Result<()> {
+        if !delay.is_zero() {
+            tracing::debug!("delaying control command by {}ms", delay.as_millis());
+            tokio::time::sleep(delay).await;
+        }
+
+        self.publish(&self.options.control_topic(), false, command)
+            .await
+    }
+
+    /// Waits for the next device info reported by the device, without requesting an update.
+    ///
+    /// Returns immediately if this instance has not yet seen the most recently received device
+    /// info.
+    pub async fn next_device_info(&mut self) -> Result<DeviceInfo> {
+        loop {
+            if self.device_info.changed().await.is_err() {
+                return Err(Error::Disconnected);
+            }
+
+            let value = self.device_info.borrow_and_update();
+            if value.data.is_some() {
+                return Ok(to_device_info(&self.options, &value));
+            }
+        }
+    }
+
+    /// Subscribes to every device info reported by the device, without requesting updates.
+    ///
+    /// Unlike [`Self::next_device_info`], which only returns the most recent device info, every
+    /// subscriber receives all device infos reported after subscribing, in order.
+    pub fn subscribe_device_info(&self) -> DeviceInfoUpdates {
+        DeviceInfoUpdates {
+            options: self.options.clone(),
+            receiver: self.device_infos.subscribe(),
+        }
+    }
+
+    /// Requests a status update from the device and parses it with `parser`.
+    ///
+    /// Unlike [`Self::device_info`], this works for device types without built-in support.
+    #[tracing::instrument(
+        name = "request",
+        level = "debug",
+        skip_all,
+        fields(mac = %self.options.mac, command = Command::STATUS.name),
+        err(level = "debug")
+    )]
+    pub async fn read(&mut self, parser: &dyn Parser) -> Result<Reading> {
+        let _in_flight = self.in_flight.track(self.poll_command());
+        let mut attempts = self.options.retry.attempts();
+        loop {
+            self.message.mark_unchanged();
+
+            let result = async {
+                self.request_device_info().await?;
+                attempts
+                    .timeout(async {
+                        match self.message.changed().await {
+                            Ok(()) => Ok(()),
+                            Err(_) => Err(Error::Disconnected),
+                        }
+                    })
+                    .await
+            }
+            .await;
+            match result {
+                Ok(()) => break,
+                Err(err) => self.retry_status(&mut attempts, err).await?,
+            }
+        }
+        let value = self.message.borrow_and_update();
+        let message = value.data.as_ref().expect("valid measurement");
+
+        Ok(Reading {
+            timestamp: value.time,
+            fields: parser.parse(message)?,
+        })
+    }
+
+    /// Requests a status update from the device and returns the energy counters of the day.
+    #[tracing::instrument(
+        name = "request",
+        level = "debug",
+        skip_all,
+        fields(mac = %self.options.mac, command = Command::STATUS.name),
+        err(level = "debug")
+    )]
+    pub async fn daily_energy(&mut self) -> Result<DailyEnergy> {
+        let _in_flight = self.in_flight.track(self.poll_command());
+        let mut attempts = self.options.retry.attempts();
+        let value = loop {
+            self.expect_response(Command::STATUS);
+
+            let result = async {
+                self.request_device_info().await?;
+                attempts.timeout(self.response(Command::STATUS)).await
+            }
+            .await;
+            match result {
+                Ok(value) => break value,
+                Err(err) => self.retry_status(&mut attempts, err).await?,
+            }
+        };
+        let message = value.data.as_ref().expect("valid measurement");
+        let measurement = Measurement {
+            time: value.time,
+            data: Some(RawDailyEnergy::try_from(message)?),
+        };
+        Ok(DailyEnergy::from(&measurement))
+    }
+
+    /// Requests the extended battery data (`cd=16`) from the device.
+    ///
+    /// The fields of the response are not decoded yet and returned as the raw message.
+    pub async fn battery_data(&mut self) -> Result<Message> {
+        let value = self.query(ControlCommand::ReadCellData).await?;
+        Ok(value.data.expect("valid measurement"))
+    }
+
+    /// Sends `command` and waits for its response, retried according to
+    /// [`DeviceOptions::retry`].
+    #[tracing::instrument(
+        name = "request",
+        level = "debug",
+        skip_all,
+        fields(mac = %self.options.mac, command = command.spec().name),
+        err(level = "debug")
+    )]
+    async fn query(&mut self, command: ControlCommand) -> Result<Measurement<Message>> {
+        let spec = command.spec();
+        let request = bytes::Bytes::from(command.encode());
+        let _in_flight = self.in_flight.track(request.clone());
+        let mut attempts = self.options.retry.attempts();
+        loop {
+            self.expect_response(spec);
+
+            let result = async {
+                let delay = self.throttle.reserve(Instant::now());
+                self.publish_control(delay, request.to_vec()).await?;
+                attempts.timeout(self.response(spec)).await
+            }
+            .await;
+            match result {
+                Ok(value) => return Ok(value),
+                Err(err) => attempts.retry(err).await?,
+            }
+        }
+    }
+
+    /// Only accepts responses to `command`, which are received after this call.
+    ///
+    /// Clones of the device may have not seen responses received for previous requests.
+    fn expect_response(&mut self, command: Command) {
+        self.response_channel(command).mark_unchanged();
+    }
+
+    /// Waits for the next response to `command`, unrelated messages and responses to other
+    /// commands are skipped.
+    async fn response(&mut self, command: Command) -> Result<Measurement<Message>> {
+        let response = self.response_channel(command);
+        if response.changed().await.is_err() {
+            return Err(Error::Disconnected);
+        }
+        Ok(response.borrow_and_update().clone())
+    }
+
+    fn response_channel(&mut self, command: Command) -> &mut watch::Receiver<Measurement<Message>> {
+        self.responses
+            .get_mut(&command.code)
+            .expect("command has a response")
+    }
+
+    /// Sets the clock of the device to `time`.
+    ///
+    /// The device uses its clock for timed output schedules.
+    pub async fn sync_time(&mut self, time: LocalTime) -> Result<()> {
+        self.send_command(
+            ControlCommand::SyncTime(time),
+            "synchronizing the device clock",
+        )
+        .await
+    }
+
+    /// Waits until the status of the device reflects `command`, see
+    /// [`ControlCommand::expected_status`].
+    ///
+    /// Devices silently ignore values they do not accept, this fails if the change was not
+    /// applied within `timeout`. Commands which are not reflected in the status and commands
+    /// which were not sent because of a dry run are not verified.
+    pub async fn verify(&mut self, command: ControlCommand, timeout: Duration) -> Result<()> {
+        let Some((field, expected)) = command.expected_status() else {
+            return Ok(());
+        };
+        if self.options.dry_run {
+            return Ok(());
+        }
+
+        let deadline = tokio::time::Instant::now() + timeout;
+        let mut actual = None;
+        loop {
+            self.expect_response(Command::STATUS);
+            self.request_device_info().await?;
+
+            let Ok(value) = tokio::time::timeout_at(deadline, self.response(Command::STATUS)).await
+            else {
+                return Err(Error::NotApplied {
+                    field,
+                    expected,
+                    actual,
+                });
+            };
+            let value = value?;
+            let message = value.data.as_ref().expect("valid measurement");
+            actual = message.get(field).map(str::to_owned);
+            if actual.as_deref() == Some(expected.as_str()) {
+                return Ok(());
+            }
+            tracing::debug!(
+                "status reports `{field}={}`, waiting for `{expected}`",
+                actual.as_deref().unwrap_or_default()
+            );
+        }
+    }
+
+    /// Sends a control command, which changes the state of the device.
+    ///
+    /// The `reason` explains why the command is sent, in dry run mode the command
+    /// and reason are only logged.
+    pub async fn send_command(&self, command: ControlCommand, reason: &str) -> Result<()> {
+        let topic = self.options.control_topic();
+        if self.options.dry_run {
+            match command.expected_status() {
+                Some((field, value)) => tracing::info!(
+                    %topic,
+                    "dry run: would send `{command}`, {reason}, expecting `{field}={value}` in the status"
+                ),
+                None => tracing::info!(%topic, "dry run: would send `{command}`, {reason}"),
+            }
+            return Ok(());
+        }
+
+        tracing::debug!(%topic, "sending `{command}`, {reason}");
+        let delay = self.throttle.reserve(Instant::now());
+        self.publish_control(delay, command.encode().into_bytes())
+            .await
+    }
+
+    /// Publishes an arbitrary `payload` to `topic` on the broker the device is connected to.
+    pub async fn publish(&self, topic: &str, retain: bool, payload: Vec<u8>) -> Result<()> {
+        let payload = bytes::Bytes::from(payload);
+        self.client
+            .publish_bytes(topic, QoS::AtLeastOnce, retain, payload.clone())
+            .await
+            .inspect_err(|_| {
+                self.publish_failures.fetch_add(1, Ordering::Relaxed);
+            })?;
+
+        let _ = self.traffic.send(Record {
+            timestamp: self.clock.now(),
+            direction: Direction::Outbound,
+            topic: topic.to_owned(),
+            payload,
+        });
+
+        Ok(())
+    }
+
+    /// Subscribes to all raw MQTT traffic of the device.
+    ///
+    /// Contains every message received on the subscribed topics and every message
+    /// published through this device.
+    pub fn traffic(&self) -> broadcast::Receiver<Record> {
+        self.traffic.subscribe()
+    }
+
+    /// Subscribes to the control topic of the device.
+    ///
+    /// Commands published by other clients, e.g. the official app, then appear as inbound
+    /// [`Self::traffic`] on the control topic. Commands sent by this device are received as
+    /// well, unless the broker does not echo them.
+    pub fn watch_control(&self) -> Result<()> {
+        let topic = self.options.control_topic();
+        if let Some(route) = self.lock_routes().get_mut(&self.options.data_topic()) {
+            route.watch_control = true;
+        }
+        self.client.try_subscribe(topic, self.subscription_qos)?;
+        Ok(())
     }
 
     /// Disconnects the client from the broker.
     ///
     /// This disconnects the device loop from the broker, rendering all instances of this
-    /// client disconnected and no longer functional.
+    /// client and all attached devices disconnected and no longer functional.
     pub async fn disconnect(&mut self) -> Result<()> {
         self.client.disconnect().await?;
+        // Without a connection the disconnect is never sent, the device loop exits instead.
+        self.shutdown.send_replace(true);
         Ok(())
     }
 }
 
+/// Attached devices by their data topic.
+type Routes = Arc<Mutex<HashMap<String, Route>>>;
+
+/// Receiving end of an attached device in the device loop.
+#[derive(Debug)]
+struct Route {
+    transform: Transform,
+    device_info: watch::Sender<Measurement<RawDeviceInfo>>,
+    device_infos: broadcast::Sender<Measurement<RawDeviceInfo>>,
+    message: watch::Sender<Measurement<Message>>,
+    responses: HashMap<u8, watch::Sender<Measurement<Message>>>,
+    traffic: broadcast::Sender<Record>,
+    publish_failures: Arc<AtomicU64>,
+    parse_errors: Arc<AtomicU64>,
+    throttle: Arc<Throttle>,
+    /// Topic the requests in flight are re-issued to after a reconnect.
+    control_topic: String,
+    in_flight: Arc<InFlight>,
+    /// Whether the control topic is subscribed, see [`Device::watch_control`].
+    watch_control: bool,
+}
+
+impl Route {
+    fn new(device: &DeviceOptions) -> Self {
+        let responses = Command::ALL
+            .iter()
+            .filter(|command| !command.response.is_empty())
+            .map(|command| (command.code, watch::Sender::new(Default::default())))
+            .collect();
+
+        Self {
+            transform: device.protocol.transform.clone(),
+            device_info: watch::Sender::new(Default::default()),
+            device_infos: broadcast::Sender::new(16),
+            message: watch::Sender::new(Default::default()),
+            responses,
+            traffic: broadcast::Sender::new(64),
+            publish_failures: Arc::new(AtomicU64::new(0)),
+            parse_errors: Arc::new(AtomicU64::new(0)),
+            throttle: Arc::new(Throttle::new(device.min_interval)),
+            control_topic: device.control_topic(),
+            in_flight: Arc::default(),
+            watch_control: false,
+        }
+    }
+
+    fn subscribe_responses(&self) -> HashMap<u8, watch::Receiver<Measurement<Message>>> {
+        self.responses
+            .iter()
+            .map(|(&code, response)| (code, response.subscribe()))
+            .collect()
+    }
+}
+
 pub struct DeviceLoop {
     ev: EventLoop,
+    client: Client,
+    routes: Routes,
     disconnect: bool,
-    device_info: watch::Sender<Measurement<RawDeviceInfo>>,
+    /// Whether the broker accepted a connection before.
+    connected: bool,
+    /// Time the broker accepted the current connection.
+    connected_at: Option<Instant>,
+    /// Number of consecutive connections, which were lost shortly after connecting.
+    rapid_disconnects: u32,
+    /// Delay before the next reconnect attempt.
+    backoff: Duration,
+    subscription_qos: QoS,
+    clock: Arc<dyn Clock>,
+    reconnects: Arc<AtomicU64>,
+    /// Time of the last packet while connected, `None` while not connected.
+    activity: Arc<Mutex<Option<Instant>>>,
+    /// Set when the client disconnects.
+    shutdown: watch::Receiver<bool>,
+    /// Spans the time from losing the connection until the broker accepts a new one.
+    reconnecting: Option<tracing::Span>,
 }
 
 impl IntoFuture for DeviceLoop {
@@ -249,50 +1110,344 @@ impl IntoFuture for DeviceLoop {
 }
 
 impl DeviceLoop {
+    const MIN_BACKOFF: Duration = Duration::from_secs(1);
+    const MAX_BACKOFF: Duration = Duration::from_secs(60);
+    /// Minimum delay before reconnecting after the broker rate limited the client.
+    const RATE_LIMIT_BACKOFF: Duration = Duration::from_secs(30);
+    /// Connections lost within this duration after connecting are considered rapid disconnects.
+    const RAPID_DISCONNECT: Duration = Duration::from_secs(10);
+    /// Number of consecutive rapid disconnects, which are treated as a rate limit.
+    const RAPID_DISCONNECTS: u32 = 3;
+
     async fn run(mut self) -> Result<()> {
-        // TODO: error handling
         loop {
-            match self.ev.poll().await {
-                Ok(Event::Incoming(Packet::Publish(message))) => {
-                    tracing::debug!("received on {} value {:?}", message.topic, message.payload);
-
-                    // TODO: filter topic
-                    let message = Message::parse(message.payload).unwrap();
-                    let device_info = RawDeviceInfo::try_from(&message).unwrap();
-                    let Ok(()) = self.device_info.send(Measurement::new(device_info)) else {
-                        tracing::debug!("sender disconnected, exiting event loop");
+            let event = tokio::select! {
+                event = self.ev.poll() => event,
+                _ = shutdown(&mut self.shutdown), if self.connected_at.is_none() => {
+                    tracing::debug!("not connected while disconnecting, exiting event loop");
+                    return Ok(());
+                }
+            };
+
+            if self.connected_at.is_some() {
+                self.set_activity(Some(Instant::now()));
+            }
+
+            match event {
+                Event::ConnAck { session_present } => {
+                    let reconnect = std::mem::replace(&mut self.connected, true);
+                    if reconnect {
+                        let reconnects = self.reconnects.fetch_add(1, Ordering::Relaxed) + 1;
+                        tracing::info!(reconnects, "reconnected to the broker");
+                    }
+                    self.connected_at = Some(Instant::now());
+                    self.set_activity(self.connected_at);
+                    self.reconnecting = None;
+
+                    // A persistent session restores the subscriptions of the previous connection.
+                    if !reconnect || !session_present {
+                        for (topic, route) in self.lock_routes().iter() {
+                            let control = route.watch_control.then_some(&route.control_topic);
+                            for topic in std::iter::once(topic).chain(control) {
+                                let result = self
+                                    .client
+                                    .try_subscribe(topic.clone(), self.subscription_qos);
+                                if let Err(err) = result {
+                                    tracing::warn!(%topic, "failed to subscribe: {err}");
+                                }
+                            }
+                        }
+                    }
+                    if reconnect {
+                        self.reissue_requests();
+                    }
+                }
+                Event::Publish { topic, payload } => {
+                    tracing::debug!(%topic, "received value {payload:?}");
+
+                    if !self.dispatch(topic, payload) {
+                        tracing::debug!("all devices dropped, exiting event loop");
                         return Ok(());
-                    };
+                    }
                 }
-                Ok(Event::Incoming(packet)) => {
-                    tracing::trace!("received {packet:?}");
+                Event::Incoming(packet) => {
+                    tracing::trace!("received {packet}");
                 }
-                Ok(Event::Outgoing(Outgoing::Disconnect)) => {
+                Event::Disconnect => {
                     tracing::debug!("client wants to disconnect");
                     self.disconnect = true;
                 }
-                Ok(Event::Outgoing(out)) => {
-                    tracing::trace!("sent: {out:?}");
+                Event::Outgoing(out) => {
+                    tracing::trace!("sent: {out}");
                 }
-                Err(ConnectionError::MqttState(StateError::Io(io)))
-                    if io.kind() == ErrorKind::ConnectionAborted && self.disconnect =>
-                {
+                Event::ConnectionAborted if self.disconnect => {
                     // Client sent a disconnect and the connection is now closed.
                     return Ok(());
                 }
-                Err(err) => {
-                    tracing::warn!("connection error: {err}");
+                Event::ConnectionAborted => {
+                    self.connection_lost();
+                    self.reconnect("connection aborted").await;
+                }
+                Event::Error(err) => {
+                    self.connection_lost();
+                    self.reconnect(&err).await;
+                }
+                Event::RateLimited(err) => {
+                    self.connected_at = None;
+                    self.set_activity(None);
+                    self.rate_limited(&err);
+                    self.reconnect(&err).await;
+                }
+                Event::Closed => {
+                    tracing::debug!("no more events, exiting event loop");
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    /// Hands a message received on `topic` to the device attached with this data topic.
+    ///
+    /// Returns `false` once all devices were dropped.
+    fn dispatch(&self, topic: String, payload: bytes::Bytes) -> bool {
+        let mut routes = self.lock_routes();
+
+        // Commands on a watched control topic are only recorded, they are no device messages.
+        if let Some(route) = routes
+            .values()
+            .find(|route| route.watch_control && route.control_topic == topic)
+        {
+            let _ = route.traffic.send(Record {
+                timestamp: self.clock.now(),
+                direction: Direction::Inbound,
+                topic,
+                payload,
+            });
+            return true;
+        }
+
+        let route = match routes.get(&topic) {
+            Some(route) => route,
+            // A single device receives all messages, e.g. a replayed capture of another MAC.
+            None if routes.len() == 1 => routes.values().next().expect("one route"),
+            None => {
+                tracing::debug!(%topic, "received message for an unknown device");
+                return true;
+            }
+        };
+
+        let _ = route.traffic.send(Record {
+            timestamp: self.clock.now(),
+            direction: Direction::Inbound,
+            topic: topic.clone(),
+            payload: payload.clone(),
+        });
+
+        let message = match route
+            .transform
+            .apply(payload.clone())
+            .and_then(Message::parse)
+        {
+            Ok(message) => message,
+            Err(_) if route.transform == Transform::None && looks_encrypted(&payload) => {
+                route.parse_errors.fetch_add(1, Ordering::Relaxed);
+                tracing::warn!(
+                    %topic,
+                    "received a message which looks encrypted, newer firmware versions \
+                     encrypt their messages, a `transform` can be configured for the \
+                     device type"
+                );
+                return true;
+            }
+            Err(err) => {
+                route.parse_errors.fetch_add(1, Ordering::Relaxed);
+                tracing::warn!(%topic, "received invalid message: {err}");
+                return true;
+            }
+        };
+
+        // Device types without built-in support only produce raw messages.
+        match RawDeviceInfo::try_from(&message) {
+            Ok(device_info) => {
+                route.throttle.status_received(Instant::now());
+                let measurement = Measurement::new(device_info, &*self.clock);
+                // Fails only without subscribers.
+                let _ = route.device_infos.send(measurement.clone());
+                route.device_info.send_replace(measurement);
+            }
+            Err(err) => tracing::debug!(%topic, "message is not a device info: {err}"),
+        }
+
+        // Every response has its own channel, a response to a concurrent request
+        // for a different command does not replace it before it was seen.
+        if let Some(command) = Command::response_to(&message)
+            && let Some(response) = route.responses.get(&command.code)
+        {
+            tracing::trace!(%topic, "message is a response to `{}`", command.name);
+            response.send_replace(Measurement::new(message.clone(), &*self.clock));
+        }
+
+        if route
+            .message
+            .send(Measurement::new(message, &*self.clock))
+            .is_err()
+        {
+            tracing::debug!(%topic, "device dropped, no longer dispatching its messages");
+            routes.retain(|_, route| !route.message.is_closed());
+        }
+        !routes.is_empty()
+    }
+
+    fn lock_routes(&self) -> MutexGuard<'_, HashMap<String, Route>> {
+        self.routes.lock().expect("routes lock poisoned")
+    }
+
+    /// Publishes the requests, which were waiting for their response when the connection was
+    /// lost, again.
+    ///
+    /// The device may never have received them, the requesting devices would otherwise wait
+    /// for the timeout of their [`RetryPolicy`].
+    fn reissue_requests(&self) {
+        for route in self.lock_routes().values() {
+            for request in route.in_flight.requests() {
+                let topic = &route.control_topic;
+                tracing::debug!(%topic, "re-issuing request {request:?} after reconnecting");
+                let result = self.client.try_publish(
+                    topic.clone(),
+                    QoS::AtLeastOnce,
+                    false,
+                    request.clone(),
+                );
+                if let Err(err) = result {
+                    route.publish_failures.fetch_add(1, Ordering::Relaxed);
+                    tracing::warn!(%topic, "failed to re-issue request: {err}");
+                    continue;
                 }
+
+                let _ = route.traffic.send(Record {
+                    timestamp: self.clock.now(),
+                    direction: Direction::Outbound,
+                    topic: topic.clone(),
+                    payload: request,
+                });
             }
         }
     }
+
+    /// Resets the backoff after a stable connection and detects brokers, which silently drop
+    /// the connections of clients exceeding a rate limit.
+    fn connection_lost(&mut self) {
+        self.set_activity(None);
+        let Some(connected_at) = self.connected_at.take() else {
+            return;
+        };
+        if connected_at.elapsed() >= Self::RAPID_DISCONNECT {
+            self.rapid_disconnects = 0;
+            self.backoff = Self::MIN_BACKOFF;
+            return;
+        }
+
+        self.rapid_disconnects += 1;
+        if self.rapid_disconnects >= Self::RAPID_DISCONNECTS {
+            self.rapid_disconnects = 0;
+            self.rate_limited("repeated disconnects shortly after connecting");
+        }
+    }
+
+    fn set_activity(&self, activity: Option<Instant>) {
+        *self.activity.lock().unwrap() = activity;
+    }
+
+    /// Slows down status requests and reconnects after the broker rate limited the client.
+    fn rate_limited(&mut self, reason: &str) {
+        let now = Instant::now();
+        let mut spacing = Duration::ZERO;
+        let mut rate_limits = 0;
+        for route in self.lock_routes().values() {
+            spacing = spacing.max(route.throttle.limit(now));
+            rate_limits = rate_limits.max(route.throttle.rate_limits());
+        }
+        tracing::warn!(
+            rate_limits,
+            "broker rate limits the client ({reason}), sending status requests at most every {}s",
+            spacing.as_secs()
+        );
+        self.backoff = self.backoff.max(Self::RATE_LIMIT_BACKOFF);
+    }
+
+    /// Waits with an exponential backoff before the event loop reconnects.
+    async fn reconnect(&mut self, err: &str) {
+        if *self.shutdown.borrow() {
+            return;
+        }
+
+        tracing::warn!(
+            "connection error: {err}, reconnecting in {}s",
+            self.backoff.as_secs()
+        );
+        if self.reconnecting.is_none() {
+            self.reconnecting = Some(tracing::debug_span!("reconnect", error = %err));
+        }
+        tokio::select! {
+            _ = tokio::time::sleep(self.backoff) => {}
+            _ = shutdown(&mut self.shutdown) => {}
+        }
+        self.backoff = (self.backoff * 2).min(Self::MAX_BACKOFF);
+    }
 }
 
-struct Message {
+/// Decodes `payload` like a status message received by the device loop, for the fuzz targets.
+#[cfg(fuzzing)]
+#[doc(hidden)]
+pub fn fuzz_status(options: &DeviceOptions, payload: bytes::Bytes) -> Option<DeviceInfo> {
+    let message = options
+        .protocol
+        .transform
+        .apply(payload)
+        .and_then(Message::parse)
+        .ok()?;
+    let device_info = RawDeviceInfo::try_from(&message).ok()?;
+    Some(to_device_info(
+        options,
+        &Measurement::new(device_info, &SystemClock),
+    ))
+}
+
+/// Returns `true` if `payload` is not a `key=value` message, but binary or encoded data.
+///
+/// Status messages are printable ASCII and contain at least one `=`, Base64 encoded payloads only
+/// contain `=` as padding at the end.
+fn looks_encrypted(payload: &[u8]) -> bool {
+    let payload = payload.trim_ascii();
+    let binary = payload
+        .iter()
+        .any(|&byte| !byte.is_ascii_graphic() && byte != b' ');
+    let assignment = payload
+        .strip_suffix(b"==")
+        .or(payload.strip_suffix(b"="))
+        .unwrap_or(payload)
+        .contains(&b'=');
+    binary || !assignment
+}
+
+/// Resolves once the client disconnects, never if all devices were dropped without disconnecting.
+async fn shutdown(shutdown: &mut watch::Receiver<bool>) {
+    if shutdown.wait_for(|shutdown| *shutdown).await.is_err() {
+        std::future::pending::<()>().await;
+    }
+}
+
+/// A raw status message, a list of comma separated `key=value` pairs.
+#[derive(Clone)]
+pub struct Message {
     payload: BTreeMap<String, String>,
 }
 
 impl Message {
+    /// Parses a status message, never panics on any input.
+    ///
+    /// Fails if a part is not a `key=value` pair, duplicate keys are accepted and the last value
+    /// is used.
     pub fn parse(raw_message: bytes::Bytes) -> Result<Self> {
         let message = std::str::from_utf8(&raw_message)
             .map_err(|_| InvalidStatus::InvalidFormat(raw_message.clone()))?
@@ -311,12 +1466,43 @@ impl Message {
         Ok(Message { payload })
     }
 
+    /// Returns the raw value of the field `name`.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.payload.get(name).map(String::as_str)
+    }
+
+    /// Returns all fields of the message as raw `key`, `value` pairs, ordered by key.
+    pub fn fields(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.payload
+            .iter()
+            .map(|(key, value)| (key.as_str(), value.as_str()))
+    }
+
     pub fn get_value<T: FromStr>(&self, name: &str) -> Result<Option<T>, T::Err> {
         self.payload
             .get(name)
             .map(|value| value.parse())
             .transpose()
     }
+
+    /// Sets the raw value of the field `name`.
+    pub fn set(&mut self, name: impl Into<String>, value: impl Into<String>) -> &mut Self {
+        self.payload.insert(name.into(), value.into());
+        self
+    }
+}
+
+impl fmt::Display for Message {
+    /// Formats the message as it is sent by the device.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (i, (name, value)) in self.payload.iter().enumerate() {
+            if i > 0 {
+                f.write_str(",")?;
+            }
+            write!(f, "{name}={value}")?;
+        }
+        Ok(())
+    }
 }
 
 impl fmt::Debug for Message {
@@ -329,16 +1515,16 @@ impl fmt::Debug for Message {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 struct Measurement<T> {
     pub time: SystemTime,
     pub data: Option<T>,
 }
 
 impl<T> Measurement<T> {
-    pub fn new(data: T) -> Self {
+    pub fn new(data: T, clock: &dyn Clock) -> Self {
         Self {
-            time: SystemTime::now(),
+            time: clock.now(),
             data: Some(data),
         }
     }
@@ -353,33 +1539,107 @@ impl<T> Default for Measurement<T> {
     }
 }
 
+/// A value which can be extracted from a field of a [`Message`].
+trait FieldValue: Sized {
+    fn from_message(message: &Message, field: &'static str) -> Result<Self, InvalidStatus>;
+}
+
+impl<T> FieldValue for Option<T>
+where
+    T: FromStr,
+    T::Err: std::error::Error + Send + Sync + 'static,
+{
+    fn from_message(message: &Message, field: &'static str) -> Result<Self, InvalidStatus> {
+        message
+            .get_value(field)
+            .map_err(|err| InvalidStatus::InvalidField(field, Box::new(err)))
+    }
+}
+
+macro_rules! impl_required_field_value {
+    ($($ty:ty),*) => {
+        $(impl FieldValue for $ty {
+            fn from_message(message: &Message, field: &'static str) -> Result<Self, InvalidStatus> {
+                Option::<Self>::from_message(message, field)?
+                    .ok_or(InvalidStatus::MissingField(field))
+            }
+        })*
+    };
+}
+impl_required_field_value!(u8, Watt, WattHours, Celsius, Percentage, Scene);
+
+/// Describes the value of a field in the [`FieldDoc`] of a message.
+trait FieldUnit {
+    const UNIT: Option<&'static str> = None;
+    const OPTIONAL: bool = false;
+}
+
+impl<T: FieldUnit> FieldUnit for Option<T> {
+    const UNIT: Option<&'static str> = T::UNIT;
+    const OPTIONAL: bool = true;
+}
+
+impl FieldUnit for u8 {}
+impl FieldUnit for f32 {}
+impl FieldUnit for Scene {}
+
+macro_rules! impl_field_unit {
+    ($($ty:ident),*) => {
+        $(impl FieldUnit for $ty {
+            const UNIT: Option<&'static str> = Some($ty::SYMBOL);
+        })*
+    };
+}
+impl_field_unit!(Watt, WattHours, Celsius, Percentage, Volt, Hertz);
+
+/// Strips the `r#` prefix of a raw identifier in constants.
+const fn raw_ident(ident: &'static str) -> &'static str {
+    match ident.as_bytes() {
+        [b'r', b'#', rest @ ..] => match std::str::from_utf8(rest) {
+            Ok(rest) => rest,
+            Err(_) => ident,
+        },
+        _ => ident,
+    }
+}
+
 macro_rules! message {
     (struct $name:ident {
         $(
-            $(#[$attr:meta])*
+            $(#[doc = $doc:literal])*
             $field:ident: $ty:ty,
         )*
     }) => {
         #[derive(Debug, Clone)]
-        struct $name {
+        pub(crate) struct $name {
             $(
-                $(#[$attr])*
+                $(#[doc = $doc])*
                 $field: $ty,
             )*
         }
 
+        impl $name {
+            /// Documentation of all fields, generated from the field documentation.
+            pub(crate) const FIELDS: &[FieldDoc] = &[
+                $(FieldDoc {
+                    key: raw_ident(stringify!($field)),
+                    description: concat!($($doc),*).trim_ascii(),
+                    unit: <$ty as FieldUnit>::UNIT,
+                    optional: <$ty as FieldUnit>::OPTIONAL,
+                },)*
+            ];
+        }
+
         impl TryFrom<&Message> for $name {
             type Error = Error;
 
             fn try_from(message: &Message) -> Result<Self, Self::Error> {
                 Ok(Self {
                     $(
-                        $field: match stringify!($field).trim_start_matches("r#") {
-                            field => message
-                                .get_value(field)
-                                .map_err(|err| InvalidStatus::InvalidField(field, Box::new(err)))?
-                                .ok_or(InvalidStatus::MissingField(field))?,
-                        },
+                        $field: FieldValue::from_message(
+                            message,
+                            stringify!($field).trim_start_matches("r#"),
+                        )?,
                     )*
 
                 })
@@ -425,6 +1685,29 @@ message! {
 
         /// Host Battery Status.
         l0: u8,
+
+        /// Grid Voltage in V, only AC coupled models. The B2500 reports its firmware version.
+        vv: Option<f32>,
+        /// Grid Frequency in Hz, only AC coupled models. The B2500 reports its firmware version.
+        sv: Option<f32>,
+
+        /// Charge Power Limit, only newer firmware versions.
+        lmi: Option<Watt>,
+        /// Output Power Limit, only newer firmware versions.
+        lmo: Option<Watt>,
+    }
+}
+
+message! {
+    struct RawDailyEnergy {
+        /// Battery Charge Today.
+        bc: WattHours,
+        /// Battery Discharge Today.
+        bs: WattHours,
+        /// Solar Energy Today.
+        pt: WattHours,
+        /// Output Energy Today.
+        it: WattHours,
     }
 }
 
@@ -439,11 +1722,31 @@ fn ser_system_time_secs<S: serde::Serializer>(
     serializer.serialize_u64(seconds)
 }
 
+fn de_system_time_secs<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> Result<SystemTime, D::Error> {
+    let seconds = u64::deserialize(deserializer)?;
+    Ok(SystemTime::UNIX_EPOCH + Duration::from_secs(seconds))
+}
+
 #[cfg(test)]
 mod tests {
     use bytes::Bytes;
+    use tokio::task::JoinHandle;
 
     use super::*;
+    use crate::mqtt::Simulator;
+    use crate::mqtt::broker::Broker;
+    use crate::time::LocalTime;
+
+    #[test]
+    fn test_looks_encrypted() {
+        assert!(!looks_encrypted(b"p1=1,p2=1,w1=23\n"));
+        assert!(looks_encrypted(b"cHYxPTEsdzE9MjE="));
+        assert!(looks_encrypted(b"cHYxPTEsdzE9"));
+        assert!(looks_encrypted(&[0x2a, 0x6b, 0x67, 0x68, 0x6b]));
+        assert!(looks_encrypted(b"p1=\x01\x02"));
+    }
 
     #[test]
     fn test_message_device_info() {
@@ -491,10 +1794,188 @@ mod tests {
                 27,
             ),
             l0: 1,
+            vv: Some(
+                220.0,
+            ),
+            sv: Some(
+                12.0,
+            ),
+            lmi: Some(
+                Watt(
+                    272,
+                ),
+            ),
+            lmo: Some(
+                Watt(
+                    1830,
+                ),
+            ),
         }
         "###);
     }
 
+    #[test]
+    fn test_metrics() {
+        let payload = b"p1=1,p2=1,w1=23,w2=23,pe=99,vv=220,sv=12,cs=0,cd=0,am=0,o1=1,o2=1,do=80,lv=200,cj=2,kn=2217,g1=1,g2=0,b1=0,b2=0,md=0,d1=1,e1=0:0,f1=23:59,h1=200,d2=0,e2=0:0,f2=0:0,h2=600,d3=0,e3=0:0,f3=0:0,h3=0,sg=0,sp=80,st=0,tl=27,th=27,tc=0,tf=0,fc=202310231502,id=5,a0=99,a1=0,a2=0,l0=1,l1=0,c0=255,c1=0,bc=2025,bs=329,pt=3332,it=1518,m0=0,m1=0,m2=0,m3=1,d4=0,e4=0:0,f4=24:0,h4=80,d5=0,e5=0:0,f5=24:0,h5=80,lmo=1830,lmi=272,lmf=1";
+        let message = Message::parse(Bytes::from_static(payload)).unwrap();
+        let raw = RawDeviceInfo::try_from(&message).unwrap();
+        let mut device_info = DeviceInfo::from(&Measurement::new(raw, &SystemClock));
+        // Only reported by AC coupled models, see `test_device_info_grid`.
+        device_info.grid = Some(GridInfo {
+            voltage: Volt(230.0),
+            frequency: Hertz(50.0),
+        });
+
+        for metric in DeviceInfo::METRICS {
+            assert!(device_info.metric(metric).is_some(), "{metric}");
+        }
+        assert_eq!(device_info.metric("battery.chrge"), None);
+    }
+
+    #[test]
+    fn test_derived() {
+        let payload = b"p1=1,p2=1,w1=23,w2=20,pe=99,o1=1,o2=1,do=80,lv=200,cj=2,kn=2217,g1=100,g2=50,tl=27,th=27,l0=1";
+        let message = Message::parse(Bytes::from_static(payload)).unwrap();
+        let raw = RawDeviceInfo::try_from(&message).unwrap();
+        let mut device_info = DeviceInfo::from(&Measurement::new(raw, &SystemClock));
+
+        let derived = device_info.derived();
+        assert_eq!(derived.solar_power, Watt(43));
+        assert_eq!(derived.output_power, Watt(150));
+        assert_eq!(derived.net_power, -107);
+        assert_eq!(device_info.metric("derived.net_power"), Some(-107.0));
+
+        device_info.solar1.power = Watt(u32::MAX);
+        assert_eq!(device_info.derived().solar_power, Watt(u32::MAX));
+        assert_eq!(device_info.derived().net_power, i64::from(u32::MAX) - 150);
+    }
+
+    #[test]
+    fn test_device_info_roundtrip() {
+        let payload = b"p1=1,p2=3,w1=23,w2=0,pe=99,vv=220,sv=50,o1=1,o2=0,do=80,lv=200,cj=2,kn=2217,g1=1,g2=0,tl=27,th=28,l0=5";
+        let message = Message::parse(Bytes::from_static(payload)).unwrap();
+        let raw = RawDeviceInfo::try_from(&message).unwrap();
+        let options = DeviceOptions {
+            ty: "HMG-50".to_owned(),
+            ..options()
+        };
+        let mut device_info = to_device_info(&options, &Measurement::new(raw, &SystemClock));
+
+        let json = serde_json::to_string(&device_info).unwrap();
+        let roundtrip: DeviceInfo = serde_json::from_str(&json).unwrap();
+        assert_eq!(serde_json::to_string(&roundtrip).unwrap(), json);
+        assert!(roundtrip.grid.is_some());
+
+        // Sub-second precision is lost, the timestamp is serialized in seconds.
+        let seconds = device_info
+            .timestamp
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        assert_eq!(
+            roundtrip.timestamp,
+            SystemTime::UNIX_EPOCH + Duration::from_secs(seconds)
+        );
+
+        device_info.grid = None;
+        let json = serde_json::to_string(&device_info).unwrap();
+        let roundtrip: DeviceInfo = serde_json::from_str(&json).unwrap();
+        assert!(roundtrip.grid.is_none());
+
+        let later = roundtrip.timestamp + Duration::from_secs(90);
+        assert_eq!(roundtrip.age_at(later), Duration::from_secs(90));
+        assert_eq!(roundtrip.age_at(SystemTime::UNIX_EPOCH), Duration::ZERO);
+    }
+
+    #[test]
+    fn test_device_info_grid() {
+        let payload = b"p1=1,p2=1,w1=23,w2=0,pe=99,vv=229.5,sv=49.98,o1=1,o2=0,do=80,lv=200,cj=2,kn=2217,g1=1,g2=0,tl=27,th=28,l0=5";
+        let message = Message::parse(Bytes::from_static(payload)).unwrap();
+        let raw = RawDeviceInfo::try_from(&message).unwrap();
+        let measurement = Measurement::new(raw, &SystemClock);
+
+        let venus = DeviceOptions {
+            ty: "HMG-50".to_owned(),
+            ..options()
+        };
+        assert_eq!(
+            to_device_info(&venus, &measurement).grid,
+            Some(GridInfo {
+                voltage: Volt(229.5),
+                frequency: Hertz(49.98),
+            })
+        );
+        // The B2500 reports its firmware version in the same keys.
+        assert_eq!(to_device_info(&options(), &measurement).grid, None);
+    }
+
+    #[test]
+    fn test_device_info_limits() {
+        let limits = |payload: &'static [u8]| {
+            let message = Message::parse(Bytes::from_static(payload)).unwrap();
+            let raw = RawDeviceInfo::try_from(&message).unwrap();
+            DeviceInfo::from(&Measurement::new(raw, &SystemClock)).limits
+        };
+
+        // Payload obtained by sending `cd=01`.
+        let payload = b"p1=1,p2=1,w1=23,w2=23,pe=99,vv=220,sv=12,cs=0,cd=0,am=0,o1=1,o2=1,do=80,lv=200,cj=2,kn=2217,g1=1,g2=0,b1=0,b2=0,md=0,d1=1,e1=0:0,f1=23:59,h1=200,d2=0,e2=0:0,f2=0:0,h2=600,d3=0,e3=0:0,f3=0:0,h3=0,sg=0,sp=80,st=0,tl=27,th=27,tc=0,tf=0,fc=202310231502,id=5,a0=99,a1=0,a2=0,l0=1,l1=0,c0=255,c1=0,bc=2025,bs=329,pt=3332,it=1518,m0=0,m1=0,m2=0,m3=1,d4=0,e4=0:0,f4=24:0,h4=80,d5=0,e5=0:0,f5=24:0,h5=80,lmo=1830,lmi=272,lmf=1";
+        assert_eq!(
+            limits(payload),
+            Some(LimitInfo {
+                charge: Watt(272),
+                output: Watt(1830),
+            })
+        );
+        // Older firmware versions do not report the limits.
+        let payload = b"p1=1,p2=1,w1=23,w2=0,pe=99,vv=220,sv=12,o1=1,o2=0,do=80,lv=200,cj=2,kn=2217,g1=1,g2=0,tl=27,th=28,l0=5";
+        assert_eq!(limits(payload), None);
+    }
+
+    #[test]
+    fn test_device_info_eq() {
+        let payload = b"p1=1,p2=3,w1=23,w2=0,pe=99,vv=220,sv=50,o1=1,o2=0,do=80,lv=200,cj=2,kn=2217,g1=1,g2=0,tl=27,th=28,l0=5";
+        let message = Message::parse(Bytes::from_static(payload)).unwrap();
+        let raw = RawDeviceInfo::try_from(&message).unwrap();
+        let device_info = DeviceInfo::from(&Measurement::new(raw, &SystemClock));
+
+        let mut changed = device_info;
+        changed.solar1.power = Watt(24);
+        assert_eq!(device_info, device_info);
+        assert_ne!(device_info, changed);
+
+        // Sub-structs without floating point values can be stored in sets.
+        let solar = std::collections::HashSet::from([
+            device_info.solar1,
+            changed.solar1,
+            device_info.solar1,
+        ]);
+        assert_eq!(solar.len(), 2);
+        let scenes = std::collections::HashSet::from([Scene::Day, Scene::Night, Scene::Day]);
+        assert_eq!(scenes.len(), 2);
+    }
+
+    #[test]
+    fn test_daily_energy() {
+        // Payload obtained by sending `cd=01`.
+        let payload = b"p1=1,p2=1,w1=23,w2=23,pe=99,vv=220,sv=12,cs=0,cd=0,am=0,o1=1,o2=1,do=80,lv=200,cj=2,kn=2217,g1=1,g2=0,b1=0,b2=0,md=0,d1=1,e1=0:0,f1=23:59,h1=200,d2=0,e2=0:0,f2=0:0,h2=600,d3=0,e3=0:0,f3=0:0,h3=0,sg=0,sp=80,st=0,tl=27,th=27,tc=0,tf=0,fc=202310231502,id=5,a0=99,a1=0,a2=0,l0=1,l1=0,c0=255,c1=0,bc=2025,bs=329,pt=3332,it=1518,m0=0,m1=0,m2=0,m3=1,d4=0,e4=0:0,f4=24:0,h4=80,d5=0,e5=0:0,f5=24:0,h5=80,lmo=1830,lmi=272,lmf=1";
+        let message = Message::parse(Bytes::from_static(payload)).unwrap();
+
+        let measurement = Measurement {
+            time: SystemTime::UNIX_EPOCH,
+            data: Some(RawDailyEnergy::try_from(&message).unwrap()),
+        };
+        let energy = serde_json::to_string_pretty(&DailyEnergy::from(&measurement)).unwrap();
+        insta::assert_snapshot!(energy, @r#"
+        {
+          "timestamp": 0,
+          "battery_charge": 2025,
+          "battery_discharge": 329,
+          "solar": 3332,
+          "output": 1518
+        }
+        "#);
+    }
+
     #[test]
     fn test_message_battery_data() {
         // Payload obtained by sending `cd=16`.
@@ -538,4 +2019,473 @@ mod tests {
             }
         "###);
     }
+
+    /// Status of an HMG-50, corrupted by the property tests.
+    const STATUS: &str = "p1=1,p2=1,w1=23,w2=23,pe=99,vv=220,sv=12,cs=0,cd=0,am=0,o1=1,o2=1,do=80,lv=200,cj=2,kn=2217,g1=1,g2=0,tl=27,th=27,l0=1,bc=2025,bs=329,pt=3332,it=1518";
+
+    /// Decodes `payload` with every transform and parses it like the device loop does.
+    fn decode_any(payload: &[u8]) {
+        let options = DeviceOptions {
+            ty: "HMG-50".to_owned(),
+            mac: "abc".to_owned(),
+            protocol: Protocol::default(),
+            dry_run: false,
+            min_interval: Duration::ZERO,
+            retry: RetryPolicy::default(),
+        };
+        for transform in [
+            Transform::None,
+            Transform::Base64,
+            Transform::Xor(vec![0x5a]),
+        ] {
+            let Ok(message) = transform
+                .apply(Bytes::copy_from_slice(payload))
+                .and_then(Message::parse)
+            else {
+                continue;
+            };
+
+            if let Ok(device_info) = RawDeviceInfo::try_from(&message) {
+                let device_info =
+                    to_device_info(&options, &Measurement::new(device_info, &SystemClock));
+                for metric in DeviceInfo::METRICS {
+                    device_info.metric(metric);
+                }
+            }
+            let _ = RawDailyEnergy::try_from(&message);
+        }
+    }
+
+    proptest::proptest! {
+        #[test]
+        fn test_message_parse_never_panics(payload in proptest::collection::vec(proptest::num::u8::ANY, 0..256)) {
+            decode_any(&payload);
+        }
+
+        #[test]
+        fn test_message_parse_corrupted(
+            index in 0..STATUS.len(),
+            byte in proptest::sample::select(vec![b',', b'=', b' ', 0x00, 0xff]),
+            truncate in proptest::bool::ANY,
+        ) {
+            let mut payload = STATUS.as_bytes().to_vec();
+            match truncate {
+                true => payload.truncate(index),
+                false => payload[index] = byte,
+            }
+            decode_any(&payload);
+        }
+
+        #[test]
+        fn test_message_parse_values(
+            key in proptest::sample::select(vec!["pe", "w1", "kn", "cj", "tl", "vv", "sv", "l0"]),
+            value in "-?[0-9]{0,30}|1e400|inf|NaN|=|9{10000}",
+        ) {
+            decode_any(format!("{STATUS},{key}={value}").as_bytes());
+        }
+
+        #[test]
+        fn test_message_roundtrip(
+            fields in proptest::collection::btree_map("[a-z0-9]{1,3}", "[a-zA-Z0-9:.=-]{0,8}", 1..20),
+        ) {
+            let raw = fields
+                .iter()
+                .map(|(key, value)| format!("{key}={value}"))
+                .collect::<Vec<_>>()
+                .join(",");
+            let message = Message::parse(Bytes::from(raw.clone())).unwrap();
+            proptest::prop_assert_eq!(message.to_string(), raw);
+            proptest::prop_assert!(message.fields().eq(fields.iter().map(|(k, v)| (k.as_str(), v.as_str()))));
+        }
+    }
+
+    #[test]
+    fn test_message_parse() {
+        // Duplicate keys, the last value wins.
+        let message = Message::parse(Bytes::from_static(b"pe=99,pe=42")).unwrap();
+        assert_eq!(message.get("pe"), Some("42"));
+
+        assert!(Message::parse(Bytes::from_static(b"")).is_err());
+        assert!(Message::parse(Bytes::from_static(b"pe=99,w1")).is_err());
+        assert!(Message::parse(Bytes::from_static(b"\xff=1")).is_err());
+    }
+
+    #[test]
+    fn test_message_set() {
+        let mut message = Message::parse(Bytes::from_static(b"pe=99,cd=0")).unwrap();
+        message.set("pe", "42").set("w1", "23");
+
+        assert_eq!(message.to_string(), "cd=0,pe=42,w1=23");
+        let message = Message::parse(Bytes::from(message.to_string())).unwrap();
+        assert_eq!(message.get_value::<u8>("pe").unwrap(), Some(42));
+    }
+
+    fn options() -> DeviceOptions {
+        DeviceOptions {
+            ty: "HMA-1".to_owned(),
+            mac: "abc".to_owned(),
+            protocol: Protocol::default(),
+            dry_run: false,
+            min_interval: Duration::ZERO,
+            retry: RetryPolicy::default(),
+        }
+    }
+
+    /// Connects a simulated device and a device to `broker`, once both are subscribed.
+    async fn connect(broker: &Broker) -> (Device, JoinHandle<Result<()>>) {
+        let options = options();
+        tokio::spawn(Simulator::new(broker.options("simulator"), options.clone()).run());
+        broker.subscribed(&options.control_topic()).await;
+
+        let (device, ev) = Device::new(broker.options("hmtk"), options.clone()).unwrap();
+        let ev = tokio::spawn(ev.into_future());
+        broker.subscribed(&options.data_topic()).await;
+
+        (device, ev)
+    }
+
+    /// Fails the test instead of hanging if `future` does not complete.
+    async fn timeout<T>(future: impl Future<Output = T>) -> T {
+        tokio::time::timeout(Duration::from_secs(10), future)
+            .await
+            .expect("timed out")
+    }
+
+    /// Waits until `condition` is met.
+    async fn until(mut condition: impl FnMut() -> bool) {
+        timeout(async {
+            while !condition() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+    }
+
+    #[tokio::test]
+    async fn test_device_requests() {
+        let broker = Broker::start().await;
+        let (mut device, _ev) = timeout(connect(&broker)).await;
+
+        let device_info = timeout(device.device_info()).await.unwrap();
+        assert_eq!(device_info.metric("battery.charge"), Some(99.0));
+        assert!(device.current_device_info().is_some());
+
+        let battery_data = timeout(device.battery_data()).await.unwrap();
+        assert_eq!(battery_data.get("bb"), Some("56"));
+
+        let time = LocalTime {
+            year: 2024,
+            month: 3,
+            day: 5,
+            hour: 13,
+            minute: 7,
+            second: 9,
+            utc_offset: 0,
+        };
+        timeout(device.sync_time(time)).await.unwrap();
+        let control_topic = device.options().control_topic();
+        until(|| broker.published(&control_topic).len() == 3).await;
+        assert_eq!(
+            broker.published(&control_topic),
+            [
+                "cd=1",
+                "cd=16",
+                "cd=8,wy=0,yy=124,mm=2,rr=5,hh=13,mn=7,ss=9"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_device_verify() {
+        let broker = Broker::start().await;
+        let (mut device, _ev) = timeout(connect(&broker)).await;
+
+        let command = ControlCommand::SetOutputThreshold(Watt(250));
+        timeout(device.send_command(command, "testing"))
+            .await
+            .unwrap();
+        timeout(device.verify(command, Duration::from_secs(5)))
+            .await
+            .unwrap();
+
+        // The device reports the previous threshold, e.g. after ignoring the command.
+        let command = ControlCommand::SetOutputThreshold(Watt(300));
+        let err = timeout(device.verify(command, Duration::from_millis(200)))
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "device did not apply `lv=300`, it reports `lv=250`"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_device_coalesce_requests() {
+        let broker = Broker::start().await;
+        let (device, _ev) = timeout(connect(&broker)).await;
+
+        let (mut a, mut b) = (device.clone(), device.clone());
+        let (a, b) = timeout(async { tokio::join!(a.device_info(), b.device_info()) }).await;
+        assert_eq!(a.unwrap().metric("battery.charge"), Some(99.0));
+        assert_eq!(b.unwrap().metric("battery.charge"), Some(99.0));
+
+        let control_topic = device.options().control_topic();
+        assert_eq!(broker.published(&control_topic), ["cd=1"]);
+    }
+
+    #[tokio::test]
+    async fn test_device_subscribe_device_info() {
+        let broker = Broker::start().await;
+        let (mut device, _ev) = timeout(connect(&broker)).await;
+
+        // Every subscriber receives every device info, not only the most recent one.
+        let mut updates = [
+            device.subscribe_device_info(),
+            device.subscribe_device_info(),
+        ];
+        timeout(device.device_info()).await.unwrap();
+        timeout(device.device_info()).await.unwrap();
+        for updates in &mut updates {
+            for _ in 0..2 {
+                let device_info = timeout(updates.recv()).await.unwrap();
+                assert_eq!(device_info.metric("battery.charge"), Some(99.0));
+            }
+        }
+
+        let control_topic = device.options().control_topic();
+        assert_eq!(broker.published(&control_topic), ["cd=1", "cd=1"]);
+    }
+
+    #[tokio::test]
+    async fn test_device_correlate_responses() {
+        let broker = Broker::start().await;
+        let (device, _ev) = timeout(connect(&broker)).await;
+
+        // Concurrent requests for different commands each receive their own response.
+        let (mut a, mut b, mut c) = (device.clone(), device.clone(), device.clone());
+        let (device_info, battery_data, daily_energy) =
+            timeout(async { tokio::join!(a.device_info(), b.battery_data(), c.daily_energy()) })
+                .await;
+        assert_eq!(device_info.unwrap().metric("battery.charge"), Some(99.0));
+        assert_eq!(battery_data.unwrap().get("bb"), Some("56"));
+        assert_eq!(daily_energy.unwrap().solar.0, 3332);
+    }
+
+    #[tokio::test]
+    async fn test_device_attach() {
+        let broker = Broker::start().await;
+        let (mut device, _ev) = timeout(connect(&broker)).await;
+
+        let options = DeviceOptions {
+            mac: "def".to_owned(),
+            ..options()
+        };
+        let mut simulator = Simulator::new(broker.options("simulator-2"), options.clone());
+        simulator.status().set("pe", "42");
+        tokio::spawn(simulator.run());
+        broker.subscribed(&options.control_topic()).await;
+
+        let mut other = device.attach(options.clone());
+        broker.subscribed(&options.data_topic()).await;
+
+        // Both devices are served by the same connection and only see their own messages.
+        let (a, b) =
+            timeout(async { tokio::join!(device.device_info(), other.device_info()) }).await;
+        assert_eq!(a.unwrap().metric("battery.charge"), Some(99.0));
+        assert_eq!(b.unwrap().metric("battery.charge"), Some(42.0));
+        assert_eq!(broker.published(&options.control_topic()), ["cd=1"]);
+
+        let again = device.attach(options.clone());
+        assert_eq!(
+            again
+                .current_device_info()
+                .unwrap()
+                .metric("battery.charge"),
+            Some(42.0)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_device_watch_control() {
+        let broker = Broker::start().await;
+        let options = options();
+        let (device, ev) = Device::new(broker.options("hmtk"), options.clone()).unwrap();
+        tokio::spawn(ev.into_future());
+        device.watch_control().unwrap();
+        broker.subscribed(&options.control_topic()).await;
+
+        let mut traffic = device.traffic();
+        let command = ControlCommand::SetOutputThreshold(Watt(250));
+        timeout(device.send_command(command, "test")).await.unwrap();
+
+        let record = timeout(async {
+            loop {
+                let record = traffic.recv().await.unwrap();
+                if record.direction == Direction::Inbound {
+                    return record;
+                }
+            }
+        })
+        .await;
+        assert_eq!(record.topic, options.control_topic());
+        assert_eq!(record.payload, "cd=6,md=250");
+        // Commands are not mistaken for messages of the device.
+        assert!(device.current_device_info().is_none());
+        assert_eq!(device.parse_errors(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_device_persistent_session() {
+        let broker = Broker::start().await;
+        let (device, _ev) = timeout(connect(&broker)).await;
+        let data_topic = device.options().data_topic();
+        assert_eq!(broker.requested_qos(&data_topic), Some(0));
+
+        // The broker only queues the reports for QoS 1 subscriptions of persistent sessions.
+        let mut mqtt = broker.options("persistent");
+        mqtt.set_clean_session(false);
+        let (_persistent, ev) = Device::new(mqtt, options()).unwrap();
+        tokio::spawn(ev.into_future());
+        until(|| broker.requested_qos(&data_topic) == Some(1)).await;
+    }
+
+    #[tokio::test]
+    async fn test_device_reconnect() {
+        let broker = Broker::start().await;
+        let (mut device, _ev) = timeout(connect(&broker)).await;
+        timeout(device.device_info()).await.unwrap();
+
+        broker.drop_connections();
+        // Both clients resubscribe after reconnecting, the device would not see responses otherwise.
+        until(|| broker.connects() == 4).await;
+        broker.subscribed(&device.options().control_topic()).await;
+        broker.subscribed(&device.options().data_topic()).await;
+
+        let device_info = timeout(device.device_info()).await.unwrap();
+        assert_eq!(device_info.metric("battery.charge"), Some(99.0));
+        assert_eq!(device.reconnects(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_device_failover() {
+        let broker = Broker::start().await;
+        let unreachable = Broker::start().await;
+        let first = unreachable.options("hmtk");
+        unreachable.stop();
+
+        let options = options();
+        tokio::spawn(Simulator::new(broker.options("simulator"), options.clone()).run());
+        broker.subscribed(&options.control_topic()).await;
+
+        let mqtt = ClientOptions::failover(vec![first.into(), broker.options("hmtk").into()]);
+        let (mut device, ev) = Device::new(mqtt, options.clone()).unwrap();
+        let _ev = tokio::spawn(ev.into_future());
+
+        // The first broker refuses the connection, the device loop fails over to the second.
+        broker.subscribed(&options.data_topic()).await;
+        let device_info = timeout(device.device_info()).await.unwrap();
+        assert_eq!(device_info.metric("battery.charge"), Some(99.0));
+        assert_eq!(unreachable.connects(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_device_retry() {
+        let broker = Broker::start().await;
+        let options = DeviceOptions {
+            retry: RetryPolicy {
+                retries: 1,
+                timeout: Duration::from_millis(100),
+                backoff: Duration::from_millis(10),
+            },
+            ..options()
+        };
+        let (mut device, ev) = Device::new(broker.options("hmtk"), options.clone()).unwrap();
+        let _ev = tokio::spawn(ev.into_future());
+        broker.subscribed(&options.data_topic()).await;
+
+        // Without a device every attempt times out, each one sends a new request.
+        let err = timeout(device.device_info()).await.unwrap_err();
+        assert!(matches!(err, Error::Timeout(_)));
+        assert_eq!(broker.published(&options.control_topic()), ["cd=1", "cd=1"]);
+
+        let err = timeout(device.battery_data()).await.unwrap_err();
+        assert!(matches!(err, Error::Timeout(_)));
+        assert_eq!(
+            broker.published(&options.control_topic())[2..],
+            ["cd=16", "cd=16"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_device_reissue_after_reconnect() {
+        let broker = Broker::start().await;
+        let options = options();
+        let (mut device, ev) = Device::new(broker.options("hmtk"), options.clone()).unwrap();
+        let _ev = tokio::spawn(ev.into_future());
+        broker.subscribed(&options.data_topic()).await;
+
+        let control_topic = options.control_topic();
+        let request = tokio::spawn(async move { device.device_info().await });
+        until(|| broker.published(&control_topic).len() == 1).await;
+
+        // The unanswered request is sent again right after reconnecting, long before it times out.
+        broker.drop_connections();
+        until(|| broker.published(&control_topic).len() == 2).await;
+        assert_eq!(broker.connects(), 2);
+        request.abort();
+    }
+
+    #[tokio::test]
+    async fn test_device_stats() {
+        let broker = Broker::start().await;
+        let (mut device, _ev) = timeout(connect(&broker)).await;
+        assert_eq!(device.latency(), None);
+
+        timeout(device.device_info()).await.unwrap();
+        assert!(device.latency().is_some());
+
+        let topic = device.options().data_topic();
+        device.publish(&topic, false, b"p1".to_vec()).await.unwrap();
+        until(|| device.parse_errors() == 1).await;
+        assert_eq!(device.publish_failures(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_device_health() {
+        let broker = Broker::start().await;
+        let (device, _ev) = timeout(connect(&broker)).await;
+        assert!(device.is_healthy());
+
+        broker.stop();
+        until(|| !device.is_healthy()).await;
+    }
+
+    #[tokio::test]
+    async fn test_device_disconnect() {
+        let broker = Broker::start().await;
+        let (mut device, ev) = timeout(connect(&broker)).await;
+
+        device.disconnect().await.unwrap();
+        timeout(ev).await.unwrap().unwrap();
+        assert_eq!(broker.disconnects(), 1);
+        assert!(matches!(
+            device.device_info().await,
+            Err(Error::MqttClientError(_) | Error::Disconnected)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_device_disconnect_unreachable() {
+        let broker = Broker::start().await;
+        let mqtt = broker.options("hmtk");
+        drop(broker);
+
+        // Never connects, the device loop exits without waiting for the connection.
+        let (mut device, ev) = Device::new(mqtt, options()).unwrap();
+        let ev = tokio::spawn(ev.into_future());
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        device.disconnect().await.unwrap();
+        timeout(ev).await.unwrap().unwrap();
+    }
 }
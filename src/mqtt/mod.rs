@@ -1,6 +1,20 @@
+#[cfg(test)]
+mod broker;
+mod client;
 mod device;
+mod replay;
+mod retry;
+mod simulator;
+mod spec;
+mod throttle;
+mod transform;
 
+pub use self::client::ClientOptions;
 pub use self::device::*;
+pub use self::retry::RetryPolicy;
+pub use self::simulator::Simulator;
+pub use self::spec::{Command, ControlCommand, DeviceModel, FieldDoc};
+pub use self::transform::{Decode, DecodeError, Transform};
 
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
@@ -9,6 +23,31 @@ pub enum Error {
     InvalidStatus(#[from] InvalidStatus),
     #[error("failed to publish mqttt message {0}")]
     MqttClientError(#[from] rumqttc::ClientError),
+    #[error("failed to publish mqttt message {0}")]
+    MqttV5ClientError(#[from] Box<rumqttc::v5::ClientError>),
+    /// The device did not apply a command, see [`Device::verify`].
+    #[error("device did not apply `{field}={expected}`, it reports {}", match actual {
+        Some(actual) => format!("`{field}={actual}`"),
+        None => "no value".to_owned(),
+    })]
+    NotApplied {
+        field: &'static str,
+        expected: String,
+        actual: Option<String>,
+    },
+    /// The device did not answer a request, see [`RetryPolicy`].
+    #[error("device did not respond within {}s", .0.as_secs_f32())]
+    Timeout(std::time::Duration),
+    /// The device loop exited, no more data will be received.
+    #[error("device loop is no longer running")]
+    Disconnected,
+    /// The status message does not match the parser of the device type.
+    #[error("failed to parse device status: {0}")]
+    Parser(#[from] crate::parser::Error),
+    /// The runtime of the blocking client could not be started.
+    #[cfg(feature = "blocking")]
+    #[error("failed to start runtime: {0}")]
+    Runtime(std::io::Error),
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -22,6 +61,8 @@ pub enum InvalidStatus {
     ),
     #[error("field '{0}' is required, but missing in the status message")]
     MissingField(&'static str),
+    #[error("failed to decode {0:?}: {1}")]
+    Decode(bytes::Bytes, #[source] self::transform::DecodeError),
 }
 
 pub type Result<T, E = Error> = std::result::Result<T, E>;
@@ -0,0 +1,75 @@
+//! Replays captured traffic instead of connecting to a broker.
+use std::time::SystemTime;
+
+use tokio::sync::mpsc;
+use tokio::time::Instant;
+
+use crate::capture::{Direction, Record};
+use crate::mqtt::client::Event;
+use crate::time::ManualClock;
+
+/// Feeds the inbound messages of a capture into the device loop.
+///
+/// Messages are delayed by their original distance divided by the speed, the clock is
+/// set to the original timestamp of each message before it is delivered.
+pub(crate) struct ReplayLoop {
+    records: std::vec::IntoIter<Record>,
+    speed: f64,
+    clock: ManualClock,
+    /// Real time and capture time of the first replayed message.
+    start: Option<(Instant, SystemTime)>,
+    disconnect: mpsc::UnboundedReceiver<()>,
+    disconnected: bool,
+}
+
+impl ReplayLoop {
+    pub fn new(
+        records: Vec<Record>,
+        speed: f64,
+        clock: ManualClock,
+    ) -> (mpsc::UnboundedSender<()>, Self) {
+        let records = records
+            .into_iter()
+            .filter(|record| record.direction == Direction::Inbound)
+            .collect::<Vec<_>>();
+        let (disconnect_tx, disconnect) = mpsc::unbounded_channel();
+
+        let ev = Self {
+            records: records.into_iter(),
+            speed,
+            clock,
+            start: None,
+            disconnect,
+            disconnected: false,
+        };
+        (disconnect_tx, ev)
+    }
+
+    pub async fn poll(&mut self) -> Event {
+        if self.disconnected {
+            return Event::ConnectionAborted;
+        }
+        let Some(record) = self.records.next() else {
+            return Event::Closed;
+        };
+
+        let (start, first) = *self
+            .start
+            .get_or_insert_with(|| (Instant::now(), record.timestamp));
+        let offset = record.timestamp.duration_since(first).unwrap_or_default();
+
+        tokio::select! {
+            _ = tokio::time::sleep_until(start + offset.div_f64(self.speed)) => {}
+            _ = self.disconnect.recv() => {
+                self.disconnected = true;
+                return Event::Disconnect;
+            }
+        }
+
+        self.clock.set(record.timestamp);
+        Event::Publish {
+            topic: record.topic,
+            payload: record.payload,
+        }
+    }
+}
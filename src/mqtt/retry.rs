@@ -0,0 +1,180 @@
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use bytes::Bytes;
+
+use super::{Error, Result};
+
+/// How requests to the device are retried, if they fail or remain unanswered.
+///
+/// A failed attempt is retried after the `backoff`, which doubles with every further attempt.
+/// Requests still waiting for their response are additionally re-issued by the device loop
+/// after it reconnected to the broker.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Number of attempts after the first one, `0` disables retries.
+    pub retries: u32,
+    /// Time to wait for the response of an attempt.
+    pub timeout: Duration,
+    /// Delay before the first retry.
+    pub backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            retries: 2,
+            timeout: Duration::from_secs(10),
+            backoff: Duration::from_secs(1),
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub(crate) fn attempts(self) -> Attempts {
+        Attempts {
+            policy: self,
+            attempt: 0,
+        }
+    }
+}
+
+/// Attempts of a single request according to a [`RetryPolicy`].
+pub(crate) struct Attempts {
+    policy: RetryPolicy,
+    attempt: u32,
+}
+
+impl Attempts {
+    /// Waits for the response of the current attempt, fails with [`Error::Timeout`] if it takes
+    /// longer than the timeout of the policy.
+    pub async fn timeout<T>(&self, response: impl Future<Output = Result<T>>) -> Result<T> {
+        tokio::time::timeout(self.policy.timeout, response)
+            .await
+            .map_err(|_| Error::Timeout(self.policy.timeout))?
+    }
+
+    /// Waits before the next attempt after the current one failed with `err`.
+    ///
+    /// Returns `err` if all attempts are used up or the request cannot succeed on a retry.
+    pub async fn retry(&mut self, err: Error) -> Result<()> {
+        if self.attempt >= self.policy.retries || !is_retryable(&err) {
+            return Err(err);
+        }
+
+        let backoff = self.policy.backoff * 2u32.saturating_pow(self.attempt);
+        self.attempt += 1;
+        tracing::debug!(
+            attempt = self.attempt,
+            "request failed: {err}, retrying in {}ms",
+            backoff.as_millis()
+        );
+        tokio::time::sleep(backoff).await;
+        Ok(())
+    }
+}
+
+/// Publishes which did not reach the broker and unanswered requests may succeed on a retry.
+fn is_retryable(err: &Error) -> bool {
+    matches!(
+        err,
+        Error::Timeout(_) | Error::MqttClientError(_) | Error::MqttV5ClientError(_)
+    )
+}
+
+/// Payloads of the requests of a device, which are waiting for their response.
+#[derive(Debug, Default)]
+pub(crate) struct InFlight {
+    requests: Mutex<Vec<Bytes>>,
+}
+
+impl InFlight {
+    /// Tracks `request` until the returned guard is dropped.
+    pub fn track(self: &Arc<Self>, request: Bytes) -> InFlightGuard {
+        self.lock().push(request.clone());
+        InFlightGuard {
+            in_flight: Arc::clone(self),
+            request,
+        }
+    }
+
+    /// Returns all requests in flight, every payload only once.
+    pub fn requests(&self) -> Vec<Bytes> {
+        let mut requests = self.lock().clone();
+        requests.sort();
+        requests.dedup();
+        requests
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Vec<Bytes>> {
+        self.requests.lock().expect("in flight lock poisoned")
+    }
+}
+
+/// Removes a request from [`InFlight`] once its response was received or it was abandoned.
+pub(crate) struct InFlightGuard {
+    in_flight: Arc<InFlight>,
+    request: Bytes,
+}
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        let mut requests = self.in_flight.lock();
+        if let Some(index) = requests.iter().position(|r| *r == self.request) {
+            requests.swap_remove(index);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_attempts() {
+        let policy = RetryPolicy {
+            retries: 2,
+            timeout: Duration::from_millis(10),
+            backoff: Duration::from_millis(10),
+        };
+        let mut attempts = policy.attempts();
+        let start = std::time::Instant::now();
+
+        let err = attempts
+            .timeout(std::future::pending::<Result<()>>())
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Error::Timeout(_)));
+        attempts.retry(err).await.unwrap();
+        attempts
+            .retry(Error::Timeout(policy.timeout))
+            .await
+            .unwrap();
+        assert!(
+            attempts
+                .retry(Error::Timeout(policy.timeout))
+                .await
+                .is_err()
+        );
+        // One timeout and a backoff of 10ms and 20ms.
+        assert!(start.elapsed() >= Duration::from_millis(40));
+
+        let mut attempts = policy.attempts();
+        assert!(attempts.retry(Error::Disconnected).await.is_err());
+    }
+
+    #[test]
+    fn test_in_flight() {
+        let in_flight = Arc::new(InFlight::default());
+        let a = in_flight.track(Bytes::from_static(b"cd=1"));
+        let b = in_flight.track(Bytes::from_static(b"cd=1"));
+        let c = in_flight.track(Bytes::from_static(b"cd=13"));
+        assert_eq!(in_flight.requests(), ["cd=1", "cd=13"]);
+
+        drop(a);
+        drop(c);
+        assert_eq!(in_flight.requests(), ["cd=1"]);
+        drop(b);
+        assert!(in_flight.requests().is_empty());
+    }
+}
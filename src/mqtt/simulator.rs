@@ -0,0 +1,124 @@
+use std::time::Duration;
+
+use bytes::Bytes;
+use rumqttc::QoS;
+
+use super::client::{Client, Event, EventLoop};
+use super::{ClientOptions, ControlCommand, DeviceOptions, Message, Result};
+
+/// Status message of a B2500, sent in response to [`ControlCommand::ReadStatus`].
+const STATUS: &[u8] = b"p1=1,p2=1,w1=23,w2=23,pe=99,vv=220,sv=12,cs=0,cd=0,am=0,o1=1,o2=1,do=80,lv=200,cj=2,kn=2217,g1=1,g2=0,b1=0,b2=0,md=0,d1=1,e1=0:0,f1=23:59,h1=200,d2=0,e2=0:0,f2=0:0,h2=600,d3=0,e3=0:0,f3=0:0,h3=0,sg=0,sp=80,st=0,tl=27,th=27,tc=0,tf=0,fc=202310231502,id=5,a0=99,a1=0,a2=0,l0=1,l1=0,c0=255,c1=0,bc=2025,bs=329,pt=3332,it=1518,m0=0,m1=0,m2=0,m3=1,d4=0,e4=0:0,f4=24:0,h4=80,d5=0,e5=0:0,f5=24:0,h5=80,lmo=1830,lmi=272,lmf=1";
+/// Extended battery data of a B2500, sent in response to [`ControlCommand::ReadCellData`].
+const BATTERY_DATA: &[u8] = b"p1=0,p2=0,m1=36957,m2=37457,c1=1,c2=0,w1=0,w2=0,e1=1,e2=1,o1=2,o2=2,i1=39732,i2=39482,c3=3692,c4=3580,g1=116,g2=112,sg=0,sp=80,st=0,ps=3,bb=56,bv=46463,bc=1521,sb=0,sv=0,sc=0,lb=0,lv=0,lc=0";
+
+/// Pretends to be a device on the topics of its protocol, for tests and demos without hardware.
+///
+/// Status requests are answered with [`Simulator::status`], requests for the extended battery
+/// data with [`Simulator::battery_data`]. Setting the output threshold updates the status,
+/// setting the clock is logged, unknown commands are logged as warning.
+pub struct Simulator {
+    client: Client,
+    ev: EventLoop,
+    device: DeviceOptions,
+    status: Message,
+    battery_data: Message,
+}
+
+impl Simulator {
+    /// Creates a simulator of `device`, which answers with the messages of a B2500.
+    pub fn new(mqtt: impl Into<ClientOptions>, device: DeviceOptions) -> Self {
+        let (client, ev) = Client::new(mqtt.into());
+        Self {
+            client,
+            ev,
+            device,
+            status: Message::parse(Bytes::from_static(STATUS)).expect("valid status message"),
+            battery_data: Message::parse(Bytes::from_static(BATTERY_DATA))
+                .expect("valid battery data"),
+        }
+    }
+
+    /// The status message sent by the simulated device.
+    pub fn status(&mut self) -> &mut Message {
+        &mut self.status
+    }
+
+    /// The extended battery data sent by the simulated device.
+    pub fn battery_data(&mut self) -> &mut Message {
+        &mut self.battery_data
+    }
+
+    /// Answers requests until the event loop is closed, reconnects after connection errors.
+    pub async fn run(mut self) -> Result<()> {
+        let control_topic = self.device.control_topic();
+        let data_topic = self.device.data_topic();
+
+        loop {
+            match self.ev.poll().await {
+                Event::ConnAck { .. } => {
+                    tracing::info!(topic = %control_topic, "simulating device");
+                    self.client
+                        .try_subscribe(control_topic.clone(), QoS::AtMostOnce)?;
+                }
+                Event::Publish { topic, payload } if topic == control_topic => {
+                    let Some(response) = self.handle_command(payload) else {
+                        continue;
+                    };
+                    // Published from a task, the event loop has to keep running to send it.
+                    let client = self.client.clone();
+                    let topic = data_topic.clone();
+                    tokio::spawn(async move {
+                        let payload = Bytes::from(response.to_string());
+                        if let Err(err) = client
+                            .publish_bytes(&topic, QoS::AtMostOnce, false, payload)
+                            .await
+                        {
+                            tracing::warn!("failed to publish response: {err}");
+                        }
+                    });
+                }
+                Event::Publish { .. } | Event::Incoming(_) | Event::Outgoing(_) => {}
+                Event::Disconnect => {}
+                Event::Closed => return Ok(()),
+                // The simulator never disconnects itself, the broker closed the connection.
+                Event::ConnectionAborted => {
+                    tracing::warn!("connection closed by the broker, reconnecting in 1s");
+                    tokio::time::sleep(Duration::from_secs(1)).await;
+                }
+                Event::Error(err) | Event::RateLimited(err) => {
+                    tracing::warn!("connection error: {err}, reconnecting in 1s");
+                    tokio::time::sleep(Duration::from_secs(1)).await;
+                }
+            }
+        }
+    }
+
+    /// Applies the command in `payload` and returns the response of the device.
+    fn handle_command(&mut self, payload: Bytes) -> Option<Message> {
+        let message = match Message::parse(payload) {
+            Ok(message) => message,
+            Err(err) => {
+                tracing::warn!("received invalid command: {err}");
+                return None;
+            }
+        };
+
+        match ControlCommand::decode(&message) {
+            Some(ControlCommand::ReadStatus) => Some(self.status.clone()),
+            Some(ControlCommand::ReadCellData) => Some(self.battery_data.clone()),
+            Some(command @ ControlCommand::SyncTime(_)) => {
+                tracing::info!("device clock set: {command}");
+                None
+            }
+            Some(ControlCommand::SetOutputThreshold(power)) => {
+                tracing::info!("output threshold set to {} W", power.0);
+                self.status.set("lv", power.0.to_string());
+                None
+            }
+            None => {
+                tracing::warn!("received unknown command: {message}");
+                None
+            }
+        }
+    }
+}
@@ -0,0 +1,448 @@
+//! Machine readable description of the device protocol, as implemented by hmtk.
+//!
+//! The device implementation uses these registries itself, a description generated from them
+//! cannot drift from the implementation.
+use std::fmt;
+
+use serde::Serialize;
+
+use super::Protocol;
+use super::device::{Message, RawDailyEnergy, RawDeviceInfo};
+use crate::time::LocalTime;
+use crate::units::Watt;
+
+/// A command understood by the device, sent as `cd=<code>` to the control topic.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Command {
+    pub code: u8,
+    pub name: &'static str,
+    pub description: &'static str,
+    /// Additional `key=value` arguments of the command.
+    pub arguments: &'static [&'static str],
+    /// Fields which identify the response on the data topic, empty if the device does not
+    /// respond to the command.
+    pub response: &'static [&'static str],
+}
+
+impl Command {
+    /// Requests a status message.
+    pub const STATUS: Self = Self {
+        code: 1,
+        name: "status",
+        description: "Requests a status message on the data topic.",
+        arguments: &[],
+        response: &["pe", "kn"],
+    };
+    /// Sets the clock of the device.
+    pub const SYNC_TIME: Self = Self {
+        code: 8,
+        name: "sync-time",
+        description: "Sets the device clock, the year is relative to 1900 and the month starts at 0.",
+        arguments: &["wy", "yy", "mm", "rr", "hh", "mn", "ss"],
+        response: &[],
+    };
+    /// Sets the output threshold.
+    pub const SET_OUTPUT_THRESHOLD: Self = Self {
+        code: 6,
+        name: "set-output-threshold",
+        description: "Sets the power output to the inverter in W, reported as `lv`.",
+        arguments: &["md"],
+        response: &[],
+    };
+    /// Requests the extended battery data.
+    pub const BATTERY_DATA: Self = Self {
+        code: 16,
+        name: "battery-data",
+        description: "Requests the extended battery data, the response is not decoded yet.",
+        arguments: &[],
+        response: &["bb", "bv"],
+    };
+
+    /// All commands known to hmtk.
+    pub const ALL: &[Self] = &[
+        Self::STATUS,
+        Self::SET_OUTPUT_THRESHOLD,
+        Self::SYNC_TIME,
+        Self::BATTERY_DATA,
+    ];
+
+    /// Returns the payload of the command without arguments, e.g. `cd=1`.
+    ///
+    /// Use [`ControlCommand`] to encode a command with its arguments.
+    pub fn payload(&self) -> String {
+        format!("cd={}", self.code)
+    }
+
+    /// Returns `true` if `message` is a response to this command.
+    pub fn is_response(&self, message: &Message) -> bool {
+        !self.response.is_empty() && self.response.iter().all(|key| message.get(key).is_some())
+    }
+
+    /// Returns the command `message` is a response to.
+    ///
+    /// Returns `None` for messages which do not belong to a known command, e.g. of device
+    /// types without built-in support.
+    pub fn response_to(message: &Message) -> Option<Self> {
+        Self::ALL
+            .iter()
+            .find(|command| command.is_response(message))
+            .copied()
+    }
+}
+
+/// A command with its arguments, which can be sent to the control topic of the device.
+///
+/// Every variant corresponds to one of the documented [`Command`]s.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ControlCommand {
+    /// Requests a status message.
+    ReadStatus,
+    /// Requests the extended battery data.
+    ReadCellData,
+    /// Sets the clock of the device.
+    SyncTime(LocalTime),
+    /// Sets the power output to the inverter.
+    SetOutputThreshold(Watt),
+}
+
+impl ControlCommand {
+    /// Returns the documentation of the command.
+    pub fn spec(&self) -> Command {
+        match self {
+            Self::ReadStatus => Command::STATUS,
+            Self::ReadCellData => Command::BATTERY_DATA,
+            Self::SyncTime(_) => Command::SYNC_TIME,
+            Self::SetOutputThreshold(_) => Command::SET_OUTPUT_THRESHOLD,
+        }
+    }
+
+    /// Returns the status field and value reported by the device once it applied the command,
+    /// e.g. `lv` and `250` for an output threshold of 250 W.
+    ///
+    /// Returns `None` for commands which are not reflected in the status.
+    pub fn expected_status(&self) -> Option<(&'static str, String)> {
+        match self {
+            Self::SetOutputThreshold(power) => Some(("lv", power.0.to_string())),
+            Self::ReadStatus | Self::ReadCellData | Self::SyncTime(_) => None,
+        }
+    }
+
+    /// Returns a human readable description of the command with its arguments, e.g.
+    /// `set the output threshold to 250 W`.
+    pub fn describe(&self) -> String {
+        match self {
+            Self::ReadStatus => "request the status".to_owned(),
+            Self::ReadCellData => "request the extended battery data".to_owned(),
+            Self::SyncTime(time) => {
+                let offset = time.utc_offset.unsigned_abs();
+                format!(
+                    "set the clock to {}-{:02}-{:02} {:02}:{:02}:{:02} UTC{}{:02}:{:02}",
+                    time.year,
+                    time.month,
+                    time.day,
+                    time.hour,
+                    time.minute,
+                    time.second,
+                    if time.utc_offset < 0 { '-' } else { '+' },
+                    offset / 60,
+                    offset % 60,
+                )
+            }
+            Self::SetOutputThreshold(power) => {
+                format!("set the output threshold to {} W", power.0)
+            }
+        }
+    }
+
+    /// Encodes the command as payload of the control topic, e.g. `cd=1`.
+    pub fn encode(&self) -> String {
+        self.to_string()
+    }
+
+    /// Decodes a command received on the control topic.
+    ///
+    /// Returns `None` for unknown commands and commands with missing or invalid arguments.
+    pub fn decode(message: &Message) -> Option<Self> {
+        let value = |key| message.get_value::<i32>(key).ok().flatten();
+        let byte = |key| u8::try_from(value(key)?).ok();
+
+        let code = byte("cd")?;
+        Some(match code {
+            _ if code == Command::STATUS.code => Self::ReadStatus,
+            _ if code == Command::BATTERY_DATA.code => Self::ReadCellData,
+            _ if code == Command::SYNC_TIME.code => {
+                let time = LocalTime {
+                    year: value("yy")?.checked_add(1900)?,
+                    month: byte("mm")?.checked_add(1)?,
+                    day: byte("rr")?,
+                    hour: byte("hh")?,
+                    minute: byte("mn")?,
+                    second: byte("ss")?,
+                    utc_offset: value("wy")?,
+                };
+                Self::SyncTime(time.is_valid().then_some(time)?)
+            }
+            _ if code == Command::SET_OUTPUT_THRESHOLD.code => {
+                Self::SetOutputThreshold(Watt(message.get_value("md").ok()??))
+            }
+            _ => return None,
+        })
+    }
+}
+
+impl fmt::Display for ControlCommand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "cd={}", self.spec().code)?;
+        match self {
+            Self::ReadStatus | Self::ReadCellData => Ok(()),
+            Self::SyncTime(time) => write!(
+                f,
+                ",wy={},yy={},mm={},rr={},hh={},mn={},ss={}",
+                time.utc_offset,
+                time.year.saturating_sub(1900),
+                time.month.saturating_sub(1),
+                time.day,
+                time.hour,
+                time.minute,
+                time.second,
+            ),
+            Self::SetOutputThreshold(power) => write!(f, ",md={}", power.0),
+        }
+    }
+}
+
+/// A family of devices, selected by the prefix of the device type.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DeviceModel {
+    /// The B2500 series of DC coupled batteries.
+    B2500,
+    /// The Venus series of AC coupled batteries.
+    Venus,
+}
+
+impl DeviceModel {
+    /// All device models known to hmtk.
+    pub const ALL: &[Self] = &[Self::B2500, Self::Venus];
+
+    /// Returns the model of the device type `ty`, e.g. [`Self::B2500`] for `HMA-1`.
+    pub fn from_type(ty: &str) -> Option<Self> {
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|model| model.prefixes().iter().any(|prefix| ty.starts_with(prefix)))
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::B2500 => "B2500",
+            Self::Venus => "Venus",
+        }
+    }
+
+    /// Prefixes of the device types of the model.
+    pub fn prefixes(self) -> &'static [&'static str] {
+        match self {
+            Self::B2500 => &["HMA", "HMB", "HMJ", "HMK"],
+            Self::Venus => &["HMG"],
+        }
+    }
+
+    /// Device types known to report the grid voltage and frequency in `vv` and `sv`.
+    ///
+    /// The B2500 series reports its firmware version in the same keys, the grid is therefore
+    /// only parsed for the types in this list, not for every type of an AC coupled model.
+    pub const GRID_TYPES: &[&str] = &["HMG-50"];
+
+    /// Whether the model is AC coupled and reports grid measurements.
+    pub fn ac_coupled(self) -> bool {
+        match self {
+            Self::B2500 => false,
+            Self::Venus => true,
+        }
+    }
+
+    /// Returns the MQTT protocol of the model.
+    pub fn protocol(self) -> Protocol {
+        match self {
+            // Both series use the topics of the Hame app.
+            Self::B2500 | Self::Venus => Protocol::default(),
+        }
+    }
+
+    /// Returns the fields of the status message decoded for the model.
+    pub fn fields(self) -> impl Iterator<Item = &'static FieldDoc> {
+        // DC coupled models use the keys of the grid fields for the firmware version.
+        FieldDoc::status()
+            .filter(move |field| self.ac_coupled() || !matches!(field.key, "vv" | "sv"))
+    }
+}
+
+/// A field of a status message decoded by hmtk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct FieldDoc {
+    pub key: &'static str,
+    pub description: &'static str,
+    /// Unit symbol of the value, e.g. `W`.
+    pub unit: Option<&'static str>,
+    /// Whether the field is only reported by some models.
+    pub optional: bool,
+}
+
+impl FieldDoc {
+    /// Returns all fields of the status message, which are decoded by hmtk.
+    pub fn status() -> impl Iterator<Item = &'static Self> {
+        RawDeviceInfo::FIELDS.iter().chain(RawDailyEnergy::FIELDS)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_status_fields() {
+        let field = |key: &str| *FieldDoc::status().find(|field| field.key == key).unwrap();
+
+        assert_eq!(
+            field("do"),
+            FieldDoc {
+                key: "do",
+                description: "Discharge Depth.",
+                unit: Some("%"),
+                optional: false,
+            }
+        );
+        assert!(field("vv").description.starts_with("Grid Voltage in V"));
+        assert!(field("vv").optional);
+        assert_eq!(field("pt").unit, Some("Wh"));
+    }
+
+    #[test]
+    fn test_device_model() {
+        assert_eq!(DeviceModel::from_type("HMA-1"), Some(DeviceModel::B2500));
+        assert_eq!(DeviceModel::from_type("HMG-50"), Some(DeviceModel::Venus));
+        assert_eq!(DeviceModel::from_type("HMX-1"), None);
+
+        let grid = |model: DeviceModel| model.fields().any(|field| field.key == "vv");
+        assert!(!grid(DeviceModel::B2500));
+        assert!(grid(DeviceModel::Venus));
+        let limits = |model: DeviceModel| model.fields().any(|field| field.key == "lmi");
+        assert!(limits(DeviceModel::B2500));
+        assert!(limits(DeviceModel::Venus));
+        assert_eq!(Command::BATTERY_DATA.payload(), "cd=16");
+    }
+
+    #[test]
+    fn test_control_command() {
+        let time = LocalTime {
+            year: 2024,
+            month: 3,
+            day: 5,
+            hour: 13,
+            minute: 7,
+            second: 9,
+            utc_offset: 60,
+        };
+        let commands = [
+            (ControlCommand::ReadStatus, "cd=1"),
+            (ControlCommand::ReadCellData, "cd=16"),
+            (
+                ControlCommand::SyncTime(time),
+                "cd=8,wy=60,yy=124,mm=2,rr=5,hh=13,mn=7,ss=9",
+            ),
+            (ControlCommand::SetOutputThreshold(Watt(250)), "cd=6,md=250"),
+        ];
+
+        for (command, payload) in commands {
+            assert_eq!(command.encode(), payload);
+            assert!(Command::ALL.contains(&command.spec()));
+
+            let message = Message::parse(payload.into()).unwrap();
+            assert_eq!(ControlCommand::decode(&message), Some(command));
+        }
+
+        assert_eq!(
+            ControlCommand::SetOutputThreshold(Watt(250)).expected_status(),
+            Some(("lv", "250".to_owned()))
+        );
+        assert_eq!(ControlCommand::SyncTime(time).expected_status(), None);
+    }
+
+    #[test]
+    fn test_control_command_decode() {
+        let decode = |payload: &'static str| {
+            ControlCommand::decode(&Message::parse(payload.into()).unwrap())
+        };
+
+        assert_eq!(decode("cd=99"), None);
+        assert_eq!(decode("cd=6"), None);
+        assert_eq!(decode("cd=6,md=-1"), None);
+        assert_eq!(decode("cd=8,wy=0,yy=124"), None);
+
+        // Values of other clients are not trusted, they neither overflow nor exceed the ranges.
+        assert_eq!(decode("cd=8,wy=0,yy=0,mm=255,rr=1,hh=0,mn=0,ss=0"), None);
+        assert_eq!(
+            decode("cd=8,wy=0,yy=2147483647,mm=0,rr=1,hh=0,mn=0,ss=0"),
+            None
+        );
+        assert_eq!(decode("cd=8,wy=0,yy=124,mm=12,rr=1,hh=0,mn=0,ss=0"), None);
+        assert_eq!(decode("cd=8,wy=0,yy=124,mm=11,rr=0,hh=0,mn=0,ss=0"), None);
+        assert_eq!(decode("cd=8,wy=0,yy=124,mm=11,rr=31,hh=24,mn=0,ss=0"), None);
+        assert_eq!(
+            decode("cd=8,wy=0,yy=124,mm=11,rr=31,hh=23,mn=60,ss=0"),
+            None
+        );
+        assert_eq!(
+            decode("cd=8,wy=0,yy=124,mm=11,rr=31,hh=23,mn=59,ss=60"),
+            None
+        );
+        assert!(decode("cd=8,wy=0,yy=124,mm=11,rr=31,hh=23,mn=59,ss=59").is_some());
+
+        // Hand-built times are encoded without underflow.
+        let time = LocalTime {
+            year: 0,
+            month: 0,
+            day: 0,
+            hour: 0,
+            minute: 0,
+            second: 0,
+            utc_offset: 0,
+        };
+        assert_eq!(
+            ControlCommand::SyncTime(time).encode(),
+            "cd=8,wy=0,yy=-1900,mm=0,rr=0,hh=0,mn=0,ss=0"
+        );
+    }
+
+    #[test]
+    fn test_control_command_describe() {
+        let describe = |payload: &'static str| {
+            ControlCommand::decode(&Message::parse(payload.into()).unwrap())
+                .unwrap()
+                .describe()
+        };
+
+        assert_eq!(describe("cd=1"), "request the status");
+        assert_eq!(describe("cd=6,md=250"), "set the output threshold to 250 W");
+        assert_eq!(
+            describe("cd=8,wy=-150,yy=124,mm=2,rr=5,hh=13,mn=7,ss=9"),
+            "set the clock to 2024-03-05 13:07:09 UTC-02:30"
+        );
+    }
+
+    #[test]
+    fn test_response_to() {
+        let response =
+            |payload: &'static str| Command::response_to(&Message::parse(payload.into()).unwrap());
+
+        // Status messages report the last command as `cd`, which must not be mistaken.
+        assert_eq!(response("pe=99,kn=2217,cd=16"), Some(Command::STATUS));
+        assert_eq!(
+            response("bb=56,bv=46463,c3=3692"),
+            Some(Command::BATTERY_DATA)
+        );
+        assert_eq!(response("cd=8"), None);
+        assert_eq!(response("foo=bar"), None);
+    }
+}
@@ -0,0 +1,206 @@
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+/// Spaces out publishes to the control topic and slows them down after the broker rate limited
+/// the client.
+///
+/// Publishes are at least [`Self::new`]'s `min_interval` apart. Every rate limit doubles the
+/// minimum spacing, up to [`Self::MAX_FACTOR`]. Without further rate limits the spacing is halved
+/// again every [`Self::RECOVERY`].
+#[derive(Debug, Default)]
+pub(crate) struct Throttle {
+    min_interval: Duration,
+    state: Mutex<State>,
+    rate_limits: AtomicU64,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    /// Multiple of [`Throttle::SPACING`] at the time of the last rate limit, `0` if never limited.
+    factor: u32,
+    limited_at: Option<Instant>,
+    last_request: Option<Instant>,
+    /// Time the unanswered status request was sent.
+    pending_status: Option<Instant>,
+    /// Time between the last answered status request and its response.
+    latency: Option<Duration>,
+}
+
+impl Throttle {
+    /// Minimum spacing of status requests after the first rate limit.
+    pub const SPACING: Duration = Duration::from_secs(10);
+    const MAX_FACTOR: u32 = 32;
+    const RECOVERY: Duration = Duration::from_secs(10 * 60);
+    /// Time after which an unanswered status request is considered lost.
+    const STATUS_TIMEOUT: Duration = Duration::from_secs(5);
+
+    /// Creates a throttle, which spaces out publishes at least `min_interval`.
+    pub fn new(min_interval: Duration) -> Self {
+        Self {
+            min_interval,
+            ..Default::default()
+        }
+    }
+
+    /// Records a rate limit at `now` and returns the new minimum spacing of status requests.
+    pub fn limit(&self, now: Instant) -> Duration {
+        self.rate_limits.fetch_add(1, Ordering::Relaxed);
+
+        let mut state = self.state.lock().expect("throttle lock poisoned");
+        let factor = (state.factor(now) * 2).clamp(1, Self::MAX_FACTOR);
+        state.factor = factor;
+        state.limited_at = Some(now);
+        Self::SPACING * factor
+    }
+
+    /// Returns how often the broker rate limited the client.
+    pub fn rate_limits(&self) -> u64 {
+        self.rate_limits.load(Ordering::Relaxed)
+    }
+
+    /// Returns how long to wait from `now` before the next publish may be sent.
+    ///
+    /// The publish is accounted for at the returned delay, concurrent publishes are spaced out.
+    pub fn reserve(&self, now: Instant) -> Duration {
+        let mut state = self.state.lock().expect("throttle lock poisoned");
+        self.reserve_locked(&mut state, now)
+    }
+
+    /// Reserves a status request like [`Self::reserve`], unless a previous status request is
+    /// still waiting for its response.
+    ///
+    /// Returns `None` if the pending request should be awaited instead.
+    pub fn reserve_status(&self, now: Instant) -> Option<Duration> {
+        let mut state = self.state.lock().expect("throttle lock poisoned");
+        if state
+            .pending_status
+            .is_some_and(|sent| now < sent + Self::STATUS_TIMEOUT)
+        {
+            return None;
+        }
+
+        let delay = self.reserve_locked(&mut state, now);
+        state.pending_status = Some(now + delay);
+        Some(delay)
+    }
+
+    /// Records that the device reported its status at `now`, the next status request is sent
+    /// again.
+    pub fn status_received(&self, now: Instant) {
+        let mut state = self.state.lock().expect("throttle lock poisoned");
+        if let Some(sent) = state.pending_status.take() {
+            state.latency = Some(now.saturating_duration_since(sent));
+        }
+    }
+
+    /// Forgets the pending status request after it remained unanswered, the next status request
+    /// is sent immediately.
+    pub fn status_lost(&self) {
+        self.state
+            .lock()
+            .expect("throttle lock poisoned")
+            .pending_status = None;
+    }
+
+    /// Returns the time between the last answered status request and its response.
+    pub fn latency(&self) -> Option<Duration> {
+        self.state.lock().expect("throttle lock poisoned").latency
+    }
+
+    fn reserve_locked(&self, state: &mut State, now: Instant) -> Duration {
+        let spacing = (Self::SPACING * state.factor(now)).max(self.min_interval);
+
+        let at = match state.last_request {
+            Some(last) => (last + spacing).max(now),
+            None => now,
+        };
+        state.last_request = Some(at);
+        at - now
+    }
+}
+
+impl State {
+    fn factor(&self, now: Instant) -> u32 {
+        let Some(limited_at) = self.limited_at else {
+            return 0;
+        };
+        let halvings = (now - limited_at).as_secs() / Throttle::RECOVERY.as_secs();
+        match halvings {
+            0..32 => self.factor >> halvings,
+            _ => 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_throttle() {
+        let start = Instant::now();
+        let at = |secs: u64| start + Duration::from_secs(secs);
+
+        let throttle = Throttle::default();
+        assert_eq!(throttle.reserve(at(0)), Duration::ZERO);
+        assert_eq!(throttle.reserve(at(0)), Duration::ZERO);
+
+        assert_eq!(throttle.limit(at(0)), Duration::from_secs(10));
+        assert_eq!(throttle.limit(at(0)), Duration::from_secs(20));
+        assert_eq!(throttle.rate_limits(), 2);
+
+        // Requests are spaced out from the last request.
+        assert_eq!(throttle.reserve(at(0)), Duration::from_secs(20));
+        assert_eq!(throttle.reserve(at(0)), Duration::from_secs(40));
+        assert_eq!(throttle.reserve(at(100)), Duration::ZERO);
+
+        // Recovers without further rate limits.
+        assert_eq!(throttle.reserve(at(700)), Duration::ZERO);
+        assert_eq!(throttle.reserve(at(700)), Duration::from_secs(10));
+        assert_eq!(throttle.reserve(at(1300)), Duration::ZERO);
+        assert_eq!(throttle.reserve(at(1300)), Duration::ZERO);
+        assert_eq!(throttle.limit(at(1300)), Duration::from_secs(10));
+    }
+
+    #[test]
+    fn test_throttle_min_interval() {
+        let start = Instant::now();
+        let at = |secs: u64| start + Duration::from_secs(secs);
+
+        let throttle = Throttle::new(Duration::from_secs(2));
+        assert_eq!(throttle.reserve(at(0)), Duration::ZERO);
+        assert_eq!(throttle.reserve(at(1)), Duration::from_secs(1));
+        assert_eq!(throttle.reserve(at(10)), Duration::ZERO);
+
+        // Rate limits space out further than the minimum interval.
+        throttle.limit(at(10));
+        assert_eq!(throttle.reserve(at(10)), Duration::from_secs(10));
+    }
+
+    #[test]
+    fn test_throttle_status() {
+        let start = Instant::now();
+        let at = |secs: u64| start + Duration::from_secs(secs);
+
+        let throttle = Throttle::new(Duration::from_secs(2));
+        assert_eq!(throttle.reserve_status(at(0)), Some(Duration::ZERO));
+        // Concurrent requests wait for the pending response.
+        assert_eq!(throttle.reserve_status(at(1)), None);
+
+        assert_eq!(throttle.latency(), None);
+        throttle.status_received(at(1));
+        assert_eq!(throttle.latency(), Some(Duration::from_secs(1)));
+        assert_eq!(throttle.reserve_status(at(1)), Some(Duration::from_secs(1)));
+
+        // Unanswered requests are retried after a timeout.
+        assert_eq!(throttle.reserve_status(at(4)), None);
+        assert_eq!(throttle.reserve_status(at(7)), Some(Duration::ZERO));
+        throttle.status_received(at(9));
+        assert_eq!(throttle.latency(), Some(Duration::from_secs(2)));
+
+        // Unsolicited status updates do not change the latency.
+        throttle.status_received(at(20));
+        assert_eq!(throttle.latency(), Some(Duration::from_secs(2)));
+    }
+}
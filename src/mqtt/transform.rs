@@ -0,0 +1,220 @@
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use bytes::Bytes;
+
+use super::{InvalidStatus, Result};
+
+/// Error returned by a [`Decode`] implementation.
+pub type DecodeError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Decodes the payload of a status message, before it is parsed.
+///
+/// Implemented for closures, which allows supporting custom schemes without changing the parser:
+///
+/// ```
+/// use hmtk::mqtt::Transform;
+///
+/// let transform = Transform::custom(|payload: &[u8]| Ok(payload.to_ascii_lowercase()));
+/// ```
+pub trait Decode: Send + Sync {
+    fn decode(&self, payload: &[u8]) -> Result<Vec<u8>, DecodeError>;
+}
+
+impl<F> Decode for F
+where
+    F: Fn(&[u8]) -> Result<Vec<u8>, DecodeError> + Send + Sync,
+{
+    fn decode(&self, payload: &[u8]) -> Result<Vec<u8>, DecodeError> {
+        self(payload)
+    }
+}
+
+/// Transformation applied to the payload of status messages, for firmware variants which encrypt
+/// or obfuscate their messages.
+///
+/// The built-in schemes can be selected by name, see the [`FromStr`] implementation.
+#[derive(Clone, Default)]
+pub enum Transform {
+    /// The payload is used as is.
+    #[default]
+    None,
+    /// The payload is Base64 encoded.
+    Base64,
+    /// Every byte of the payload is XORed with the repeating key.
+    Xor(Vec<u8>),
+    /// A custom scheme.
+    Custom(Arc<dyn Decode>),
+}
+
+impl Transform {
+    /// Creates a transform, which decodes payloads with `decode`.
+    pub fn custom(decode: impl Decode + 'static) -> Self {
+        Self::Custom(Arc::new(decode))
+    }
+
+    /// Decodes the `payload` of a status message.
+    pub fn apply(&self, payload: Bytes) -> Result<Bytes> {
+        let decoded = match self {
+            Self::None => return Ok(payload),
+            Self::Base64 => base64(&payload).ok_or_else(|| "invalid base64".into()),
+            Self::Xor(key) => Ok(payload
+                .iter()
+                .zip(key.iter().cycle())
+                .map(|(byte, key)| byte ^ key)
+                .collect()),
+            Self::Custom(decode) => decode.decode(&payload),
+        };
+        match decoded {
+            Ok(decoded) => Ok(decoded.into()),
+            Err(err) => Err(InvalidStatus::Decode(payload, err).into()),
+        }
+    }
+}
+
+impl FromStr for Transform {
+    type Err = String;
+
+    /// Parses `none`, `base64` or `xor:<key>` with a hex encoded key.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            None if s == "none" => Ok(Self::None),
+            None if s == "base64" => Ok(Self::Base64),
+            Some(("xor", key)) => match hex(key) {
+                Some(key) if !key.is_empty() => Ok(Self::Xor(key)),
+                _ => Err(format!(
+                    "invalid xor key `{key}`, expected hex encoded bytes"
+                )),
+            },
+            _ => Err(format!(
+                "unknown transform `{s}`, expected `none`, `base64` or `xor:<key>`"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Transform {
+    /// Formats the transform as it is parsed, custom transforms are formatted as `custom`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::None => f.write_str("none"),
+            Self::Base64 => f.write_str("base64"),
+            Self::Xor(key) => {
+                f.write_str("xor:")?;
+                key.iter().try_for_each(|byte| write!(f, "{byte:02x}"))
+            }
+            Self::Custom(_) => f.write_str("custom"),
+        }
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for Transform {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+impl fmt::Debug for Transform {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::None => f.write_str("None"),
+            Self::Base64 => f.write_str("Base64"),
+            Self::Xor(key) => f.debug_tuple("Xor").field(key).finish(),
+            Self::Custom(_) => f.write_str("Custom"),
+        }
+    }
+}
+
+impl PartialEq for Transform {
+    /// Custom transforms are only equal to clones of themselves.
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Self::None, Self::None) | (Self::Base64, Self::Base64) => true,
+            (Self::Xor(a), Self::Xor(b)) => a == b,
+            (Self::Custom(a), Self::Custom(b)) => Arc::ptr_eq(a, b),
+            _ => false,
+        }
+    }
+}
+
+impl Eq for Transform {}
+
+fn hex(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+fn base64(data: &[u8]) -> Option<Vec<u8>> {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let data = data.trim_ascii();
+    let data = data
+        .strip_suffix(b"==")
+        .or(data.strip_suffix(b"="))
+        .unwrap_or(data);
+
+    let mut result = Vec::with_capacity(data.len() * 3 / 4);
+    let (mut buffer, mut bits) = (0u32, 0);
+    for &c in data {
+        let value = ALPHABET.iter().position(|&a| a == c)? as u32;
+        buffer = buffer << 6 | value;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            result.push((buffer >> bits) as u8);
+        }
+    }
+    Some(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_transform() {
+        let payload = Bytes::from_static(b"cHYxPTEsdzE9MjE=");
+        let transform: Transform = "base64".parse().unwrap();
+        assert_eq!(transform.apply(payload).unwrap(), "pv1=1,w1=21");
+
+        let transform: Transform = "xor:5a".parse().unwrap();
+        assert_eq!(transform.to_string(), "xor:5a");
+        let payload = Bytes::from_iter(b"w1=21".iter().map(|b| b ^ 0x5a));
+        assert_eq!(transform.apply(payload).unwrap(), "w1=21");
+
+        let transform = Transform::custom(|payload: &[u8]| Ok(payload.to_ascii_lowercase()));
+        assert_eq!(
+            transform.apply(Bytes::from_static(b"W1=21")).unwrap(),
+            "w1=21"
+        );
+        assert_eq!(transform, transform.clone());
+        assert_ne!(transform, Transform::custom(|_: &[u8]| Ok(Vec::new())));
+
+        let err = Transform::Base64
+            .apply(Bytes::from_static(b"w1=21"))
+            .unwrap_err();
+        let crate::mqtt::Error::InvalidStatus(err) = err else {
+            panic!("expected an invalid status, got: {err}");
+        };
+        assert_eq!(
+            err.to_string(),
+            "failed to decode b\"w1=21\": invalid base64"
+        );
+
+        assert_eq!(
+            "xor:5".parse::<Transform>().unwrap_err(),
+            "invalid xor key `5`, expected hex encoded bytes"
+        );
+        assert_eq!(
+            "aes".parse::<Transform>().unwrap_err(),
+            "unknown transform `aes`, expected `none`, `base64` or `xor:<key>`"
+        );
+    }
+}
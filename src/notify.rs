@@ -0,0 +1,349 @@
+//! Notification channels for alerts and device events.
+//!
+//! hmtk delivers notifications with a [`Webhook`], an [`Exec`]uted command or an
+//! [`MqttPublish`], other channels, e.g. Matrix or Pushover, can implement [`Notifier`].
+use std::fmt;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use futures::FutureExt;
+use futures::future::BoxFuture;
+use serde_json::{Value, json};
+
+use crate::alerts::Alert;
+use crate::events::Event;
+use crate::http;
+use crate::mqtt::{Device, DeviceOptions};
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("{0}")]
+    Io(#[from] std::io::Error),
+    #[error("{0}")]
+    Http(#[from] http::Error),
+    /// The command exited with a failure.
+    #[error("command exited with {0}")]
+    Command(std::process::ExitStatus),
+    #[error("{0}")]
+    Mqtt(#[from] crate::mqtt::Error),
+    /// Failure of a notifier implemented outside of hmtk.
+    #[error("{0}")]
+    Other(Box<dyn std::error::Error + Send + Sync>),
+}
+
+/// An alert or a device event to notify about.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Notification {
+    /// Name of the alert or the event, e.g. `battery_low` or `output_toggled`.
+    pub name: String,
+    /// Human readable description, e.g. `alert battery_low raised, battery.charge is 8`.
+    pub message: String,
+    /// JSON object posted to webhooks and published to MQTT.
+    pub payload: Value,
+    /// Environment variables of executed commands, e.g. `HMTK_ALERT`.
+    pub env: Vec<(String, String)>,
+}
+
+impl Notification {
+    /// Creates the notification about an `alert` of the `device`.
+    pub fn alert(device: &DeviceOptions, alert: &Alert<'_>) -> Self {
+        let rule = alert.rule();
+        let env = [
+            ("HMTK_ALERT", rule.name.clone()),
+            ("HMTK_ALERT_STATE", alert.state().to_owned()),
+            ("HMTK_ALERT_METRIC", rule.metric.clone()),
+            ("HMTK_ALERT_VALUE", alert.value().to_string()),
+            ("HMTK_DEVICE_TYPE", device.ty.clone()),
+            ("HMTK_DEVICE_MAC", device.mac.clone()),
+        ];
+
+        Self {
+            name: rule.name.clone(),
+            message: format!(
+                "alert {} {}, {} is {}",
+                rule.name,
+                alert.state(),
+                rule.metric,
+                alert.value()
+            ),
+            payload: json!({
+                "alert": rule.name,
+                "state": alert.state(),
+                "metric": rule.metric,
+                "value": alert.value(),
+                "device_type": device.ty,
+                "device_mac": device.mac,
+            }),
+            env: env.map(|(key, value)| (key.to_owned(), value)).to_vec(),
+        }
+    }
+
+    /// Creates the notification about an `event` of the `device`, observed at `timestamp`.
+    pub fn event(device: &DeviceOptions, event: &Event, timestamp: SystemTime) -> Self {
+        let mut payload = serde_json::to_value(event).expect("events serialize");
+        payload["device_type"] = device.ty.clone().into();
+        payload["device_mac"] = device.mac.clone().into();
+        payload["timestamp"] = timestamp
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs()
+            .into();
+
+        let mut env = vec![
+            ("HMTK_EVENT", event.name().to_owned()),
+            ("HMTK_DEVICE_TYPE", device.ty.clone()),
+            ("HMTK_DEVICE_MAC", device.mac.clone()),
+        ];
+        match event {
+            Event::SceneChanged { from, to } => {
+                env.push(("HMTK_SCENE_FROM", from.as_str().to_owned()));
+                env.push(("HMTK_SCENE_TO", to.as_str().to_owned()));
+            }
+            Event::OutputToggled { output, active } => {
+                env.push(("HMTK_OUTPUT", output.to_string()));
+                env.push(("HMTK_OUTPUT_ACTIVE", active.to_string()));
+            }
+            Event::ChargingStarted
+            | Event::ChargingStopped
+            | Event::UndervoltageSet
+            | Event::UndervoltageCleared => {}
+        }
+
+        Self {
+            name: event.name().to_owned(),
+            message: event.to_string(),
+            payload,
+            env: env
+                .into_iter()
+                .map(|(key, value)| (key.to_owned(), value))
+                .collect(),
+        }
+    }
+}
+
+/// A channel notifications are delivered to.
+pub trait Notifier: fmt::Debug + Send + Sync {
+    fn notify<'a>(&'a self, notification: &'a Notification) -> BoxFuture<'a, Result<(), Error>>;
+}
+
+/// Delivers the `notification` with all `notifiers` in the background, failures are logged.
+pub fn notify_all(notifiers: &[Arc<dyn Notifier>], notification: Notification) {
+    let notification = Arc::new(notification);
+    for notifier in notifiers {
+        let notifier = Arc::clone(notifier);
+        let notification = Arc::clone(&notification);
+        tokio::spawn(async move {
+            if let Err(err) = notifier.notify(&notification).await {
+                tracing::warn!(
+                    "notifier {notifier:?} failed to deliver {}: {err}",
+                    notification.name
+                );
+            }
+        });
+    }
+}
+
+/// Posts the JSON payload of notifications to an HTTP URL.
+#[derive(Debug, Clone)]
+pub struct Webhook {
+    url: http::Url,
+}
+
+impl Webhook {
+    /// Creates a webhook posting to `url`, returns `None` unless it is an `http://` or
+    /// `https://` URL.
+    pub fn new(url: &str) -> Option<Self> {
+        let url = http::parse_url(url).ok()?;
+        Some(Self { url })
+    }
+}
+
+impl Notifier for Webhook {
+    fn notify<'a>(&'a self, notification: &'a Notification) -> BoxFuture<'a, Result<(), Error>> {
+        async move {
+            let body = serde_json::to_vec(&notification.payload).expect("json values serialize");
+            http::send(
+                http::Method::POST,
+                self.url.clone(),
+                "application/json",
+                body,
+            )
+            .await?;
+            Ok(())
+        }
+        .boxed()
+    }
+}
+
+/// Runs a command with `sh -c`, the notification is passed in environment variables.
+#[derive(Debug, Clone)]
+pub struct Exec {
+    command: String,
+}
+
+impl Exec {
+    pub fn new(command: &str) -> Self {
+        Self {
+            command: command.to_owned(),
+        }
+    }
+}
+
+impl Notifier for Exec {
+    fn notify<'a>(&'a self, notification: &'a Notification) -> BoxFuture<'a, Result<(), Error>> {
+        async move {
+            let status = tokio::process::Command::new("sh")
+                .arg("-c")
+                .arg(&self.command)
+                .envs(notification.env.iter().cloned())
+                .status()
+                .await?;
+            if !status.success() {
+                return Err(Error::Command(status));
+            }
+            Ok(())
+        }
+        .boxed()
+    }
+}
+
+/// Publishes the JSON payload of notifications to a topic on the broker of a device.
+#[derive(Debug, Clone)]
+pub struct MqttPublish {
+    device: Device,
+    topic: String,
+    retain: bool,
+}
+
+impl MqttPublish {
+    pub fn new(device: Device, topic: &str, retain: bool) -> Self {
+        Self {
+            device,
+            topic: topic.to_owned(),
+            retain,
+        }
+    }
+}
+
+impl Notifier for MqttPublish {
+    fn notify<'a>(&'a self, notification: &'a Notification) -> BoxFuture<'a, Result<(), Error>> {
+        async move {
+            let payload = serde_json::to_vec(&notification.payload).expect("json values serialize");
+            self.device
+                .publish(&self.topic, self.retain, payload)
+                .await?;
+            Ok(())
+        }
+        .boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    use super::*;
+    use crate::alerts::Rule;
+    use crate::mqtt::{Protocol, RetryPolicy, Scene};
+
+    fn device() -> DeviceOptions {
+        DeviceOptions {
+            ty: "HMA-1".to_owned(),
+            mac: "abc".to_owned(),
+            protocol: Protocol::default(),
+            dry_run: false,
+            min_interval: std::time::Duration::ZERO,
+            retry: RetryPolicy::default(),
+        }
+    }
+
+    #[test]
+    fn test_notification() {
+        let rule = Rule::parse("battery_low", "battery.charge < 10").unwrap();
+        let alert = Notification::alert(
+            &device(),
+            &Alert::Raised {
+                rule: &rule,
+                value: 8.0,
+            },
+        );
+        assert_eq!(
+            alert.message,
+            "alert battery_low raised, battery.charge is 8"
+        );
+        assert_eq!(alert.payload["state"], "raised");
+        assert!(
+            alert
+                .env
+                .contains(&("HMTK_ALERT_VALUE".to_owned(), "8".to_owned()))
+        );
+
+        let event = Notification::event(
+            &device(),
+            &Event::SceneChanged {
+                from: Scene::Day,
+                to: Scene::Dusk,
+            },
+            SystemTime::UNIX_EPOCH,
+        );
+        assert_eq!(event.name, "scene_changed");
+        assert_eq!(event.message, "scene changed from day to dusk");
+        assert_eq!(
+            event.payload,
+            json!({
+                "event": "scene_changed",
+                "from": "day",
+                "to": "dusk",
+                "device_type": "HMA-1",
+                "device_mac": "abc",
+                "timestamp": 0,
+            })
+        );
+        assert!(
+            event
+                .env
+                .contains(&("HMTK_SCENE_TO".to_owned(), "dusk".to_owned()))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_webhook() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        let server = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = vec![0; 4096];
+            let n = stream.read(&mut request).await.unwrap();
+            stream
+                .write_all(b"HTTP/1.1 204 No Content\r\n\r\n")
+                .await
+                .unwrap();
+            String::from_utf8_lossy(&request[..n]).into_owned()
+        });
+
+        let notification =
+            Notification::event(&device(), &Event::ChargingStarted, SystemTime::UNIX_EPOCH);
+        let webhook = Webhook::new(&url).unwrap();
+        webhook.notify(&notification).await.unwrap();
+
+        let request = server.await.unwrap();
+        assert!(request.starts_with("POST /hook HTTP/1.1\r\n"));
+        assert!(request.contains(r#""event":"charging_started""#));
+        assert!(Webhook::new("https://example.com").is_some());
+        assert!(Webhook::new("ftp://example.com").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_exec() {
+        let notification =
+            Notification::event(&device(), &Event::ChargingStopped, SystemTime::UNIX_EPOCH);
+        Exec::new(r#"test "$HMTK_EVENT" = charging_stopped"#)
+            .notify(&notification)
+            .await
+            .unwrap();
+
+        let err = Exec::new("exit 3").notify(&notification).await.unwrap_err();
+        assert!(matches!(err, Error::Command(status) if status.code() == Some(3)));
+    }
+}
@@ -0,0 +1,437 @@
+//! Parsers for the status messages of device types without built-in support.
+//!
+//! A [`Parser`] turns a raw [`Message`] into a list of named values. Parsers are registered
+//! per device type in a [`Registry`], either in code or from TOML definitions:
+//!
+//! ```toml
+//! [[device]]
+//! type = "HMX-1"
+//!
+//! [device.fields]
+//! "solar.power" = { key = "w1", type = "integer", unit = "W" }
+//! "battery.charge" = { key = "pe", type = "float", scale = 0.1, unit = "%" }
+//! "output.active" = { key = "o1", type = "bool", bit = 0 }
+//! firmware = { key = "fv", type = "string", optional = true }
+//! ```
+//!
+//! Definitions may also override the MQTT [`Protocol`] of the device, which allows
+//! supporting generic devices that use the same `key=value` message format on other topics:
+//!
+//! ```toml
+//! [[device]]
+//! type = "HMM-1"
+//! data_topic = "plug/{mac}/status"
+//! control_topic = "plug/{mac}/command"
+//! poll_command = "cd=01"
+//! fields."power" = { key = "p", unit = "W" }
+//! ```
+//!
+//! Firmware variants, which encrypt or obfuscate their messages, are supported with a
+//! [`Transform`](crate::mqtt::Transform) of the payload. Without `fields`, only the protocol is
+//! registered, which keeps the built-in support of a device type:
+//!
+//! ```toml
+//! [[device]]
+//! type = "HMA-1"
+//! transform = "xor:5a"
+//! ```
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use serde::{Deserialize, Serialize};
+
+use crate::mqtt::{DeviceModel, Message, Protocol, Transform};
+
+/// Errors which can occur when parsing a message.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("field '{0}' is required, but missing in the status message")]
+    MissingField(String),
+    #[error("field '{field}' contains invalid data: {value:?}")]
+    InvalidField { field: String, value: String },
+}
+
+/// A parsed value.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(untagged)]
+pub enum Value {
+    Integer(i64),
+    Float(f64),
+    Bool(bool),
+    String(String),
+}
+
+impl Value {
+    /// Returns the numeric value, booleans are represented as `0` or `1`.
+    ///
+    /// Returns `None` for strings.
+    pub fn as_f64(&self) -> Option<f64> {
+        match *self {
+            Self::Integer(value) => Some(value as f64),
+            Self::Float(value) => Some(value),
+            Self::Bool(value) => Some(if value { 1.0 } else { 0.0 }),
+            Self::String(_) => None,
+        }
+    }
+}
+
+/// A named value extracted from a message.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Field {
+    /// Name of the field, dotted paths like `solar.power` are used for grouping.
+    pub name: String,
+    pub value: Value,
+    /// Unit of the value, e.g. `W`.
+    pub unit: Option<String>,
+}
+
+/// All fields parsed from a single message.
+#[derive(Debug, Clone)]
+pub struct Reading {
+    pub timestamp: SystemTime,
+    pub fields: Vec<Field>,
+}
+
+/// Parses the status messages of a device type.
+pub trait Parser: Send + Sync {
+    /// Extracts all known fields from `message`.
+    fn parse(&self, message: &Message) -> Result<Vec<Field>, Error>;
+}
+
+/// Parsers and protocols by device type.
+#[derive(Clone, Default)]
+pub struct Registry {
+    parsers: BTreeMap<String, Arc<dyn Parser>>,
+    protocols: BTreeMap<String, Protocol>,
+}
+
+impl Registry {
+    /// Registers `parser` for the device type `ty`, replacing any previously registered parser.
+    pub fn register(&mut self, ty: impl Into<String>, parser: impl Parser + 'static) -> &mut Self {
+        self.parsers.insert(ty.into(), Arc::new(parser));
+        self
+    }
+
+    /// Registers the MQTT `protocol` used by the device type `ty`.
+    pub fn register_protocol(&mut self, ty: impl Into<String>, protocol: Protocol) -> &mut Self {
+        self.protocols.insert(ty.into(), protocol);
+        self
+    }
+
+    /// Registers the protocol and, if declared, a [`FieldMap`] parser for every device in the
+    /// TOML `definitions`.
+    pub fn load(&mut self, definitions: &str) -> Result<&mut Self, crate::toml::Error> {
+        let definitions: Definitions = crate::toml::from_str(definitions)?;
+        for device in definitions.device {
+            let default = Protocol::default();
+            let protocol = Protocol {
+                data_topic: device.data_topic.unwrap_or(default.data_topic),
+                control_topic: device.control_topic.unwrap_or(default.control_topic),
+                poll_command: device.poll_command.unwrap_or(default.poll_command),
+                transform: device.transform.unwrap_or(default.transform),
+            };
+            self.register_protocol(device.ty.clone(), protocol);
+            if let Some(fields) = device.fields {
+                self.register(device.ty, fields);
+            }
+        }
+        Ok(self)
+    }
+
+    /// Returns the parser registered for the device type `ty`.
+    pub fn get(&self, ty: &str) -> Option<&dyn Parser> {
+        self.parsers.get(ty).map(|parser| &**parser)
+    }
+
+    /// Returns all device types with a registered protocol, ordered by type.
+    pub fn protocols(&self) -> impl Iterator<Item = (&str, &Protocol)> {
+        self.protocols
+            .iter()
+            .map(|(ty, protocol)| (ty.as_str(), protocol))
+    }
+
+    /// Returns the protocol of the device type `ty`.
+    ///
+    /// Without a registered protocol, the protocol of the [`DeviceModel`] of the type is used and
+    /// the default protocol for unknown types.
+    pub fn protocol(&self, ty: &str) -> Protocol {
+        match self.protocols.get(ty) {
+            Some(protocol) => protocol.clone(),
+            None => DeviceModel::from_type(ty)
+                .map(DeviceModel::protocol)
+                .unwrap_or_default(),
+        }
+    }
+}
+
+impl std::fmt::Debug for Registry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Registry")
+            .field("parsers", &self.parsers.keys())
+            .field("protocols", &self.protocols)
+            .finish()
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Definitions {
+    #[serde(default)]
+    device: Vec<DeviceDefinition>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct DeviceDefinition {
+    #[serde(rename = "type")]
+    ty: String,
+    #[serde(default)]
+    data_topic: Option<String>,
+    #[serde(default)]
+    control_topic: Option<String>,
+    #[serde(default)]
+    poll_command: Option<String>,
+    #[serde(default)]
+    transform: Option<Transform>,
+    /// Without fields, the device type keeps its built-in support.
+    #[serde(default)]
+    fields: Option<FieldMap>,
+}
+
+/// A declarative [`Parser`], mapping message keys to named fields.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(transparent)]
+pub struct FieldMap {
+    fields: BTreeMap<String, FieldSpec>,
+}
+
+impl FieldMap {
+    /// Adds the field `name`, extracted according to `spec`.
+    pub fn field(mut self, name: impl Into<String>, spec: FieldSpec) -> Self {
+        self.fields.insert(name.into(), spec);
+        self
+    }
+}
+
+impl Parser for FieldMap {
+    fn parse(&self, message: &Message) -> Result<Vec<Field>, Error> {
+        let mut fields = Vec::with_capacity(self.fields.len());
+        for (name, spec) in &self.fields {
+            let Some(raw) = message.get(&spec.key) else {
+                match spec.optional {
+                    true => continue,
+                    false => return Err(Error::MissingField(spec.key.clone())),
+                }
+            };
+
+            let value = spec.extract(raw).ok_or_else(|| Error::InvalidField {
+                field: spec.key.clone(),
+                value: raw.to_owned(),
+            })?;
+
+            fields.push(Field {
+                name: name.clone(),
+                value,
+                unit: spec.unit.clone(),
+            });
+        }
+        Ok(fields)
+    }
+}
+
+/// Describes how a field is extracted from a message.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FieldSpec {
+    /// Key of the value in the message, e.g. `w1`.
+    pub key: String,
+    /// Type of the value.
+    #[serde(rename = "type", default)]
+    pub ty: FieldType,
+    /// Factor numeric values are multiplied with, turns integers into floats.
+    #[serde(default)]
+    pub scale: Option<f64>,
+    /// Extracts a single bit of an integer value as boolean.
+    #[serde(default)]
+    pub bit: Option<u8>,
+    /// Unit of the value.
+    #[serde(default)]
+    pub unit: Option<String>,
+    /// Skips the field instead of failing if it is missing in the message.
+    #[serde(default)]
+    pub optional: bool,
+}
+
+impl FieldSpec {
+    /// Creates a required field of type `ty` extracted from `key`.
+    pub fn new(key: impl Into<String>, ty: FieldType) -> Self {
+        Self {
+            key: key.into(),
+            ty,
+            scale: None,
+            bit: None,
+            unit: None,
+            optional: false,
+        }
+    }
+
+    fn extract(&self, raw: &str) -> Option<Value> {
+        let value = match self.ty {
+            FieldType::Integer => Value::Integer(raw.parse().ok()?),
+            FieldType::Float => Value::Float(raw.parse().ok().filter(|v: &f64| v.is_finite())?),
+            FieldType::Bool => match self.bit {
+                Some(bit) => {
+                    Value::Bool(raw.parse::<u64>().ok()?.checked_shr(bit.into())? & 1 == 1)
+                }
+                None => Value::Bool(match raw {
+                    "0" | "false" => false,
+                    "1" | "true" => true,
+                    _ => return None,
+                }),
+            },
+            FieldType::String => Value::String(raw.to_owned()),
+        };
+
+        let value = match (self.scale, value) {
+            (Some(scale), Value::Integer(value)) => Value::Float(value as f64 * scale),
+            (Some(scale), Value::Float(value)) => Value::Float(value * scale),
+            (_, value) => value,
+        };
+        // Infinite values, e.g. `inf` or huge values scaled up, are not valid measurements.
+        match value {
+            Value::Float(value) if !value.is_finite() => None,
+            value => Some(value),
+        }
+    }
+}
+
+/// Type of a [`FieldSpec`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FieldType {
+    #[default]
+    Integer,
+    Float,
+    Bool,
+    String,
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+
+    use super::*;
+
+    const DEFINITIONS: &str = r#"
+        [[device]]
+        type = "HMX-1"
+
+        [device.fields]
+        "solar.power" = { key = "w1", unit = "W" }
+        "battery.charge" = { key = "pe", type = "float", scale = 0.1, unit = "%" }
+        "output.active" = { key = "o1", type = "bool", bit = 1 }
+        firmware = { key = "fv", type = "string", optional = true }
+    "#;
+
+    #[test]
+    fn test_field_map() {
+        let mut registry = Registry::default();
+        registry.load(DEFINITIONS).unwrap();
+        assert!(registry.get("HMA-1").is_none());
+
+        let parser = registry.get("HMX-1").unwrap();
+        let message = Message::parse(Bytes::from_static(b"w1=23,pe=995,o1=2")).unwrap();
+        insta::assert_debug_snapshot!(parser.parse(&message).unwrap(), @r###"
+        [
+            Field {
+                name: "battery.charge",
+                value: Float(
+                    99.5,
+                ),
+                unit: Some(
+                    "%",
+                ),
+            },
+            Field {
+                name: "output.active",
+                value: Bool(
+                    true,
+                ),
+                unit: None,
+            },
+            Field {
+                name: "solar.power",
+                value: Integer(
+                    23,
+                ),
+                unit: Some(
+                    "W",
+                ),
+            },
+        ]
+        "###);
+
+        let message = Message::parse(Bytes::from_static(b"w1=x,pe=995,o1=2")).unwrap();
+        let err = parser.parse(&message).unwrap_err();
+        assert_eq!(err.to_string(), "field 'w1' contains invalid data: \"x\"");
+
+        let message = Message::parse(Bytes::from_static(b"w1=23,pe=inf,o1=2")).unwrap();
+        let err = parser.parse(&message).unwrap_err();
+        assert_eq!(err.to_string(), "field 'pe' contains invalid data: \"inf\"");
+
+        let message = Message::parse(Bytes::from_static(b"pe=995,o1=2")).unwrap();
+        let err = parser.parse(&message).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "field 'w1' is required, but missing in the status message"
+        );
+    }
+
+    #[test]
+    fn test_protocol() {
+        let mut registry = Registry::default();
+        registry
+            .load(
+                r#"
+                [[device]]
+                type = "HMM-1"
+                data_topic = "plug/{mac}/status"
+                poll_command = "cd=01"
+                fields.power = { key = "p", unit = "W" }
+                "#,
+            )
+            .unwrap();
+
+        insta::assert_debug_snapshot!(registry.protocol("HMM-1"), @r###"
+        Protocol {
+            data_topic: "plug/{mac}/status",
+            control_topic: "hame_energy/{type}/App/{mac}/ctrl",
+            poll_command: "cd=01",
+            transform: None,
+        }
+        "###);
+        assert_eq!(registry.protocol("HMA-1"), Protocol::default());
+
+        registry
+            .load("[[device]]\ntype = \"HMA-1\"\ntransform = \"xor:5a\"")
+            .unwrap();
+        assert_eq!(
+            registry.protocol("HMA-1").transform,
+            Transform::Xor(vec![0x5a])
+        );
+        assert!(registry.get("HMA-1").is_none());
+    }
+
+    #[test]
+    fn test_invalid_definitions() {
+        let err = Registry::default()
+            .load("[[device]]\ntype = \"HMX-1\"\nfields.power = { key = \"w1\", type = \"watt\" }")
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "line 3: `device[0].fields.power.type`: unknown variant `watt`, \
+             expected one of `integer`, `float`, `bool`, `string`"
+        );
+    }
+}
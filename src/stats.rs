@@ -0,0 +1,93 @@
+//! Descriptive statistics over a series of samples.
+use serde::Serialize;
+
+/// Summary of a series of samples.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct Summary {
+    pub count: u64,
+    pub min: f64,
+    pub max: f64,
+    pub mean: f64,
+    /// Population variance of the samples.
+    pub variance: f64,
+}
+
+impl Summary {
+    /// Standard deviation of the samples.
+    pub fn std_dev(&self) -> f64 {
+        self.variance.sqrt()
+    }
+}
+
+/// Incrementally computes a [`Summary`] using Welford's online algorithm.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Accumulator {
+    count: u64,
+    min: f64,
+    max: f64,
+    mean: f64,
+    m2: f64,
+}
+
+impl Accumulator {
+    /// Adds a sample.
+    pub fn push(&mut self, value: f64) {
+        if self.count == 0 {
+            self.min = value;
+            self.max = value;
+        }
+        self.count += 1;
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+
+        let delta = value - self.mean;
+        self.mean += delta / self.count as f64;
+        self.m2 += delta * (value - self.mean);
+    }
+
+    /// Returns the summary of all samples added so far.
+    pub fn summary(&self) -> Summary {
+        Summary {
+            count: self.count,
+            min: self.min,
+            max: self.max,
+            mean: self.mean,
+            variance: match self.count {
+                0 => 0.0,
+                count => self.m2 / count as f64,
+            },
+        }
+    }
+}
+
+impl Extend<f64> for Accumulator {
+    fn extend<T: IntoIterator<Item = f64>>(&mut self, iter: T) {
+        for value in iter {
+            self.push(value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_summary() {
+        let mut acc = Accumulator::default();
+        acc.extend([2.0, 4.0, 4.0, 4.0, 5.0, 5.0, 7.0, 9.0]);
+
+        let summary = acc.summary();
+        assert_eq!(
+            summary,
+            Summary {
+                count: 8,
+                min: 2.0,
+                max: 9.0,
+                mean: 5.0,
+                variance: 4.0,
+            }
+        );
+        assert_eq!(summary.std_dev(), 2.0);
+    }
+}
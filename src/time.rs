@@ -0,0 +1,140 @@
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+/// Source of the current time.
+///
+/// Allows tests and replays of captures to control the time instead of using the wall clock.
+pub trait Clock: fmt::Debug + Send + Sync {
+    /// Returns the current time.
+    fn now(&self) -> SystemTime;
+}
+
+/// The wall clock of the host.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+}
+
+/// A clock which only changes when it is set or advanced.
+///
+/// Clones share the same time.
+#[derive(Debug, Clone)]
+pub struct ManualClock(Arc<Mutex<SystemTime>>);
+
+impl ManualClock {
+    pub fn new(now: SystemTime) -> Self {
+        Self(Arc::new(Mutex::new(now)))
+    }
+
+    /// Sets the current time.
+    pub fn set(&self, now: SystemTime) {
+        *self.0.lock().unwrap() = now;
+    }
+
+    /// Moves the current time forward by `duration`.
+    pub fn advance(&self, duration: Duration) {
+        *self.0.lock().unwrap() += duration;
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> SystemTime {
+        *self.0.lock().unwrap()
+    }
+}
+
+/// A point in time in the local timezone of the host.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LocalTime {
+    pub year: i32,
+    /// Month of the year, `1..=12`.
+    pub month: u8,
+    /// Day of the month, `1..=31`.
+    pub day: u8,
+    /// Hour of the day, `0..24`.
+    pub hour: u8,
+    /// Minute of the hour, `0..60`.
+    pub minute: u8,
+    /// Second of the minute, `0..60`.
+    pub second: u8,
+    /// Offset from UTC in minutes.
+    pub utc_offset: i32,
+}
+
+impl LocalTime {
+    /// Returns the current local time.
+    pub fn now() -> Self {
+        Self::from_system_time(SystemTime::now())
+    }
+
+    /// Returns whether all fields are within their ranges, e.g. of a time decoded from a payload.
+    ///
+    /// Only the ranges are checked, the 31st of February is valid.
+    pub fn is_valid(&self) -> bool {
+        (1..=12).contains(&self.month)
+            && (1..=31).contains(&self.day)
+            && self.hour < 24
+            && self.minute < 60
+            && self.second < 60
+    }
+
+    /// Converts `time` into the local timezone of the host.
+    pub fn from_system_time(time: SystemTime) -> Self {
+        let timestamp = jiff::Timestamp::try_from(time).unwrap_or(jiff::Timestamp::UNIX_EPOCH);
+        let zoned = timestamp.to_zoned(jiff::tz::TimeZone::system());
+
+        Self {
+            year: zoned.year().into(),
+            month: zoned.month() as u8,
+            day: zoned.day() as u8,
+            hour: zoned.hour() as u8,
+            minute: zoned.minute() as u8,
+            second: zoned.second() as u8,
+            utc_offset: zoned.offset().seconds() / 60,
+        }
+    }
+
+    /// Returns the time since local midnight.
+    pub fn since_midnight(&self) -> Duration {
+        Duration::from_secs(
+            u64::from(self.hour) * 60 * 60 + u64::from(self.minute) * 60 + u64::from(self.second),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_manual_clock() {
+        let clock = ManualClock::new(SystemTime::UNIX_EPOCH);
+        let shared: Arc<dyn Clock> = Arc::new(clock.clone());
+
+        clock.advance(Duration::from_secs(90));
+        assert_eq!(
+            shared.now(),
+            SystemTime::UNIX_EPOCH + Duration::from_secs(90)
+        );
+
+        clock.set(SystemTime::UNIX_EPOCH);
+        assert_eq!(shared.now(), SystemTime::UNIX_EPOCH);
+    }
+
+    #[test]
+    fn test_local_time() {
+        let secs = 1_700_000_000;
+        let local = LocalTime::from_system_time(SystemTime::UNIX_EPOCH + Duration::from_secs(secs));
+
+        // Independent of the timezone of the host, the local time is shifted by the offset.
+        let shifted = secs as i64 + i64::from(local.utc_offset) * 60;
+        let since_midnight = shifted.rem_euclid(24 * 60 * 60) as u64;
+        assert_eq!(local.since_midnight(), Duration::from_secs(since_midnight));
+        assert_eq!(local.second, 20);
+    }
+}
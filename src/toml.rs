@@ -0,0 +1,426 @@
+//! TOML documents of hmtk, e.g. the configuration file, parsed with the `toml` crate.
+//!
+//! Errors of the parser and of the deserialization are reported with the line and path of the
+//! offending value. Strings can reference environment variables, see [`interpolate`].
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::ops::Range;
+
+use serde::de::DeserializeOwned;
+use toml::Spanned;
+use toml::de::{DeTable, DeValue};
+
+/// Errors which can occur when parsing a TOML document.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    /// The document is not valid TOML or does not match the expected structure.
+    #[error("{}{message}", location(*line, path))]
+    Invalid {
+        /// Line of the offending value, if known.
+        line: Option<usize>,
+        /// Path of the offending value, e.g. `alerts[0].when`.
+        path: String,
+        message: String,
+    },
+    /// A placeholder in a string could not be replaced, see [`interpolate`].
+    #[error("{}{reason}", location(*line, path))]
+    Interpolation {
+        line: Option<usize>,
+        path: String,
+        reason: String,
+    },
+}
+
+fn location(line: Option<usize>, path: &str) -> String {
+    match (line, path) {
+        (Some(line), "") => format!("line {line}: "),
+        (None, "") => String::new(),
+        (Some(line), path) => format!("line {line}: `{path}`: "),
+        (None, path) => format!("`{path}`: "),
+    }
+}
+
+/// A parsed document, strings still reference the source.
+pub type Document<'a> = Spanned<DeTable<'a>>;
+
+/// Lines of the keys, tables and array elements of a document, indexed by their path.
+#[derive(Debug, Default, Clone)]
+pub struct Spans {
+    /// Byte offsets of the starts of all lines.
+    lines: Vec<usize>,
+    /// Byte ranges of the entries, from the start of the key to the end of the value.
+    paths: BTreeMap<String, Range<usize>>,
+}
+
+impl Spans {
+    fn new(input: &str) -> Self {
+        let lines = std::iter::once(0)
+            .chain(input.match_indices('\n').map(|(i, _)| i + 1))
+            .collect();
+        Self {
+            lines,
+            paths: BTreeMap::new(),
+        }
+    }
+
+    /// Returns the line of the value at `path`, or of its closest parent with a known line.
+    pub fn line(&self, mut path: &str) -> Option<usize> {
+        loop {
+            if let Some(range) = self.paths.get(path) {
+                return Some(self.line_at(range.start));
+            }
+            path = &path[..path.rfind(['.', '['])?];
+        }
+    }
+
+    fn line_at(&self, offset: usize) -> usize {
+        self.lines.partition_point(|&start| start <= offset)
+    }
+
+    /// Returns the path of the innermost entry containing `offset`.
+    fn path_at(&self, offset: usize) -> Option<&str> {
+        self.paths
+            .iter()
+            .filter(|(_, range)| range.contains(&offset))
+            .max_by_key(|(path, _)| path.len())
+            .map(|(path, _)| path.as_str())
+    }
+
+    fn collect_table(&mut self, path: &str, table: &DeTable<'_>) {
+        for (key, value) in table {
+            self.collect(join(path, key.get_ref()), key.span().start, value);
+        }
+    }
+
+    fn collect(&mut self, path: String, start: usize, value: &Spanned<DeValue<'_>>) {
+        match value.get_ref() {
+            DeValue::Table(table) => self.collect_table(&path, table),
+            DeValue::Array(values) => {
+                for (i, value) in values.iter().enumerate() {
+                    self.collect(format!("{path}[{i}]"), value.span().start, value);
+                }
+            }
+            _ => {}
+        }
+        let end = value.span().end;
+        self.paths.insert(path, start.min(end)..end.max(start + 1));
+    }
+
+    /// Converts an error of the `toml` crate, which is located by its byte offset.
+    fn error(&self, error: toml::de::Error) -> Error {
+        let offset = error.span().map(|span| span.start);
+        Error::Invalid {
+            line: offset.map(|offset| self.line_at(offset)),
+            path: offset
+                .and_then(|offset| self.path_at(offset))
+                .unwrap_or_default()
+                .to_owned(),
+            message: error.message().trim_end().to_owned(),
+        }
+    }
+}
+
+/// Parses a TOML document and deserializes it into `T`.
+pub fn from_str<T: DeserializeOwned>(s: &str) -> Result<T, Error> {
+    let (document, spans) = parse_spanned(s)?;
+    from_document(document, &spans)
+}
+
+/// Deserializes a parsed document into `T`, errors are located with the `spans` of the document.
+pub fn from_document<T: DeserializeOwned>(
+    document: Document<'_>,
+    spans: &Spans,
+) -> Result<T, Error> {
+    T::deserialize(toml::de::Deserializer::from(document)).map_err(|error| spans.error(error))
+}
+
+/// Parses a TOML document, additionally returns the lines of all values.
+pub fn parse_spanned(s: &str) -> Result<(Document<'_>, Spans), Error> {
+    let mut spans = Spans::new(s);
+    let document = DeTable::parse(s).map_err(|error| spans.error(error))?;
+    spans.collect_table("", document.get_ref());
+    Ok((document, spans))
+}
+
+/// Replaces `${NAME}` placeholders in all strings of `document` with the variable `NAME` from
+/// `env`.
+///
+/// `${NAME:-default}` falls back to `default` if the variable is not set or empty,
+/// `$${` is replaced with a literal `${`. Variables without a default must be set.
+pub fn interpolate(
+    document: &mut Document<'_>,
+    spans: &Spans,
+    env: &dyn Fn(&str) -> Option<String>,
+) -> Result<(), Error> {
+    for (key, value) in document.get_mut().iter_mut() {
+        interpolate_at(value.get_mut(), key.get_ref().to_string(), spans, env)?;
+    }
+    Ok(())
+}
+
+fn interpolate_at(
+    value: &mut DeValue<'_>,
+    path: String,
+    spans: &Spans,
+    env: &dyn Fn(&str) -> Option<String>,
+) -> Result<(), Error> {
+    match value {
+        DeValue::String(s) => {
+            let interpolated = interpolate_str(s, env).map_err(|reason| Error::Interpolation {
+                line: spans.line(&path),
+                path,
+                reason,
+            })?;
+            *s = Cow::Owned(interpolated);
+        }
+        DeValue::Array(values) => {
+            for (i, value) in values.iter_mut().enumerate() {
+                interpolate_at(value.get_mut(), format!("{path}[{i}]"), spans, env)?;
+            }
+        }
+        DeValue::Table(entries) => {
+            for (key, value) in entries.iter_mut() {
+                interpolate_at(value.get_mut(), join(&path, key.get_ref()), spans, env)?;
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+fn interpolate_str(s: &str, env: &dyn Fn(&str) -> Option<String>) -> Result<String, String> {
+    let mut result = String::new();
+    let mut rest = s;
+    while let Some(start) = rest.find('$') {
+        result.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        if let Some(r) = rest.strip_prefix("$${") {
+            result.push_str("${");
+            rest = r;
+            continue;
+        }
+        let Some(r) = rest.strip_prefix("${") else {
+            result.push('$');
+            rest = &rest[1..];
+            continue;
+        };
+
+        let end = r.find('}').ok_or("unterminated `${`")?;
+        let (name, default) = match r[..end].split_once(":-") {
+            Some((name, default)) => (name, Some(default)),
+            None => (&r[..end], None),
+        };
+        if name.is_empty() {
+            return Err("empty variable name in `${}`".to_owned());
+        }
+        let value = match (env(name), default) {
+            (Some(value), Some(default)) if value.is_empty() => default.to_owned(),
+            (Some(value), _) => value,
+            (None, Some(default)) => default.to_owned(),
+            (None, None) => return Err(format!("environment variable `{name}` is not set")),
+        };
+        result.push_str(&value);
+        rest = &r[end + 1..];
+    }
+    result.push_str(rest);
+    Ok(result)
+}
+
+fn join(path: &str, key: &str) -> String {
+    match path {
+        "" => key.to_owned(),
+        path => format!("{path}.{key}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let document = r#"
+            # Comment.
+            name = "hmtk" # Trailing comment.
+            literal = 'C:\path'
+            "quoted key" = "\"escaped\"\n"
+            numbers = [1, -2, 3_000, 1.5, 1e3]
+            dotted.key = true
+
+            [table]
+            inline = { a = 1, b.c = "x" }
+            multi = [
+                "a", # first
+                "b",
+            ]
+
+            [[rules]]
+            metric = "battery.charge"
+
+            [[rules]]
+            metric = "temperature.max"
+            [rules.action]
+            exec = "notify"
+        "#;
+
+        let value: serde_json::Value = from_str(document).unwrap();
+        insta::assert_snapshot!(serde_json::to_string_pretty(&value).unwrap(), @r###"
+        {
+          "dotted": {
+            "key": true
+          },
+          "literal": "C:\\path",
+          "name": "hmtk",
+          "numbers": [
+            1,
+            -2,
+            3000,
+            1.5,
+            1000.0
+          ],
+          "quoted key": "\"escaped\"\n",
+          "rules": [
+            {
+              "metric": "battery.charge"
+            },
+            {
+              "action": {
+                "exec": "notify"
+              },
+              "metric": "temperature.max"
+            }
+          ],
+          "table": {
+            "inline": {
+              "a": 1,
+              "b": {
+                "c": "x"
+              }
+            },
+            "multi": [
+              "a",
+              "b"
+            ]
+          }
+        }
+        "###);
+    }
+
+    #[test]
+    fn test_parse_error() {
+        let err = parse_spanned("a = 1\nb = \"unterminated\n").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "line 2: invalid basic string, expected `\"`"
+        );
+
+        let err = parse_spanned("a = 1\na = 2").unwrap_err();
+        assert_eq!(err.to_string(), "line 2: duplicate key");
+
+        let err = parse_spanned("a = 1\n[table\nb = 2").unwrap_err();
+        assert_eq!(err.to_string(), "line 2: unclosed table, expected `]`");
+    }
+
+    #[test]
+    fn test_invalid() {
+        #[derive(Debug, serde::Deserialize)]
+        #[serde(deny_unknown_fields)]
+        #[expect(dead_code, reason = "only deserialized")]
+        struct Document {
+            name: String,
+            #[serde(default)]
+            rules: Vec<Rule>,
+        }
+
+        #[derive(Debug, serde::Deserialize)]
+        #[serde(deny_unknown_fields)]
+        #[expect(dead_code, reason = "only deserialized")]
+        struct Rule {
+            metric: String,
+            #[serde(default)]
+            actions: Vec<Action>,
+        }
+
+        #[derive(Debug, serde::Deserialize)]
+        #[serde(rename_all = "snake_case")]
+        #[expect(dead_code, reason = "only deserialized")]
+        enum Action {
+            Exec(String),
+        }
+
+        let err = from_str::<Document>("name = 1").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "line 1: `name`: invalid type: integer `1`, expected a string"
+        );
+
+        let document =
+            "name = \"hmtk\"\n\n[[rules]]\nmetric = \"a\"\n\n[[rules]]\nmetirc = \"b\"\n";
+        let err = from_str::<Document>(document).unwrap_err();
+        insta::assert_snapshot!(err, @"line 7: `rules[1].metirc`: unknown field `metirc`, expected `metric` or `actions`");
+
+        let document = "name = \"hmtk\"\n[[rules]]\n";
+        let err = from_str::<Document>(document).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "line 2: `rules[0]`: missing field `metric`"
+        );
+
+        let document = r#"
+            name = "hmtk"
+            [[rules]]
+            metric = "a"
+            actions = [
+                { exec = "true" },
+                { exek = "false" },
+            ]
+        "#;
+        let err = from_str::<Document>(document).unwrap_err();
+        insta::assert_snapshot!(err, @"line 7: `rules[0].actions[1].exek`: unknown variant `exek`, expected `exec`");
+
+        let err = from_str::<Document>("").unwrap_err();
+        assert_eq!(err.to_string(), "line 1: missing field `name`");
+    }
+
+    #[test]
+    fn test_interpolate() {
+        let env = |name: &str| match name {
+            "HOST" => Some("broker.local".to_owned()),
+            "EMPTY" => Some(String::new()),
+            _ => None,
+        };
+
+        let document = r#"
+            url = "http://${HOST}:${PORT:-8123}/api"
+            fallback = ["${EMPTY:-default}", "${EMPTY}", "$HOME $${HOST} $"]
+            number = 1
+        "#;
+        let (mut document, spans) = parse_spanned(document).unwrap();
+        interpolate(&mut document, &spans, &env).unwrap();
+        let value: serde_json::Value = from_document(document, &spans).unwrap();
+        insta::assert_snapshot!(serde_json::to_string_pretty(&value).unwrap(), @r###"
+        {
+          "fallback": [
+            "default",
+            "",
+            "$HOME ${HOST} $"
+          ],
+          "number": 1,
+          "url": "http://broker.local:8123/api"
+        }
+        "###);
+
+        let document = "[mqtt]\n\npassword = \"${SECRET}\"";
+        let (mut document, spans) = parse_spanned(document).unwrap();
+        let err = interpolate(&mut document, &spans, &env).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "line 3: `mqtt.password`: environment variable `SECRET` is not set"
+        );
+
+        let (mut document, spans) = parse_spanned("a = \"${HOST\"").unwrap();
+        let err = interpolate(&mut document, &spans, &env).unwrap_err();
+        assert_eq!(err.to_string(), "line 1: `a`: unterminated `${`");
+    }
+}
@@ -1,9 +1,16 @@
 macro_rules! impl_unit {
-    ($name:ident, $ty:ty) => {
-        #[derive(Default, Debug, Clone, Copy, serde::Serialize, serde::Deserialize)]
+    ($name:ident, $ty:ty, $symbol:literal $(, $derive:ident)*) => {
+        #[derive(
+            Default, Debug, Clone, Copy, PartialEq, $($derive,)* serde::Serialize, serde::Deserialize,
+        )]
         #[serde(transparent)]
         pub struct $name(pub $ty);
 
+        impl $name {
+            /// Symbol of the unit, e.g. `W`.
+            pub const SYMBOL: &str = $symbol;
+        }
+
         impl std::str::FromStr for $name {
             type Err = <$ty as std::str::FromStr>::Err;
 
@@ -14,7 +21,9 @@ macro_rules! impl_unit {
     };
 }
 
-impl_unit!(Watt, u32);
-impl_unit!(WattHours, u32);
-impl_unit!(Celsius, i32);
-impl_unit!(Percentage, u8);
+impl_unit!(Watt, u32, "W", Eq, Hash);
+impl_unit!(WattHours, u32, "Wh", Eq, Hash);
+impl_unit!(Celsius, i32, "°C", Eq, Hash);
+impl_unit!(Percentage, u8, "%", Eq, Hash);
+impl_unit!(Volt, f32, "V");
+impl_unit!(Hertz, f32, "Hz");